@@ -1,4 +1,5 @@
-use crate::cpu::unicorn_interface::UnicornCPU;
+use crate::cpu::guest_memory::GuestMemory;
+use crate::cpu::unicorn_interface::{InterruptReason, UnicornCPU};
 
 pub const CORE_COUNT: usize = 8;
 
@@ -13,6 +14,7 @@ pub struct CpuManager {
     // We keep the memory here to ensure it lives as long as the CPUs
     // In a real implementation, this might be a separate Memory component
     pub shared_memory: Vec<u8>,
+    memory: GuestMemory,
 }
 
 impl CpuManager {
@@ -38,9 +40,13 @@ impl CpuManager {
             }
         }
 
+        // Safety: same buffer and lifetime as the pointer handed to the cores above
+        let memory = unsafe { GuestMemory::new(memory_ptr, MEMORY_SIZE) };
+
         Self {
             cores,
             shared_memory,
+            memory,
         }
     }
 
@@ -56,4 +62,29 @@ impl CpuManager {
     pub fn get_core(&self, id: usize) -> Option<&UnicornCPU> {
         self.cores.get(id)
     }
+
+    /// Stop core `id` at its next block boundary, posting `reason`; returns
+    /// false if there is no such core
+    ///
+    /// See [`UnicornCPU::interrupt`]; other threads can do the same through
+    /// a clone of the core.
+    pub fn interrupt_core(&self, id: usize, reason: InterruptReason) -> bool {
+        self.cores
+            .get(id)
+            .map(|core| core.interrupt(reason))
+            .is_some()
+    }
+
+    /// Make every core retranslate `[addr, addr + len)` after the host
+    /// patched guest code there
+    pub fn invalidate_code(&self, addr: u64, len: u64) {
+        for core in &self.cores {
+            core.invalidate_code(addr, len);
+        }
+    }
+
+    /// Host-side view of the shared RAM, for kernel and service code
+    pub fn memory(&self) -> GuestMemory {
+        self.memory
+    }
 }
@@ -0,0 +1,98 @@
+use std::ptr;
+
+/// Host-side view of the emulated RAM shared by all cores
+///
+/// Guest addresses are identity mapped onto the backing buffer, so a guest
+/// address is also the offset into it. Every access is bounds checked; reads
+/// return `None` and writes return `false` when the range falls outside RAM.
+#[derive(Clone, Copy)]
+pub struct GuestMemory {
+    base: *mut u8,
+    size: u64,
+}
+
+impl GuestMemory {
+    /// # Safety
+    /// `base` must be valid for reads and writes of `size` bytes for as long
+    /// as this view (or any copy of it) is used.
+    pub unsafe fn new(base: *mut u8, size: u64) -> Self {
+        Self { base, size }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Whether `[addr, addr + len)` lies entirely inside RAM
+    pub fn contains(&self, addr: u64, len: u64) -> bool {
+        addr.checked_add(len).is_some_and(|end| end <= self.size)
+    }
+
+    pub fn read(&self, addr: u64, buf: &mut [u8]) -> bool {
+        if !self.contains(addr, buf.len() as u64) {
+            return false;
+        }
+        unsafe { ptr::copy(self.base.add(addr as usize), buf.as_mut_ptr(), buf.len()) };
+        true
+    }
+
+    pub fn write(&self, addr: u64, data: &[u8]) -> bool {
+        if !self.contains(addr, data.len() as u64) {
+            return false;
+        }
+        unsafe { ptr::copy(data.as_ptr(), self.base.add(addr as usize), data.len()) };
+        true
+    }
+
+    /// Fill `[addr, addr + len)` with `value`
+    pub fn fill(&self, addr: u64, len: u64, value: u8) -> bool {
+        if !self.contains(addr, len) {
+            return false;
+        }
+        unsafe { ptr::write_bytes(self.base.add(addr as usize), value, len as usize) };
+        true
+    }
+
+    /// Copy `len` bytes from `src` to `dst`; the ranges may overlap
+    pub fn copy(&self, dst: u64, src: u64, len: u64) -> bool {
+        if !self.contains(dst, len) || !self.contains(src, len) {
+            return false;
+        }
+        unsafe {
+            ptr::copy(
+                self.base.add(src as usize),
+                self.base.add(dst as usize),
+                len as usize,
+            )
+        };
+        true
+    }
+
+    pub fn read_u32(&self, addr: u64) -> Option<u32> {
+        let mut bytes = [0u8; 4];
+        self.read(addr, &mut bytes).then(|| u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&self, addr: u64) -> Option<u64> {
+        let mut bytes = [0u8; 8];
+        self.read(addr, &mut bytes).then(|| u64::from_le_bytes(bytes))
+    }
+
+    pub fn write_u32(&self, addr: u64, value: u32) -> bool {
+        self.write(addr, &value.to_le_bytes())
+    }
+
+    pub fn write_u64(&self, addr: u64, value: u64) -> bool {
+        self.write(addr, &value.to_le_bytes())
+    }
+
+    /// Read `len` bytes into a freshly allocated buffer
+    pub fn read_vec(&self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+        self.read(addr, &mut buf).then_some(buf)
+    }
+}
+
+// The backing buffer is owned by `CpuManager` and outlives every view handed out.
+unsafe impl Send for GuestMemory {}
+unsafe impl Sync for GuestMemory {}
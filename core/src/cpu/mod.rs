@@ -1,4 +1,5 @@
 pub mod unicorn_interface;
-pub use unicorn_interface::UnicornCPU;
+pub use unicorn_interface::{CpuContext, HaltReason, InterruptReason, Interrupts, UnicornCPU};
 pub mod cpu_manager;
-
+pub mod guest_memory;
+pub use guest_memory::GuestMemory;
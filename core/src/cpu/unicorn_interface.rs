@@ -1,9 +1,154 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use unicorn_engine::{Arch, Mode, Prot, RegisterARM64, Unicorn};
+use unicorn_engine::{Arch, Mode, Prot, RegisterARM64, Unicorn, uc_error};
+
+// QEMU exception numbers reported to Unicorn interrupt hooks
+const EXCP_UDEF: u32 = 1;
+const EXCP_SWI: u32 = 2;
+const EXCP_BKPT: u32 = 7;
+
+/// Why [`UnicornCPU::run_until_halt`] returned control to the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaltReason {
+    /// `SVC #imm` was executed, PC points past the instruction
+    Svc(u32),
+    /// `BRK #imm` was executed, PC points at the instruction
+    Breakpoint(u32),
+    /// Undefined instruction at PC
+    Undefined,
+    /// Any other CPU exception, by QEMU exception number
+    Exception(u32),
+    /// [`UnicornCPU::interrupt`] or the core's timer stopped it at a block
+    /// boundary, PC points at the next instruction to run
+    Interrupted(Interrupts),
+    /// Emulation stopped without an exception
+    Stopped,
+    /// Unicorn gave up, e.g. on an unmapped access
+    Error(uc_error),
+}
+
+/// Why a core was asked to stop through [`UnicornCPU::interrupt`]
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptReason {
+    /// The scheduler wants to pick the core's thread again
+    Reschedule = 1 << 0,
+    /// The deadline armed with [`UnicornCPU::set_timer`] passed
+    Timer = 1 << 1,
+}
+
+/// The reasons posted to a core since it last stopped for an interrupt
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Interrupts(u32);
+
+impl Interrupts {
+    pub fn contains(self, reason: InterruptReason) -> bool {
+        self.0 & reason as u32 != 0
+    }
+}
+
+/// Interrupt state shared by a core's block hook and whoever interrupts
+/// it; only atomics, so posting never waits for the running core
+struct InterruptState {
+    /// [`InterruptReason`] bits not yet delivered
+    pending: AtomicU32,
+    /// Instructions the core has run
+    instructions: AtomicU64,
+    /// Value of `instructions` at which to post [`InterruptReason::Timer`]
+    timer: AtomicU64,
+}
+
+const TIMER_DISARMED: u64 = u64::MAX;
+
+const X_REGISTERS: [RegisterARM64; 31] = [
+    RegisterARM64::X0,
+    RegisterARM64::X1,
+    RegisterARM64::X2,
+    RegisterARM64::X3,
+    RegisterARM64::X4,
+    RegisterARM64::X5,
+    RegisterARM64::X6,
+    RegisterARM64::X7,
+    RegisterARM64::X8,
+    RegisterARM64::X9,
+    RegisterARM64::X10,
+    RegisterARM64::X11,
+    RegisterARM64::X12,
+    RegisterARM64::X13,
+    RegisterARM64::X14,
+    RegisterARM64::X15,
+    RegisterARM64::X16,
+    RegisterARM64::X17,
+    RegisterARM64::X18,
+    RegisterARM64::X19,
+    RegisterARM64::X20,
+    RegisterARM64::X21,
+    RegisterARM64::X22,
+    RegisterARM64::X23,
+    RegisterARM64::X24,
+    RegisterARM64::X25,
+    RegisterARM64::X26,
+    RegisterARM64::X27,
+    RegisterARM64::X28,
+    RegisterARM64::X29,
+    RegisterARM64::X30,
+];
+
+const Q_REGISTERS: [RegisterARM64; 32] = [
+    RegisterARM64::Q0,
+    RegisterARM64::Q1,
+    RegisterARM64::Q2,
+    RegisterARM64::Q3,
+    RegisterARM64::Q4,
+    RegisterARM64::Q5,
+    RegisterARM64::Q6,
+    RegisterARM64::Q7,
+    RegisterARM64::Q8,
+    RegisterARM64::Q9,
+    RegisterARM64::Q10,
+    RegisterARM64::Q11,
+    RegisterARM64::Q12,
+    RegisterARM64::Q13,
+    RegisterARM64::Q14,
+    RegisterARM64::Q15,
+    RegisterARM64::Q16,
+    RegisterARM64::Q17,
+    RegisterARM64::Q18,
+    RegisterARM64::Q19,
+    RegisterARM64::Q20,
+    RegisterARM64::Q21,
+    RegisterARM64::Q22,
+    RegisterARM64::Q23,
+    RegisterARM64::Q24,
+    RegisterARM64::Q25,
+    RegisterARM64::Q26,
+    RegisterARM64::Q27,
+    RegisterARM64::Q28,
+    RegisterARM64::Q29,
+    RegisterARM64::Q30,
+    RegisterARM64::Q31,
+];
+
+/// User-visible register state of a guest thread, swapped in and out of a
+/// core on every context switch
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CpuContext {
+    pub x: [u64; 31],
+    pub sp: u64,
+    pub pc: u64,
+    pub nzcv: u64,
+    pub fpcr: u64,
+    pub fpsr: u64,
+    pub tpidr_el0: u64,
+    pub tpidrro_el0: u64,
+    pub v: [u128; 32],
+}
 
 /// Safe wrapper for Unicorn CPU emulator
 pub struct UnicornCPU {
     emu: Arc<Mutex<Unicorn<'static, ()>>>,
+    last_exception: Arc<Mutex<Option<HaltReason>>>,
+    interrupts: Arc<InterruptState>,
     pub core_id: u32,
 }
 
@@ -29,8 +174,13 @@ impl UnicornCPU {
         // Initialize stack pointer
         let _ = emu.reg_write(RegisterARM64::SP, (8 * 1024 * 1024) - 0x1000);
 
+        let last_exception = Self::install_exception_hook(&mut emu)?;
+        let interrupts = Self::install_interrupt_hook(&mut emu, last_exception.clone())?;
+
         Some(Self {
             emu: Arc::new(Mutex::new(emu)),
+            last_exception,
+            interrupts,
             core_id: 0,
         })
     }
@@ -64,12 +214,117 @@ impl UnicornCPU {
         let stack_top = memory_size - (core_id as u64 * 0x100000);
         let _ = emu.reg_write(RegisterARM64::SP, stack_top);
 
+        let last_exception = Self::install_exception_hook(&mut emu)?;
+        let interrupts = Self::install_interrupt_hook(&mut emu, last_exception.clone())?;
+
         Some(Self {
             emu: Arc::new(Mutex::new(emu)),
+            last_exception,
+            interrupts,
             core_id,
         })
     }
 
+    /// Stop emulation on every CPU exception and remember why, so the
+    /// host can service SVCs and resume
+    fn install_exception_hook(
+        emu: &mut Unicorn<'static, ()>,
+    ) -> Option<Arc<Mutex<Option<HaltReason>>>> {
+        let last_exception = Arc::new(Mutex::new(None));
+        let hook_exception = last_exception.clone();
+
+        emu.add_intr_hook(move |uc, intno| {
+            let pc = uc.reg_read(RegisterARM64::PC).unwrap_or(0);
+            // Both SVC and BRK carry their immediate in bits [20:5]
+            let imm_at = |addr: u64| {
+                let mut bytes = [0u8; 4];
+                let _ = uc.mem_read(addr, &mut bytes);
+                (u32::from_le_bytes(bytes) >> 5) & 0xFFFF
+            };
+            let reason = match intno {
+                EXCP_SWI => HaltReason::Svc(imm_at(pc.wrapping_sub(4))),
+                EXCP_BKPT => HaltReason::Breakpoint(imm_at(pc)),
+                EXCP_UDEF => HaltReason::Undefined,
+                other => HaltReason::Exception(other),
+            };
+            *hook_exception.lock().unwrap() = Some(reason);
+            let _ = uc.emu_stop();
+        })
+        .inspect_err(|e| eprintln!("Failed to install exception hook: {e:?}"))
+        .ok()?;
+
+        Some(last_exception)
+    }
+
+    /// Count instructions and stop at the start of a block once an
+    /// interrupt is pending or the timer has expired
+    fn install_interrupt_hook(
+        emu: &mut Unicorn<'static, ()>,
+        last_exception: Arc<Mutex<Option<HaltReason>>>,
+    ) -> Option<Arc<InterruptState>> {
+        let state = Arc::new(InterruptState {
+            pending: AtomicU32::new(0),
+            instructions: AtomicU64::new(0),
+            timer: AtomicU64::new(TIMER_DISARMED),
+        });
+
+        // begin > end hooks every address. Instructions are counted a block
+        // at a time, as the block starts, which is as fine as the timer needs
+        let hook_state = state.clone();
+        emu.add_block_hook(1, 0, move |uc, _, size| {
+            let count = hook_state.instructions.load(Ordering::Relaxed);
+            if count >= hook_state.timer.load(Ordering::Relaxed) {
+                hook_state.timer.store(TIMER_DISARMED, Ordering::Relaxed);
+                hook_state
+                    .pending
+                    .fetch_or(InterruptReason::Timer as u32, Ordering::AcqRel);
+            }
+            let pending = hook_state.pending.swap(0, Ordering::AcqRel);
+            if pending != 0 {
+                let reason = HaltReason::Interrupted(Interrupts(pending));
+                *last_exception.lock().unwrap() = Some(reason);
+                let _ = uc.emu_stop();
+                return;
+            }
+            hook_state
+                .instructions
+                .store(count + u64::from(size / 4), Ordering::Relaxed);
+        })
+        .inspect_err(|e| eprintln!("Failed to install interrupt hook: {e:?}"))
+        .ok()?;
+
+        Some(state)
+    }
+
+    /// Stop the core at its next block boundary with
+    /// [`HaltReason::Interrupted`]
+    ///
+    /// Unlike [`Self::halt`] this does not lock the core, so it is safe to
+    /// call from another thread while the core runs, through a clone of it.
+    /// A reason posted while the core is stopped is delivered as soon as it
+    /// runs again.
+    pub fn interrupt(&self, reason: InterruptReason) {
+        self.interrupts
+            .pending
+            .fetch_or(reason as u32, Ordering::AcqRel);
+    }
+
+    /// Instructions the core has run, counted a whole block at a time as
+    /// each block starts
+    pub fn instructions(&self) -> u64 {
+        self.interrupts.instructions.load(Ordering::Relaxed)
+    }
+
+    /// Post [`InterruptReason::Timer`] once [`Self::instructions`] reaches
+    /// `at`, replacing any earlier timer
+    pub fn set_timer(&self, at: u64) {
+        self.interrupts.timer.store(at, Ordering::Relaxed);
+    }
+
+    pub fn cancel_timer(&self) {
+        self.set_timer(TIMER_DISARMED);
+    }
+
     /// Run the core until halt or breakpoint
     pub fn run(&self) -> u64 {
         let mut emu = self.emu.lock().unwrap();
@@ -90,6 +345,27 @@ impl UnicornCPU {
         }
     }
 
+    /// Run from the current PC until the core raises an exception or stops
+    pub fn run_until_halt(&self) -> HaltReason {
+        self.run_for(0)
+    }
+
+    /// Like [`Self::run_until_halt`], but give up after `max_instructions`
+    /// (0 = no limit), returning [`HaltReason::Stopped`] with PC at the next
+    /// instruction so the core can be resumed later
+    pub fn run_for(&self, max_instructions: usize) -> HaltReason {
+        let mut emu = self.emu.lock().unwrap();
+        let pc = emu.reg_read(RegisterARM64::PC).unwrap_or(0);
+        *self.last_exception.lock().unwrap() = None;
+
+        let result = emu.emu_start(pc, u64::MAX, 0, max_instructions);
+        match (self.last_exception.lock().unwrap().take(), result) {
+            (Some(reason), _) => reason,
+            (None, Ok(())) => HaltReason::Stopped,
+            (None, Err(e)) => HaltReason::Error(e),
+        }
+    }
+
     /// Execute a single step
     pub fn step(&self) -> u64 {
         let mut emu = self.emu.lock().unwrap();
@@ -107,6 +383,13 @@ impl UnicornCPU {
         let _ = emu.emu_stop();
     }
 
+    /// Drop translated code covering `[addr, addr + len)`, so code the host
+    /// rewrote there is retranslated before it next runs
+    pub fn invalidate_code(&self, addr: u64, len: u64) {
+        let mut emu = self.emu.lock().unwrap();
+        let _ = emu.ctl_remove_cache(addr, addr + len);
+    }
+
     /// Read register Xn (0-30)
     pub fn get_x(&self, reg_index: u32) -> u64 {
         let emu = self.emu.lock().unwrap();
@@ -221,6 +504,52 @@ impl UnicornCPU {
         let _ = emu.reg_write(RegisterARM64::PC, value);
     }
 
+    /// Capture the register state of the thread currently on this core
+    pub fn save_context(&self) -> CpuContext {
+        let emu = self.emu.lock().unwrap();
+        let read = |reg: RegisterARM64| emu.reg_read(reg).unwrap_or(0);
+
+        let mut ctx = CpuContext {
+            sp: read(RegisterARM64::SP),
+            pc: read(RegisterARM64::PC),
+            nzcv: read(RegisterARM64::NZCV),
+            fpcr: read(RegisterARM64::FPCR),
+            fpsr: read(RegisterARM64::FPSR),
+            tpidr_el0: read(RegisterARM64::TPIDR_EL0),
+            tpidrro_el0: read(RegisterARM64::TPIDRRO_EL0),
+            ..Default::default()
+        };
+        for (value, reg) in ctx.x.iter_mut().zip(X_REGISTERS) {
+            *value = read(reg);
+        }
+        for (value, reg) in ctx.v.iter_mut().zip(Q_REGISTERS) {
+            if let Ok(bytes) = emu.reg_read_long(reg) {
+                let mut le = [0u8; 16];
+                le.copy_from_slice(&bytes[..16]);
+                *value = u128::from_le_bytes(le);
+            }
+        }
+        ctx
+    }
+
+    /// Replace the register state of this core with `ctx`
+    pub fn load_context(&self, ctx: &CpuContext) {
+        let mut emu = self.emu.lock().unwrap();
+        for (value, reg) in ctx.x.iter().zip(X_REGISTERS) {
+            let _ = emu.reg_write(reg, *value);
+        }
+        for (value, reg) in ctx.v.iter().zip(Q_REGISTERS) {
+            let _ = emu.reg_write_long(reg, &value.to_le_bytes());
+        }
+        let _ = emu.reg_write(RegisterARM64::SP, ctx.sp);
+        let _ = emu.reg_write(RegisterARM64::PC, ctx.pc);
+        let _ = emu.reg_write(RegisterARM64::NZCV, ctx.nzcv);
+        let _ = emu.reg_write(RegisterARM64::FPCR, ctx.fpcr);
+        let _ = emu.reg_write(RegisterARM64::FPSR, ctx.fpsr);
+        let _ = emu.reg_write(RegisterARM64::TPIDR_EL0, ctx.tpidr_el0);
+        let _ = emu.reg_write(RegisterARM64::TPIDRRO_EL0, ctx.tpidrro_el0);
+    }
+
     /// Write a 32-bit value to emulated memory
     pub fn write_u32(&self, vaddr: u64, value: u32) {
         let mut emu = self.emu.lock().unwrap();
@@ -264,6 +593,8 @@ impl Clone for UnicornCPU {
         // This allows multiple references to the same core
         Self {
             emu: self.emu.clone(),
+            last_exception: self.last_exception.clone(),
+            interrupts: self.interrupts.clone(),
             core_id: self.core_id,
         }
     }
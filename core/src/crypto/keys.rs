@@ -0,0 +1,364 @@
+//! Console keys, read from the user's `prod.keys` and `title.keys`
+//!
+//! Both files are `name = hex` lines as written by the usual dumping tools.
+//! `prod.keys` names a key and may index it by master key revision
+//! (`master_key_05`); keys this module does not use are skipped. The keys
+//! needed to decrypt NCAs are derived from the master keys and key sources
+//! unless the file already holds them. `title.keys` maps a rights ID to the
+//! title key from its ticket, still encrypted with the revision's titlekek.
+
+use super::{Key128, Key256, aes_ecb_decrypt};
+use crate::fs::invalid_data;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+/// How many master key revisions a key file may index
+pub const MASTER_KEY_COUNT: usize = 0x20;
+
+/// The `prod.keys` keys this module uses, with their size in bytes and
+/// whether they are indexed by master key revision
+const KNOWN_KEYS: &[(&str, usize, bool)] = &[
+    ("master_key", 0x10, true),
+    ("master_kek", 0x10, true),
+    ("master_key_source", 0x10, false),
+    ("aes_kek_generation_source", 0x10, false),
+    ("aes_key_generation_source", 0x10, false),
+    ("header_kek_source", 0x10, false),
+    ("header_key_source", 0x20, false),
+    ("header_key", 0x20, false),
+    ("key_area_key_application_source", 0x10, false),
+    ("key_area_key_ocean_source", 0x10, false),
+    ("key_area_key_system_source", 0x10, false),
+    ("key_area_key_application", 0x10, true),
+    ("key_area_key_ocean", 0x10, true),
+    ("key_area_key_system", 0x10, true),
+    ("titlekek_source", 0x10, false),
+    ("titlekek", 0x10, true),
+];
+
+/// Which key-area key an NCA's key area is encrypted with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAreaKey {
+    Application,
+    Ocean,
+    System,
+}
+
+impl KeyAreaKey {
+    pub const ALL: [Self; 3] = [Self::Application, Self::Ocean, Self::System];
+
+    /// The kind an NCA header's key index names
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Application => "key_area_key_application",
+            Self::Ocean => "key_area_key_ocean",
+            Self::System => "key_area_key_system",
+        }
+    }
+}
+
+/// Keys read from the user's key files, and those derived from them
+#[derive(Default)]
+pub struct Keyset {
+    keys: HashMap<String, Vec<u8>>,
+    /// Encrypted title keys by rights ID
+    title_keys: HashMap<[u8; 0x10], Key128>,
+}
+
+impl Keyset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read `prod.keys`, and `title.keys` if there is one, from `dir` and
+    /// derive what can be derived
+    pub fn load(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut keyset = Self::new();
+        let prod = std::fs::read_to_string(dir.join("prod.keys")).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("cannot read prod.keys in {}: {err}", dir.display()),
+            )
+        })?;
+        keyset.add_prod_keys(&prod)?;
+        match std::fs::read_to_string(dir.join("title.keys")) {
+            Ok(title) => keyset.add_title_keys(&title)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        keyset.derive();
+        Ok(keyset)
+    }
+
+    /// Add the keys in the text of a `prod.keys` file, replacing any of the
+    /// same name
+    pub fn add_prod_keys(&mut self, text: &str) -> io::Result<()> {
+        for (line, name, value) in lines("prod.keys", text)? {
+            let at = |msg: String| invalid_data(format!("prod.keys line {line}: {msg}"));
+            if !name
+                .bytes()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == b'_')
+            {
+                return Err(at(format!("{name:?} is not a key name")));
+            }
+            let Some(size) = key_size(name).map_err(at)? else {
+                log::debug!("Skipping unused key {name}");
+                continue;
+            };
+            if value.len() != size {
+                let found = value.len();
+                return Err(at(format!("{name} should be {size} bytes, found {found}")));
+            }
+            self.keys.insert(name.to_string(), value);
+        }
+        Ok(())
+    }
+
+    /// Add the rights IDs and encrypted title keys in the text of a
+    /// `title.keys` file
+    pub fn add_title_keys(&mut self, text: &str) -> io::Result<()> {
+        for (line, name, value) in lines("title.keys", text)? {
+            let at = |msg: &str| invalid_data(format!("title.keys line {line}: {msg}"));
+            let rights_id = parse_hex(name)
+                .and_then(|id| <[u8; 0x10]>::try_from(id).ok())
+                .ok_or_else(|| at("a rights ID is 16 bytes of hex"))?;
+            let key = <Key128>::try_from(value).map_err(|_| at("a title key is 16 bytes"))?;
+            self.title_keys.insert(rights_id, key);
+        }
+        Ok(())
+    }
+
+    /// Derive the header key, and each revision's key-area keys and
+    /// titlekek, from the master keys and key sources; keys the files
+    /// already hold are kept
+    pub fn derive(&mut self) {
+        if let Some(source) = self.key128("master_key_source") {
+            for revision in 0..MASTER_KEY_COUNT {
+                let name = indexed("master_key", revision);
+                let master_kek = self.key128(&indexed("master_kek", revision));
+                if let (None, Some(master_kek)) = (self.get(&name), master_kek) {
+                    let mut master_key = source;
+                    aes_ecb_decrypt(&master_kek, &mut master_key);
+                    self.keys.insert(name, master_key.to_vec());
+                }
+            }
+        }
+
+        let kek_seed = self.key128("aes_kek_generation_source");
+        let key_seed = self.key128("aes_key_generation_source");
+        let seeds = kek_seed.zip(key_seed);
+        if self.get("header_key").is_none()
+            && let (
+                Some((kek_seed, key_seed)),
+                Some(master_key),
+                Some(kek_source),
+                Some(key_source),
+            ) = (
+                seeds,
+                self.key128("master_key_00"),
+                self.key128("header_kek_source"),
+                self.get("header_key_source"),
+            )
+        {
+            let header_kek = generate_kek(&kek_source, &master_key, &kek_seed, &key_seed);
+            let mut header_key = key_source.to_vec();
+            aes_ecb_decrypt(&header_kek, &mut header_key);
+            self.keys.insert("header_key".to_string(), header_key);
+        }
+
+        for revision in 0..MASTER_KEY_COUNT {
+            let Some(master_key) = self.key128(&indexed("master_key", revision)) else {
+                continue;
+            };
+            let name = indexed("titlekek", revision);
+            if let (None, Some(mut titlekek)) = (self.get(&name), self.key128("titlekek_source")) {
+                aes_ecb_decrypt(&master_key, &mut titlekek);
+                self.keys.insert(name, titlekek.to_vec());
+            }
+            for kind in KeyAreaKey::ALL {
+                let name = indexed(kind.name(), revision);
+                let source = self.key128(&format!("{}_source", kind.name()));
+                if let (None, Some(source), Some((kek_seed, key_seed))) =
+                    (self.get(&name), source, seeds)
+                {
+                    let key = generate_kek(&source, &master_key, &kek_seed, &key_seed);
+                    self.keys.insert(name, key.to_vec());
+                }
+            }
+        }
+    }
+
+    /// The key NCA headers are encrypted with
+    pub fn header_key(&self) -> io::Result<Key256> {
+        self.require("header_key")
+    }
+
+    /// The key an NCA's key area is encrypted with, for a master key
+    /// revision
+    pub fn key_area_key(&self, kind: KeyAreaKey, revision: u8) -> io::Result<Key128> {
+        self.require(&indexed(kind.name(), revision as usize))
+    }
+
+    /// The key title keys are encrypted with, for a master key revision
+    pub fn titlekek(&self, revision: u8) -> io::Result<Key128> {
+        self.require(&indexed("titlekek", revision as usize))
+    }
+
+    /// The decrypted title key for `rights_id`, whose NCAs are from master
+    /// key revision `revision`
+    pub fn title_key(&self, rights_id: &[u8; 0x10], revision: u8) -> io::Result<Key128> {
+        let mut key = *self.title_keys.get(rights_id).ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "title.keys has no title key for rights ID {}",
+                    hex(rights_id)
+                ),
+            )
+        })?;
+        aes_ecb_decrypt(&self.titlekek(revision)?, &mut key);
+        Ok(key)
+    }
+
+    /// The `prod.keys` entries that would let the header key, and the
+    /// key-area keys and titlekek of each master key revision present, be
+    /// derived; empty when nothing is missing
+    pub fn missing(&self) -> Vec<String> {
+        let mut missing = BTreeSet::new();
+        let mut need = |names: &[&str]| {
+            for name in names {
+                if self.get(name).is_none() {
+                    missing.insert(name.to_string());
+                }
+            }
+        };
+        let seeds = ["aes_kek_generation_source", "aes_key_generation_source"];
+        if self.get("header_key").is_none() {
+            need(&["master_key_00", "header_kek_source", "header_key_source"]);
+            need(&seeds);
+        }
+
+        let mut revisions = (0..MASTER_KEY_COUNT)
+            .filter(|&revision| self.get(&indexed("master_key", revision)).is_some())
+            .peekable();
+        if revisions.peek().is_none() {
+            need(&["master_key_00"]);
+        }
+        for revision in revisions {
+            if self.get(&indexed("titlekek", revision)).is_none() {
+                need(&["titlekek_source"]);
+            }
+            for kind in KeyAreaKey::ALL {
+                if self.get(&indexed(kind.name(), revision)).is_none() {
+                    need(&[&format!("{}_source", kind.name())]);
+                    need(&seeds);
+                }
+            }
+        }
+        missing.into_iter().collect()
+    }
+
+    fn get(&self, name: &str) -> Option<&[u8]> {
+        self.keys.get(name).map(Vec::as_slice)
+    }
+
+    fn key128(&self, name: &str) -> Option<Key128> {
+        self.get(name)?.try_into().ok()
+    }
+
+    fn require<const N: usize>(&self, name: &str) -> io::Result<[u8; N]> {
+        self.get(name)
+            .and_then(|key| key.try_into().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("{name} is missing and cannot be derived from prod.keys"),
+                )
+            })
+    }
+}
+
+/// The `name = value` lines of a key file, numbered from 1, skipping blank
+/// lines and comments
+fn lines<'a>(file: &str, text: &'a str) -> io::Result<Vec<(usize, &'a str, Vec<u8>)>> {
+    let mut entries = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let at = |msg: &str| invalid_data(format!("{file} line {}: {msg}", number + 1));
+        let (name, value) = line
+            .split_once(['=', ','])
+            .ok_or_else(|| at("expected name = value"))?;
+        let value = parse_hex(value.trim()).ok_or_else(|| at("the value is not hex"))?;
+        entries.push((number + 1, name.trim(), value));
+    }
+    Ok(entries)
+}
+
+/// The size of a key this module uses, `None` for others, checking a
+/// revision index is in range
+fn key_size(name: &str) -> Result<Option<usize>, String> {
+    let (family, revision) = match name.rsplit_once('_') {
+        Some((family, index)) if index.len() == 2 => match usize::from_str_radix(index, 16) {
+            Ok(revision) => (family, Some(revision)),
+            Err(_) => (name, None),
+        },
+        _ => (name, None),
+    };
+    let Some(&(_, size, indexed)) = KNOWN_KEYS.iter().find(|(known, ..)| *known == family) else {
+        return Ok(None);
+    };
+    match revision {
+        Some(revision) if revision >= MASTER_KEY_COUNT => {
+            Err(format!("{name} is past the last master key revision"))
+        }
+        Some(_) if indexed => Ok(Some(size)),
+        None if !indexed => Ok(Some(size)),
+        Some(_) => Err(format!("{family} is not indexed by revision")),
+        None => Err(format!("{name} needs a revision, as in {name}_00")),
+    }
+}
+
+/// The name of a key indexed by master key revision
+fn indexed(family: &str, revision: usize) -> String {
+    format!("{family}_{revision:02x}")
+}
+
+/// Decrypt `source` into a key through the console's key generation
+/// sources, as the boot ROM's KEK generation does
+fn generate_kek(
+    source: &Key128,
+    master_key: &Key128,
+    kek_seed: &Key128,
+    key_seed: &Key128,
+) -> Key128 {
+    let mut kek = *kek_seed;
+    aes_ecb_decrypt(master_key, &mut kek);
+    let mut source_kek = *source;
+    aes_ecb_decrypt(&kek, &mut source_kek);
+    let mut key = *key_seed;
+    aes_ecb_decrypt(&source_kek, &mut key);
+    key
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,58 @@
+//! Cryptography for title contents
+//!
+//! Everything here works from keys the user dumped from their own console
+//! and supplies in [`Keyset`]; no key material is built into the emulator.
+
+mod keys;
+
+pub use keys::{KeyAreaKey, Keyset, MASTER_KEY_COUNT};
+
+use aes::Aes128;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+
+pub type Key128 = [u8; 0x10];
+pub type Key256 = [u8; 0x20];
+
+/// Decrypt `data`, a whole number of blocks, with AES-128 in ECB mode
+pub(crate) fn aes_ecb_decrypt(key: &Key128, data: &mut [u8]) {
+    let cipher = Aes128::new(key.into());
+    for block in data.chunks_exact_mut(0x10) {
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+}
+
+/// Decrypt `data` with AES-128-XTS in sectors of `sector_size`, the first
+/// being sector `sector`. The first half of `key` encrypts the data and the
+/// second the tweak, which is the sector number big-endian rather than the
+/// standard's little-endian.
+pub(crate) fn aes_xts_decrypt(key: &Key256, data: &mut [u8], sector: u64, sector_size: usize) {
+    let cipher = Aes128::new(GenericArray::from_slice(&key[..0x10]));
+    let tweak_cipher = Aes128::new(GenericArray::from_slice(&key[0x10..]));
+    for (i, sector_data) in data.chunks_mut(sector_size).enumerate() {
+        let mut tweak = GenericArray::from((sector as u128 + i as u128).to_be_bytes());
+        tweak_cipher.encrypt_block(&mut tweak);
+        for block in sector_data.chunks_exact_mut(0x10) {
+            block.iter_mut().zip(&tweak).for_each(|(b, t)| *b ^= t);
+            cipher.decrypt_block(GenericArray::from_mut_slice(block));
+            block.iter_mut().zip(&tweak).for_each(|(b, t)| *b ^= t);
+            // Multiply the tweak by x in GF(2^128), little-endian
+            let carry = tweak[15] >> 7;
+            for j in (1..0x10).rev() {
+                tweak[j] = (tweak[j] << 1) | (tweak[j - 1] >> 7);
+            }
+            tweak[0] = (tweak[0] << 1) ^ (0x87 * carry);
+        }
+    }
+}
+
+/// XOR `data` with the AES-128-CTR keystream from counter block `counter`,
+/// which both encrypts and decrypts
+pub(crate) fn aes_ctr_apply(key: &Key128, counter: u128, data: &mut [u8]) {
+    let cipher = Aes128::new(key.into());
+    for (i, chunk) in data.chunks_mut(0x10).enumerate() {
+        let mut stream = GenericArray::from(counter.wrapping_add(i as u128).to_be_bytes());
+        cipher.encrypt_block(&mut stream);
+        chunk.iter_mut().zip(stream).for_each(|(b, k)| *b ^= k);
+    }
+}
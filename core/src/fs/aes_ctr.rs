@@ -0,0 +1,94 @@
+//! AES-CTR encrypted NCA sections
+//!
+//! The counter block is the section's 8-byte upper counter followed by the
+//! big-endian offset in the NCA over 0x10. Update NCAs encrypt parts of a
+//! section under different generations (CTR-EX): a table of subsections
+//! gives the generation that replaces the low word of the upper counter.
+
+use super::bucket_tree::BucketTree;
+use super::{VfsFile, VirtualFile};
+use crate::crypto::{Key128, aes_ctr_apply};
+use std::io;
+
+const BLOCK_SIZE: u64 = 0x10;
+
+/// A decrypted view of an encrypted section
+pub(super) struct AesCtrFile {
+    base: VirtualFile,
+    key: Key128,
+    upper: u64,
+    /// Where the section starts in the NCA, for the counter
+    offset: u64,
+    /// CTR-EX subsections and their generations
+    generations: Option<BucketTree<u32>>,
+}
+
+impl AesCtrFile {
+    pub fn new(base: VirtualFile, key: Key128, upper: u64, offset: u64) -> Self {
+        Self {
+            base,
+            key,
+            upper,
+            offset,
+            generations: None,
+        }
+    }
+
+    /// A CTR-EX section; past the last subsection, as for the tables
+    /// themselves, the section's own upper counter applies
+    pub fn with_generations(mut self, generations: BucketTree<u32>) -> Self {
+        self.generations = Some(generations);
+        self
+    }
+
+    /// The upper counter at `offset`, and where it stops applying
+    fn upper_at(&self, offset: u64) -> (u64, u64) {
+        let Some(tree) = &self.generations else {
+            return (self.upper, u64::MAX);
+        };
+        match tree.find(offset) {
+            Some(index) => {
+                let generation = tree.entries[index].1 as u64;
+                let upper = (self.upper & !0xFFFF_FFFF) | generation;
+                (upper, tree.entry_end(index))
+            }
+            None if offset < tree.end => {
+                let first = tree.entries.first().map_or(tree.end, |&(start, _)| start);
+                (self.upper, first)
+            }
+            None => (self.upper, u64::MAX),
+        }
+    }
+}
+
+impl VfsFile for AesCtrFile {
+    fn name(&self) -> String {
+        self.base.name()
+    }
+
+    fn size(&self) -> u64 {
+        self.base.size()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let end = (offset + buf.len() as u64).min(self.size());
+        if offset >= end {
+            return Ok(0);
+        }
+        let start = offset - offset % BLOCK_SIZE;
+        let mut data = self.base.read_vec(start, (end - start) as usize)?;
+
+        let mut position = start;
+        while position < end {
+            let (upper, until) = self.upper_at(position);
+            let until = until.min(end);
+            let chunk = &mut data[(position - start) as usize..(until - start) as usize];
+            let counter = ((upper as u128) << 64) | ((self.offset + position) / BLOCK_SIZE) as u128;
+            aes_ctr_apply(&self.key, counter, chunk);
+            position = until;
+        }
+        let len = (end - offset) as usize;
+        buf[..len].copy_from_slice(&data[(offset - start) as usize..]);
+        Ok(len)
+    }
+}
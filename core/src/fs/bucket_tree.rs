@@ -0,0 +1,105 @@
+//! Bucket trees: the sorted offset tables update NCAs carry
+//!
+//! A table starts with a node listing where each entry set begins, then
+//! the entry sets, each in a node of its own. Every entry starts with the
+//! virtual offset it covers from, up to the next entry or the tree's end.
+//! The 0x10-byte header saying how many entries there are is kept in the
+//! section's FS header rather than with the table.
+
+use super::{VirtualFile, invalid_data};
+use std::io;
+
+const MAGIC: &[u8; 4] = b"BKTR";
+const NODE_SIZE: usize = 0x4000;
+const NODE_HEADER_SIZE: usize = 0x10;
+
+/// The entries of a bucket tree and the offset the last one runs to
+pub(super) struct BucketTree<T> {
+    pub entries: Vec<(u64, T)>,
+    pub end: u64,
+}
+
+impl<T> BucketTree<T> {
+    /// Read the table in `table` that `header` describes, parsing each
+    /// `entry_size`-byte entry with `parse`
+    pub fn read(
+        table: &VirtualFile,
+        header: &[u8],
+        entry_size: usize,
+        parse: impl Fn(&[u8]) -> T,
+    ) -> io::Result<Self> {
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("bucket tree header has no BKTR magic"));
+        }
+        let count = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+        if count == 0 {
+            return Ok(Self {
+                entries: Vec::new(),
+                end: 0,
+            });
+        }
+        let per_set = (NODE_SIZE - NODE_HEADER_SIZE) / entry_size;
+        let sets = count.div_ceil(per_set);
+        if sets > (NODE_SIZE - NODE_HEADER_SIZE) / 8 {
+            return Err(invalid_data(
+                "bucket trees with a second level are not supported",
+            ));
+        }
+        if table.size() < ((1 + sets) * NODE_SIZE) as u64 {
+            return Err(invalid_data("bucket tree runs past the end of its table"));
+        }
+
+        let node = table.read_vec(0, NODE_SIZE)?;
+        let word = |bytes: &[u8], offset: usize| {
+            u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        let end = u64::from_le_bytes(node[8..16].try_into().unwrap());
+        if word(&node, 4) != sets {
+            return Err(invalid_data("bucket tree node does not match its header"));
+        }
+
+        let mut entries = Vec::with_capacity(count);
+        for set in 0..sets {
+            let bytes = table.read_vec(((1 + set) * NODE_SIZE) as u64, NODE_SIZE)?;
+            let set_count = word(&bytes, 4);
+            if word(&bytes, 0) != set || set_count == 0 || set_count > per_set {
+                return Err(invalid_data(format!(
+                    "bucket tree entry set {set} is malformed"
+                )));
+            }
+            for entry in bytes[NODE_HEADER_SIZE..]
+                .chunks_exact(entry_size)
+                .take(set_count)
+            {
+                let offset = u64::from_le_bytes(entry[..8].try_into().unwrap());
+                if entries.last().is_some_and(|&(last, _)| last >= offset) || offset >= end {
+                    return Err(invalid_data("bucket tree entries are out of order"));
+                }
+                entries.push((offset, parse(entry)));
+            }
+        }
+        if entries.len() != count {
+            return Err(invalid_data(
+                "bucket tree does not hold as many entries as its header",
+            ));
+        }
+        Ok(Self { entries, end })
+    }
+
+    /// The index of the entry covering `offset`, if any does
+    pub fn find(&self, offset: u64) -> Option<usize> {
+        if offset >= self.end {
+            return None;
+        }
+        self.entries
+            .partition_point(|&(start, _)| start <= offset)
+            .checked_sub(1)
+    }
+
+    /// Where the entry at `index` stops covering
+    pub fn entry_end(&self, index: usize) -> u64 {
+        self.entries
+            .get(index + 1)
+            .map_or(self.end, |&(start, _)| start)
+    }
+}
@@ -0,0 +1,189 @@
+//! Files and directories on the host filesystem, e.g. an extracted title or
+//! the folder save data is kept in
+
+use super::{VfsDirectory, VfsFile, VirtualDir, VirtualFile, file_name, read_only};
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub struct HostFile {
+    name: String,
+    file: Mutex<fs::File>,
+    writable: bool,
+}
+
+impl HostFile {
+    /// Open an existing file, for writing too if `writable`
+    pub fn open(path: impl AsRef<Path>, writable: bool) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new().read(true).write(writable).open(path)?;
+        Ok(Self {
+            name: file_name(path),
+            file: Mutex::new(file),
+            writable,
+        })
+    }
+}
+
+impl VfsFile for HostFile {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn size(&self) -> u64 {
+        let file = self.file.lock().unwrap();
+        file.metadata().map_or(0, |metadata| metadata.len())
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        let mut done = 0;
+        while done < buf.len() {
+            match file.read(&mut buf[done..]) {
+                Ok(0) => break,
+                Ok(read) => done += read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(done)
+    }
+
+    fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> io::Result<usize> {
+        if !self.writable {
+            return Err(read_only());
+        }
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        Ok(data.len())
+    }
+
+    fn set_size(&self, size: u64) -> io::Result<()> {
+        if !self.writable {
+            return Err(read_only());
+        }
+        self.file.lock().unwrap().set_len(size)
+    }
+}
+
+/// A host directory; its files are opened as they are asked for
+pub struct HostDirectory {
+    path: PathBuf,
+    writable: bool,
+}
+
+impl HostDirectory {
+    pub fn open(path: impl Into<PathBuf>, writable: bool) -> io::Result<Self> {
+        let path = path.into();
+        if !fs::metadata(&path)?.is_dir() {
+            return Err(io::Error::new(ErrorKind::NotADirectory, "not a directory"));
+        }
+        Ok(Self { path, writable })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Host path of the entry `name`, which must be a plain name
+    fn entry(&self, name: &str) -> io::Result<PathBuf> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        Ok(self.path.join(name))
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(read_only())
+        }
+    }
+
+    /// Entries whose type matches `is_dir`, sorted by name
+    fn entries(&self, is_dir: bool) -> io::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&self.path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() == is_dir {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+impl VfsDirectory for HostDirectory {
+    fn name(&self) -> String {
+        file_name(&self.path)
+    }
+
+    fn files(&self) -> io::Result<Vec<VirtualFile>> {
+        self.entries(false)?
+            .into_iter()
+            .map(|path| Ok(Arc::new(HostFile::open(path, self.writable)?) as VirtualFile))
+            .collect()
+    }
+
+    fn subdirectories(&self) -> io::Result<Vec<VirtualDir>> {
+        self.entries(true)?
+            .into_iter()
+            .map(|path| Ok(Arc::new(HostDirectory::open(path, self.writable)?) as VirtualDir))
+            .collect()
+    }
+
+    fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    fn create_file(&self, name: &str) -> io::Result<VirtualFile> {
+        self.check_writable()?;
+        let path = self.entry(name)?;
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Arc::new(HostFile::open(path, true)?))
+    }
+
+    fn create_subdirectory(&self, name: &str) -> io::Result<VirtualDir> {
+        self.check_writable()?;
+        let path = self.entry(name)?;
+        fs::create_dir(&path)?;
+        Ok(Arc::new(HostDirectory::open(path, true)?))
+    }
+
+    fn delete_file(&self, name: &str) -> io::Result<()> {
+        self.check_writable()?;
+        fs::remove_file(self.entry(name)?)
+    }
+
+    fn delete_subdirectory(&self, name: &str) -> io::Result<()> {
+        self.check_writable()?;
+        fs::remove_dir_all(self.entry(name)?)
+    }
+
+    fn file(&self, name: &str) -> io::Result<VirtualFile> {
+        let path = self.entry(name)?;
+        if !fs::metadata(&path)?.is_file() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok(Arc::new(HostFile::open(path, self.writable)?))
+    }
+
+    fn subdirectory(&self, name: &str) -> io::Result<VirtualDir> {
+        Ok(Arc::new(HostDirectory::open(
+            self.entry(name)?,
+            self.writable,
+        )?))
+    }
+}
@@ -0,0 +1,93 @@
+//! Indirect storage: an update's section laid over the base title's
+//!
+//! An update NCA ships only what changed. Its section's relocation table
+//! (the indirect half of BKTR) maps each range of the patched section to
+//! either the same bytes of the base section or new bytes in the update's
+//! own, so reading the patched section reads from both.
+
+use super::bucket_tree::BucketTree;
+use super::{VfsFile, VirtualFile, invalid_data};
+use std::io;
+
+/// Size of a relocation entry: virtual offset, physical offset, storage
+const ENTRY_SIZE: usize = 0x14;
+
+/// Which storage a relocation entry reads from
+const BASE: u32 = 0;
+const PATCH: u32 = 1;
+
+/// The patched section, read through the relocation table
+pub(super) struct IndirectFile {
+    name: String,
+    base: VirtualFile,
+    patch: VirtualFile,
+    /// Physical offset and storage of each relocation entry
+    table: BucketTree<(u64, u32)>,
+}
+
+impl IndirectFile {
+    /// Read the relocation table in `table`, described by `header`, for
+    /// laying `patch` over `base`
+    pub fn new(
+        base: VirtualFile,
+        patch: VirtualFile,
+        table: &VirtualFile,
+        header: &[u8],
+    ) -> io::Result<Self> {
+        let table = BucketTree::read(table, header, ENTRY_SIZE, |entry| {
+            (
+                u64::from_le_bytes(entry[8..16].try_into().unwrap()),
+                u32::from_le_bytes(entry[16..20].try_into().unwrap()),
+            )
+        })?;
+        if let Some(&(offset, (_, storage))) = table
+            .entries
+            .iter()
+            .find(|(_, (_, storage))| *storage > PATCH)
+        {
+            return Err(invalid_data(format!(
+                "relocation at {offset:#x} names storage {storage}"
+            )));
+        }
+        if table.entries.first().is_some_and(|&(start, _)| start != 0) {
+            return Err(invalid_data("relocation table does not start at 0"));
+        }
+        Ok(Self {
+            name: patch.name(),
+            base,
+            patch,
+            table,
+        })
+    }
+}
+
+impl VfsFile for IndirectFile {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn size(&self) -> u64 {
+        self.table.end
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let end = (offset + buf.len() as u64).min(self.size());
+        let mut position = offset;
+        while position < end {
+            // Entries start at 0 and run to the end, so one always covers
+            let index = self.table.find(position).unwrap();
+            let (start, (physical, storage)) = self.table.entries[index];
+            let until = self.table.entry_end(index).min(end);
+            let storage = if storage == BASE {
+                &self.base
+            } else {
+                &self.patch
+            };
+            let done = (position - offset) as usize;
+            let len = (until - position) as usize;
+            storage.read_exact_at(physical + (position - start), &mut buf[done..done + len])?;
+            position = until;
+        }
+        Ok(end.saturating_sub(offset) as usize)
+    }
+}
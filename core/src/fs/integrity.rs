@@ -0,0 +1,163 @@
+//! Hash-verified section data
+//!
+//! NCA sections keep their data under layers of SHA-256 hashes: PFS0
+//! sections a single hash table (HierarchicalSha256), RomFS sections an IVFC
+//! tree of up to six levels, each hashing the blocks of the next. The top
+//! layer is checked against the master hash in the FS header, and every
+//! block is checked the first time it is read, so a damaged dump fails
+//! where it is damaged without the whole section being hashed up front.
+
+use super::{MemoryFile, OffsetFile, VfsFile, VirtualFile, invalid_data};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+const HASH_SIZE: usize = 0x20;
+const IVFC_MAGIC: &[u8; 4] = b"IVFC";
+const IVFC_MAX_LEVELS: usize = 6;
+
+/// `data`, with each block checked against its hash in `hashes`
+struct HashedFile {
+    data: VirtualFile,
+    hashes: VirtualFile,
+    block_size: u64,
+    /// Whether a short last block is hashed padded with zeros, as IVFC does
+    pad: bool,
+    verified: Mutex<Vec<bool>>,
+}
+
+impl HashedFile {
+    fn new(data: VirtualFile, hashes: VirtualFile, block_size: u64, pad: bool) -> io::Result<Self> {
+        if block_size == 0 || block_size > 1 << 24 {
+            return Err(invalid_data("hash block size is out of range"));
+        }
+        let blocks = data.size().div_ceil(block_size);
+        if blocks * HASH_SIZE as u64 > hashes.size() {
+            return Err(invalid_data("hash table is too small for its data"));
+        }
+        Ok(Self {
+            data,
+            hashes,
+            block_size,
+            pad,
+            verified: Mutex::new(vec![false; blocks as usize]),
+        })
+    }
+
+    /// Block `index`, checked unless it already has been
+    fn block(&self, index: u64) -> io::Result<Vec<u8>> {
+        let start = index * self.block_size;
+        let len = self.block_size.min(self.data.size() - start) as usize;
+        let block = self.data.read_vec(start, len)?;
+        if self.verified.lock().unwrap()[index as usize] {
+            return Ok(block);
+        }
+        let expected = self.hashes.read_vec(index * HASH_SIZE as u64, HASH_SIZE)?;
+        let mut hasher = Sha256::new();
+        hasher.update(&block);
+        if self.pad {
+            hasher.update(vec![0; self.block_size as usize - len]);
+        }
+        if hasher.finalize().as_slice() != expected {
+            let name = self.data.name();
+            return Err(invalid_data(format!(
+                "{name} block {index:#x} does not match its hash"
+            )));
+        }
+        self.verified.lock().unwrap()[index as usize] = true;
+        Ok(block)
+    }
+}
+
+impl VfsFile for HashedFile {
+    fn name(&self) -> String {
+        self.data.name()
+    }
+
+    fn size(&self) -> u64 {
+        self.data.size()
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let end = (offset + buf.len() as u64).min(self.size());
+        let mut position = offset;
+        while position < end {
+            let index = position / self.block_size;
+            let block = self.block(index)?;
+            let from = (position - index * self.block_size) as usize;
+            let len = (block.len() - from).min((end - position) as usize);
+            let done = (position - offset) as usize;
+            buf[done..done + len].copy_from_slice(&block[from..from + len]);
+            position += len as u64;
+        }
+        Ok(end.saturating_sub(offset) as usize)
+    }
+}
+
+/// A region of `storage` as given by an (offset, size) pair at `at` in a
+/// hash header
+fn region(storage: &VirtualFile, header: &[u8], at: usize, name: &str) -> io::Result<VirtualFile> {
+    let offset = u64::from_le_bytes(header[at..at + 8].try_into().unwrap());
+    let size = u64::from_le_bytes(header[at + 8..at + 16].try_into().unwrap());
+    if offset
+        .checked_add(size)
+        .is_none_or(|end| end > storage.size())
+    {
+        return Err(invalid_data(format!(
+            "{name} runs past the end of its section"
+        )));
+    }
+    Ok(Arc::new(OffsetFile::new(
+        storage.clone(),
+        name,
+        offset,
+        size,
+    )))
+}
+
+/// The data layer of a HierarchicalSha256 section, given the hash data of
+/// its FS header
+pub(super) fn open_sha256(storage: &VirtualFile, hash_data: &[u8]) -> io::Result<VirtualFile> {
+    let block_size = u32::from_le_bytes(hash_data[0x20..0x24].try_into().unwrap()) as u64;
+    let layers = u32::from_le_bytes(hash_data[0x24..0x28].try_into().unwrap());
+    if layers != 2 {
+        return Err(invalid_data(format!(
+            "expected 2 hash layers, found {layers}"
+        )));
+    }
+    let table = region(storage, hash_data, 0x28, "hash table")?;
+    if table.size() > 1 << 24 {
+        return Err(invalid_data("hash table is too large"));
+    }
+    if Sha256::digest(table.read_all()?).as_slice() != &hash_data[..HASH_SIZE] {
+        return Err(invalid_data("hash table does not match the master hash"));
+    }
+    let data = region(storage, hash_data, 0x38, &storage.name())?;
+    Ok(Arc::new(HashedFile::new(data, table, block_size, false)?))
+}
+
+/// The data level of an IVFC section, given the hash data of its FS header
+pub(super) fn open_ivfc(storage: &VirtualFile, hash_data: &[u8]) -> io::Result<VirtualFile> {
+    if &hash_data[..4] != IVFC_MAGIC {
+        return Err(invalid_data("IVFC header has no IVFC magic"));
+    }
+    let levels = u32::from_le_bytes(hash_data[0xC..0x10].try_into().unwrap()) as usize;
+    if !(2..=IVFC_MAX_LEVELS + 1).contains(&levels) {
+        return Err(invalid_data(format!("IVFC tree has {levels} levels")));
+    }
+    let master = &hash_data[0xC0..0xC0 + HASH_SIZE];
+    let mut hashes: VirtualFile = Arc::new(MemoryFile::read_only("master hash", master.to_vec()));
+    for level in 0..levels - 1 {
+        let at = 0x10 + level * 0x18;
+        let order = u32::from_le_bytes(hash_data[at + 0x10..at + 0x14].try_into().unwrap());
+        let name = if level == levels - 2 {
+            storage.name()
+        } else {
+            format!("IVFC level {}", level + 1)
+        };
+        let data = region(storage, hash_data, at, &name)?;
+        let block_size = 1u64.checked_shl(order).unwrap_or(0);
+        hashes = Arc::new(HashedFile::new(data, hashes, block_size, true)?);
+    }
+    Ok(hashes)
+}
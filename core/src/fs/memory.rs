@@ -0,0 +1,227 @@
+//! Files and directories held in host memory, for trees built on the fly
+//! such as a container's contents or a save created for a test
+
+use super::{VfsDirectory, VfsFile, VirtualDir, VirtualFile, read_only, read_slice};
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+use std::sync::{Arc, RwLock};
+
+pub struct MemoryFile {
+    name: String,
+    data: RwLock<Vec<u8>>,
+    writable: bool,
+}
+
+impl MemoryFile {
+    pub fn new(name: impl Into<String>, data: Vec<u8>) -> Self {
+        Self {
+            name: name.into(),
+            data: RwLock::new(data),
+            writable: true,
+        }
+    }
+
+    pub fn read_only(name: impl Into<String>, data: Vec<u8>) -> Self {
+        Self {
+            writable: false,
+            ..Self::new(name, data)
+        }
+    }
+}
+
+impl VfsFile for MemoryFile {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn size(&self) -> u64 {
+        self.data.read().unwrap().len() as u64
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(read_slice(&self.data.read().unwrap(), offset, buf))
+    }
+
+    fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> io::Result<usize> {
+        if !self.writable {
+            return Err(read_only());
+        }
+        let mut contents = self.data.write().unwrap();
+        let start = usize::try_from(offset).map_err(|_| too_large())?;
+        let end = start.checked_add(data.len()).ok_or_else(too_large)?;
+        if end > contents.len() {
+            resize(&mut contents, end)?;
+        }
+        contents[start..end].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn set_size(&self, size: u64) -> io::Result<()> {
+        if !self.writable {
+            return Err(read_only());
+        }
+        let size = usize::try_from(size).map_err(|_| too_large())?;
+        resize(&mut self.data.write().unwrap(), size)
+    }
+}
+
+fn too_large() -> io::Error {
+    ErrorKind::FileTooLarge.into()
+}
+
+/// Resize `contents`, zero filling, failing rather than aborting when the
+/// host cannot hold that much
+fn resize(contents: &mut Vec<u8>, size: usize) -> io::Result<()> {
+    let additional = size.saturating_sub(contents.len());
+    contents
+        .try_reserve_exact(additional)
+        .map_err(|_| too_large())?;
+    contents.resize(size, 0);
+    Ok(())
+}
+
+#[derive(Default)]
+struct Entries {
+    files: BTreeMap<String, VirtualFile>,
+    subdirectories: BTreeMap<String, VirtualDir>,
+}
+
+/// A directory whose entries live in a map; entries may come from any
+/// backend
+pub struct MemoryDirectory {
+    name: String,
+    entries: RwLock<Entries>,
+    writable: bool,
+}
+
+impl MemoryDirectory {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            entries: RwLock::default(),
+            writable: true,
+        }
+    }
+
+    /// A directory the guest cannot change, though the host can still
+    /// [`Self::add_file`] to it
+    pub fn read_only(name: impl Into<String>) -> Self {
+        Self {
+            writable: false,
+            ..Self::new(name)
+        }
+    }
+
+    /// Add `file` under its own name, replacing any file of that name
+    pub fn add_file(&self, file: VirtualFile) {
+        let mut entries = self.entries.write().unwrap();
+        entries.files.insert(file.name(), file);
+    }
+
+    /// Add `dir` under its own name, replacing any subdirectory of that name
+    pub fn add_subdirectory(&self, dir: VirtualDir) {
+        let mut entries = self.entries.write().unwrap();
+        entries.subdirectories.insert(dir.name(), dir);
+    }
+
+    fn check_new_entry(&self, entries: &Entries, name: &str) -> io::Result<()> {
+        if !self.writable {
+            return Err(read_only());
+        }
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        if entries.files.contains_key(name) || entries.subdirectories.contains_key(name) {
+            return Err(ErrorKind::AlreadyExists.into());
+        }
+        Ok(())
+    }
+}
+
+impl VfsDirectory for MemoryDirectory {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn files(&self) -> io::Result<Vec<VirtualFile>> {
+        Ok(self
+            .entries
+            .read()
+            .unwrap()
+            .files
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    fn subdirectories(&self) -> io::Result<Vec<VirtualDir>> {
+        let entries = self.entries.read().unwrap();
+        Ok(entries.subdirectories.values().cloned().collect())
+    }
+
+    fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    fn create_file(&self, name: &str) -> io::Result<VirtualFile> {
+        let mut entries = self.entries.write().unwrap();
+        self.check_new_entry(&entries, name)?;
+        let file: VirtualFile = Arc::new(MemoryFile::new(name, Vec::new()));
+        entries.files.insert(name.to_string(), file.clone());
+        Ok(file)
+    }
+
+    fn create_subdirectory(&self, name: &str) -> io::Result<VirtualDir> {
+        let mut entries = self.entries.write().unwrap();
+        self.check_new_entry(&entries, name)?;
+        let dir: VirtualDir = Arc::new(MemoryDirectory::new(name));
+        entries.subdirectories.insert(name.to_string(), dir.clone());
+        Ok(dir)
+    }
+
+    fn delete_file(&self, name: &str) -> io::Result<()> {
+        if !self.writable {
+            return Err(read_only());
+        }
+        let mut entries = self.entries.write().unwrap();
+        entries
+            .files
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| ErrorKind::NotFound.into())
+    }
+
+    fn delete_subdirectory(&self, name: &str) -> io::Result<()> {
+        if !self.writable {
+            return Err(read_only());
+        }
+        let mut entries = self.entries.write().unwrap();
+        entries
+            .subdirectories
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| ErrorKind::NotFound.into())
+    }
+
+    fn file(&self, name: &str) -> io::Result<VirtualFile> {
+        let entries = self.entries.read().unwrap();
+        entries
+            .files
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ErrorKind::NotFound.into())
+    }
+
+    fn subdirectory(&self, name: &str) -> io::Result<VirtualDir> {
+        let entries = self.entries.read().unwrap();
+        entries
+            .subdirectories
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ErrorKind::NotFound.into())
+    }
+}
@@ -1,22 +1,251 @@
+//! Virtual filesystem
+//!
+//! Title contents, save data and host folders are all reached through
+//! [`VfsFile`] and [`VfsDirectory`], so a container format is read from any
+//! file it is handed and `fsp-srv` can serve any tree. Files and directories
+//! are shared as [`VirtualFile`] and [`VirtualDir`] handles; writes go
+//! through `&self`, so a view such as [`OffsetFile`] writes into the file it
+//! was cut from.
+
+mod aes_ctr;
+mod bucket_tree;
+mod host;
+mod indirect;
+mod integrity;
+mod memory;
+mod nca;
+mod offset;
+mod partition;
+mod romfs;
+mod xci;
+
+pub use host::{HostDirectory, HostFile};
+pub use memory::{MemoryDirectory, MemoryFile};
+pub use nca::{
+    Nca, NcaContentType, NcaDistribution, NcaEncryption, NcaFsType, NcaHashType, NcaHeader,
+    NcaPatchInfo, NcaSection,
+};
+pub use offset::OffsetFile;
+pub use partition::{PartitionFs, write_pfs0};
+pub use romfs::{RomFs, RomFsBuilder};
+pub use xci::{Xci, XciPartition};
+
 use memmap2::Mmap;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::io::{self, ErrorKind};
 use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+pub type VirtualFile = Arc<dyn VfsFile>;
+pub type VirtualDir = Arc<dyn VfsDirectory>;
+
+/// A file that can be read, and perhaps written, at any offset
+pub trait VfsFile: Send + Sync {
+    fn name(&self) -> String;
+
+    fn size(&self) -> u64;
+
+    /// Read into `buf` from `offset`, returning how many bytes were read;
+    /// fewer than asked for only at the end of the file
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    /// Write `data` at `offset`, returning how many bytes were written;
+    /// files that can grow do so when the write runs past the end
+    fn write_at(&self, _offset: u64, _data: &[u8]) -> io::Result<usize> {
+        Err(read_only())
+    }
+
+    /// Truncate the file, or extend it with zeros
+    fn set_size(&self, _size: u64) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    /// Fill `buf` from `offset`, failing if the file ends first
+    fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            match self.read_at(offset + done as u64, &mut buf[done..])? {
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                read => done += read,
+            }
+        }
+        Ok(())
+    }
+
+    /// `len` bytes from `offset` in a freshly allocated buffer
+    fn read_vec(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        self.read_exact_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// The whole file
+    fn read_all(&self) -> io::Result<Vec<u8>> {
+        self.read_vec(0, self.size() as usize)
+    }
+}
+
+impl fmt::Debug for dyn VfsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VfsFile")
+            .field("name", &self.name())
+            .field("size", &self.size())
+            .finish()
+    }
+}
+
+/// A directory of named files and subdirectories
+pub trait VfsDirectory: Send + Sync {
+    fn name(&self) -> String;
+
+    fn files(&self) -> io::Result<Vec<VirtualFile>>;
+
+    fn subdirectories(&self) -> io::Result<Vec<VirtualDir>>;
+
+    /// Whether entries can be created and deleted
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    /// Create an empty file, failing if an entry of that name exists
+    fn create_file(&self, _name: &str) -> io::Result<VirtualFile> {
+        Err(read_only())
+    }
+
+    /// Create an empty subdirectory, failing if an entry of that name exists
+    fn create_subdirectory(&self, _name: &str) -> io::Result<VirtualDir> {
+        Err(read_only())
+    }
+
+    fn delete_file(&self, _name: &str) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    /// Delete a subdirectory and everything in it
+    fn delete_subdirectory(&self, _name: &str) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    fn file(&self, name: &str) -> io::Result<VirtualFile> {
+        self.files()?
+            .into_iter()
+            .find(|file| file.name() == name)
+            .ok_or_else(|| ErrorKind::NotFound.into())
+    }
 
+    fn subdirectory(&self, name: &str) -> io::Result<VirtualDir> {
+        self.subdirectories()?
+            .into_iter()
+            .find(|dir| dir.name() == name)
+            .ok_or_else(|| ErrorKind::NotFound.into())
+    }
+}
+
+impl fmt::Debug for dyn VfsDirectory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VfsDirectory")
+            .field("name", &self.name())
+            .finish()
+    }
+}
+
+/// Open the file at `path`, `/`-separated and relative to `root`
+pub fn open_file(root: &VirtualDir, path: &str) -> io::Result<VirtualFile> {
+    let (parent, name) = split_last(path)?;
+    open_directory(root, parent)?.file(name)
+}
+
+/// Open the directory at `path`, `/`-separated and relative to `root`; an
+/// empty path is `root` itself
+pub fn open_directory(root: &VirtualDir, path: &str) -> io::Result<VirtualDir> {
+    let mut dir = root.clone();
+    for component in components(path)? {
+        dir = dir.subdirectory(component)?;
+    }
+    Ok(dir)
+}
+
+/// Create an empty file at `path` in an existing directory
+pub fn create_file(root: &VirtualDir, path: &str) -> io::Result<VirtualFile> {
+    let (parent, name) = split_last(path)?;
+    open_directory(root, parent)?.create_file(name)
+}
+
+/// Open the directory at `path`, creating any part of it that is missing
+pub fn create_directories(root: &VirtualDir, path: &str) -> io::Result<VirtualDir> {
+    let mut dir = root.clone();
+    for component in components(path)? {
+        dir = match dir.subdirectory(component) {
+            Err(err) if err.kind() == ErrorKind::NotFound => dir.create_subdirectory(component)?,
+            other => other?,
+        };
+    }
+    Ok(dir)
+}
+
+/// The names along `path`, which may not leave the directory it starts in
+fn components(path: &str) -> io::Result<Vec<&str>> {
+    let components: Vec<&str> = path
+        .split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect();
+    if components.contains(&"..") {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "path leaves its directory",
+        ));
+    }
+    Ok(components)
+}
+
+/// The directory part of `path` and the name of the entry in it
+fn split_last(path: &str) -> io::Result<(&str, &str)> {
+    components(path)?;
+    let path = path.trim_end_matches(['/', '\\']);
+    let (parent, name) = path.rsplit_once(['/', '\\']).unwrap_or(("", path));
+    if name.is_empty() || name == "." {
+        return Err(ErrorKind::InvalidInput.into());
+    }
+    Ok((parent, name))
+}
+
+pub(crate) fn read_only() -> io::Error {
+    io::Error::new(ErrorKind::PermissionDenied, "read-only")
+}
+
+/// A file that is not in the format it was opened as
+pub(crate) fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+/// A host file mapped into memory, read-only
 pub struct File {
+    name: String,
     map: Mmap,
 }
 
 impl File {
-    pub async fn open<const W: bool, P>(path: P) -> Result<Self, Box<dyn std::error::Error>>
+    pub fn open<P>(path: P) -> io::Result<Self>
     where
-        P: AsRef<Path>
+        P: AsRef<Path>,
     {
+        let path = path.as_ref();
         let file = fs::File::open(path)?;
-        
+
+        // Safety: the mapping is only read; like any mapped file it may
+        // change if another process writes to it
         let map = unsafe { Mmap::map(&file)? };
 
-        Ok(Self { map })
+        Ok(Self {
+            name: file_name(path),
+            map,
+        })
     }
 }
 
@@ -26,4 +255,32 @@ impl Deref for File {
     fn deref(&self) -> &Self::Target {
         &self.map
     }
-}
\ No newline at end of file
+}
+
+impl VfsFile for File {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn size(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(read_slice(&self.map, offset, buf))
+    }
+}
+
+/// The last component of `path`, as an entry name
+pub(crate) fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+}
+
+/// Copy from `data` at `offset` into `buf`, as much as there is
+pub(crate) fn read_slice(data: &[u8], offset: u64, buf: &mut [u8]) -> usize {
+    let start = (offset as usize).min(data.len());
+    let len = buf.len().min(data.len() - start);
+    buf[..len].copy_from_slice(&data[start..start + len]);
+    len
+}
@@ -0,0 +1,461 @@
+//! Nintendo Content Archives (NCA3)
+//!
+//! Everything a title installs is an NCA: a 0xC00-byte header encrypted
+//! with AES-XTS under the header key, then up to four sections. The header
+//! describes the title and where each section lies; each section's FS
+//! header says whether it holds a PFS0 or a RomFS, how its data is hashed
+//! and how it is encrypted. Sections are AES-CTR encrypted with a key from
+//! the header's key area, or with the title key for titles bought with a
+//! ticket, and update NCAs encrypt parts of theirs under several counters
+//! (CTR-EX).
+
+use super::aes_ctr::AesCtrFile;
+use super::bucket_tree::BucketTree;
+use super::indirect::IndirectFile;
+use super::integrity::{open_ivfc, open_sha256};
+use super::{OffsetFile, PartitionFs, RomFs, VfsDirectory, VirtualDir, VirtualFile, invalid_data};
+use crate::crypto::{Key128, KeyAreaKey, Keyset, aes_ecb_decrypt, aes_xts_decrypt};
+use sha2::{Digest, Sha256};
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"NCA3";
+const HEADER_SIZE: usize = 0xC00;
+const SECTOR_SIZE: usize = 0x200;
+/// Section offsets in the header count in these
+const MEDIA_SIZE: u64 = 0x200;
+const SECTION_COUNT: usize = 4;
+const FS_HEADERS_OFFSET: usize = 0x400;
+const FS_HEADER_SIZE: usize = 0x200;
+/// Size of an AES-CTR-EX table entry: offset, reserved, generation
+const AES_CTR_EX_ENTRY_SIZE: usize = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NcaDistribution {
+    Download,
+    GameCard,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NcaContentType {
+    Program,
+    Meta,
+    Control,
+    Manual,
+    Data,
+    PublicData,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NcaFsType {
+    RomFs,
+    PartitionFs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NcaHashType {
+    None,
+    HierarchicalSha256,
+    /// IVFC
+    HierarchicalIntegrity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NcaEncryption {
+    None,
+    AesXts,
+    AesCtr,
+    AesCtrEx,
+    AesCtrSkipLayerHash,
+    AesCtrExSkipLayerHash,
+}
+
+/// The decrypted NCA header's description of the title
+#[derive(Debug, Clone)]
+pub struct NcaHeader {
+    pub distribution: NcaDistribution,
+    pub content_type: NcaContentType,
+    pub content_size: u64,
+    pub program_id: u64,
+    pub content_index: u32,
+    pub sdk_version: u32,
+    /// The key generation, as the larger of the header's two fields
+    pub key_generation: u8,
+    pub key_area_key: KeyAreaKey,
+    pub rights_id: [u8; 0x10],
+}
+
+impl NcaHeader {
+    /// Whether the sections are encrypted with a title key rather than the
+    /// key area
+    pub fn has_rights_id(&self) -> bool {
+        self.rights_id != [0; 0x10]
+    }
+
+    /// The master key revision the keys come from; generations 0 and 1
+    /// both use the first
+    pub fn master_key_revision(&self) -> u8 {
+        self.key_generation.saturating_sub(1)
+    }
+}
+
+/// Where an update section's BKTR tables lie in it, and their headers
+#[derive(Debug, Clone, Copy)]
+pub struct NcaPatchInfo {
+    pub indirect_offset: u64,
+    pub indirect_size: u64,
+    pub indirect_header: [u8; 0x10],
+    pub aes_ctr_ex_offset: u64,
+    pub aes_ctr_ex_size: u64,
+    pub aes_ctr_ex_header: [u8; 0x10],
+}
+
+impl NcaPatchInfo {
+    fn parse(bytes: &[u8]) -> Self {
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        Self {
+            indirect_offset: u64_at(0x00),
+            indirect_size: u64_at(0x08),
+            indirect_header: bytes[0x10..0x20].try_into().unwrap(),
+            aes_ctr_ex_offset: u64_at(0x20),
+            aes_ctr_ex_size: u64_at(0x28),
+            aes_ctr_ex_header: bytes[0x30..0x40].try_into().unwrap(),
+        }
+    }
+}
+
+/// One section of an NCA
+pub struct NcaSection {
+    pub index: usize,
+    pub fs_type: NcaFsType,
+    pub hash_type: NcaHashType,
+    pub encryption: NcaEncryption,
+    /// Set for an update's section, which only makes sense laid over the
+    /// base title's with [`NcaSection::patch_over`]
+    pub patch: Option<NcaPatchInfo>,
+    storage: VirtualFile,
+    hash_data: Vec<u8>,
+}
+
+impl NcaSection {
+    /// The decrypted section, before any hash checks
+    pub fn storage(&self) -> VirtualFile {
+        self.storage.clone()
+    }
+
+    /// The section's PFS0 or RomFS image, read through its hash layers
+    pub fn data(&self) -> io::Result<VirtualFile> {
+        if self.patch.is_some() {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "section {} is an update patch for the base title's",
+                    self.index
+                ),
+            ));
+        }
+        self.data_over(self.storage.clone())
+    }
+
+    /// This update section laid over `base`, the same section of the base
+    /// title, as one patched PFS0 or RomFS image
+    pub fn patch_over(&self, base: &NcaSection) -> io::Result<VirtualFile> {
+        let patch = self.patch.ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("section {} is not an update patch", self.index),
+            )
+        })?;
+        let table: VirtualFile = Arc::new(OffsetFile::new(
+            self.storage.clone(),
+            "relocation table",
+            patch.indirect_offset,
+            patch.indirect_size,
+        ));
+        let storage = IndirectFile::new(
+            base.storage(),
+            self.storage(),
+            &table,
+            &patch.indirect_header,
+        )?;
+        self.data_over(Arc::new(storage))
+    }
+
+    /// The data layer of `storage`, a section laid out as this one's hash
+    /// data describes
+    fn data_over(&self, storage: VirtualFile) -> io::Result<VirtualFile> {
+        match self.hash_type {
+            NcaHashType::None => Ok(storage),
+            NcaHashType::HierarchicalSha256 => open_sha256(&storage, &self.hash_data),
+            NcaHashType::HierarchicalIntegrity => open_ivfc(&storage, &self.hash_data),
+        }
+    }
+
+    /// The section's contents as a directory
+    pub fn open(&self) -> io::Result<VirtualDir> {
+        let data = self.data()?;
+        Ok(match self.fs_type {
+            NcaFsType::PartitionFs => Arc::new(PartitionFs::open(data)?),
+            NcaFsType::RomFs => Arc::new(RomFs::open(data)?),
+        })
+    }
+}
+
+/// An NCA, with the sections that stand on their own as subdirectories:
+/// `exefs` and `romfs` for a program's code and assets, `section<n>`
+/// otherwise. An update's RomFS is left out until laid over the base
+/// title's with [`Nca::patched_romfs`].
+pub struct Nca {
+    name: String,
+    pub header: NcaHeader,
+    sections: Vec<NcaSection>,
+    dirs: Vec<VirtualDir>,
+}
+
+impl Nca {
+    /// Decrypt and check the header of the NCA in `file`, then open its
+    /// sections with keys from `keys`
+    pub fn open(file: VirtualFile, keys: &Keyset) -> io::Result<Self> {
+        let mut raw = file.read_vec(0, HEADER_SIZE)?;
+        if &raw[0x200..0x204] != MAGIC {
+            aes_xts_decrypt(&keys.header_key()?, &mut raw, 0, SECTOR_SIZE);
+        }
+        match &raw[0x200..0x204] {
+            magic if magic == MAGIC => {}
+            b"NCA2" | b"NCA0" => {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "only NCA3 archives are supported",
+                ));
+            }
+            _ => return Err(invalid_data("not an NCA, or the header key is wrong")),
+        }
+        let header = parse_header(&raw)?;
+        let mut key = None;
+
+        let mut sections = Vec::new();
+        let mut dirs = Vec::new();
+        for index in 0..SECTION_COUNT {
+            let entry = 0x240 + index * 0x10;
+            let start = u32::from_le_bytes(raw[entry..entry + 4].try_into().unwrap()) as u64;
+            let end = u32::from_le_bytes(raw[entry + 4..entry + 8].try_into().unwrap()) as u64;
+            if end <= start {
+                continue;
+            }
+            let fs_header = &raw[FS_HEADERS_OFFSET + index * FS_HEADER_SIZE..][..FS_HEADER_SIZE];
+            if Sha256::digest(fs_header).as_slice() != &raw[0x280 + index * 0x20..][..0x20] {
+                return Err(invalid_data(format!(
+                    "section {index}'s FS header does not match its hash"
+                )));
+            }
+            let (offset, size) = (start * MEDIA_SIZE, (end - start) * MEDIA_SIZE);
+            if offset + size > file.size() {
+                return Err(invalid_data(format!(
+                    "section {index} runs past the end of the NCA"
+                )));
+            }
+
+            let content_type = header.content_type;
+            let section =
+                open_section(index, fs_header, content_type, &file, offset, size, || {
+                    if key.is_none() {
+                        key = Some(section_key(&header, &raw, keys)?);
+                    }
+                    Ok(key.unwrap())
+                })?;
+            if section.patch.is_none() {
+                dirs.push(section.open()?);
+            }
+            sections.push(section);
+        }
+
+        Ok(Self {
+            name: file.name(),
+            header,
+            sections,
+            dirs,
+        })
+    }
+
+    pub fn sections(&self) -> &[NcaSection] {
+        &self.sections
+    }
+
+    /// A program's code, as a PFS0 of NSOs and `main.npdm`
+    pub fn exefs(&self) -> io::Result<VirtualDir> {
+        self.subdirectory("exefs")
+    }
+
+    /// The RomFS section's image, for those that layer on top of it
+    pub fn romfs(&self) -> io::Result<VirtualFile> {
+        self.romfs_section()?.data()
+    }
+
+    /// This update's RomFS laid over `base`'s, as one image
+    pub fn patched_romfs(&self, base: &Nca) -> io::Result<VirtualFile> {
+        self.romfs_section()?.patch_over(base.romfs_section()?)
+    }
+
+    fn romfs_section(&self) -> io::Result<&NcaSection> {
+        self.sections
+            .iter()
+            .find(|section| section.fs_type == NcaFsType::RomFs)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "the NCA has no RomFS section"))
+    }
+}
+
+impl VfsDirectory for Nca {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn files(&self) -> io::Result<Vec<VirtualFile>> {
+        Ok(Vec::new())
+    }
+
+    fn subdirectories(&self) -> io::Result<Vec<VirtualDir>> {
+        Ok(self.dirs.clone())
+    }
+}
+
+fn parse_header(raw: &[u8]) -> io::Result<NcaHeader> {
+    let u32_at = |offset: usize| u32::from_le_bytes(raw[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_le_bytes(raw[offset..offset + 8].try_into().unwrap());
+    let distribution = match raw[0x204] {
+        0 => NcaDistribution::Download,
+        1 => NcaDistribution::GameCard,
+        other => return Err(invalid_data(format!("unknown distribution type {other}"))),
+    };
+    let content_type = match raw[0x205] {
+        0 => NcaContentType::Program,
+        1 => NcaContentType::Meta,
+        2 => NcaContentType::Control,
+        3 => NcaContentType::Manual,
+        4 => NcaContentType::Data,
+        5 => NcaContentType::PublicData,
+        other => return Err(invalid_data(format!("unknown content type {other}"))),
+    };
+    let key_area_key = KeyAreaKey::from_index(raw[0x207])
+        .ok_or_else(|| invalid_data(format!("unknown key area key {}", raw[0x207])))?;
+    Ok(NcaHeader {
+        distribution,
+        content_type,
+        content_size: u64_at(0x208),
+        program_id: u64_at(0x210),
+        content_index: u32_at(0x218),
+        sdk_version: u32_at(0x21C),
+        key_generation: raw[0x206].max(raw[0x220]),
+        key_area_key,
+        rights_id: raw[0x230..0x240].try_into().unwrap(),
+    })
+}
+
+/// The AES-CTR key of the sections: the title key for titles with a
+/// rights ID, otherwise the key area's CTR key
+fn section_key(header: &NcaHeader, raw: &[u8], keys: &Keyset) -> io::Result<Key128> {
+    let revision = header.master_key_revision();
+    if header.has_rights_id() {
+        return keys.title_key(&header.rights_id, revision);
+    }
+    let mut key: Key128 = raw[0x320..0x330].try_into().unwrap();
+    aes_ecb_decrypt(&keys.key_area_key(header.key_area_key, revision)?, &mut key);
+    Ok(key)
+}
+
+/// Parse a section's FS header and decrypt the `size` bytes of `file` at
+/// `offset` it describes, asking `key` for the section key only if they are
+/// encrypted
+fn open_section(
+    index: usize,
+    fs_header: &[u8],
+    content_type: NcaContentType,
+    file: &VirtualFile,
+    offset: u64,
+    size: u64,
+    key: impl FnOnce() -> io::Result<Key128>,
+) -> io::Result<NcaSection> {
+    let unsupported = |what: &str| {
+        io::Error::new(
+            ErrorKind::Unsupported,
+            format!("section {index} uses {what}, which is not supported"),
+        )
+    };
+    let fs_type = match fs_header[2] {
+        0 => NcaFsType::RomFs,
+        1 => NcaFsType::PartitionFs,
+        other => return Err(invalid_data(format!("unknown FS type {other}"))),
+    };
+    let hash_type = match (fs_header[3], fs_type) {
+        (0, NcaFsType::RomFs) | (3, _) => NcaHashType::HierarchicalIntegrity,
+        (0, NcaFsType::PartitionFs) | (2, _) => NcaHashType::HierarchicalSha256,
+        (1, _) => NcaHashType::None,
+        (other, _) => return Err(invalid_data(format!("unknown hash type {other}"))),
+    };
+    let encryption = match fs_header[4] {
+        1 => NcaEncryption::None,
+        2 => NcaEncryption::AesXts,
+        0 | 3 => NcaEncryption::AesCtr,
+        4 => NcaEncryption::AesCtrEx,
+        5 => NcaEncryption::AesCtrSkipLayerHash,
+        6 => NcaEncryption::AesCtrExSkipLayerHash,
+        other => return Err(invalid_data(format!("unknown encryption type {other}"))),
+    };
+    let u64_at =
+        |offset: usize| u64::from_le_bytes(fs_header[offset..offset + 8].try_into().unwrap());
+    if u64_at(0x150) != 0 {
+        return Err(unsupported("sparse storage"));
+    }
+    if u64_at(0x180) != 0 {
+        return Err(unsupported("compression"));
+    }
+    let patch = NcaPatchInfo::parse(&fs_header[0x100..0x140]);
+    let patch = (patch.indirect_size != 0).then_some(patch);
+    let upper = u64_at(0x140);
+
+    // Name the storage after the directory the section becomes
+    let name = match fs_type {
+        NcaFsType::RomFs => "romfs".to_string(),
+        NcaFsType::PartitionFs if index == 0 && content_type == NcaContentType::Program => {
+            "exefs".to_string()
+        }
+        NcaFsType::PartitionFs => format!("section{index}"),
+    };
+    let raw: VirtualFile = Arc::new(OffsetFile::new(file.clone(), name, offset, size));
+
+    let storage: VirtualFile = match encryption {
+        NcaEncryption::None => raw,
+        NcaEncryption::AesCtr => Arc::new(AesCtrFile::new(raw, key()?, upper, offset)),
+        NcaEncryption::AesCtrEx => {
+            let patch = patch.ok_or_else(|| invalid_data("CTR-EX section without patch info"))?;
+            let key = key()?;
+            let plain: VirtualFile = Arc::new(AesCtrFile::new(raw.clone(), key, upper, offset));
+            let table: VirtualFile = Arc::new(OffsetFile::new(
+                plain.clone(),
+                "AES-CTR-EX table",
+                patch.aes_ctr_ex_offset,
+                patch.aes_ctr_ex_size,
+            ));
+            let generations = BucketTree::read(
+                &table,
+                &patch.aes_ctr_ex_header,
+                AES_CTR_EX_ENTRY_SIZE,
+                |e| u32::from_le_bytes(e[12..16].try_into().unwrap()),
+            )?;
+            Arc::new(AesCtrFile::new(raw, key, upper, offset).with_generations(generations))
+        }
+        NcaEncryption::AesXts => return Err(unsupported("AES-XTS")),
+        NcaEncryption::AesCtrSkipLayerHash | NcaEncryption::AesCtrExSkipLayerHash => {
+            return Err(unsupported("unencrypted hash layers"));
+        }
+    };
+    Ok(NcaSection {
+        index,
+        fs_type,
+        hash_type,
+        encryption,
+        patch,
+        storage,
+        hash_data: fs_header[0x08..0x100].to_vec(),
+    })
+}
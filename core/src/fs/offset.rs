@@ -0,0 +1,83 @@
+//! A window onto part of another file, e.g. one entry of a container
+
+use super::{VfsFile, VirtualFile};
+use std::io::{self, ErrorKind};
+
+/// `size` bytes of `parent` from `offset`, under a name of its own
+///
+/// Reads and writes are clipped to the window, which cannot be resized:
+/// growing it would overwrite whatever follows it in the parent.
+pub struct OffsetFile {
+    parent: VirtualFile,
+    name: String,
+    offset: u64,
+    size: u64,
+}
+
+impl OffsetFile {
+    pub fn new(parent: VirtualFile, name: impl Into<String>, offset: u64, size: u64) -> Self {
+        Self {
+            parent,
+            name: name.into(),
+            offset,
+            size,
+        }
+    }
+
+    pub fn parent(&self) -> &VirtualFile {
+        &self.parent
+    }
+
+    /// Where the window starts in the parent
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The part of `len` bytes at `offset` that falls inside the window
+    fn clip(&self, offset: u64, len: usize) -> usize {
+        len.min(self.size.saturating_sub(offset) as usize)
+    }
+}
+
+impl VfsFile for OffsetFile {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.clip(offset, buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        self.parent.read_at(self.offset + offset, &mut buf[..len])
+    }
+
+    fn is_writable(&self) -> bool {
+        self.parent.is_writable()
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> io::Result<usize> {
+        let len = self.clip(offset, data.len());
+        if len == 0 && !data.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "write past the end of a view",
+            ));
+        }
+        self.parent.write_at(self.offset + offset, &data[..len])
+    }
+
+    fn set_size(&self, size: u64) -> io::Result<()> {
+        if size == self.size {
+            return Ok(());
+        }
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "views cannot be resized",
+        ))
+    }
+}
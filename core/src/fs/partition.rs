@@ -0,0 +1,146 @@
+//! PartitionFS (PFS0) and HashFS (HFS0): flat archives of named files
+//!
+//! NSPs and an NCA's ExeFS are PFS0; a cartridge image's partitions are
+//! HFS0, whose entries also carry a SHA-256 hash of their first bytes.
+//! Both are a header, a table of entries and a string table of names,
+//! followed by the file contents.
+
+use super::{OffsetFile, VfsDirectory, VirtualDir, VirtualFile, invalid_data};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::sync::Arc;
+
+const PFS0_MAGIC: &[u8; 4] = b"PFS0";
+const HFS0_MAGIC: &[u8; 4] = b"HFS0";
+const HEADER_SIZE: usize = 0x10;
+const PFS0_ENTRY_SIZE: usize = 0x18;
+const HFS0_ENTRY_SIZE: usize = 0x40;
+/// The writer pads the string table so file contents start on this
+const DATA_ALIGNMENT: usize = 0x20;
+
+/// A PFS0 or HFS0 archive read from any file
+pub struct PartitionFs {
+    name: String,
+    files: Vec<VirtualFile>,
+    is_hfs: bool,
+}
+
+impl PartitionFs {
+    /// Read the archive in `file`, checking each HFS0 entry against its
+    /// hash
+    pub fn open(file: VirtualFile) -> io::Result<Self> {
+        let header = file.read_vec(0, HEADER_SIZE)?;
+        let (is_hfs, entry_size) = match &header[..4] {
+            magic if magic == PFS0_MAGIC => (false, PFS0_ENTRY_SIZE),
+            magic if magic == HFS0_MAGIC => (true, HFS0_ENTRY_SIZE),
+            _ => return Err(invalid_data("not a PFS0 or HFS0 archive")),
+        };
+        let word =
+            |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+        let count = word(4) as usize;
+        let strings_size = word(8) as usize;
+        let table_size = count
+            .checked_mul(entry_size)
+            .filter(|&size| HEADER_SIZE as u64 + size as u64 + strings_size as u64 <= file.size())
+            .ok_or_else(|| invalid_data("archive header runs past the end of the file"))?;
+        let table = file.read_vec(HEADER_SIZE as u64, table_size + strings_size)?;
+        let (entries, strings) = table.split_at(table_size);
+        let data_start = (HEADER_SIZE + table_size + strings_size) as u64;
+
+        let mut files = Vec::with_capacity(count);
+        for entry in entries.chunks_exact(entry_size) {
+            let offset = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+            let size = u64::from_le_bytes(entry[8..16].try_into().unwrap());
+            let name_offset = u32::from_le_bytes(entry[16..20].try_into().unwrap()) as usize;
+            let name = entry_name(strings, name_offset)
+                .ok_or_else(|| invalid_data("entry name outside the string table"))?;
+            let start = data_start
+                .checked_add(offset)
+                .filter(|start| {
+                    start
+                        .checked_add(size)
+                        .is_some_and(|end| end <= file.size())
+                })
+                .ok_or_else(|| invalid_data(format!("{name} runs past the end of the archive")))?;
+            let entry_file: VirtualFile =
+                Arc::new(OffsetFile::new(file.clone(), name, start, size));
+
+            if is_hfs {
+                let hashed_size = u32::from_le_bytes(entry[20..24].try_into().unwrap()) as u64;
+                let hashed = entry_file.read_vec(0, hashed_size.min(size) as usize)?;
+                if Sha256::digest(&hashed).as_slice() != &entry[0x20..0x40] {
+                    let name = entry_file.name();
+                    return Err(invalid_data(format!("{name} does not match its hash")));
+                }
+            }
+            files.push(entry_file);
+        }
+
+        Ok(Self {
+            name: file.name(),
+            files,
+            is_hfs,
+        })
+    }
+
+    /// Whether this is an HFS0 archive rather than PFS0
+    pub fn is_hfs(&self) -> bool {
+        self.is_hfs
+    }
+}
+
+impl VfsDirectory for PartitionFs {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn files(&self) -> io::Result<Vec<VirtualFile>> {
+        Ok(self.files.clone())
+    }
+
+    fn subdirectories(&self) -> io::Result<Vec<VirtualDir>> {
+        Ok(Vec::new())
+    }
+}
+
+/// The NUL-terminated name at `offset` in the string table
+fn entry_name(strings: &[u8], offset: usize) -> Option<String> {
+    let name = strings.get(offset..)?;
+    let len = name.iter().position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&name[..len]).into_owned())
+}
+
+/// Pack `files` into a PFS0 image, in the order given
+pub fn write_pfs0(files: &[VirtualFile]) -> io::Result<Vec<u8>> {
+    let mut strings = Vec::new();
+    let mut entries = Vec::with_capacity(files.len() * PFS0_ENTRY_SIZE);
+    let mut offset = 0u64;
+    for file in files {
+        let size = file.size();
+        entries.extend(offset.to_le_bytes());
+        entries.extend(size.to_le_bytes());
+        entries.extend((strings.len() as u32).to_le_bytes());
+        entries.extend(0u32.to_le_bytes());
+        strings.extend(file.name().as_bytes());
+        strings.push(0);
+        offset += size;
+    }
+    let header_size = HEADER_SIZE + entries.len() + strings.len();
+    strings.resize(
+        strings.len() + header_size.next_multiple_of(DATA_ALIGNMENT) - header_size,
+        0,
+    );
+
+    let mut image =
+        Vec::with_capacity(HEADER_SIZE + entries.len() + strings.len() + offset as usize);
+    image.extend(PFS0_MAGIC);
+    image.extend((files.len() as u32).to_le_bytes());
+    image.extend((strings.len() as u32).to_le_bytes());
+    image.extend(0u32.to_le_bytes());
+    image.extend(entries);
+    image.extend(strings);
+    for file in files {
+        image.extend(file.read_all()?);
+    }
+    Ok(image)
+}
@@ -0,0 +1,501 @@
+//! RomFS, the read-only filesystem a title's assets ship in
+//!
+//! A header points at four tables and the file data. Directory and file
+//! entries are kept in metadata tables, linked to their parent, next
+//! sibling and, for directories, first child of each kind; the two hash
+//! tables chain entries by a hash of their parent and name, so a lookup by
+//! name touches a single bucket.
+
+use super::{VfsDirectory, VfsFile, VirtualDir, VirtualFile, invalid_data, read_slice};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+const HEADER_SIZE: u64 = 0x50;
+/// Where the builder starts the file data
+const DATA_OFFSET: u64 = 0x200;
+const DATA_ALIGNMENT: u64 = 0x10;
+const EMPTY: u32 = u32::MAX;
+const DIR_ENTRY_SIZE: usize = 0x18;
+const FILE_ENTRY_SIZE: usize = 0x20;
+
+/// Hash of an entry's name within the directory at `parent`
+fn hash(parent: u32, name: &[u8]) -> u32 {
+    name.iter().fold(parent ^ 123456789, |hash, &c| {
+        hash.rotate_right(5) ^ c as u32
+    })
+}
+
+/// Buckets in a hash table for `count` entries
+fn bucket_count(count: usize) -> usize {
+    match count {
+        0..3 => 3,
+        3..19 => count | 1,
+        _ => (count..)
+            .find(|n| [2, 3, 5, 7, 11, 13, 17].iter().all(|p| n % p != 0))
+            .unwrap(),
+    }
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+struct Tables {
+    file: VirtualFile,
+    data_offset: u64,
+    dir_hash: Vec<u32>,
+    dir_meta: Vec<u8>,
+    file_hash: Vec<u32>,
+    file_meta: Vec<u8>,
+}
+
+struct DirEntry {
+    sibling: u32,
+    child_dir: u32,
+    child_file: u32,
+}
+
+struct FileEntry {
+    sibling: u32,
+    offset: u64,
+    size: u64,
+}
+
+/// An entry's parent, the entry kind's own fields, its hash chain link and
+/// name
+struct Entry<T> {
+    parent: u32,
+    fields: T,
+    hash_next: u32,
+    name: String,
+}
+
+impl Tables {
+    /// The entry at `offset` in `meta`, whose fixed part is `size` bytes
+    /// ending with the hash link and name length
+    fn entry<T>(
+        meta: &[u8],
+        offset: u32,
+        size: usize,
+        fields: impl Fn(&[u8]) -> T,
+    ) -> io::Result<Entry<T>> {
+        let offset = offset as usize;
+        let bytes = meta
+            .get(offset..offset.saturating_add(size))
+            .ok_or_else(|| invalid_data("RomFS entry outside its table"))?;
+        let name_len = u32_at(bytes, size - 4) as usize;
+        let name = meta
+            .get(offset + size..(offset + size).saturating_add(name_len))
+            .ok_or_else(|| invalid_data("RomFS entry name outside its table"))?;
+        Ok(Entry {
+            parent: u32_at(bytes, 0),
+            fields: fields(bytes),
+            hash_next: u32_at(bytes, size - 8),
+            name: String::from_utf8_lossy(name).into_owned(),
+        })
+    }
+
+    fn dir(&self, offset: u32) -> io::Result<Entry<DirEntry>> {
+        Self::entry(&self.dir_meta, offset, DIR_ENTRY_SIZE, |bytes| DirEntry {
+            sibling: u32_at(bytes, 4),
+            child_dir: u32_at(bytes, 8),
+            child_file: u32_at(bytes, 12),
+        })
+    }
+
+    fn file(&self, offset: u32) -> io::Result<Entry<FileEntry>> {
+        Self::entry(&self.file_meta, offset, FILE_ENTRY_SIZE, |bytes| {
+            FileEntry {
+                sibling: u32_at(bytes, 4),
+                offset: u64_at(bytes, 8),
+                size: u64_at(bytes, 16),
+            }
+        })
+    }
+
+    fn open_file(&self, entry: Entry<FileEntry>) -> io::Result<VirtualFile> {
+        let start = self
+            .data_offset
+            .checked_add(entry.fields.offset)
+            .filter(|start| {
+                start
+                    .checked_add(entry.fields.size)
+                    .is_some_and(|end| end <= self.file.size())
+            })
+            .ok_or_else(|| {
+                invalid_data(format!("{} runs past the end of the RomFS", entry.name))
+            })?;
+        Ok(Arc::new(super::OffsetFile::new(
+            self.file.clone(),
+            entry.name,
+            start,
+            entry.fields.size,
+        )))
+    }
+}
+
+/// A directory of a RomFS image, the root one as opened and named after
+/// the image
+pub struct RomFs {
+    tables: Arc<Tables>,
+    offset: u32,
+    name: String,
+}
+
+impl RomFs {
+    pub fn open(file: VirtualFile) -> io::Result<Self> {
+        let header = file.read_vec(0, HEADER_SIZE as usize)?;
+        if u64_at(&header, 0) < HEADER_SIZE {
+            return Err(invalid_data("not a RomFS image"));
+        }
+        let table = |i: usize| -> io::Result<Vec<u8>> {
+            let offset = u64_at(&header, 8 + i * 0x10);
+            let size = u64_at(&header, 0x10 + i * 0x10);
+            if offset.checked_add(size).is_none_or(|end| end > file.size()) {
+                return Err(invalid_data("RomFS table runs past the end of the image"));
+            }
+            file.read_vec(offset, size as usize)
+        };
+        let words = |bytes: Vec<u8>| bytes.chunks_exact(4).map(|w| u32_at(w, 0)).collect();
+        let tables = Tables {
+            dir_hash: words(table(0)?),
+            dir_meta: table(1)?,
+            file_hash: words(table(2)?),
+            file_meta: table(3)?,
+            data_offset: u64_at(&header, 0x48),
+            file: file.clone(),
+        };
+        tables.dir(0)?;
+        Ok(Self {
+            tables: Arc::new(tables),
+            offset: 0,
+            name: file.name(),
+        })
+    }
+
+    /// Follow a sibling chain from `first`, stopping after `limit` entries
+    /// in case the links form a loop
+    fn chain<T>(
+        &self,
+        first: u32,
+        limit: usize,
+        entry: impl Fn(u32) -> io::Result<Entry<T>>,
+        sibling: impl Fn(&T) -> u32,
+    ) -> io::Result<Vec<(u32, Entry<T>)>> {
+        let mut entries = Vec::new();
+        let mut offset = first;
+        while offset != EMPTY {
+            if entries.len() == limit {
+                return Err(invalid_data("RomFS entries form a loop"));
+            }
+            let next = entry(offset)?;
+            let following = sibling(&next.fields);
+            entries.push((offset, next));
+            offset = following;
+        }
+        Ok(entries)
+    }
+
+    /// Look `name` up in `buckets`, returning the entry in this directory
+    fn find<T>(
+        &self,
+        buckets: &[u32],
+        limit: usize,
+        name: &str,
+        entry: impl Fn(u32) -> io::Result<Entry<T>>,
+    ) -> io::Result<(u32, Entry<T>)> {
+        if buckets.is_empty() {
+            return Err(ErrorKind::NotFound.into());
+        }
+        let bucket = hash(self.offset, name.as_bytes()) as usize % buckets.len();
+        let mut offset = buckets[bucket];
+        for _ in 0..limit {
+            if offset == EMPTY {
+                break;
+            }
+            let candidate = entry(offset)?;
+            if candidate.parent == self.offset && candidate.name == name {
+                return Ok((offset, candidate));
+            }
+            offset = candidate.hash_next;
+        }
+        Err(ErrorKind::NotFound.into())
+    }
+
+    fn subdir(&self, offset: u32, name: String) -> VirtualDir {
+        Arc::new(Self {
+            tables: self.tables.clone(),
+            offset,
+            name,
+        })
+    }
+}
+
+impl VfsDirectory for RomFs {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn files(&self) -> io::Result<Vec<VirtualFile>> {
+        let tables = &self.tables;
+        let first = tables.dir(self.offset)?.fields.child_file;
+        let limit = tables.file_meta.len() / FILE_ENTRY_SIZE;
+        self.chain(first, limit, |o| tables.file(o), |f| f.sibling)?
+            .into_iter()
+            .map(|(_, entry)| tables.open_file(entry))
+            .collect()
+    }
+
+    fn subdirectories(&self) -> io::Result<Vec<VirtualDir>> {
+        let tables = &self.tables;
+        let first = tables.dir(self.offset)?.fields.child_dir;
+        let limit = tables.dir_meta.len() / DIR_ENTRY_SIZE;
+        let dirs = self.chain(first, limit, |o| tables.dir(o), |d| d.sibling)?;
+        Ok(dirs
+            .into_iter()
+            .map(|(offset, entry)| self.subdir(offset, entry.name))
+            .collect())
+    }
+
+    fn file(&self, name: &str) -> io::Result<VirtualFile> {
+        let tables = &self.tables;
+        let limit = tables.file_meta.len() / FILE_ENTRY_SIZE;
+        let (_, entry) = self.find(&tables.file_hash, limit, name, |o| tables.file(o))?;
+        tables.open_file(entry)
+    }
+
+    fn subdirectory(&self, name: &str) -> io::Result<VirtualDir> {
+        let tables = &self.tables;
+        let limit = tables.dir_meta.len() / DIR_ENTRY_SIZE;
+        let (offset, entry) = self.find(&tables.dir_hash, limit, name, |o| tables.dir(o))?;
+        Ok(self.subdir(offset, entry.name))
+    }
+}
+
+#[derive(Default)]
+struct BuildDir {
+    dirs: BTreeMap<String, BuildDir>,
+    files: BTreeMap<String, VirtualFile>,
+}
+
+impl BuildDir {
+    fn add(&mut self, dir: &VirtualDir) -> io::Result<()> {
+        for file in dir.files()? {
+            self.files.insert(file.name(), file);
+        }
+        for subdir in dir.subdirectories()? {
+            self.dirs.entry(subdir.name()).or_default().add(&subdir)?;
+        }
+        Ok(())
+    }
+}
+
+/// Packs directory trees into a RomFS image
+///
+/// Trees added later win where paths collide, so a tree of modded files
+/// added after a title's RomFS overlays it. File contents are read from
+/// the trees when the image is, not when it is built.
+#[derive(Default)]
+pub struct RomFsBuilder {
+    root: BuildDir,
+}
+
+/// A directory laid out for the metadata table
+struct FlatDir<'a> {
+    name: &'a str,
+    parent: usize,
+    dir: &'a BuildDir,
+    children: Vec<usize>,
+    offset: u32,
+    first_file: u32,
+}
+
+impl RomFsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add everything under `dir` to the image's root
+    pub fn add(&mut self, dir: &VirtualDir) -> io::Result<&mut Self> {
+        self.root.add(dir)?;
+        Ok(self)
+    }
+
+    /// The image, as a read-only file called `name`
+    pub fn build(&self, name: &str) -> VirtualFile {
+        // Directories breadth first, so each one's children are adjacent
+        // and siblings follow each other in the table
+        let mut dirs = vec![FlatDir {
+            name: "",
+            parent: 0,
+            dir: &self.root,
+            children: Vec::new(),
+            offset: 0,
+            first_file: EMPTY,
+        }];
+        let mut queue = VecDeque::from([0]);
+        while let Some(index) = queue.pop_front() {
+            for (name, dir) in &dirs[index].dir.dirs {
+                let child = dirs.len();
+                dirs.push(FlatDir {
+                    name,
+                    parent: index,
+                    dir,
+                    children: Vec::new(),
+                    offset: 0,
+                    first_file: EMPTY,
+                });
+                dirs[index].children.push(child);
+                queue.push_back(child);
+            }
+        }
+        let mut dir_meta_size = 0;
+        for dir in &mut dirs {
+            dir.offset = dir_meta_size as u32;
+            dir_meta_size += DIR_ENTRY_SIZE + dir.name.len().next_multiple_of(4);
+        }
+
+        let mut dir_meta = Vec::with_capacity(dir_meta_size);
+        let mut dir_hash = vec![EMPTY; bucket_count(dirs.len())];
+        let mut file_meta = Vec::new();
+        let mut file_hash = vec![EMPTY; bucket_count(self.file_count())];
+        let mut parts = vec![(0, Part::Bytes(Vec::new()))];
+        let mut data_size = 0u64;
+        for dir in &mut dirs {
+            let (parent, files) = (dir.offset, &dir.dir.files);
+            if !files.is_empty() {
+                dir.first_file = file_meta.len() as u32;
+            }
+            for (i, (name, file)) in files.iter().enumerate() {
+                let offset = file_meta.len() as u32;
+                let entry_size = FILE_ENTRY_SIZE + name.len().next_multiple_of(4);
+                let sibling = if i + 1 < files.len() {
+                    offset + entry_size as u32
+                } else {
+                    EMPTY
+                };
+                let bucket = hash(parent, name.as_bytes()) as usize % file_hash.len();
+                file_meta.extend(parent.to_le_bytes());
+                file_meta.extend(sibling.to_le_bytes());
+                file_meta.extend(data_size.to_le_bytes());
+                file_meta.extend(file.size().to_le_bytes());
+                file_meta.extend(file_hash[bucket].to_le_bytes());
+                file_meta.extend((name.len() as u32).to_le_bytes());
+                file_meta.extend(name.as_bytes());
+                file_meta.resize(offset as usize + entry_size, 0);
+                file_hash[bucket] = offset;
+
+                parts.push((DATA_OFFSET + data_size, Part::File(file.clone())));
+                data_size = (data_size + file.size()).next_multiple_of(DATA_ALIGNMENT);
+            }
+        }
+        for (index, dir) in dirs.iter().enumerate() {
+            let offset = |i: Option<&usize>| i.map_or(EMPTY, |&i| dirs[i].offset);
+            let siblings = &dirs[dir.parent].children;
+            let position = siblings.iter().position(|&i| i == index);
+            let sibling = position.map_or(EMPTY, |p| offset(siblings.get(p + 1)));
+            let bucket =
+                hash(dirs[dir.parent].offset, dir.name.as_bytes()) as usize % dir_hash.len();
+            dir_meta.extend(dirs[dir.parent].offset.to_le_bytes());
+            dir_meta.extend(sibling.to_le_bytes());
+            dir_meta.extend(offset(dir.children.first()).to_le_bytes());
+            dir_meta.extend(dir.first_file.to_le_bytes());
+            dir_meta.extend(dir_hash[bucket].to_le_bytes());
+            dir_meta.extend((dir.name.len() as u32).to_le_bytes());
+            dir_meta.extend(dir.name.as_bytes());
+            dir_meta.resize(
+                dir.offset as usize + DIR_ENTRY_SIZE + dir.name.len().next_multiple_of(4),
+                0,
+            );
+            dir_hash[bucket] = dir.offset;
+        }
+
+        let words =
+            |table: Vec<u32>| -> Vec<u8> { table.into_iter().flat_map(u32::to_le_bytes).collect() };
+        let tables_offset = DATA_OFFSET + data_size;
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend(HEADER_SIZE.to_le_bytes());
+        let mut metadata = Vec::new();
+        for table in [words(dir_hash), dir_meta, words(file_hash), file_meta] {
+            header.extend((tables_offset + metadata.len() as u64).to_le_bytes());
+            header.extend((table.len() as u64).to_le_bytes());
+            metadata.extend(table);
+        }
+        header.extend(DATA_OFFSET.to_le_bytes());
+        let size = tables_offset + metadata.len() as u64;
+        parts[0].1 = Part::Bytes(header);
+        parts.push((tables_offset, Part::Bytes(metadata)));
+        Arc::new(RomFsImage {
+            name: name.to_string(),
+            parts,
+            size,
+        })
+    }
+
+    fn file_count(&self) -> usize {
+        fn count(dir: &BuildDir) -> usize {
+            dir.files.len() + dir.dirs.values().map(count).sum::<usize>()
+        }
+        count(&self.root)
+    }
+}
+
+enum Part {
+    Bytes(Vec<u8>),
+    File(VirtualFile),
+}
+
+/// A built image: the header and tables held in memory and the file data
+/// read from the files it came from, with zeros in the gaps
+struct RomFsImage {
+    name: String,
+    /// By offset, in order
+    parts: Vec<(u64, Part)>,
+    size: u64,
+}
+
+impl VfsFile for RomFsImage {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.size.saturating_sub(offset) as usize);
+        let buf = &mut buf[..len];
+        buf.fill(0);
+        let first = self
+            .parts
+            .partition_point(|(start, _)| *start <= offset)
+            .saturating_sub(1);
+        for (start, part) in &self.parts[first..] {
+            let end = offset + len as u64;
+            if *start >= end {
+                break;
+            }
+            // The overlap of the part with the read, relative to both
+            let skip = offset.saturating_sub(*start);
+            let into = start.saturating_sub(offset) as usize;
+            match part {
+                Part::Bytes(bytes) => {
+                    read_slice(bytes, skip, &mut buf[into..]);
+                }
+                Part::File(file) => {
+                    let part_len = file.size().saturating_sub(skip) as usize;
+                    let dest = &mut buf[into..];
+                    let dest_len = dest.len().min(part_len);
+                    file.read_exact_at(skip, &mut dest[..dest_len])?;
+                }
+            }
+        }
+        Ok(len)
+    }
+}
@@ -0,0 +1,96 @@
+//! Cartridge images (XCI)
+//!
+//! After the cartridge header comes a root HFS0 whose entries are the
+//! partitions, each an HFS0 of its own: `update` holds the system update
+//! the cartridge requires and `secure` the title's NCAs. The header carries
+//! a hash of the root HFS0's header, so a damaged dump is caught on open.
+
+use super::{OffsetFile, PartitionFs, VfsDirectory, VirtualDir, VirtualFile, invalid_data};
+use sha2::{Digest, Sha256};
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+
+const HEADER_MAGIC: &[u8; 4] = b"HEAD";
+const HEADER_SIZE: usize = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XciPartition {
+    Update,
+    Normal,
+    Secure,
+    Logo,
+}
+
+impl XciPartition {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Update => "update",
+            Self::Normal => "normal",
+            Self::Secure => "secure",
+            Self::Logo => "logo",
+        }
+    }
+}
+
+/// An XCI's partitions, as subdirectories
+pub struct Xci {
+    name: String,
+    partitions: Vec<VirtualDir>,
+}
+
+impl Xci {
+    pub fn open(file: VirtualFile) -> io::Result<Self> {
+        let header = file.read_vec(0, HEADER_SIZE)?;
+        if &header[0x100..0x104] != HEADER_MAGIC {
+            return Err(invalid_data("not a cartridge image"));
+        }
+        let offset = u64::from_le_bytes(header[0x130..0x138].try_into().unwrap());
+        let header_size = u64::from_le_bytes(header[0x138..0x140].try_into().unwrap());
+        let size = file
+            .size()
+            .checked_sub(offset)
+            .filter(|&size| size >= header_size)
+            .ok_or_else(|| invalid_data("root partition runs past the end of the image"))?;
+        let root_header = file.read_vec(offset, header_size as usize)?;
+        if Sha256::digest(&root_header).as_slice() != &header[0x140..0x160] {
+            return Err(invalid_data("root partition does not match its hash"));
+        }
+
+        let root: VirtualFile = Arc::new(OffsetFile::new(file.clone(), "root", offset, size));
+        let partitions = PartitionFs::open(root)?
+            .files()?
+            .into_iter()
+            .map(|partition| Ok(Arc::new(PartitionFs::open(partition)?) as VirtualDir))
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            name: file.name(),
+            partitions,
+        })
+    }
+
+    pub fn partition(&self, partition: XciPartition) -> io::Result<VirtualDir> {
+        self.subdirectory(partition.name())
+    }
+}
+
+impl VfsDirectory for Xci {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn files(&self) -> io::Result<Vec<VirtualFile>> {
+        Ok(Vec::new())
+    }
+
+    fn subdirectories(&self) -> io::Result<Vec<VirtualDir>> {
+        Ok(self.partitions.clone())
+    }
+
+    fn subdirectory(&self, name: &str) -> io::Result<VirtualDir> {
+        self.partitions
+            .iter()
+            .find(|partition| partition.name() == name)
+            .cloned()
+            .ok_or_else(|| ErrorKind::NotFound.into())
+    }
+}
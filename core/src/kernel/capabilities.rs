@@ -0,0 +1,235 @@
+//! Kernel capability descriptors
+//!
+//! A process' NPDM lists what the kernel lets it do as 32-bit descriptors.
+//! The type of a descriptor is the number of trailing one bits, and the
+//! payload sits above the terminating zero.
+
+use crate::kernel::memory::PAGE_SIZE;
+use crate::kernel::result::{self, ResultCode};
+use crate::kernel::svc::MAX_SVC_ID;
+use std::collections::BTreeSet;
+
+/// Interrupt ID of an unused half of an interrupt pair
+const UNUSED_INTERRUPT: u32 = 0x3FF;
+/// SVCs each `EnableSystemCalls` descriptor covers
+const SVCS_PER_DESCRIPTOR: u32 = 24;
+
+const THREAD_INFO: u32 = 3;
+const ENABLE_SYSTEM_CALLS: u32 = 4;
+const MAP_RANGE: u32 = 6;
+const MAP_IO_PAGE: u32 = 7;
+const MAP_REGION: u32 = 10;
+const ENABLE_INTERRUPTS: u32 = 11;
+const PROGRAM_TYPE: u32 = 13;
+const KERNEL_VERSION: u32 = 14;
+const HANDLE_TABLE_SIZE: u32 = 15;
+const DEBUG_FLAGS: u32 = 16;
+/// `0xFFFFFFFF`, padding that carries nothing
+const EMPTY: u32 = 32;
+
+/// Physical memory the process may map through `svcQueryMemoryMapping`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub address: u64,
+    pub size: u64,
+    pub read_only: bool,
+    /// Normal memory rather than device registers
+    pub normal: bool,
+}
+
+impl MemoryMap {
+    pub fn contains(&self, address: u64, size: u64) -> bool {
+        address >= self.address
+            && address
+                .checked_add(size)
+                .is_some_and(|end| end <= self.address + self.size)
+    }
+}
+
+/// What a process' kernel capabilities allow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Cores the process' threads may run on
+    pub core_mask: u64,
+    /// Priorities the process' threads may use, one bit per level
+    pub priority_mask: u64,
+    /// Permitted SVCs, one bit per SVC ID
+    pub svc_mask: [u64; 3],
+    pub memory_maps: Vec<MemoryMap>,
+    pub interrupts: BTreeSet<u32>,
+    /// 0 for a system module, 1 for an application, 2 for an applet
+    pub program_type: u32,
+    /// `(major, minor)` of the kernel the process was built for
+    pub kernel_version: Option<(u32, u32)>,
+    pub handle_table_size: Option<usize>,
+    pub allow_debug: bool,
+    pub force_debug: bool,
+}
+
+impl Capabilities {
+    /// Capabilities that allow everything, for processes created without
+    /// metadata
+    pub fn all(core_count: usize) -> Self {
+        Self {
+            core_mask: (1 << core_count) - 1,
+            priority_mask: u64::MAX,
+            svc_mask: [u64::MAX; 3],
+            memory_maps: Vec::new(),
+            interrupts: BTreeSet::new(),
+            program_type: 1,
+            kernel_version: None,
+            handle_table_size: None,
+            allow_debug: false,
+            force_debug: false,
+        }
+    }
+
+    /// Decode `descriptors`, rejecting them the way `svcCreateProcess` does
+    pub fn parse(descriptors: &[u32], core_count: usize) -> Result<Self, ResultCode> {
+        let mut caps = Self {
+            core_mask: 0,
+            priority_mask: 0,
+            svc_mask: [0; 3],
+            ..Self::all(core_count)
+        };
+        let mut seen = BTreeSet::new();
+        let mut svc_indices = BTreeSet::new();
+        let mut iter = descriptors.iter().copied();
+        while let Some(desc) = iter.next() {
+            let kind = desc.trailing_ones();
+            // These may appear only once
+            let single = matches!(
+                kind,
+                THREAD_INFO | PROGRAM_TYPE | KERNEL_VERSION | HANDLE_TABLE_SIZE | DEBUG_FLAGS
+            );
+            if single && !seen.insert(kind) {
+                return Err(result::INVALID_COMBINATION);
+            }
+            match kind {
+                THREAD_INFO => {
+                    let lowest = bits(desc, 4, 6);
+                    let highest = bits(desc, 10, 6);
+                    let (min_core, max_core) = (bits(desc, 16, 8), bits(desc, 24, 8));
+                    if highest > lowest || min_core > max_core {
+                        return Err(result::INVALID_COMBINATION);
+                    }
+                    if max_core as usize >= core_count {
+                        return Err(result::INVALID_CORE_ID);
+                    }
+                    caps.priority_mask = range_mask(highest, lowest);
+                    caps.core_mask = range_mask(min_core, max_core);
+                }
+                ENABLE_SYSTEM_CALLS => {
+                    let index = bits(desc, 29, 3);
+                    if !svc_indices.insert(index) {
+                        return Err(result::INVALID_COMBINATION);
+                    }
+                    let mask = bits(desc, 5, SVCS_PER_DESCRIPTOR);
+                    for bit in (0..SVCS_PER_DESCRIPTOR).filter(|b| mask & (1 << b) != 0) {
+                        let id = index * SVCS_PER_DESCRIPTOR + bit;
+                        if id > MAX_SVC_ID {
+                            return Err(result::OUT_OF_RANGE);
+                        }
+                        caps.svc_mask[id as usize / 64] |= 1 << (id % 64);
+                    }
+                }
+                MAP_RANGE => {
+                    // The size comes in a second descriptor of the same type
+                    let size_desc = iter.next().ok_or(result::INVALID_COMBINATION)?;
+                    if size_desc.trailing_ones() != MAP_RANGE {
+                        return Err(result::INVALID_COMBINATION);
+                    }
+                    let pages = bits(size_desc, 7, 20) as u64;
+                    if pages == 0 {
+                        return Err(result::INVALID_SIZE);
+                    }
+                    caps.memory_maps.push(MemoryMap {
+                        address: bits(desc, 7, 24) as u64 * PAGE_SIZE,
+                        size: pages * PAGE_SIZE,
+                        read_only: desc >> 31 != 0,
+                        normal: size_desc >> 31 != 0,
+                    });
+                }
+                MAP_IO_PAGE => caps.memory_maps.push(MemoryMap {
+                    address: bits(desc, 8, 24) as u64 * PAGE_SIZE,
+                    size: PAGE_SIZE,
+                    read_only: false,
+                    normal: false,
+                }),
+                // Regions of kernel-reserved memory, not backed by anything
+                // here
+                MAP_REGION => {}
+                ENABLE_INTERRUPTS => {
+                    for irq in [bits(desc, 12, 10), bits(desc, 22, 10)] {
+                        if irq != UNUSED_INTERRUPT {
+                            caps.interrupts.insert(irq);
+                        }
+                    }
+                }
+                PROGRAM_TYPE => {
+                    caps.program_type = bits(desc, 14, 3);
+                    if caps.program_type > 2 {
+                        return Err(result::INVALID_ENUM_VALUE);
+                    }
+                }
+                KERNEL_VERSION => {
+                    caps.kernel_version = Some((bits(desc, 19, 13), bits(desc, 15, 4)));
+                }
+                HANDLE_TABLE_SIZE => {
+                    caps.handle_table_size = Some(bits(desc, 16, 10) as usize);
+                }
+                DEBUG_FLAGS => {
+                    caps.allow_debug = desc & (1 << 17) != 0;
+                    caps.force_debug = desc & (1 << 18) != 0;
+                }
+                EMPTY => {}
+                _ => return Err(result::INVALID_ARGUMENT),
+            }
+        }
+        if !seen.contains(&THREAD_INFO) {
+            // No thread may be created without priorities and cores
+            return Err(result::INVALID_COMBINATION);
+        }
+        Ok(caps)
+    }
+
+    pub fn is_svc_permitted(&self, id: u32) -> bool {
+        id <= MAX_SVC_ID && self.svc_mask[id as usize / 64] & (1 << (id % 64)) != 0
+    }
+
+    pub fn is_interrupt_permitted(&self, irq: u32) -> bool {
+        self.interrupts.contains(&irq)
+    }
+
+    /// The memory map covering `[address, address + size)`, if any
+    pub fn memory_map(&self, address: u64, size: u64) -> Option<&MemoryMap> {
+        self.memory_maps.iter().find(|m| m.contains(address, size))
+    }
+
+    /// Whether a process may be granted `requested`, as the loader checks a
+    /// title's ACI0 against what its ACID allows
+    pub fn permits(&self, requested: &Self) -> bool {
+        let within = |have: u64, want: u64| want & !have == 0;
+        within(self.core_mask, requested.core_mask)
+            && within(self.priority_mask, requested.priority_mask)
+            && (0..3).all(|i| within(self.svc_mask[i], requested.svc_mask[i]))
+            && requested.interrupts.is_subset(&self.interrupts)
+            && requested.memory_maps.iter().all(|want| {
+                self.memory_maps.iter().any(|have| {
+                    have.contains(want.address, want.size) && (!have.read_only || want.read_only)
+                })
+            })
+            && (self.allow_debug || !requested.allow_debug)
+            && (self.force_debug || !requested.force_debug)
+    }
+}
+
+/// `len` bits of `desc` starting at `shift`
+fn bits(desc: u32, shift: u32, len: u32) -> u32 {
+    (desc >> shift) & ((1 << len) - 1)
+}
+
+/// Bits `low..=high` set, for `high` below 64
+fn range_mask(low: u32, high: u32) -> u64 {
+    (u64::MAX >> (63 - high)) & (u64::MAX << low)
+}
@@ -0,0 +1,425 @@
+//! Debug SVCs and crash reports
+//!
+//! `svcBreak` and unhandled CPU exceptions produce a [`CrashReport`]: every
+//! thread's registers with a frame-pointer backtrace, the module map and the
+//! memory around the crash. Reports are logged, kept in [`State`] and, when
+//! [`State::report_dir`] is set, written there as text files.
+
+use crate::cpu::{CpuContext, HaltReason};
+use crate::kernel::Kernel;
+use crate::kernel::process::Module;
+use crate::kernel::result::{self, ResultCode};
+use crate::kernel::thread::{ThreadId, ThreadState};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// Set in the `svcBreak` reason when the break only notifies a debugger,
+/// e.g. of a module being loaded, and execution should carry on
+pub const BREAK_NOTIFICATION_ONLY: u32 = 1 << 31;
+
+/// Most bytes of the `svcBreak` argument buffer kept in a report
+const MAX_BREAK_ARGUMENT: u64 = 0x1000;
+/// Most frames a backtrace follows before giving up on a looping chain
+const MAX_FRAMES: usize = 32;
+
+/// Why the guest called `svcBreak`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+    Panic,
+    Assert,
+    User,
+    PreLoadDll,
+    PostLoadDll,
+    PreUnloadDll,
+    PostUnloadDll,
+    CppException,
+    Unknown(u32),
+}
+
+impl BreakReason {
+    /// Decode the reason argument, without [`BREAK_NOTIFICATION_ONLY`]
+    pub fn from_raw(raw: u32) -> Self {
+        match raw & !BREAK_NOTIFICATION_ONLY {
+            0 => Self::Panic,
+            1 => Self::Assert,
+            2 => Self::User,
+            3 => Self::PreLoadDll,
+            4 => Self::PostLoadDll,
+            5 => Self::PreUnloadDll,
+            6 => Self::PostUnloadDll,
+            7 => Self::CppException,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+/// What brought the process down
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Cause {
+    /// `svcBreak`, with the argument buffer the guest passed
+    Break {
+        reason: BreakReason,
+        argument: Vec<u8>,
+    },
+    /// A CPU exception no one handled
+    Exception(HaltReason),
+    /// `svcReturnFromException` outside of an exception handler
+    ReturnFromException(ResultCode),
+    /// An SVC the process' capabilities do not permit, which raises an
+    /// exception on hardware
+    InvalidSystemCall(u32),
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Break { reason, argument } => {
+                write!(
+                    f,
+                    "svcBreak ({reason:?}), {} argument bytes",
+                    argument.len()
+                )
+            }
+            Self::Exception(reason) => write!(f, "unhandled exception ({reason:?})"),
+            Self::ReturnFromException(result) => {
+                write!(f, "svcReturnFromException ({result})")
+            }
+            Self::InvalidSystemCall(id) => write!(f, "SVC {id:#04x} is not permitted"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadDump {
+    pub id: ThreadId,
+    pub state: ThreadState,
+    /// Core the thread was running on, if it was
+    pub core: Option<usize>,
+    pub context: CpuContext,
+    /// Return addresses, innermost first, starting with the PC
+    pub backtrace: Vec<u64>,
+}
+
+/// Guest memory copied into a report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryDump {
+    pub label: &'static str,
+    pub address: u64,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashReport {
+    pub process_id: u64,
+    pub program_id: u64,
+    pub cause: Cause,
+    /// Core and thread that crashed
+    pub core: usize,
+    pub thread: ThreadId,
+    /// Emulated time of the crash, in nanoseconds
+    pub time: u64,
+    pub threads: Vec<ThreadDump>,
+    pub modules: Vec<Module>,
+    /// Memory around the crashed thread's PC and SP
+    pub memory: Vec<MemoryDump>,
+}
+
+impl CrashReport {
+    /// Name of the file [`State::report_dir`] gets for this report
+    pub fn file_name(&self) -> String {
+        format!("crash-{:016x}-{}.txt", self.program_id, self.time)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    fn symbolize(&self, addr: u64) -> String {
+        let Some(module) = self.modules.iter().find(|m| m.contains(addr)) else {
+            return String::from("?");
+        };
+        let location = format!("{}+{:#x}", module.name, addr - module.base);
+        match module.symbol_at(addr) {
+            Some((symbol, offset)) => format!("{location} ({symbol}+{offset:#x})"),
+            None => location,
+        }
+    }
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Process {:#x} (program {:016x}) crashed: {}",
+            self.process_id, self.program_id, self.cause
+        )?;
+        writeln!(f, "Thread {} on core {}", self.thread, self.core)?;
+        if let Cause::Break { argument, .. } = &self.cause
+            && !argument.is_empty()
+        {
+            writeln!(f, "\nBreak argument")?;
+            hex_dump(f, 0, argument)?;
+        }
+
+        writeln!(f, "\nModules")?;
+        for module in &self.modules {
+            writeln!(
+                f,
+                "  {:#018x}-{:#018x}  {}",
+                module.base,
+                module.base + module.size,
+                module.name
+            )?;
+        }
+
+        for thread in &self.threads {
+            write!(f, "\nThread {} ({:?}", thread.id, thread.state)?;
+            if let Some(core) = thread.core {
+                write!(f, ", core {core}")?;
+            }
+            let crashed = if thread.id == self.thread {
+                ", crashed"
+            } else {
+                ""
+            };
+            writeln!(f, "){crashed}")?;
+            registers(f, &thread.context)?;
+            writeln!(f, "  Backtrace")?;
+            for (i, &addr) in thread.backtrace.iter().enumerate() {
+                writeln!(f, "    #{i:<2} {addr:#018x}  {}", self.symbolize(addr))?;
+            }
+        }
+
+        for dump in &self.memory {
+            writeln!(f, "\nMemory at {}", dump.label)?;
+            hex_dump(f, dump.address, &dump.bytes)?;
+        }
+        Ok(())
+    }
+}
+
+fn registers(f: &mut fmt::Formatter<'_>, context: &CpuContext) -> fmt::Result {
+    let named = (0..29).map(|i| (format!("X{i}"), context.x[i])).chain([
+        (String::from("FP"), context.x[29]),
+        (String::from("LR"), context.x[30]),
+        (String::from("SP"), context.sp),
+        (String::from("PC"), context.pc),
+    ]);
+    for (i, (name, value)) in named.enumerate() {
+        let end = if i % 4 == 3 { "\n" } else { "" };
+        write!(f, "  {name:<3} {value:#018x}{end}")?;
+    }
+    writeln!(f)
+}
+
+fn hex_dump(f: &mut fmt::Formatter<'_>, address: u64, bytes: &[u8]) -> fmt::Result {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        write!(f, "  {:#018x} ", address + i as u64 * 16)?;
+        for byte in line {
+            write!(f, " {byte:02x}")?;
+        }
+        writeln!(f)?;
+    }
+    Ok(())
+}
+
+/// Crash reports of the run
+#[derive(Default)]
+pub struct State {
+    /// Directory every crash report is written to, if any
+    pub report_dir: Option<PathBuf>,
+    pub last_report: Option<CrashReport>,
+    stop_requested: bool,
+}
+
+impl State {
+    /// Whether the process stopped since the last call
+    pub(crate) fn take_stop(&mut self) -> bool {
+        std::mem::take(&mut self.stop_requested)
+    }
+}
+
+impl Kernel {
+    /// `svcBreak` from the thread on `core`
+    ///
+    /// There is no debugger to notify, so a notification returns at once
+    /// and any other break ends the process with a crash report.
+    pub fn break_process(
+        &mut self,
+        core: usize,
+        reason: u32,
+        address: u64,
+        size: u64,
+    ) -> Result<(), ResultCode> {
+        let kind = BreakReason::from_raw(reason);
+        if reason & BREAK_NOTIFICATION_ONLY != 0 {
+            log::debug!("Break notification {kind:?} ignored without a debugger");
+            return Ok(());
+        }
+        let thread = self.scheduler.current(core).ok_or(result::INVALID_STATE)?;
+        let size = size.min(MAX_BREAK_ARGUMENT);
+        let argument = if self.process.address_space.is_readable(address, size) {
+            self.cpu.memory().read_vec(address, size as usize)
+        } else {
+            None
+        };
+        let cause = Cause::Break {
+            reason: kind,
+            argument: argument.unwrap_or_default(),
+        };
+        self.stop_process(core, thread, cause);
+        Ok(())
+    }
+
+    /// `svcOutputDebugString`: log the guest's text under the process'
+    /// [`log_target`](crate::kernel::process::Process::log_target)
+    pub fn output_debug_string(&mut self, address: u64, size: u64) -> Result<(), ResultCode> {
+        if size == 0 {
+            return Ok(());
+        }
+        let space = &self.process.address_space;
+        let bytes = space
+            .is_readable(address, size)
+            .then(|| space.memory().read_vec(address, size as usize))
+            .flatten()
+            .ok_or(result::INVALID_CURRENT_MEMORY)?;
+        let text = String::from_utf8_lossy(&bytes);
+        let text = text.trim_end_matches(['\n', '\0']);
+        log::info!(target: &self.process.log_target(), "{text}");
+        Ok(())
+    }
+
+    /// `svcReturnFromException` from the thread on `core`
+    ///
+    /// Exceptions are never handed to a user handler, so the caller cannot
+    /// be returning from one and the process ends as if it were unhandled.
+    pub fn return_from_exception(
+        &mut self,
+        core: usize,
+        result: ResultCode,
+    ) -> Result<(), ResultCode> {
+        let thread = self.scheduler.current(core).ok_or(result::INVALID_STATE)?;
+        self.stop_process(core, thread, Cause::ReturnFromException(result));
+        Ok(())
+    }
+
+    /// Snapshot the process after `thread` on `core` crashed
+    pub fn crash_report(&self, core: usize, thread: ThreadId, cause: Cause) -> CrashReport {
+        let threads = self
+            .scheduler
+            .threads()
+            .filter(|t| t.state != ThreadState::Terminated)
+            .map(|t| {
+                let core =
+                    (0..self.cpu.cores.len()).find(|&c| self.scheduler.current(c) == Some(t.id));
+                let context = match core {
+                    Some(core) => self.cpu.cores[core].save_context(),
+                    None => t.context.clone(),
+                };
+                ThreadDump {
+                    id: t.id,
+                    state: t.state,
+                    core,
+                    backtrace: self.backtrace(&context),
+                    context,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut memory = Vec::new();
+        if let Some(crashed) = threads.iter().find(|t| t.id == thread) {
+            let pc = crashed.context.pc & !0xF;
+            memory.extend(self.dump_memory("PC", pc.saturating_sub(0x40), 0x80));
+            memory.extend(self.dump_memory("SP", crashed.context.sp & !0xF, 0x100));
+        }
+
+        let mut modules = self.process.modules.clone();
+        modules.sort_by_key(|m| m.base);
+        CrashReport {
+            process_id: self.process.id,
+            program_id: self.process.program_id,
+            cause,
+            core,
+            thread,
+            time: self.scheduler.now(),
+            threads,
+            modules,
+            memory,
+        }
+    }
+
+    /// Log `report`, write it to the report directory and keep it
+    pub fn record_crash(&mut self, report: CrashReport) {
+        log::error!("{report}");
+        if let Some(dir) = &self.debug.report_dir {
+            let path = dir.join(report.file_name());
+            if let Err(err) = report.write(&path) {
+                log::warn!("Could not write crash report to {}: {err}", path.display());
+            }
+        }
+        self.debug.last_report = Some(report);
+    }
+
+    /// Report the crash and end every thread of the process
+    pub(crate) fn stop_process(&mut self, core: usize, thread: ThreadId, cause: Cause) {
+        let report = self.crash_report(core, thread, cause);
+        self.record_crash(report);
+        let live: Vec<_> = self
+            .scheduler
+            .threads()
+            .filter(|t| t.state != ThreadState::Terminated)
+            .map(|t| t.id)
+            .collect();
+        for id in live {
+            self.exit_thread(id);
+        }
+        self.debug.stop_requested = true;
+    }
+
+    /// Return addresses found by following the frame pointer chain
+    ///
+    /// The LR is included for a leaf function that has no frame record yet;
+    /// if it does, the first record repeats the LR and is skipped.
+    fn backtrace(&self, context: &CpuContext) -> Vec<u64> {
+        let space = &self.process.address_space;
+        let mut frames = vec![context.pc];
+        if context.x[30] != 0 {
+            frames.push(context.x[30]);
+        }
+        let mut fp = context.x[29];
+        while frames.len() < MAX_FRAMES && fp != 0 && fp.is_multiple_of(16) {
+            if !space.is_readable(fp, 16) {
+                break;
+            }
+            let memory = space.memory();
+            let (Some(next), Some(ret)) = (memory.read_u64(fp), memory.read_u64(fp + 8)) else {
+                break;
+            };
+            if ret == 0 {
+                break;
+            }
+            if frames.last() != Some(&ret) {
+                frames.push(ret);
+            }
+            // Callers' frames are further up the stack
+            if next <= fp {
+                break;
+            }
+            fp = next;
+        }
+        frames
+    }
+
+    fn dump_memory(&self, label: &'static str, address: u64, size: u64) -> Option<MemoryDump> {
+        if !self.process.address_space.is_readable(address, size) {
+            return None;
+        }
+        let bytes = self.cpu.memory().read_vec(address, size as usize)?;
+        Some(MemoryDump {
+            label,
+            address,
+            bytes,
+        })
+    }
+}
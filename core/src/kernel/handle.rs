@@ -0,0 +1,95 @@
+use crate::kernel::ipc::{PortId, SessionId};
+use crate::kernel::result::{self, ResultCode};
+use crate::kernel::shared_memory::{SharedMemoryId, TransferMemoryId};
+use crate::kernel::sync::EventId;
+use crate::kernel::thread::ThreadId;
+
+pub type Handle = u32;
+
+/// Pseudo-handle accepted wherever a process handle is expected
+pub const CURRENT_PROCESS: Handle = 0xFFFF_8000;
+/// Pseudo-handle accepted wherever a thread handle is expected
+pub const CURRENT_THREAD: Handle = 0xFFFF_8001;
+
+/// Handle table capacity of a process without a resource limit
+pub const DEFAULT_HANDLE_TABLE_SIZE: usize = 1024;
+
+/// A kernel object a handle can refer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelObject {
+    Thread(ThreadId),
+    /// The waitable half of an event
+    ReadableEvent(EventId),
+    /// The half of an event that may signal it
+    WritableEvent(EventId),
+    /// The end of a session requests are sent on
+    ClientSession(SessionId),
+    /// The end of a session a guest server receives requests on
+    ServerSession(SessionId),
+    /// The end of a port a guest server accepts sessions on
+    ServerPort(PortId),
+    SharedMemory(SharedMemoryId),
+    TransferMemory(TransferMemoryId),
+}
+
+/// Per-process table translating handles to kernel objects
+///
+/// Handles follow the Horizon layout `(linear_id << 15) | index`, so a stale
+/// handle whose slot has been reused is rejected and no handle ever collides
+/// with 0 or the pseudo-handles.
+pub struct HandleTable {
+    slots: Vec<Option<(u32, KernelObject)>>,
+    count: usize,
+    next_id: u32,
+}
+
+impl HandleTable {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            slots: vec![None; capacity],
+            count: 0,
+            next_id: 1,
+        }
+    }
+
+    /// Insert `object`, returning its new handle
+    pub fn add(&mut self, object: KernelObject) -> Result<Handle, ResultCode> {
+        let index = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(result::OUT_OF_HANDLES)?;
+        let id = self.next_id;
+        self.next_id = (self.next_id % 0x7FFF) + 1;
+        self.slots[index] = Some((id, object));
+        self.count += 1;
+        Ok((id << 15) | index as u32)
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<KernelObject> {
+        match self.slots.get((handle & 0x7FFF) as usize)? {
+            Some((id, object)) if *id == handle >> 15 => Some(*object),
+            _ => None,
+        }
+    }
+
+    pub fn remove(&mut self, handle: Handle) -> Option<KernelObject> {
+        let object = self.get(handle)?;
+        self.slots[(handle & 0x7FFF) as usize] = None;
+        self.count -= 1;
+        Some(object)
+    }
+
+    /// Whether any handle still refers to `object`
+    pub fn references(&self, object: KernelObject) -> bool {
+        self.slots.iter().flatten().any(|&(_, o)| o == object)
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
@@ -0,0 +1,320 @@
+//! HIPC, the kernel-level IPC message format
+//!
+//! A message is up to [`MESSAGE_BUFFER_SIZE`] bytes, normally in the sending
+//! thread's TLS. It is laid out as:
+//!
+//! 1. a two-word header with the message type and descriptor counts
+//! 2. an optional special header, followed by the sender's process ID and
+//!    the copied and moved handles
+//! 3. X (send static), A (send), B (receive) and W (exchange) buffer
+//!    descriptors
+//! 4. the raw data words, which CMIF and TIPC interpret
+//! 5. C (receive list) descriptors, where the receiver wants X buffers
+//!    copied
+//!
+//! The kernel only reads the parts it translates; everything else is carried
+//! through as is.
+
+use crate::kernel::handle::Handle;
+use crate::kernel::result::{self, ResultCode};
+
+/// Size of the message buffer at the start of a thread's TLS
+pub const MESSAGE_BUFFER_SIZE: usize = 0x100;
+
+/// Most descriptors of one kind a header can count
+const MAX_DESCRIPTORS: usize = 0xF;
+const MAX_DATA_WORDS: usize = 0x3FF;
+const MAX_SPECIAL_HANDLES: usize = 0xF;
+
+/// An X buffer: data the kernel copies into the receiver's C buffers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StaticDescriptor {
+    /// Which of the receiver's C buffers to copy into
+    pub index: u8,
+    pub address: u64,
+    pub size: u16,
+}
+
+/// An A, B or W buffer, which the receiver accesses in place
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferDescriptor {
+    pub address: u64,
+    pub size: u64,
+    /// Memory state the buffer must be in; 0 is normal memory
+    pub mode: u8,
+}
+
+/// A C buffer: room for data sent through X buffers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReceiveListEntry {
+    pub address: u64,
+    pub size: u16,
+}
+
+/// A parsed HIPC message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    /// Command type; meaning depends on the CMIF or TIPC layer above
+    pub kind: u16,
+    /// Send the special header even when it holds no PID or handles; set
+    /// when parsing a message that does, so its data stays where it was
+    pub special_header: bool,
+    /// Sender's process ID, present if the sender asked the kernel for it
+    pub pid: Option<u64>,
+    pub copy_handles: Vec<Handle>,
+    pub move_handles: Vec<Handle>,
+    pub x_buffers: Vec<StaticDescriptor>,
+    pub a_buffers: Vec<BufferDescriptor>,
+    pub b_buffers: Vec<BufferDescriptor>,
+    pub w_buffers: Vec<BufferDescriptor>,
+    pub data: Vec<u32>,
+    pub c_buffers: Vec<ReceiveListEntry>,
+}
+
+/// Reads little-endian words from a message buffer
+struct Reader<'a> {
+    buffer: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn word(&mut self) -> Result<u32, ResultCode> {
+        let bytes = self
+            .buffer
+            .get(self.offset..self.offset + 4)
+            .ok_or(result::MESSAGE_TOO_LARGE)?;
+        self.offset += 4;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn words<const N: usize>(&mut self) -> Result<[u32; N], ResultCode> {
+        let mut out = [0; N];
+        for word in &mut out {
+            *word = self.word()?;
+        }
+        Ok(out)
+    }
+}
+
+/// Writes little-endian words into a message buffer
+struct Writer<'a> {
+    buffer: &'a mut [u8],
+    offset: usize,
+}
+
+impl Writer<'_> {
+    fn word(&mut self, value: u32) -> Result<(), ResultCode> {
+        let bytes = self
+            .buffer
+            .get_mut(self.offset..self.offset + 4)
+            .ok_or(result::MESSAGE_TOO_LARGE)?;
+        bytes.copy_from_slice(&value.to_le_bytes());
+        self.offset += 4;
+        Ok(())
+    }
+}
+
+impl StaticDescriptor {
+    fn decode([word0, word1]: [u32; 2]) -> Self {
+        Self {
+            index: (word0 & 0x3F) as u8,
+            address: word1 as u64
+                | (((word0 >> 12) & 0xF) as u64) << 32
+                | (((word0 >> 6) & 0x3F) as u64) << 36,
+            size: (word0 >> 16) as u16,
+        }
+    }
+
+    fn encode(&self) -> [u32; 2] {
+        let word0 = (self.index as u32 & 0x3F)
+            | (((self.address >> 36) & 0x3F) as u32) << 6
+            | (((self.address >> 32) & 0xF) as u32) << 12
+            | (self.size as u32) << 16;
+        [word0, self.address as u32]
+    }
+}
+
+impl BufferDescriptor {
+    fn decode([size_low, address_low, word2]: [u32; 3]) -> Self {
+        Self {
+            address: address_low as u64
+                | (((word2 >> 28) & 0xF) as u64) << 32
+                | (((word2 >> 2) & 0x3F_FFFF) as u64) << 36,
+            size: size_low as u64 | (((word2 >> 24) & 0xF) as u64) << 32,
+            mode: (word2 & 0x3) as u8,
+        }
+    }
+
+    fn encode(&self) -> [u32; 3] {
+        let word2 = (self.mode as u32 & 0x3)
+            | (((self.address >> 36) & 0x3F_FFFF) as u32) << 2
+            | (((self.size >> 32) & 0xF) as u32) << 24
+            | (((self.address >> 32) & 0xF) as u32) << 28;
+        [self.size as u32, self.address as u32, word2]
+    }
+}
+
+impl ReceiveListEntry {
+    fn decode([address_low, word1]: [u32; 2]) -> Self {
+        Self {
+            address: address_low as u64 | ((word1 & 0xFFFF) as u64) << 32,
+            size: (word1 >> 16) as u16,
+        }
+    }
+
+    fn encode(&self) -> [u32; 2] {
+        let word1 = ((self.address >> 32) & 0xFFFF) as u32 | (self.size as u32) << 16;
+        [self.address as u32, word1]
+    }
+}
+
+impl Message {
+    /// Parse the message at the start of `buffer`
+    pub fn parse(buffer: &[u8]) -> Result<Self, ResultCode> {
+        let mut reader = Reader { buffer, offset: 0 };
+        let [header0, header1] = reader.words()?;
+        let count = |shift: u32| ((header0 >> shift) & 0xF) as usize;
+        let (x_count, a_count, b_count, w_count) = (count(16), count(20), count(24), count(28));
+        let data_words = (header1 & 0x3FF) as usize;
+        // 0 and 1 mean no receive list, 2 a single buffer for every X
+        // descriptor and anything above one buffer per index
+        let c_count = match (header1 >> 10) & 0xF {
+            0 | 1 => 0,
+            2 => 1,
+            flags => flags as usize - 2,
+        };
+
+        let mut message = Self {
+            kind: header0 as u16,
+            ..Default::default()
+        };
+        if header1 & 0x8000_0000 != 0 {
+            let special = reader.word()?;
+            if special & 1 != 0 {
+                let [low, high] = reader.words()?;
+                message.pid = Some(low as u64 | (high as u64) << 32);
+            }
+            let copy_count = ((special >> 1) & 0xF) as usize;
+            let move_count = ((special >> 5) & 0xF) as usize;
+            for _ in 0..copy_count {
+                message.copy_handles.push(reader.word()?);
+            }
+            for _ in 0..move_count {
+                message.move_handles.push(reader.word()?);
+            }
+            message.special_header = !message.has_special_header();
+        }
+
+        for _ in 0..x_count {
+            message
+                .x_buffers
+                .push(StaticDescriptor::decode(reader.words()?));
+        }
+        for (buffers, count) in [
+            (&mut message.a_buffers, a_count),
+            (&mut message.b_buffers, b_count),
+            (&mut message.w_buffers, w_count),
+        ] {
+            for _ in 0..count {
+                buffers.push(BufferDescriptor::decode(reader.words()?));
+            }
+        }
+        for _ in 0..data_words {
+            message.data.push(reader.word()?);
+        }
+        for _ in 0..c_count {
+            message
+                .c_buffers
+                .push(ReceiveListEntry::decode(reader.words()?));
+        }
+        Ok(message)
+    }
+
+    /// Byte offset of the first data word within the message
+    pub fn data_offset(&self) -> usize {
+        let mut words = 2;
+        if self.has_special_header() {
+            words += 1 + self.copy_handles.len() + self.move_handles.len();
+            if self.pid.is_some() {
+                words += 2;
+            }
+        }
+        words += self.x_buffers.len() * 2;
+        words += (self.a_buffers.len() + self.b_buffers.len() + self.w_buffers.len()) * 3;
+        words * 4
+    }
+
+    /// Serialize the message into the start of `buffer`
+    pub fn write(&self, buffer: &mut [u8]) -> Result<(), ResultCode> {
+        let descriptor_counts = [
+            self.x_buffers.len(),
+            self.a_buffers.len(),
+            self.b_buffers.len(),
+            self.w_buffers.len(),
+        ];
+        let too_many = descriptor_counts.iter().any(|&n| n > MAX_DESCRIPTORS)
+            || self.c_buffers.len() > MAX_DESCRIPTORS - 2
+            || self.copy_handles.len() > MAX_SPECIAL_HANDLES
+            || self.move_handles.len() > MAX_SPECIAL_HANDLES
+            || self.data.len() > MAX_DATA_WORDS;
+        if too_many {
+            return Err(result::OUT_OF_RANGE);
+        }
+
+        let c_flags = match self.c_buffers.len() {
+            0 => 0,
+            n => n as u32 + 2,
+        };
+        let header0 = self.kind as u32
+            | (self.x_buffers.len() as u32) << 16
+            | (self.a_buffers.len() as u32) << 20
+            | (self.b_buffers.len() as u32) << 24
+            | (self.w_buffers.len() as u32) << 28;
+        let header1 =
+            self.data.len() as u32 | c_flags << 10 | (self.has_special_header() as u32) << 31;
+
+        let mut writer = Writer { buffer, offset: 0 };
+        writer.word(header0)?;
+        writer.word(header1)?;
+        if self.has_special_header() {
+            writer.word(
+                self.pid.is_some() as u32
+                    | (self.copy_handles.len() as u32) << 1
+                    | (self.move_handles.len() as u32) << 5,
+            )?;
+            if let Some(pid) = self.pid {
+                writer.word(pid as u32)?;
+                writer.word((pid >> 32) as u32)?;
+            }
+            for &handle in self.copy_handles.iter().chain(&self.move_handles) {
+                writer.word(handle)?;
+            }
+        }
+
+        let descriptors = self
+            .x_buffers
+            .iter()
+            .flat_map(StaticDescriptor::encode)
+            .chain(
+                self.a_buffers
+                    .iter()
+                    .chain(&self.b_buffers)
+                    .chain(&self.w_buffers)
+                    .flat_map(BufferDescriptor::encode),
+            );
+        for word in descriptors
+            .chain(self.data.iter().copied())
+            .chain(self.c_buffers.iter().flat_map(ReceiveListEntry::encode))
+        {
+            writer.word(word)?;
+        }
+        Ok(())
+    }
+
+    fn has_special_header(&self) -> bool {
+        self.special_header
+            || self.pid.is_some()
+            || !self.copy_handles.is_empty()
+            || !self.move_handles.is_empty()
+    }
+}
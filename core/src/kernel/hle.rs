@@ -0,0 +1,238 @@
+//! Guest functions replaced by host implementations
+//!
+//! A function is identified by address, by an offset into a loaded module or
+//! by a dynamic symbol a module exports. Its first two instructions are
+//! overwritten with a trampoline, `SVC #imm; RET`, whose immediate lies above
+//! every Horizon SVC; the kernel runs the replacement when the SVC is raised
+//! and the `RET` returns to the caller. Replacements follow the AArch64
+//! calling convention, taking arguments in X0-X7 and returning in X0.
+//!
+//! This speeds up hot library routines such as `memcpy`, and stubs library
+//! code that does not work under emulation yet without patching binaries.
+
+use crate::cpu::UnicornCPU;
+use crate::kernel::Kernel;
+use crate::kernel::result::{self, ResultCode};
+use std::rc::Rc;
+
+/// First SVC immediate used by trampolines; replacement `n` raises
+/// `TRAMPOLINE_SVC_BASE + n`
+pub const TRAMPOLINE_SVC_BASE: u32 = 0x8000;
+/// Replacements that fit in the rest of the 16-bit SVC immediate
+const MAX_REPLACEMENTS: usize = 0x8000;
+
+/// Bytes a trampoline overwrites at the start of a function
+pub const TRAMPOLINE_SIZE: u64 = 8;
+
+const RET: u32 = 0xD65F_03C0;
+
+/// Where the function to replace lives in the guest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Address(u64),
+    /// An offset from the base of the module loaded under this name
+    Offset {
+        module: String,
+        offset: u64,
+    },
+    /// A dynamic symbol exported by a loaded module
+    Symbol(String),
+}
+
+/// Host code run in place of a guest function, with its arguments still in
+/// the core's registers; it leaves the return value in X0
+pub type Handler = Rc<dyn Fn(&mut Kernel, &UnicornCPU)>;
+
+struct Replacement {
+    target: Target,
+    handler: Handler,
+    /// Where the trampoline was written, once the target resolved
+    address: Option<u64>,
+}
+
+/// Functions registered with [`Kernel::replace_function`]
+#[derive(Default)]
+pub struct Replacements {
+    entries: Vec<Replacement>,
+}
+
+impl Replacements {
+    /// Whether SVC `id` is the trampoline of an installed replacement
+    pub fn is_trampoline(&self, id: u32) -> bool {
+        self.installed_entry(id).is_some()
+    }
+
+    /// Every installed replacement's target and the address it patched
+    pub fn installed(&self) -> impl Iterator<Item = (&Target, u64)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| Some((&entry.target, entry.address?)))
+    }
+
+    fn installed_entry(&self, id: u32) -> Option<&Replacement> {
+        let index = id.checked_sub(TRAMPOLINE_SVC_BASE)? as usize;
+        self.entries.get(index).filter(|e| e.address.is_some())
+    }
+}
+
+impl Kernel {
+    /// Run `handler` whenever the guest calls the function at `target`
+    ///
+    /// The trampoline is written as soon as `target` resolves: right away,
+    /// or in [`Self::install_replacements`] once the module it names has been
+    /// loaded.
+    pub fn replace_function(
+        &mut self,
+        target: Target,
+        handler: impl Fn(&mut Kernel, &UnicornCPU) + 'static,
+    ) -> Result<(), ResultCode> {
+        if self.hle.entries.len() >= MAX_REPLACEMENTS {
+            return Err(result::OUT_OF_RESOURCE);
+        }
+        self.hle.entries.push(Replacement {
+            target,
+            handler: Rc::new(handler),
+            address: None,
+        });
+        let index = self.hle.entries.len() - 1;
+        self.install_replacement(index)
+            .map(|_| ())
+            .inspect_err(|_| {
+                self.hle.entries.pop();
+            })
+    }
+
+    /// Replace the guest's [`STRING_FUNCTIONS`] wherever a loaded module
+    /// exports them
+    pub fn replace_string_functions(&mut self) -> Result<(), ResultCode> {
+        for (name, function) in STRING_FUNCTIONS {
+            self.replace_function(Target::Symbol(String::from(name)), function)?;
+        }
+        Ok(())
+    }
+
+    /// Write the trampolines of replacements whose targets resolve now,
+    /// e.g. after the loader placed more modules; returns how many it wrote
+    pub fn install_replacements(&mut self) -> usize {
+        let mut installed = 0;
+        for index in 0..self.hle.entries.len() {
+            match self.install_replacement(index) {
+                Ok(true) => installed += 1,
+                Ok(false) => {}
+                Err(err) => {
+                    log::warn!("Cannot replace {:?}: {err}", self.hle.entries[index].target)
+                }
+            }
+        }
+        installed
+    }
+
+    /// Guest address of `target`, if the module it names is loaded
+    pub fn resolve_target(&self, target: &Target) -> Option<u64> {
+        match target {
+            Target::Address(address) => Some(*address),
+            Target::Offset { module, offset } => self
+                .process
+                .modules
+                .iter()
+                .find(|m| &m.name == module)
+                .map(|m| m.base + offset),
+            Target::Symbol(name) => self.process.symbol_address(name),
+        }
+    }
+
+    /// Patch in the trampoline of replacement `index`; false if it is
+    /// already installed or its target does not resolve yet
+    fn install_replacement(&mut self, index: usize) -> Result<bool, ResultCode> {
+        let entry = &self.hle.entries[index];
+        if entry.address.is_some() {
+            return Ok(false);
+        }
+        let Some(address) = self.resolve_target(&entry.target) else {
+            return Ok(false);
+        };
+        let memory = self.cpu.memory();
+        if !address.is_multiple_of(4) || !memory.contains(address, TRAMPOLINE_SIZE) {
+            return Err(result::INVALID_ADDRESS);
+        }
+        if self.hle.installed().any(|(_, other)| other == address) {
+            return Err(result::INVALID_STATE);
+        }
+
+        let id = TRAMPOLINE_SVC_BASE + index as u32;
+        memory.write_u32(address, 0xD400_0001 | (id << 5));
+        memory.write_u32(address + 4, RET);
+        self.cpu.invalidate_code(address, TRAMPOLINE_SIZE);
+        log::debug!("Replaced {:?} at {address:#x}", entry.target);
+        self.hle.entries[index].address = Some(address);
+        Ok(true)
+    }
+
+    /// Run the replacement whose trampoline raised SVC `id` on `core`
+    pub(crate) fn call_replacement(&mut self, core: &UnicornCPU, id: u32) {
+        if let Some(entry) = self.hle.installed_entry(id) {
+            let handler = entry.handler.clone();
+            handler(self, core);
+        }
+    }
+}
+
+/// A replacement that needs no state of its own
+pub type HostFunction = fn(&mut Kernel, &UnicornCPU);
+
+/// A handler that returns `value` without doing anything else
+pub fn stub(value: u64) -> impl Fn(&mut Kernel, &UnicornCPU) {
+    move |_, core| core.set_x(0, value)
+}
+
+/// C string functions with host replacements, by symbol name
+pub const STRING_FUNCTIONS: [(&str, HostFunction); 4] = [
+    ("memcpy", memcpy),
+    ("memmove", memmove),
+    ("memset", memset),
+    ("strlen", strlen),
+];
+
+/// `void *memcpy(void *dst, const void *src, size_t n)`
+pub fn memcpy(kernel: &mut Kernel, core: &UnicornCPU) {
+    memmove(kernel, core);
+}
+
+/// `void *memmove(void *dst, const void *src, size_t n)`
+pub fn memmove(kernel: &mut Kernel, core: &UnicornCPU) {
+    let (dst, src, len) = (core.get_x(0), core.get_x(1), core.get_x(2));
+    if !kernel.cpu.memory().copy(dst, src, len) {
+        log::warn!("memmove({dst:#x}, {src:#x}, {len:#x}) is outside memory");
+    }
+}
+
+/// `void *memset(void *dst, int value, size_t n)`
+pub fn memset(kernel: &mut Kernel, core: &UnicornCPU) {
+    let (dst, value, len) = (core.get_x(0), core.get_x(1) as u8, core.get_x(2));
+    if !kernel.cpu.memory().fill(dst, len, value) {
+        log::warn!("memset({dst:#x}, {value:#x}, {len:#x}) is outside memory");
+    }
+}
+
+/// `size_t strlen(const char *s)`
+pub fn strlen(kernel: &mut Kernel, core: &UnicornCPU) {
+    let memory = kernel.cpu.memory();
+    let start = core.get_x(0);
+    let mut chunk = [0u8; 0x100];
+    let mut cursor = start;
+    loop {
+        let len = chunk
+            .len()
+            .min(memory.size().saturating_sub(cursor) as usize);
+        if len == 0 || !memory.read(cursor, &mut chunk[..len]) {
+            log::warn!("strlen({start:#x}) runs off the end of memory");
+            break;
+        }
+        if let Some(nul) = chunk[..len].iter().position(|&b| b == 0) {
+            cursor += nul as u64;
+            break;
+        }
+        cursor += len as u64;
+    }
+    core.set_x(0, cursor - start);
+}
@@ -0,0 +1,212 @@
+//! `svcGetInfo` and `svcGetSystemInfo`
+//!
+//! Most info types describe the current process and take its pseudo-handle;
+//! the rest take no handle, or a thread handle for tick counts. Ticks are
+//! counted at [`TICKS_PER_SECOND`] from emulated time.
+
+use crate::kernel::handle::{CURRENT_PROCESS, Handle};
+use crate::kernel::process::APPLICATION_PROCESS_ID;
+use crate::kernel::resource_limit::LimitableResource;
+use crate::kernel::result::{self, ResultCode};
+use crate::kernel::{Kernel, svc};
+
+/// Frequency of the system counter, `CNTFRQ_EL0`
+pub const TICKS_PER_SECOND: u64 = 19_200_000;
+
+/// `svcGetInfo` info types
+pub const CORE_MASK: u32 = 0;
+pub const PRIORITY_MASK: u32 = 1;
+pub const ALIAS_REGION_ADDRESS: u32 = 2;
+pub const ALIAS_REGION_SIZE: u32 = 3;
+pub const HEAP_REGION_ADDRESS: u32 = 4;
+pub const HEAP_REGION_SIZE: u32 = 5;
+pub const TOTAL_MEMORY_SIZE: u32 = 6;
+pub const USED_MEMORY_SIZE: u32 = 7;
+pub const DEBUGGER_ATTACHED: u32 = 8;
+pub const RESOURCE_LIMIT: u32 = 9;
+pub const IDLE_TICK_COUNT: u32 = 10;
+pub const RANDOM_ENTROPY: u32 = 11;
+pub const ASLR_REGION_ADDRESS: u32 = 12;
+pub const ASLR_REGION_SIZE: u32 = 13;
+pub const STACK_REGION_ADDRESS: u32 = 14;
+pub const STACK_REGION_SIZE: u32 = 15;
+pub const SYSTEM_RESOURCE_SIZE_TOTAL: u32 = 16;
+pub const SYSTEM_RESOURCE_SIZE_USED: u32 = 17;
+pub const PROGRAM_ID: u32 = 18;
+pub const USER_EXCEPTION_CONTEXT_ADDRESS: u32 = 20;
+pub const TOTAL_NON_SYSTEM_MEMORY_SIZE: u32 = 21;
+pub const USED_NON_SYSTEM_MEMORY_SIZE: u32 = 22;
+pub const IS_APPLICATION: u32 = 23;
+pub const FREE_THREAD_COUNT: u32 = 24;
+pub const THREAD_TICK_COUNT: u32 = 25;
+pub const IS_SVC_PERMITTED: u32 = 26;
+pub const ALIAS_REGION_EXTRA_SIZE: u32 = 28;
+
+/// `svcGetSystemInfo` info types
+pub const SYSTEM_TOTAL_PHYSICAL_MEMORY_SIZE: u32 = 0;
+pub const SYSTEM_USED_PHYSICAL_MEMORY_SIZE: u32 = 1;
+pub const SYSTEM_INITIAL_PROCESS_ID_RANGE: u32 = 2;
+
+/// Memory pools `svcGetSystemInfo` reports on; the process lives in the
+/// application pool
+pub const POOL_APPLICATION: u64 = 0;
+pub const POOL_SYSTEM_UNSAFE: u64 = 3;
+
+/// Subtype of the tick counts that asks for every core
+const ALL_CORES: u64 = u64::MAX;
+
+/// Convert emulated nanoseconds to system ticks
+pub fn ns_to_ticks(ns: u64) -> u64 {
+    (ns as u128 * TICKS_PER_SECOND as u128 / 1_000_000_000) as u64
+}
+
+impl Kernel {
+    /// `svcGetInfo`, called from a thread on core `core`
+    pub fn get_info(
+        &self,
+        kind: u32,
+        handle: Handle,
+        subtype: u64,
+        core: usize,
+    ) -> Result<u64, ResultCode> {
+        match kind {
+            CORE_MASK..=USED_MEMORY_SIZE
+            | ASLR_REGION_ADDRESS..=PROGRAM_ID
+            | USER_EXCEPTION_CONTEXT_ADDRESS..=IS_APPLICATION
+            | ALIAS_REGION_EXTRA_SIZE => {
+                if subtype != 0 {
+                    return Err(result::INVALID_COMBINATION);
+                }
+                if handle != CURRENT_PROCESS {
+                    return Err(result::INVALID_HANDLE);
+                }
+                Ok(self.process_info(kind))
+            }
+            DEBUGGER_ATTACHED | RESOURCE_LIMIT => {
+                if handle != 0 {
+                    return Err(result::INVALID_HANDLE);
+                }
+                if subtype != 0 {
+                    return Err(result::INVALID_COMBINATION);
+                }
+                // No debugger, and the process' resource limit is not a
+                // kernel object it could be handed a handle to
+                Ok(0)
+            }
+            IDLE_TICK_COUNT => {
+                if handle != 0 {
+                    return Err(result::INVALID_HANDLE);
+                }
+                if subtype != ALL_CORES && subtype != core as u64 {
+                    return Err(result::INVALID_COMBINATION);
+                }
+                Ok(ns_to_ticks(self.scheduler.idle_time(core)))
+            }
+            RANDOM_ENTROPY => {
+                if handle != 0 {
+                    return Err(result::INVALID_HANDLE);
+                }
+                let entropy = &self.process.random_entropy;
+                let value = entropy.get(subtype as usize);
+                value.copied().ok_or(result::INVALID_COMBINATION)
+            }
+            FREE_THREAD_COUNT => {
+                if handle != CURRENT_PROCESS {
+                    return Err(result::INVALID_HANDLE);
+                }
+                if subtype != 0 {
+                    return Err(result::INVALID_COMBINATION);
+                }
+                // Processes without a limit on threads report none free
+                let limit = &self.process.resource_limit;
+                if limit.limit(LimitableResource::Threads) == u64::MAX {
+                    return Ok(0);
+                }
+                Ok(limit.free(LimitableResource::Threads))
+            }
+            THREAD_TICK_COUNT => {
+                if subtype != ALL_CORES && subtype >= self.cpu.cores.len() as u64 {
+                    return Err(result::INVALID_COMBINATION);
+                }
+                let current = self.scheduler.current(core);
+                let id = self.thread_from_handle(handle, current)?;
+                let thread = self.scheduler.thread(id).ok_or(result::INVALID_HANDLE)?;
+                // Time is not split by core, so any core reports the total
+                Ok(ns_to_ticks(thread.cpu_time))
+            }
+            IS_SVC_PERMITTED => {
+                if handle != 0 {
+                    return Err(result::INVALID_HANDLE);
+                }
+                if subtype > svc::MAX_SVC_ID as u64 {
+                    return Err(result::INVALID_COMBINATION);
+                }
+                let capabilities = &self.process.capabilities;
+                Ok(capabilities.is_svc_permitted(subtype as u32) as u64)
+            }
+            _ => Err(result::INVALID_ENUM_VALUE),
+        }
+    }
+
+    fn process_info(&self, kind: u32) -> u64 {
+        let process = &self.process;
+        let layout = &process.address_space.layout;
+        match kind {
+            CORE_MASK => process.core_mask,
+            PRIORITY_MASK => process.priority_mask,
+            ALIAS_REGION_ADDRESS => layout.alias.base,
+            ALIAS_REGION_SIZE => layout.alias.size,
+            HEAP_REGION_ADDRESS => layout.heap.base,
+            HEAP_REGION_SIZE => layout.heap.size,
+            TOTAL_MEMORY_SIZE => process.total_memory_size(),
+            USED_MEMORY_SIZE => process.used_memory_size(),
+            ASLR_REGION_ADDRESS => layout.address_space.base,
+            ASLR_REGION_SIZE => layout.address_space.size,
+            STACK_REGION_ADDRESS => layout.stack.base,
+            STACK_REGION_SIZE => layout.stack.size,
+            SYSTEM_RESOURCE_SIZE_TOTAL => process.system_resource_size,
+            // The kernel's own bookkeeping is not charged to the process
+            SYSTEM_RESOURCE_SIZE_USED => 0,
+            PROGRAM_ID => process.program_id,
+            USER_EXCEPTION_CONTEXT_ADDRESS => process.user_exception_context,
+            TOTAL_NON_SYSTEM_MEMORY_SIZE => {
+                process.total_memory_size() - process.system_resource_size
+            }
+            USED_NON_SYSTEM_MEMORY_SIZE => process.used_memory_size(),
+            IS_APPLICATION => (process.id == APPLICATION_PROCESS_ID) as u64,
+            ALIAS_REGION_EXTRA_SIZE => 0,
+            _ => unreachable!("info type {kind} is not about the process"),
+        }
+    }
+
+    /// `svcGetSystemInfo`
+    pub fn get_system_info(
+        &self,
+        kind: u32,
+        handle: Handle,
+        subtype: u64,
+    ) -> Result<u64, ResultCode> {
+        if handle != 0 {
+            return Err(result::INVALID_HANDLE);
+        }
+        match kind {
+            SYSTEM_TOTAL_PHYSICAL_MEMORY_SIZE | SYSTEM_USED_PHYSICAL_MEMORY_SIZE => {
+                if subtype > POOL_SYSTEM_UNSAFE {
+                    return Err(result::INVALID_COMBINATION);
+                }
+                // Nothing but the process allocates from the pools
+                Ok(match (kind, subtype) {
+                    (_, pool) if pool != POOL_APPLICATION => 0,
+                    (SYSTEM_TOTAL_PHYSICAL_MEMORY_SIZE, _) => self.process.total_memory_size(),
+                    _ => self.process.used_memory_size(),
+                })
+            }
+            SYSTEM_INITIAL_PROCESS_ID_RANGE => match subtype {
+                0 => Ok(1),
+                1 => Ok(APPLICATION_PROCESS_ID - 1),
+                _ => Err(result::INVALID_COMBINATION),
+            },
+            _ => Err(result::INVALID_ENUM_VALUE),
+        }
+    }
+}
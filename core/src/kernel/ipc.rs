@@ -0,0 +1,638 @@
+//! Sessions, named ports and the IPC SVCs
+//!
+//! A session connects a client, which sends requests with
+//! `svcSendSyncRequest`, to a server. Servers are either implemented on the
+//! host through [`SessionHandler`], in which case a request is answered
+//! within the SVC, or are guest threads receiving and replying with
+//! `svcReplyAndReceive`. In the latter case the kernel copies the message
+//! between the two threads' buffers, translating handles, filling in the
+//! client's process ID and copying X buffers into the receiver's C buffers.
+//!
+//! Sessions are opened through ports. A port counts its sessions against a
+//! limit and, when a guest thread serves it, queues new sessions until the
+//! server accepts them with `svcAcceptSession`.
+
+use crate::kernel::Kernel;
+use crate::kernel::handle::{CURRENT_THREAD, Handle, KernelObject};
+use crate::kernel::hipc::{MESSAGE_BUFFER_SIZE, Message, ReceiveListEntry};
+use crate::kernel::result::{self, ResultCode};
+use crate::kernel::sync::Wait;
+use crate::kernel::thread::ThreadId;
+use std::collections::{BTreeMap, VecDeque};
+
+pub type SessionId = u64;
+pub type PortId = u64;
+
+/// Longest name `svcConnectToNamedPort` accepts, including the terminator
+pub const MAX_PORT_NAME_LENGTH: usize = 12;
+
+/// Server end of a session implemented on the host
+pub trait SessionHandler {
+    /// Answer a request sent on the session
+    ///
+    /// The host shares the guest process' handle table, so handles in the
+    /// request are the client's own and handles for the reply can be added
+    /// to the table directly. Handles the client moves are reissued to the
+    /// host and closed once this returns, unless it keeps them with
+    /// [`Kernel::take_moved_handle`]. Copied handles in the reply are
+    /// duplicated for the client, so the host keeps its own. An error fails
+    /// the client's `svcSendSyncRequest` instead of replying.
+    fn handle_request(
+        &mut self,
+        kernel: &mut Kernel,
+        request: &Message,
+    ) -> Result<Message, ResultCode>;
+}
+
+/// Creates the handler for each new connection to a named port
+pub type PortFactory = fn() -> Box<dyn SessionHandler>;
+
+/// A client's message waiting to be received or replied to
+#[derive(Debug, Clone, Copy)]
+struct Request {
+    client: ThreadId,
+    buffer: u64,
+    size: u64,
+}
+
+pub struct Session {
+    /// Handler of a host session; taken out while it runs
+    handler: Option<Box<dyn SessionHandler>>,
+    host: bool,
+    /// Requests not received by the guest server yet, oldest first
+    pending: VecDeque<Request>,
+    /// Request the guest server has received but not replied to
+    active: Option<Request>,
+    client_open: bool,
+    server_open: bool,
+    /// Port the session was opened through, which counts it until closed
+    port: Option<PortId>,
+}
+
+pub struct Port {
+    max_sessions: u32,
+    session_count: u32,
+    /// Sessions the guest server has not accepted yet, oldest first
+    pending: VecDeque<SessionId>,
+    server_open: bool,
+}
+
+/// Sessions and named ports, shared by every process
+#[derive(Default)]
+pub struct Ipc {
+    sessions: BTreeMap<SessionId, Session>,
+    named_ports: BTreeMap<String, PortFactory>,
+    next_session_id: SessionId,
+    ports: BTreeMap<PortId, Port>,
+    next_port_id: PortId,
+    /// Thread whose request a host handler is answering
+    client: Option<ThreadId>,
+    /// Handles the client moved in that request, closed once the handler
+    /// returns unless it takes them
+    moved: Vec<Handle>,
+}
+
+impl Ipc {
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// A port allowing `max_sessions` sessions at a time, without handles
+    /// to it; host services use these to limit their sessions
+    pub fn new_port(&mut self, max_sessions: u32) -> PortId {
+        let id = self.next_port_id;
+        self.next_port_id += 1;
+        self.ports.insert(
+            id,
+            Port {
+                max_sessions,
+                session_count: 0,
+                pending: VecDeque::new(),
+                server_open: true,
+            },
+        );
+        id
+    }
+
+    fn add_session(
+        &mut self,
+        handler: Option<Box<dyn SessionHandler>>,
+        port: Option<PortId>,
+    ) -> SessionId {
+        let id = self.next_session_id;
+        self.next_session_id += 1;
+        self.sessions.insert(
+            id,
+            Session {
+                host: handler.is_some(),
+                handler,
+                pending: VecDeque::new(),
+                active: None,
+                client_open: true,
+                server_open: true,
+                port,
+            },
+        );
+        id
+    }
+
+    fn remove_session(&mut self, id: SessionId) {
+        let Some(port_id) = self.sessions.remove(&id).and_then(|s| s.port) else {
+            return;
+        };
+        if let Some(port) = self.ports.get_mut(&port_id) {
+            port.session_count -= 1;
+            if !port.server_open && port.session_count == 0 {
+                self.ports.remove(&port_id);
+            }
+        }
+    }
+}
+
+impl Kernel {
+    /// Make a host service reachable through `svcConnectToNamedPort`
+    pub fn register_named_port(&mut self, name: &str, factory: PortFactory) {
+        self.ipc.named_ports.insert(name.to_string(), factory);
+    }
+
+    /// Open a session served by `handler`, returning its client handle
+    pub fn create_host_session(
+        &mut self,
+        handler: Box<dyn SessionHandler>,
+    ) -> Result<Handle, ResultCode> {
+        let id = self.ipc.add_session(Some(handler), None);
+        self.process
+            .handles
+            .add(KernelObject::ClientSession(id))
+            .inspect_err(|_| {
+                self.ipc.remove_session(id);
+            })
+    }
+
+    /// `svcCreateSession`: a session served by a guest thread, returning its
+    /// server and client handles
+    pub fn create_session(&mut self) -> Result<(Handle, Handle), ResultCode> {
+        let id = self.ipc.add_session(None, None);
+        let handles = &mut self.process.handles;
+        let server = handles.add(KernelObject::ServerSession(id));
+        let client = handles.add(KernelObject::ClientSession(id));
+        match (server, client) {
+            (Ok(server), Ok(client)) => Ok((server, client)),
+            (server, client) => {
+                for handle in [server, client].into_iter().flatten() {
+                    handles.remove(handle);
+                }
+                self.ipc.remove_session(id);
+                Err(result::OUT_OF_HANDLES)
+            }
+        }
+    }
+
+    /// Open a session through `port`, returning its client handle
+    ///
+    /// The session is served by `handler` on the host or, without one, by
+    /// the guest thread owning the port once it accepts the session.
+    pub fn connect_port(
+        &mut self,
+        port: PortId,
+        handler: Option<Box<dyn SessionHandler>>,
+    ) -> Result<Handle, ResultCode> {
+        let host = handler.is_some();
+        let entry = self.ipc.ports.get_mut(&port).ok_or(result::PORT_CLOSED)?;
+        if !host && !entry.server_open {
+            return Err(result::PORT_CLOSED);
+        }
+        if entry.session_count >= entry.max_sessions {
+            return Err(result::OUT_OF_SESSIONS);
+        }
+        entry.session_count += 1;
+
+        let id = self.ipc.add_session(handler, Some(port));
+        let client = self
+            .process
+            .handles
+            .add(KernelObject::ClientSession(id))
+            .inspect_err(|_| self.ipc.remove_session(id))?;
+        if !host {
+            self.ipc.ports.get_mut(&port).unwrap().pending.push_back(id);
+            self.signal_object(KernelObject::ServerPort(port));
+        }
+        Ok(client)
+    }
+
+    /// A port served by a guest thread, returning the handle of its server
+    /// end
+    pub fn create_server_port(
+        &mut self,
+        max_sessions: u32,
+    ) -> Result<(Handle, PortId), ResultCode> {
+        let port = self.ipc.new_port(max_sessions);
+        match self.process.handles.add(KernelObject::ServerPort(port)) {
+            Ok(handle) => Ok((handle, port)),
+            Err(code) => {
+                self.ipc.ports.remove(&port);
+                Err(code)
+            }
+        }
+    }
+
+    /// `svcAcceptSession`: take the oldest session waiting on a server port
+    pub fn accept_session(&mut self, handle: Handle) -> Result<Handle, ResultCode> {
+        let Some(KernelObject::ServerPort(port)) = self.process.handles.get(handle) else {
+            return Err(result::INVALID_HANDLE);
+        };
+        let entry = self
+            .ipc
+            .ports
+            .get_mut(&port)
+            .ok_or(result::INVALID_HANDLE)?;
+        let id = *entry.pending.front().ok_or(result::NOT_FOUND)?;
+        let server = self.process.handles.add(KernelObject::ServerSession(id))?;
+        self.ipc.ports.get_mut(&port).unwrap().pending.pop_front();
+        Ok(server)
+    }
+
+    /// Whether a server port has sessions to accept
+    pub(crate) fn is_port_signaled(&self, id: PortId) -> bool {
+        self.ipc
+            .ports
+            .get(&id)
+            .is_some_and(|p| !p.pending.is_empty())
+    }
+
+    /// The server end of a port lost its last handle, closing the sessions
+    /// it never accepted
+    pub(crate) fn close_server_port(&mut self, id: PortId) {
+        let Some(port) = self.ipc.ports.get_mut(&id) else {
+            return;
+        };
+        port.server_open = false;
+        let pending: Vec<SessionId> = port.pending.drain(..).collect();
+        for session in pending {
+            self.close_server_session(session);
+        }
+        if self
+            .ipc
+            .ports
+            .get(&id)
+            .is_some_and(|p| p.session_count == 0)
+        {
+            self.ipc.ports.remove(&id);
+        }
+    }
+
+    /// `svcConnectToNamedPort`
+    pub fn connect_to_named_port(&mut self, name: &str) -> Result<Handle, ResultCode> {
+        let factory = *self.ipc.named_ports.get(name).ok_or(result::NOT_FOUND)?;
+        self.create_host_session(factory())
+    }
+
+    /// `svcSendSyncRequest` with the message at `buffer`
+    ///
+    /// Host sessions reply before returning; a guest server's reply arrives
+    /// once it calls `svcReplyAndReceive`, until which `current` blocks.
+    pub fn send_sync_request(
+        &mut self,
+        current: ThreadId,
+        handle: Handle,
+        buffer: u64,
+        size: u64,
+    ) -> Result<(), ResultCode> {
+        let Some(KernelObject::ClientSession(id)) = self.process.handles.get(handle) else {
+            return Err(result::INVALID_HANDLE);
+        };
+        let session = self
+            .ipc
+            .sessions
+            .get_mut(&id)
+            .ok_or(result::INVALID_HANDLE)?;
+        if !session.server_open {
+            return Err(result::SESSION_CLOSED);
+        }
+
+        if !session.host {
+            // Reject malformed messages now rather than when received
+            self.read_message(buffer, size)?;
+            let session = self.ipc.sessions.get_mut(&id).unwrap();
+            session.pending.push_back(Request {
+                client: current,
+                buffer,
+                size,
+            });
+            self.scheduler.block(current, Some(Wait::Reply(id)), None);
+            self.signal_object(KernelObject::ServerSession(id));
+            return Ok(());
+        }
+
+        let mut handler = session.handler.take().ok_or(result::BUSY)?;
+        let reply = self.read_message(buffer, size).and_then(|mut request| {
+            if request.pid.is_some() {
+                request.pid = Some(self.process.id);
+            }
+            // Moved handles leave the client as the request is sent
+            self.move_handles(&mut request);
+            self.ipc.client = Some(current);
+            self.ipc.moved = request.move_handles.clone();
+            let reply = handler.handle_request(self, &request);
+            self.ipc.client = None;
+            for handle in std::mem::take(&mut self.ipc.moved) {
+                let _ = self.close_handle(handle);
+            }
+            reply
+        });
+        if let Some(session) = self.ipc.sessions.get_mut(&id) {
+            session.handler = Some(handler);
+        }
+        let mut reply = reply?;
+        self.translate_handles(&mut reply, current);
+        self.write_message(&reply, buffer, size)
+    }
+
+    /// Keep `handle`, which the client moved in the request a host handler
+    /// is answering, rather than have it closed once the handler returns
+    pub fn take_moved_handle(&mut self, handle: Handle) -> Result<Handle, ResultCode> {
+        let index = self
+            .ipc
+            .moved
+            .iter()
+            .position(|&moved| moved == handle && moved != 0)
+            .ok_or(result::INVALID_HANDLE)?;
+        self.ipc.moved.swap_remove(index);
+        Ok(handle)
+    }
+
+    /// A handle of the host's own to what the client copies as `handle` in
+    /// the request a host handler is answering
+    pub fn take_copied_handle(&mut self, handle: Handle) -> Result<Handle, ResultCode> {
+        let object = match handle {
+            CURRENT_THREAD => self.ipc.client.map(KernelObject::Thread),
+            other => self.process.handles.get(other),
+        };
+        self.process
+            .handles
+            .add(object.ok_or(result::INVALID_HANDLE)?)
+    }
+
+    /// Address of the `svcSendSyncRequest` whose request a host handler is
+    /// answering, if a guest thread sent it
+    pub fn request_pc(&self) -> Option<u64> {
+        let client = self.ipc.client?;
+        let core =
+            (0..self.cpu.cores.len()).find(|&core| self.scheduler.current(core) == Some(client));
+        let pc = match core {
+            Some(core) => self.cpu.cores[core].get_pc(),
+            None => self.scheduler.thread(client)?.context.pc,
+        };
+        // The PC has already moved past the SVC
+        Some(pc.wrapping_sub(4))
+    }
+
+    /// `svcReplyAndReceive`: reply on `reply_target` if it is not 0, then
+    /// wait for one of `handles` and receive the request if it is a server
+    /// session
+    ///
+    /// Returns the index of the signaled handle; errors concerning one of
+    /// the handles, such as its client having closed the session, carry its
+    /// index too.
+    pub fn reply_and_receive(
+        &mut self,
+        current: ThreadId,
+        handles: &[Handle],
+        reply_target: Handle,
+        timeout: i64,
+    ) -> Result<u64, (ResultCode, Option<u64>)> {
+        let objects = self
+            .waitable_objects(handles)
+            .map_err(|code| (code, None))?;
+        if reply_target != 0 {
+            let Some(KernelObject::ServerSession(id)) = self.process.handles.get(reply_target)
+            else {
+                return Err((result::INVALID_HANDLE, None));
+            };
+            self.send_reply(current, id).map_err(|code| (code, None))?;
+        }
+
+        if let Some(index) = objects.iter().position(|&o| self.is_signaled(o)) {
+            if let KernelObject::ServerSession(id) = objects[index] {
+                self.receive_request(current, id)
+                    .map_err(|code| (code, Some(index as u64)))?;
+            }
+            return Ok(index as u64);
+        }
+        if timeout == 0 {
+            return Err((result::TIMED_OUT, None));
+        }
+        if self.take_wait_cancelled(current) {
+            return Err((result::CANCELLED, None));
+        }
+
+        let timeout = (timeout >= 0).then_some(timeout as u64);
+        self.scheduler
+            .block(current, Some(Wait::Receive(objects)), timeout);
+        Err((result::TIMED_OUT, None))
+    }
+
+    /// Whether a server session has a request to receive or lost its client
+    pub(crate) fn is_session_signaled(&self, id: SessionId) -> bool {
+        self.ipc
+            .sessions
+            .get(&id)
+            .is_none_or(|s| !s.pending.is_empty() || !s.client_open)
+    }
+
+    /// Copy the oldest pending request of a session into the TLS of
+    /// `server`
+    pub(crate) fn receive_request(
+        &mut self,
+        server: ThreadId,
+        id: SessionId,
+    ) -> Result<(), ResultCode> {
+        let session = self
+            .ipc
+            .sessions
+            .get_mut(&id)
+            .ok_or(result::SESSION_CLOSED)?;
+        let request = session.pending.pop_front().ok_or(result::SESSION_CLOSED)?;
+        session.active = Some(request);
+
+        let tls = self.tls_of(server)?;
+        let size = MESSAGE_BUFFER_SIZE as u64;
+        let receive_list = self
+            .read_message(tls, size)
+            .map(|m| m.c_buffers)
+            .unwrap_or_default();
+        let out = self
+            .read_message(request.buffer, request.size)
+            .and_then(|mut message| {
+                if message.pid.is_some() {
+                    message.pid = Some(self.process.id);
+                }
+                self.translate_handles(&mut message, request.client);
+                self.copy_statics(&mut message, &receive_list);
+                self.write_message(&message, tls, size)
+            });
+        if let Err(code) = out {
+            // Fail the client too rather than leave it waiting for a reply
+            if let Some(session) = self.ipc.sessions.get_mut(&id) {
+                session.active = None;
+            }
+            self.end_wait(request.client, code, None);
+        }
+        out
+    }
+
+    /// Copy the reply in the TLS of `server` back to the client whose
+    /// request it received on the session, and wake the client
+    fn send_reply(&mut self, server: ThreadId, id: SessionId) -> Result<(), ResultCode> {
+        let session = self
+            .ipc
+            .sessions
+            .get_mut(&id)
+            .ok_or(result::INVALID_HANDLE)?;
+        let request = session.active.take().ok_or(result::INVALID_STATE)?;
+
+        let tls = self.tls_of(server)?;
+        let out = self
+            .read_message(tls, MESSAGE_BUFFER_SIZE as u64)
+            .and_then(|mut reply| {
+                // The client's C buffers are in the request it sent
+                let receive_list = self
+                    .read_message(request.buffer, request.size)
+                    .map(|m| m.c_buffers)
+                    .unwrap_or_default();
+                self.translate_handles(&mut reply, server);
+                self.copy_statics(&mut reply, &receive_list);
+                self.write_message(&reply, request.buffer, request.size)
+            });
+        self.end_wait(
+            request.client,
+            out.err().unwrap_or(ResultCode::SUCCESS),
+            None,
+        );
+        out
+    }
+
+    /// The client end of a session lost its last handle
+    pub(crate) fn close_client_session(&mut self, id: SessionId) {
+        let Some(session) = self.ipc.sessions.get_mut(&id) else {
+            return;
+        };
+        session.client_open = false;
+        if session.host || !session.server_open {
+            self.ipc.remove_session(id);
+        } else {
+            self.signal_object(KernelObject::ServerSession(id));
+        }
+    }
+
+    /// The server end of a session lost its last handle, failing every
+    /// request still waiting for a reply
+    pub(crate) fn close_server_session(&mut self, id: SessionId) {
+        let Some(session) = self.ipc.sessions.get_mut(&id) else {
+            return;
+        };
+        session.server_open = false;
+        let waiting: Vec<Request> = session
+            .active
+            .take()
+            .into_iter()
+            .chain(session.pending.drain(..))
+            .collect();
+        if !session.client_open {
+            self.ipc.remove_session(id);
+        }
+        for request in waiting {
+            self.end_wait(request.client, result::SESSION_CLOSED, None);
+        }
+    }
+
+    fn tls_of(&self, id: ThreadId) -> Result<u64, ResultCode> {
+        self.scheduler
+            .thread(id)
+            .map(|t| t.tls_address)
+            .ok_or(result::INVALID_HANDLE)
+    }
+
+    fn read_message(&self, buffer: u64, size: u64) -> Result<Message, ResultCode> {
+        let space = &self.process.address_space;
+        if !space.is_readable(buffer, size) {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        let bytes = space
+            .memory()
+            .read_vec(buffer, size as usize)
+            .ok_or(result::INVALID_CURRENT_MEMORY)?;
+        Message::parse(&bytes)
+    }
+
+    fn write_message(&self, message: &Message, buffer: u64, size: u64) -> Result<(), ResultCode> {
+        let space = &self.process.address_space;
+        if !space.is_writable(buffer, size) {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        let mut bytes = vec![0; size as usize];
+        message.write(&mut bytes)?;
+        space.memory().write(buffer, &bytes);
+        Ok(())
+    }
+
+    /// Give the receiver its own handles to the objects the sender copies
+    /// or moves
+    ///
+    /// Both ends live in the same process, so a copy adds a second handle
+    /// and a move reissues the handle. Invalid handles arrive as 0.
+    fn translate_handles(&mut self, message: &mut Message, sender: ThreadId) {
+        let handles = &mut self.process.handles;
+        for handle in &mut message.copy_handles {
+            let object = match *handle {
+                CURRENT_THREAD => Some(KernelObject::Thread(sender)),
+                other => handles.get(other),
+            };
+            *handle = object.and_then(|o| handles.add(o).ok()).unwrap_or(0);
+        }
+        self.move_handles(message);
+    }
+
+    /// Take the handles `message` moves from the sender and reissue them
+    /// to the receiver; invalid handles arrive as 0
+    fn move_handles(&mut self, message: &mut Message) {
+        let handles = &mut self.process.handles;
+        for handle in &mut message.move_handles {
+            let object = handles.remove(*handle);
+            *handle = object.and_then(|o| handles.add(o).ok()).unwrap_or(0);
+        }
+    }
+
+    /// Copy the data of X buffers into the receiver's C buffers, pointing
+    /// the descriptors at the copies
+    ///
+    /// A single C buffer takes every X buffer back to back; otherwise each
+    /// goes to the C buffer its index selects. Without a receive list the
+    /// descriptors keep pointing at the sender's data, which the receiver
+    /// can read since both share the address space.
+    fn copy_statics(&self, message: &mut Message, receive_list: &[ReceiveListEntry]) {
+        let memory = self.process.address_space.memory();
+        let mut packed = 0u16;
+        for x in &mut message.x_buffers {
+            let target = match receive_list {
+                [] => return,
+                [single] => ReceiveListEntry {
+                    address: single.address + packed as u64,
+                    size: single.size.saturating_sub(packed),
+                },
+                list => match list.get(x.index as usize) {
+                    Some(entry) => *entry,
+                    None => continue,
+                },
+            };
+            let size = x.size.min(target.size);
+            memory.copy(target.address, x.address, size as u64);
+            x.address = target.address;
+            x.size = size;
+            // Never past the single C buffer's size, so this cannot overflow
+            if receive_list.len() == 1 {
+                packed += size;
+            }
+        }
+    }
+}
@@ -0,0 +1,650 @@
+//! Guest address-space model and the memory-management SVCs built on it
+//!
+//! RAM is identity mapped for every core, so this layer is pure bookkeeping:
+//! it tracks the state, permission and attribute of every page the way the
+//! Horizon page table does, validates SVC arguments against it, and performs
+//! the zeroing and copying that the real kernel would do through page tables.
+
+use crate::cpu::GuestMemory;
+use crate::kernel::result::{self, ResultCode};
+use std::collections::BTreeMap;
+
+pub const PAGE_SIZE: u64 = 0x1000;
+/// `svcSetHeapSize` only accepts multiples of 2MB
+pub const HEAP_SIZE_ALIGNMENT: u64 = 0x20_0000;
+
+/// Memory state as reported by `svcQueryMemory`
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryState {
+    Free = 0x00,
+    Io = 0x01,
+    Static = 0x02,
+    Code = 0x03,
+    CodeData = 0x04,
+    Normal = 0x05,
+    Shared = 0x06,
+    AliasCode = 0x08,
+    AliasCodeData = 0x09,
+    Ipc = 0x0A,
+    Stack = 0x0B,
+    ThreadLocal = 0x0C,
+    Transfered = 0x0D,
+    SharedTransfered = 0x0E,
+    SharedCode = 0x0F,
+    Inaccessible = 0x10,
+    NonSecureIpc = 0x11,
+    NonDeviceIpc = 0x12,
+    Kernel = 0x13,
+    GeneratedCode = 0x14,
+    CodeOut = 0x15,
+}
+
+impl MemoryState {
+    /// Source states accepted by `svcMapMemory` (`FlagCanAlias`)
+    pub fn can_alias(self) -> bool {
+        matches!(self, Self::Normal | Self::CodeData | Self::AliasCodeData)
+    }
+
+    /// States whose attributes `svcSetMemoryAttribute` may change
+    pub fn can_change_attribute(self) -> bool {
+        matches!(self, Self::Normal | Self::CodeData | Self::AliasCodeData)
+    }
+}
+
+/// User-visible page permissions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryPermission(pub u32);
+
+impl MemoryPermission {
+    pub const NONE: Self = Self(0);
+    pub const READ: Self = Self(1);
+    pub const WRITE: Self = Self(2);
+    pub const EXECUTE: Self = Self(4);
+    pub const READ_WRITE: Self = Self(3);
+    pub const READ_EXECUTE: Self = Self(5);
+}
+
+/// Page attribute bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAttribute(pub u32);
+
+impl MemoryAttribute {
+    pub const NONE: Self = Self(0);
+    pub const LOCKED: Self = Self(1 << 0);
+    pub const IPC_LOCKED: Self = Self(1 << 1);
+    pub const DEVICE_SHARED: Self = Self(1 << 2);
+    pub const UNCACHED: Self = Self(1 << 3);
+
+    /// Bits a guest may touch through `svcSetMemoryAttribute`
+    pub const USER_CHANGEABLE: u32 = Self::UNCACHED.0;
+}
+
+/// A run of pages sharing the same state, permission and attribute
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBlock {
+    pub size: u64,
+    pub state: MemoryState,
+    pub permission: MemoryPermission,
+    pub attribute: MemoryAttribute,
+}
+
+impl MemoryBlock {
+    fn same_properties(&self, other: &Self) -> bool {
+        self.state == other.state
+            && self.permission == other.permission
+            && self.attribute == other.attribute
+    }
+}
+
+/// `svc::MemoryInfo`, the structure written by `svcQueryMemory`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryInfo {
+    pub base_address: u64,
+    pub size: u64,
+    pub state: MemoryState,
+    pub attribute: MemoryAttribute,
+    pub permission: MemoryPermission,
+    pub ipc_count: u32,
+    pub device_count: u32,
+}
+
+impl MemoryInfo {
+    pub const SIZE: usize = 0x28;
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0x00..0x08].copy_from_slice(&self.base_address.to_le_bytes());
+        out[0x08..0x10].copy_from_slice(&self.size.to_le_bytes());
+        out[0x10..0x14].copy_from_slice(&(self.state as u32).to_le_bytes());
+        out[0x14..0x18].copy_from_slice(&self.attribute.0.to_le_bytes());
+        out[0x18..0x1C].copy_from_slice(&self.permission.0.to_le_bytes());
+        out[0x1C..0x20].copy_from_slice(&self.ipc_count.to_le_bytes());
+        out[0x20..0x24].copy_from_slice(&self.device_count.to_le_bytes());
+        out
+    }
+}
+
+/// A `[base, base + size)` span of the address space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub base: u64,
+    pub size: u64,
+}
+
+impl Region {
+    pub const fn new(base: u64, size: u64) -> Self {
+        Self { base, size }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.base + self.size
+    }
+
+    /// Whether `[addr, addr + size)` fits inside this region
+    pub fn contains(&self, addr: u64, size: u64) -> bool {
+        addr >= self.base && addr.checked_add(size).is_some_and(|end| end <= self.end())
+    }
+}
+
+/// Address space width a process asks for in its metadata
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpaceType {
+    Bits32 = 0,
+    Bits36 = 1,
+    Bits32NoReserved = 2,
+    Bits39 = 3,
+}
+
+impl AddressSpaceType {
+    pub fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Bits32),
+            1 => Some(Self::Bits36),
+            2 => Some(Self::Bits32NoReserved),
+            3 => Some(Self::Bits39),
+            _ => None,
+        }
+    }
+}
+
+/// Where each kind of mapping lives in the guest address space
+///
+/// Everything has to fit inside the emulated RAM because it is identity
+/// mapped, so the layouts are scaled-down versions of the real ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressSpaceLayout {
+    pub address_space: Region,
+    pub code: Region,
+    pub alias: Region,
+    pub heap: Region,
+    pub stack: Region,
+    pub kernel_map: Region,
+}
+
+impl AddressSpaceLayout {
+    /// Layout of a 39-bit process
+    pub fn new(memory_size: u64) -> Self {
+        let address_space = Region::new(0x0800_0000, memory_size - 0x0800_0000);
+        let code = Region::new(0x0800_0000, 0x3800_0000);
+        let alias = Region::new(0x4000_0000, 0x4000_0000);
+        let heap = Region::new(0x8000_0000, 0x1_0000_0000);
+        let stack = Region::new(0x1_8000_0000, 0x4000_0000);
+        let kernel_map = Region::new(stack.end(), address_space.end() - stack.end());
+        Self {
+            address_space,
+            code,
+            alias,
+            heap,
+            stack,
+            kernel_map,
+        }
+    }
+
+    /// Layout of a process of type `kind`; 32-bit processes cannot run on
+    /// the AArch64 cores
+    pub fn for_type(kind: AddressSpaceType, memory_size: u64) -> Option<Self> {
+        match kind {
+            AddressSpaceType::Bits39 => Some(Self::new(memory_size)),
+            // The code region is larger, with the heap and alias regions
+            // after it
+            AddressSpaceType::Bits36 => {
+                let address_space = Region::new(0x0800_0000, memory_size - 0x0800_0000);
+                let code = Region::new(0x0800_0000, 0x7800_0000);
+                let heap = Region::new(0x8000_0000, 0x8000_0000);
+                let alias = Region::new(0x1_0000_0000, 0x8000_0000);
+                let stack = Region::new(0x1_8000_0000, 0x4000_0000);
+                let kernel_map = Region::new(stack.end(), address_space.end() - stack.end());
+                Some(Self {
+                    address_space,
+                    code,
+                    alias,
+                    heap,
+                    stack,
+                    kernel_map,
+                })
+            }
+            AddressSpaceType::Bits32 | AddressSpaceType::Bits32NoReserved => None,
+        }
+    }
+}
+
+/// The page-state map of one process
+pub struct AddressSpace {
+    memory: GuestMemory,
+    pub layout: AddressSpaceLayout,
+    blocks: BTreeMap<u64, MemoryBlock>,
+    heap_size: u64,
+}
+
+impl AddressSpace {
+    pub fn new(memory: GuestMemory) -> Self {
+        Self::with_layout(memory, AddressSpaceLayout::new(memory.size()))
+    }
+
+    pub fn with_layout(memory: GuestMemory, layout: AddressSpaceLayout) -> Self {
+        let mut blocks = BTreeMap::new();
+        blocks.insert(
+            0,
+            MemoryBlock {
+                size: layout.address_space.end(),
+                state: MemoryState::Free,
+                permission: MemoryPermission::NONE,
+                attribute: MemoryAttribute::NONE,
+            },
+        );
+        Self {
+            memory,
+            layout,
+            blocks,
+            heap_size: 0,
+        }
+    }
+
+    pub fn memory(&self) -> GuestMemory {
+        self.memory
+    }
+
+    pub fn heap_size(&self) -> u64 {
+        self.heap_size
+    }
+
+    /// Describe the block containing `addr`
+    pub fn query(&self, addr: u64) -> MemoryInfo {
+        let end = self.layout.address_space.end();
+        if addr >= end {
+            return MemoryInfo {
+                base_address: end,
+                size: end.wrapping_neg(),
+                state: MemoryState::Inaccessible,
+                attribute: MemoryAttribute::NONE,
+                permission: MemoryPermission::NONE,
+                ipc_count: 0,
+                device_count: 0,
+            };
+        }
+
+        let (&base, block) = self.blocks.range(..=addr).next_back().unwrap();
+        MemoryInfo {
+            base_address: base,
+            size: block.size,
+            state: block.state,
+            attribute: block.attribute,
+            permission: block.permission,
+            ipc_count: 0,
+            device_count: 0,
+        }
+    }
+
+    /// Bytes mapped in states that take up memory of their own, rather than
+    /// mirroring other pages or belonging to the kernel
+    pub fn allocated_size(&self) -> u64 {
+        self.blocks
+            .values()
+            .filter(|b| {
+                matches!(
+                    b.state,
+                    MemoryState::Code
+                        | MemoryState::CodeData
+                        | MemoryState::Normal
+                        | MemoryState::ThreadLocal
+                )
+            })
+            .map(|b| b.size)
+            .sum()
+    }
+
+    /// Check that `[addr, addr + size)` is one uniform run of pages accepted
+    /// by `accept`, returning its properties
+    pub fn check_range(
+        &self,
+        addr: u64,
+        size: u64,
+        accept: impl Fn(&MemoryBlock) -> bool,
+    ) -> Result<MemoryBlock, ResultCode> {
+        if !self.layout.address_space.contains(addr, size) {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+
+        let mut first: Option<MemoryBlock> = None;
+        let mut cursor = addr;
+        while cursor < addr + size {
+            let info = self.query(cursor);
+            let block = MemoryBlock {
+                size,
+                state: info.state,
+                permission: info.permission,
+                attribute: info.attribute,
+            };
+            match &first {
+                Some(f) if !f.same_properties(&block) => {
+                    return Err(result::INVALID_CURRENT_MEMORY);
+                }
+                Some(_) => {}
+                None => {
+                    if !accept(&block) {
+                        return Err(result::INVALID_CURRENT_MEMORY);
+                    }
+                    first = Some(block);
+                }
+            }
+            cursor = info.base_address + info.size;
+        }
+        first.ok_or(result::INVALID_CURRENT_MEMORY)
+    }
+
+    /// Whether the guest may read every byte of `[addr, addr + size)`, used
+    /// to validate input pointers passed to SVCs
+    pub fn is_readable(&self, addr: u64, size: u64) -> bool {
+        self.has_permission(addr, size, MemoryPermission::READ)
+    }
+
+    /// Whether the guest may write to every byte of `[addr, addr + size)`,
+    /// used to validate output pointers passed to SVCs
+    pub fn is_writable(&self, addr: u64, size: u64) -> bool {
+        self.has_permission(addr, size, MemoryPermission::WRITE)
+    }
+
+    fn has_permission(&self, addr: u64, size: u64, permission: MemoryPermission) -> bool {
+        if !self.layout.address_space.contains(addr, size) {
+            return false;
+        }
+        let mut cursor = addr;
+        while cursor < addr + size {
+            let info = self.query(cursor);
+            if info.permission.0 & permission.0 == 0 {
+                return false;
+            }
+            cursor = info.base_address + info.size;
+        }
+        true
+    }
+
+    /// Give `[addr, addr + size)` new properties, splitting and merging
+    /// neighbouring blocks as needed
+    pub fn update(
+        &mut self,
+        addr: u64,
+        size: u64,
+        state: MemoryState,
+        permission: MemoryPermission,
+        attribute: MemoryAttribute,
+    ) {
+        let end = addr + size;
+        self.split_at(addr);
+        self.split_at(end);
+
+        let covered: Vec<u64> = self.blocks.range(addr..end).map(|(&b, _)| b).collect();
+        for base in covered {
+            self.blocks.remove(&base);
+        }
+        self.blocks.insert(
+            addr,
+            MemoryBlock {
+                size,
+                state,
+                permission,
+                attribute,
+            },
+        );
+
+        self.merge_at(end);
+        self.merge_at(addr);
+    }
+
+    /// Map a fresh range of `Free` pages, e.g. for loaded code or TLS
+    pub fn map(
+        &mut self,
+        addr: u64,
+        size: u64,
+        state: MemoryState,
+        permission: MemoryPermission,
+    ) -> Result<(), ResultCode> {
+        if !addr.is_multiple_of(PAGE_SIZE) {
+            return Err(result::INVALID_ADDRESS);
+        }
+        if size == 0 || !size.is_multiple_of(PAGE_SIZE) {
+            return Err(result::INVALID_SIZE);
+        }
+        self.check_range(addr, size, |b| b.state == MemoryState::Free)?;
+        self.update(addr, size, state, permission, MemoryAttribute::NONE);
+        Ok(())
+    }
+
+    /// Return a range to `Free`, zeroing its contents
+    pub fn unmap(&mut self, addr: u64, size: u64) {
+        self.memory.fill(addr, size, 0);
+        self.update(
+            addr,
+            size,
+            MemoryState::Free,
+            MemoryPermission::NONE,
+            MemoryAttribute::NONE,
+        );
+    }
+
+    /// Find a page-aligned `Free` gap of `size` bytes inside `region`
+    pub fn find_free(&self, region: Region, size: u64) -> Option<u64> {
+        let mut cursor = region.base;
+        while region.contains(cursor, size) {
+            let info = self.query(cursor);
+            let block_end = info.base_address + info.size;
+            if info.state == MemoryState::Free && block_end - cursor >= size {
+                return Some(cursor);
+            }
+            cursor = block_end;
+        }
+        None
+    }
+
+    fn split_at(&mut self, addr: u64) {
+        let Some((&base, block)) = self.blocks.range(..addr).next_back() else {
+            return;
+        };
+        let block = *block;
+        if base + block.size <= addr {
+            return;
+        }
+        self.blocks.insert(
+            base,
+            MemoryBlock {
+                size: addr - base,
+                ..block
+            },
+        );
+        self.blocks.insert(
+            addr,
+            MemoryBlock {
+                size: base + block.size - addr,
+                ..block
+            },
+        );
+    }
+
+    /// Merge the block starting at `addr` into its predecessor if they match
+    fn merge_at(&mut self, addr: u64) {
+        let Some(&next) = self.blocks.get(&addr) else {
+            return;
+        };
+        let Some((&base, prev)) = self.blocks.range(..addr).next_back() else {
+            return;
+        };
+        if base + prev.size == addr && prev.same_properties(&next) {
+            let merged = MemoryBlock {
+                size: prev.size + next.size,
+                ..*prev
+            };
+            self.blocks.remove(&addr);
+            self.blocks.insert(base, merged);
+        }
+    }
+
+    /// Check a size for [`Self::set_heap_size`] without changing the heap
+    pub fn check_heap_size(&self, size: u64) -> Result<(), ResultCode> {
+        if !size.is_multiple_of(HEAP_SIZE_ALIGNMENT) {
+            return Err(result::INVALID_SIZE);
+        }
+        if size > self.layout.heap.size {
+            return Err(result::OUT_OF_MEMORY);
+        }
+        Ok(())
+    }
+
+    /// `svcSetHeapSize`: grow or shrink the heap, returning its base
+    pub fn set_heap_size(&mut self, size: u64) -> Result<u64, ResultCode> {
+        self.check_heap_size(size)?;
+
+        let base = self.layout.heap.base;
+        let current = self.heap_size;
+        if size > current {
+            let grow = size - current;
+            self.check_range(base + current, grow, |b| b.state == MemoryState::Free)?;
+            self.memory.fill(base + current, grow, 0);
+            self.update(
+                base + current,
+                grow,
+                MemoryState::Normal,
+                MemoryPermission::READ_WRITE,
+                MemoryAttribute::NONE,
+            );
+        } else if size < current {
+            let shrink = current - size;
+            self.check_range(base + size, shrink, |b| {
+                b.state == MemoryState::Normal
+                    && b.permission == MemoryPermission::READ_WRITE
+                    && b.attribute == MemoryAttribute::NONE
+            })?;
+            self.unmap(base + size, shrink);
+        }
+
+        self.heap_size = size;
+        Ok(base)
+    }
+
+    /// `svcMapMemory`: mirror `src` at `dst` inside the stack region
+    ///
+    /// The source becomes inaccessible while mapped, so the mirror is kept
+    /// coherent by copying on map and copying back on unmap.
+    pub fn map_memory(&mut self, dst: u64, src: u64, size: u64) -> Result<(), ResultCode> {
+        self.check_alias_args(dst, src, size)?;
+
+        let src_block = self.check_range(src, size, |b| {
+            b.state.can_alias()
+                && b.permission == MemoryPermission::READ_WRITE
+                && b.attribute == MemoryAttribute::NONE
+        })?;
+        self.check_range(dst, size, |b| b.state == MemoryState::Free)?;
+
+        self.memory.copy(dst, src, size);
+        self.update(
+            src,
+            size,
+            src_block.state,
+            MemoryPermission::NONE,
+            MemoryAttribute::LOCKED,
+        );
+        self.update(
+            dst,
+            size,
+            MemoryState::Stack,
+            MemoryPermission::READ_WRITE,
+            MemoryAttribute::NONE,
+        );
+        Ok(())
+    }
+
+    /// `svcUnmapMemory`: undo a previous `svcMapMemory`
+    pub fn unmap_memory(&mut self, dst: u64, src: u64, size: u64) -> Result<(), ResultCode> {
+        self.check_alias_args(dst, src, size)?;
+
+        let src_block = self.check_range(src, size, |b| {
+            b.state.can_alias()
+                && b.permission == MemoryPermission::NONE
+                && b.attribute == MemoryAttribute::LOCKED
+        })?;
+        self.check_range(dst, size, |b| {
+            b.state == MemoryState::Stack && b.attribute == MemoryAttribute::NONE
+        })?;
+
+        self.memory.copy(src, dst, size);
+        self.unmap(dst, size);
+        self.update(
+            src,
+            size,
+            src_block.state,
+            MemoryPermission::READ_WRITE,
+            MemoryAttribute::NONE,
+        );
+        Ok(())
+    }
+
+    fn check_alias_args(&self, dst: u64, src: u64, size: u64) -> Result<(), ResultCode> {
+        if !dst.is_multiple_of(PAGE_SIZE) || !src.is_multiple_of(PAGE_SIZE) {
+            return Err(result::INVALID_ADDRESS);
+        }
+        if size == 0 || !size.is_multiple_of(PAGE_SIZE) {
+            return Err(result::INVALID_SIZE);
+        }
+        if dst.checked_add(size).is_none() {
+            return Err(result::INVALID_MEMORY_REGION);
+        }
+        if src.checked_add(size).is_none() {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        if !self.layout.address_space.contains(src, size) {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        if !self.layout.stack.contains(dst, size) {
+            return Err(result::INVALID_MEMORY_REGION);
+        }
+        Ok(())
+    }
+
+    /// `svcSetMemoryAttribute`: change the user-changeable attribute bits
+    /// selected by `mask` to `attribute`
+    pub fn set_memory_attribute(
+        &mut self,
+        addr: u64,
+        size: u64,
+        mask: u32,
+        attribute: u32,
+    ) -> Result<(), ResultCode> {
+        if !addr.is_multiple_of(PAGE_SIZE) {
+            return Err(result::INVALID_ADDRESS);
+        }
+        if size == 0 || !size.is_multiple_of(PAGE_SIZE) {
+            return Err(result::INVALID_SIZE);
+        }
+        if mask | attribute != mask || (mask | attribute) & !MemoryAttribute::USER_CHANGEABLE != 0 {
+            return Err(result::INVALID_COMBINATION);
+        }
+
+        let block = self.check_range(addr, size, |b| {
+            b.state.can_change_attribute() && b.attribute.0 & !MemoryAttribute::USER_CHANGEABLE == 0
+        })?;
+
+        let new_attribute = MemoryAttribute((block.attribute.0 & !mask) | (attribute & mask));
+        self.update(addr, size, block.state, block.permission, new_attribute);
+        Ok(())
+    }
+}
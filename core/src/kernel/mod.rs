@@ -0,0 +1,404 @@
+//! High-level emulation of the Horizon kernel
+//!
+//! Guest code runs on the Unicorn cores until it executes `SVC`; the core
+//! then halts and the call is serviced here on the host before resuming.
+//! Guest threads are multiplexed onto the cores by [`scheduler::Scheduler`],
+//! one time slice per core per round. A core is interrupted partway through
+//! its slice when the next wait deadline passes, so the thread that wakes can
+//! preempt it. Guest functions replaced by host code in [`hle`] are entered
+//! through an SVC as well, but return straight to their caller.
+
+pub mod capabilities;
+pub mod debug;
+pub mod handle;
+pub mod hipc;
+pub mod hle;
+pub mod info;
+pub mod ipc;
+pub mod memory;
+pub mod process;
+pub mod resource_limit;
+pub mod result;
+pub mod scheduler;
+pub mod shared_memory;
+pub mod svc;
+pub mod sync;
+pub mod thread;
+pub mod trace;
+
+use crate::cpu::cpu_manager::CpuManager;
+use crate::cpu::{HaltReason, InterruptReason};
+use crate::kernel::debug::Cause;
+use crate::kernel::handle::{CURRENT_THREAD, Handle, KernelObject};
+use crate::kernel::ipc::Ipc;
+use crate::kernel::process::Process;
+use crate::kernel::resource_limit::LimitableResource;
+use crate::kernel::result::ResultCode;
+use crate::kernel::scheduler::Scheduler;
+use crate::kernel::thread::{Thread, ThreadId, ThreadState};
+use crate::nn;
+use crate::sys;
+
+/// Instructions a core may run before the scheduler looks at it again
+pub const SLICE_INSTRUCTIONS: usize = 10_000;
+/// Emulated time one round of slices takes, assuming about 1 instruction/ns
+pub const SLICE_NS: u64 = 10_000;
+
+/// Why [`Kernel::run`] returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelExit {
+    /// Every started thread has exited
+    AllThreadsExited,
+    /// Threads are alive but all of them wait without a timeout
+    Deadlock,
+    /// A thread stopped for a reason the kernel does not service, e.g. `BRK`
+    Halted {
+        core: usize,
+        thread: ThreadId,
+        reason: HaltReason,
+    },
+    /// A thread used a service or command that is not implemented while
+    /// the [`Policy`](nn::unimplemented::Policy) is to halt
+    Unimplemented { core: usize, thread: ThreadId },
+    /// A thread ended the process through `svcBreak`,
+    /// `svcReturnFromException` or an SVC it may not use; the report is in
+    /// [`debug::State::last_report`]
+    Crashed { core: usize, thread: ThreadId },
+}
+
+pub struct Kernel {
+    pub cpu: CpuManager,
+    pub process: Process,
+    pub scheduler: Scheduler,
+    pub ipc: Ipc,
+    /// State of the host services, including the `sm:` registry
+    pub sys: sys::State,
+    pub debug: debug::State,
+    /// Guest functions replaced by host code
+    pub hle: hle::Replacements,
+    pub trace: trace::Tracer,
+}
+
+impl Kernel {
+    pub fn new() -> Self {
+        let cpu = CpuManager::new();
+        let process = Process::new(cpu.memory());
+        let scheduler = Scheduler::new(cpu.cores.len());
+        let trace = trace::Tracer::new(cpu.cores.len());
+        let mut kernel = Self {
+            cpu,
+            process,
+            scheduler,
+            ipc: Ipc::default(),
+            sys: sys::State::new(),
+            debug: debug::State::default(),
+            hle: hle::Replacements::default(),
+            trace,
+        };
+        kernel.register_named_port("sm:", || nn::cmif::serve(nn::sm::State::new()));
+        kernel
+    }
+
+    /// Run a core, servicing its SVCs, until it halts for any other reason
+    ///
+    /// This bypasses the scheduler and runs whatever state the core holds.
+    pub fn run_core(&mut self, core_id: usize) -> HaltReason {
+        let core = self.cpu.cores[core_id].clone();
+        loop {
+            match core.run_until_halt() {
+                HaltReason::Svc(id) if self.hle.is_trampoline(id) => {
+                    self.call_replacement(&core, id)
+                }
+                HaltReason::Svc(id) => svc::call(self, &core, id),
+                other => return other,
+            }
+        }
+    }
+
+    /// Schedule guest threads until they have all exited or one halts
+    pub fn run(&mut self) -> KernelExit {
+        loop {
+            if let Some(exit) = self.run_round() {
+                return exit;
+            }
+        }
+    }
+
+    /// Give every core one time slice
+    ///
+    /// A slice ends after [`SLICE_INSTRUCTIONS`] or at the first SVC, after
+    /// which the scheduler may switch the core to another thread.
+    pub fn run_round(&mut self) -> Option<KernelExit> {
+        self.scheduler.wake_expired();
+
+        let mut ran = vec![None; self.cpu.cores.len()];
+        for (core_id, ran) in ran.iter_mut().enumerate() {
+            match self.run_slice(core_id) {
+                Ok(thread) => *ran = thread,
+                Err(exit) => return Some(exit),
+            }
+        }
+
+        if ran.iter().any(Option::is_some) {
+            for (core_id, thread) in ran.into_iter().enumerate() {
+                if thread.is_none() {
+                    self.scheduler.charge(core_id, None, SLICE_NS);
+                }
+            }
+            self.scheduler.advance(SLICE_NS);
+            None
+        } else if !self.scheduler.has_live_threads() {
+            Some(KernelExit::AllThreadsExited)
+        } else if self.scheduler.skip_to_next_deadline() {
+            None
+        } else {
+            Some(KernelExit::Deadlock)
+        }
+    }
+
+    /// Run one core's slice, returning the thread it ended with
+    ///
+    /// The core's timer is armed for the next wait deadline; when it fires,
+    /// the threads that are due wake and the scheduler picks again for the
+    /// rest of the slice. Emulated time within a slice is counted at about
+    /// 1 instruction/ns, as [`SLICE_NS`] assumes.
+    fn run_slice(&mut self, core_id: usize) -> Result<Option<ThreadId>, KernelExit> {
+        let core = self.cpu.cores[core_id].clone();
+        let start = core.instructions();
+        let mut current = None;
+        let mut charged = 0;
+        while let Some(thread) = self.scheduler.dispatch(&core) {
+            current = Some(thread);
+            let elapsed = core.instructions() - start;
+            let time = self.scheduler.now() + elapsed;
+            self.trace.on_core(core_id, Some(thread), time);
+            self.trace.resume(thread, time);
+            if elapsed >= SLICE_INSTRUCTIONS as u64 {
+                break;
+            }
+            match self.scheduler.next_deadline() {
+                Some(deadline) => {
+                    core.set_timer(start + deadline.saturating_sub(self.scheduler.now()))
+                }
+                None => core.cancel_timer(),
+            }
+
+            let halt = core.run_for(SLICE_INSTRUCTIONS - elapsed as usize);
+            core.cancel_timer();
+            match halt {
+                HaltReason::Interrupted(interrupts) => {
+                    let offset = core.instructions() - start;
+                    self.scheduler
+                        .charge(core_id, Some(thread), offset - elapsed);
+                    charged += offset - elapsed;
+                    if interrupts.contains(InterruptReason::Timer) {
+                        self.scheduler.wake_due(self.scheduler.now() + offset);
+                    }
+                    self.scheduler.update_current(&core);
+                    continue;
+                }
+                HaltReason::Svc(id) if self.hle.is_trampoline(id) => {
+                    let offset = core.instructions() - start;
+                    self.scheduler
+                        .charge(core_id, Some(thread), offset - elapsed);
+                    charged += offset - elapsed;
+                    self.call_replacement(&core, id);
+                    // The trampoline returns to the caller, so unlike an SVC
+                    // the call does not end the slice
+                    self.scheduler.update_current(&core);
+                    continue;
+                }
+                HaltReason::Svc(id) => {
+                    let time = self.scheduler.now() + core.instructions() - start;
+                    self.trace.svc_enter(thread, id, time);
+                    if self.process.capabilities.is_svc_permitted(id) {
+                        svc::call(self, &core, id);
+                    } else {
+                        self.stop_process(core_id, thread, Cause::InvalidSystemCall(id));
+                    }
+                    self.trace_svc_exit(thread, time);
+                    if self.sys.unimplemented.take_halt() {
+                        return Err(KernelExit::Unimplemented {
+                            core: core_id,
+                            thread,
+                        });
+                    }
+                    if self.debug.take_stop() {
+                        self.scheduler.update_current(&core);
+                        return Err(KernelExit::Crashed {
+                            core: core_id,
+                            thread,
+                        });
+                    }
+                }
+                HaltReason::Stopped => {}
+                reason => {
+                    let report = self.crash_report(core_id, thread, Cause::Exception(reason));
+                    self.record_crash(report);
+                    return Err(KernelExit::Halted {
+                        core: core_id,
+                        thread,
+                        reason,
+                    });
+                }
+            }
+            break;
+        }
+        self.scheduler.update_current(&core);
+        if self.scheduler.current(core_id).is_none() {
+            let end = self.scheduler.now() + SLICE_NS;
+            self.trace.on_core(core_id, None, end);
+        }
+        if current.is_some() {
+            self.scheduler
+                .charge(core_id, current, SLICE_NS.saturating_sub(charged));
+        }
+        Ok(current)
+    }
+
+    /// End the trace span of the SVC `thread` made at `time`, unless it
+    /// blocked, in which case it lasts until the thread runs again
+    fn trace_svc_exit(&mut self, thread: ThreadId, time: u64) {
+        if !self.trace.is_enabled() {
+            return;
+        }
+        let wait = self
+            .scheduler
+            .thread(thread)
+            .filter(|t| t.state == ThreadState::Waiting)
+            .map(|t| match &t.wait {
+                Some(wait) => format!("{wait:?}"),
+                None => String::from("Sleep"),
+            });
+        self.trace.svc_exit(thread, time, wait);
+    }
+
+    /// Create a suspended thread, as `svcCreateThread` does
+    ///
+    /// `core` may be [`thread::IDEAL_CORE_USE_PROCESS_VALUE`] to use the
+    /// process' ideal core.
+    pub fn create_thread(
+        &mut self,
+        entry: u64,
+        arg: u64,
+        stack_top: u64,
+        priority: u32,
+        core: i32,
+    ) -> Result<Handle, ResultCode> {
+        let core = if core == thread::IDEAL_CORE_USE_PROCESS_VALUE {
+            self.process.ideal_core as i32
+        } else {
+            core
+        };
+        if !(0..64).contains(&core) || self.process.core_mask & (1 << core) == 0 {
+            return Err(result::INVALID_CORE_ID);
+        }
+        if !self.process.is_valid_priority(priority) {
+            return Err(result::INVALID_PRIORITY);
+        }
+
+        let limit = &mut self.process.resource_limit;
+        limit.reserve(LimitableResource::Threads, 1)?;
+        let tls = self.process.allocate_tls().inspect_err(|_| {
+            self.process
+                .resource_limit
+                .release(LimitableResource::Threads, 1);
+        })?;
+        let id = self
+            .scheduler
+            .add_thread(|id| Thread::new(id, entry, arg, stack_top, priority, core as usize, tls));
+        self.process
+            .handles
+            .add(KernelObject::Thread(id))
+            .inspect_err(|_| {
+                self.scheduler.remove_thread(id);
+                self.process.free_tls(tls);
+                let limit = &mut self.process.resource_limit;
+                limit.release(LimitableResource::Threads, 1);
+            })
+    }
+
+    /// Make a thread created by [`Self::create_thread`] runnable
+    pub fn start_thread(&mut self, handle: Handle) -> Result<(), ResultCode> {
+        let id = self.thread_from_handle(handle, None)?;
+        let thread = self.scheduler.thread(id).ok_or(result::INVALID_HANDLE)?;
+        if thread.state != ThreadState::Created {
+            return Err(result::INVALID_STATE);
+        }
+        self.scheduler.wake(id);
+        Ok(())
+    }
+
+    /// Resolve a thread handle, including the current-thread pseudo-handle
+    /// when `current` is known
+    pub fn thread_from_handle(
+        &self,
+        handle: Handle,
+        current: Option<ThreadId>,
+    ) -> Result<ThreadId, ResultCode> {
+        match (handle, current) {
+            (CURRENT_THREAD, Some(id)) => Ok(id),
+            _ => match self.process.handles.get(handle) {
+                Some(KernelObject::Thread(id)) => Ok(id),
+                _ => Err(result::INVALID_HANDLE),
+            },
+        }
+    }
+
+    /// Close a handle, destroying the object once nothing refers to it
+    pub fn close_handle(&mut self, handle: Handle) -> Result<(), ResultCode> {
+        let object = self
+            .process
+            .handles
+            .remove(handle)
+            .ok_or(result::INVALID_HANDLE)?;
+        self.release_if_unreferenced(object);
+        Ok(())
+    }
+
+    /// Destroy `object` if no handle refers to it and it is finished
+    fn release_if_unreferenced(&mut self, object: KernelObject) {
+        if self.process.handles.references(object) {
+            return;
+        }
+        match object {
+            KernelObject::Thread(id) => {
+                let finished = self.scheduler.thread(id).is_some_and(|t| {
+                    matches!(t.state, ThreadState::Terminated | ThreadState::Created)
+                });
+                if finished && let Some(thread) = self.scheduler.remove_thread(id) {
+                    self.process.free_tls(thread.tls_address);
+                    let limit = &mut self.process.resource_limit;
+                    limit.release(LimitableResource::Threads, 1);
+                }
+            }
+            KernelObject::ReadableEvent(id) | KernelObject::WritableEvent(id) => {
+                let handles = &self.process.handles;
+                if !handles.references(KernelObject::ReadableEvent(id))
+                    && !handles.references(KernelObject::WritableEvent(id))
+                {
+                    self.process.remove_event(id);
+                }
+            }
+            KernelObject::ClientSession(id) => self.close_client_session(id),
+            KernelObject::ServerSession(id) => self.close_server_session(id),
+            KernelObject::ServerPort(id) => self.close_server_port(id),
+            KernelObject::SharedMemory(id) => self.release_shared_memory(id),
+            KernelObject::TransferMemory(id) => self.release_transfer_memory(id),
+        }
+    }
+
+    /// Terminate a thread, waking threads waiting for it; it is destroyed
+    /// once its last handle is closed
+    pub fn exit_thread(&mut self, id: ThreadId) {
+        self.scheduler.terminate(id);
+        self.signal_object(KernelObject::Thread(id));
+        self.release_if_unreferenced(KernelObject::Thread(id));
+    }
+}
+
+impl Default for Kernel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
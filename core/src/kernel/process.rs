@@ -0,0 +1,407 @@
+use crate::cpu::GuestMemory;
+use crate::kernel::Kernel;
+use crate::kernel::capabilities::Capabilities;
+use crate::kernel::handle::{DEFAULT_HANDLE_TABLE_SIZE, Handle, HandleTable, KernelObject};
+use crate::kernel::memory::{
+    AddressSpace, AddressSpaceLayout, MemoryPermission, MemoryState, PAGE_SIZE,
+};
+use crate::kernel::resource_limit::{LimitableResource, ResourceLimit};
+use crate::kernel::result::{self, ResultCode};
+use crate::kernel::shared_memory::{
+    SharedMemory, SharedMemoryId, TransferMemory, TransferMemoryId,
+};
+use crate::kernel::sync::{Event, EventId};
+use crate::kernel::thread::{IDEAL_CORE_USE_PROCESS_VALUE, TLS_SIZE};
+use crate::loader::npdm::Npdm;
+use std::collections::BTreeMap;
+
+const TLS_SLOTS_PER_PAGE: usize = (PAGE_SIZE / TLS_SIZE) as usize;
+
+/// Process ID Horizon gives the first process started after boot, which is
+/// the application
+pub const APPLICATION_PROCESS_ID: u64 = 0x51;
+
+/// The Switch exposes four CPU cores to applications
+pub const USER_CORE_COUNT: usize = 4;
+
+/// Main thread settings of a process created without metadata
+const DEFAULT_MAIN_THREAD_PRIORITY: u32 = 44;
+const DEFAULT_MAIN_THREAD_STACK_SIZE: u64 = 0x10_0000;
+
+/// An executable image loaded into the process
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Module {
+    pub name: String,
+    pub base: u64,
+    pub size: u64,
+    /// The build the module came from, which patches and cheats are keyed on
+    pub build_id: [u8; 0x20],
+    /// Dynamic symbols the module exports, as offsets from `base`
+    pub symbols: BTreeMap<String, u64>,
+}
+
+impl Module {
+    pub fn contains(&self, addr: u64) -> bool {
+        addr.wrapping_sub(self.base) < self.size
+    }
+
+    /// The symbol `addr` falls in, taken to be the closest one at or below
+    /// it, and how far into it `addr` is
+    pub fn symbol_at(&self, addr: u64) -> Option<(&str, u64)> {
+        if !self.contains(addr) {
+            return None;
+        }
+        let offset = addr - self.base;
+        self.symbols
+            .iter()
+            .filter(|&(_, &start)| start <= offset)
+            .max_by_key(|&(_, &start)| start)
+            .map(|(name, &start)| (name.as_str(), offset - start))
+    }
+}
+
+/// A guest process and the kernel objects it owns
+pub struct Process {
+    pub id: u64,
+    pub address_space: AddressSpace,
+    pub handles: HandleTable,
+    /// Core new threads default to
+    pub ideal_core: u32,
+    /// Cores the process' threads may run on
+    pub core_mask: u64,
+    /// Priorities the process' threads may use, one bit per level
+    pub priority_mask: u64,
+    /// Title the process was loaded from
+    pub program_id: u64,
+    /// Memory the kernel reserves for the process' own page tables and
+    /// objects, out of its total
+    pub system_resource_size: u64,
+    /// Seed values `svcGetInfo` hands out for the process' RNG
+    pub random_entropy: [u64; 4],
+    /// Where the kernel saves the context of a user exception handler
+    pub user_exception_context: u64,
+    /// Images the loader placed in the address space, by base address
+    pub modules: Vec<Module>,
+    pub capabilities: Capabilities,
+    pub resource_limit: ResourceLimit,
+    pub main_thread_priority: u32,
+    pub main_thread_stack_size: u64,
+    /// Where `svcQueryMemoryMapping` mapped each physical memory range, by
+    /// physical address
+    pub io_mappings: BTreeMap<u64, u64>,
+    pub events: BTreeMap<EventId, Event>,
+    next_event_id: EventId,
+    pub shared_memory: BTreeMap<SharedMemoryId, SharedMemory>,
+    pub transfer_memory: BTreeMap<TransferMemoryId, TransferMemory>,
+    next_memory_id: u64,
+    tls_pages: Vec<(u64, [bool; TLS_SLOTS_PER_PAGE])>,
+}
+
+impl Process {
+    /// A process allowed everything, with no limits
+    pub fn new(memory: GuestMemory) -> Self {
+        Self::with_address_space(AddressSpace::new(memory))
+    }
+
+    /// A process set up as `npdm` declares, as the loader creates it
+    pub fn from_metadata(memory: GuestMemory, npdm: &Npdm) -> Result<Self, ResultCode> {
+        let layout = AddressSpaceLayout::for_type(npdm.address_space_type, memory.size())
+            .filter(|_| npdm.is_64bit)
+            .ok_or(result::NOT_SUPPORTED)?;
+        let capabilities = npdm.capabilities(USER_CORE_COUNT)?;
+        let priority = npdm.main_thread_priority as u32;
+        if priority >= 64 || capabilities.priority_mask & (1 << priority) == 0 {
+            return Err(result::INVALID_PRIORITY);
+        }
+        let core = npdm.main_thread_core as u32;
+        if core >= 64 || capabilities.core_mask & (1 << core) == 0 {
+            return Err(result::INVALID_CORE_ID);
+        }
+        let stack_size = (npdm.main_thread_stack_size as u64).next_multiple_of(PAGE_SIZE);
+        if stack_size == 0 {
+            return Err(result::INVALID_SIZE);
+        }
+
+        let mut process = Self::with_address_space(AddressSpace::with_layout(memory, layout));
+        // A size of 0 asks for the largest table
+        let handle_table_size = capabilities.handle_table_size.filter(|&size| size != 0);
+        process.handles = HandleTable::new(handle_table_size.unwrap_or(DEFAULT_HANDLE_TABLE_SIZE));
+        process.ideal_core = core;
+        process.core_mask = capabilities.core_mask;
+        process.priority_mask = capabilities.priority_mask;
+        process.program_id = npdm.aci.program_id;
+        process.system_resource_size = npdm.system_resource_size as u64;
+        process.main_thread_priority = priority;
+        process.main_thread_stack_size = stack_size;
+        process.capabilities = capabilities;
+        process.resource_limit = ResourceLimit::application(process.total_memory_size());
+        Ok(process)
+    }
+
+    fn with_address_space(address_space: AddressSpace) -> Self {
+        let mut process = Self {
+            id: APPLICATION_PROCESS_ID,
+            address_space,
+            handles: HandleTable::new(DEFAULT_HANDLE_TABLE_SIZE),
+            ideal_core: 0,
+            core_mask: (1 << USER_CORE_COUNT) - 1,
+            priority_mask: u64::MAX,
+            program_id: 0,
+            system_resource_size: 0,
+            random_entropy: std::array::from_fn(|_| random_u64()),
+            user_exception_context: 0,
+            modules: Vec::new(),
+            capabilities: Capabilities::all(USER_CORE_COUNT),
+            resource_limit: ResourceLimit::unlimited(),
+            main_thread_priority: DEFAULT_MAIN_THREAD_PRIORITY,
+            main_thread_stack_size: DEFAULT_MAIN_THREAD_STACK_SIZE,
+            io_mappings: BTreeMap::new(),
+            events: BTreeMap::new(),
+            next_event_id: 1,
+            shared_memory: BTreeMap::new(),
+            transfer_memory: BTreeMap::new(),
+            next_memory_id: 1,
+            tls_pages: Vec::new(),
+        };
+        process.user_exception_context = process
+            .allocate_tls()
+            .expect("a new address space has room for TLS");
+        process
+    }
+
+    pub fn is_valid_priority(&self, priority: u32) -> bool {
+        priority < 64 && self.priority_mask & (1 << priority) != 0
+    }
+
+    /// Memory available to the process: all of the address space, since it
+    /// is identity mapped onto RAM
+    pub fn total_memory_size(&self) -> u64 {
+        self.address_space.layout.address_space.size
+    }
+
+    /// Memory the process has allocated: its code, data, heap and TLS, plus
+    /// shared memory it created
+    pub fn used_memory_size(&self) -> u64 {
+        let shared: u64 = self
+            .shared_memory
+            .values()
+            .filter(|s| !s.host_owned)
+            .map(|s| s.size)
+            .sum();
+        self.address_space.allocated_size() + shared
+    }
+
+    /// `svcSetHeapSize`, counting the heap against the resource limit
+    pub fn set_heap_size(&mut self, size: u64) -> Result<u64, ResultCode> {
+        self.address_space.check_heap_size(size)?;
+        let current = self.address_space.heap_size();
+        let limit = &mut self.resource_limit;
+        if size > current {
+            limit.reserve(LimitableResource::PhysicalMemory, size - current)?;
+        }
+        match self.address_space.set_heap_size(size) {
+            Ok(base) => {
+                if size < current {
+                    limit.release(LimitableResource::PhysicalMemory, current - size);
+                }
+                Ok(base)
+            }
+            Err(err) => {
+                if size > current {
+                    limit.release(LimitableResource::PhysicalMemory, size - current);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Module containing `addr`, if any
+    pub fn module_at(&self, addr: u64) -> Option<&Module> {
+        self.modules.iter().find(|m| m.contains(addr))
+    }
+
+    /// Address of the dynamic symbol `name`, from the first module loaded
+    /// that exports it
+    pub fn symbol_address(&self, name: &str) -> Option<u64> {
+        self.modules
+            .iter()
+            .find_map(|m| m.symbols.get(name).map(|offset| m.base + offset))
+    }
+
+    /// `log` target for output from the process, e.g. `guest::0100000000001000`
+    pub fn log_target(&self) -> String {
+        format!("guest::{:016x}", self.program_id)
+    }
+
+    /// Create an unsignaled event
+    pub fn add_event(&mut self) -> EventId {
+        let id = self.next_event_id;
+        self.next_event_id += 1;
+        self.events.insert(id, Event::default());
+        id
+    }
+
+    /// Destroy an event created by [`Self::add_event`]
+    pub fn remove_event(&mut self, id: EventId) {
+        if self.events.remove(&id).is_some() {
+            self.resource_limit.release(LimitableResource::Events, 1);
+        }
+    }
+
+    pub fn add_shared_memory(&mut self, shared: SharedMemory) -> SharedMemoryId {
+        let id = self.next_memory_id;
+        self.next_memory_id += 1;
+        self.shared_memory.insert(id, shared);
+        id
+    }
+
+    pub fn add_transfer_memory(&mut self, transfer: TransferMemory) -> TransferMemoryId {
+        let id = self.next_memory_id;
+        self.next_memory_id += 1;
+        self.transfer_memory.insert(id, transfer);
+        id
+    }
+
+    /// Reserve a zeroed thread-local region, mapping a new TLS page when the
+    /// existing ones are full
+    pub fn allocate_tls(&mut self) -> Result<u64, ResultCode> {
+        for (page, used) in &mut self.tls_pages {
+            if let Some(slot) = used.iter().position(|&u| !u) {
+                used[slot] = true;
+                return Ok(*page + slot as u64 * TLS_SIZE);
+            }
+        }
+
+        let region = self.address_space.layout.kernel_map;
+        let page = self
+            .address_space
+            .find_free(region, PAGE_SIZE)
+            .ok_or(result::OUT_OF_MEMORY)?;
+        self.address_space.map(
+            page,
+            PAGE_SIZE,
+            MemoryState::ThreadLocal,
+            MemoryPermission::READ_WRITE,
+        )?;
+        let mut used = [false; TLS_SLOTS_PER_PAGE];
+        used[0] = true;
+        self.tls_pages.push((page, used));
+        Ok(page)
+    }
+
+    /// Release a region returned by [`Self::allocate_tls`]
+    pub fn free_tls(&mut self, address: u64) {
+        let page = address & !(PAGE_SIZE - 1);
+        if let Some((_, used)) = self.tls_pages.iter_mut().find(|(p, _)| *p == page) {
+            used[((address - page) / TLS_SIZE) as usize] = false;
+            self.address_space.memory().fill(address, TLS_SIZE, 0);
+        }
+    }
+}
+
+impl Kernel {
+    /// Replace the process with one created from `npdm`, before any thread
+    /// has been created
+    pub fn create_process(&mut self, npdm: &Npdm) -> Result<(), ResultCode> {
+        if self.scheduler.threads().next().is_some() {
+            return Err(result::INVALID_STATE);
+        }
+        self.process = Process::from_metadata(self.cpu.memory(), npdm)?;
+        Ok(())
+    }
+
+    /// Map the main thread's stack and start it at `entry`, with X0 = 0 and
+    /// X1 = its own handle as the loader passes them
+    pub fn start_main_thread(&mut self, entry: u64) -> Result<Handle, ResultCode> {
+        let size = self.process.main_thread_stack_size;
+        let space = &mut self.process.address_space;
+        let stack = space
+            .find_free(space.layout.stack, size)
+            .ok_or(result::OUT_OF_MEMORY)?;
+        space.map(
+            stack,
+            size,
+            MemoryState::Stack,
+            MemoryPermission::READ_WRITE,
+        )?;
+
+        let priority = self.process.main_thread_priority;
+        let handle = self.create_thread(
+            entry,
+            0,
+            stack + size,
+            priority,
+            IDEAL_CORE_USE_PROCESS_VALUE,
+        )?;
+        let id = self.thread_from_handle(handle, None)?;
+        self.scheduler.thread_mut(id).unwrap().context.x[1] = handle as u64;
+        self.start_thread(handle)?;
+        Ok(handle)
+    }
+
+    /// `svcQueryMemoryMapping`: the address of physical memory the process'
+    /// capabilities grant, mapping the whole granted range on first use
+    ///
+    /// Nothing emulates the devices behind such ranges yet, so they are
+    /// backed by plain RAM.
+    pub fn query_memory_mapping(&mut self, physical: u64, size: u64) -> Result<u64, ResultCode> {
+        if size == 0 {
+            return Err(result::INVALID_SIZE);
+        }
+        let process = &mut self.process;
+        let map = *process
+            .capabilities
+            .memory_map(physical, size)
+            .ok_or(result::NOT_FOUND)?;
+        let offset = physical - map.address;
+        if let Some(&virt) = process.io_mappings.get(&map.address) {
+            return Ok(virt + offset);
+        }
+
+        let space = &mut process.address_space;
+        let virt = space
+            .find_free(space.layout.kernel_map, map.size)
+            .ok_or(result::OUT_OF_ADDRESS_SPACE)?;
+        let state = if map.normal {
+            MemoryState::Static
+        } else {
+            MemoryState::Io
+        };
+        let permission = if map.read_only {
+            MemoryPermission::READ
+        } else {
+            MemoryPermission::READ_WRITE
+        };
+        space.map(virt, map.size, state, permission)?;
+        process.io_mappings.insert(map.address, virt);
+        Ok(virt + offset)
+    }
+
+    /// `svcCreateInterruptEvent` for an interrupt the capabilities grant
+    ///
+    /// No device raises interrupts yet, so the event is never signaled.
+    pub fn create_interrupt_event(&mut self, irq: u32, kind: u32) -> Result<Handle, ResultCode> {
+        // Level or edge triggered
+        if kind > 1 {
+            return Err(result::INVALID_ENUM_VALUE);
+        }
+        if !self.process.capabilities.is_interrupt_permitted(irq) {
+            return Err(result::NOT_FOUND);
+        }
+        let limit = &mut self.process.resource_limit;
+        limit.reserve(LimitableResource::Events, 1)?;
+        let id = self.process.add_event();
+        self.process
+            .handles
+            .add(KernelObject::ReadableEvent(id))
+            .inspect_err(|_| self.process.remove_event(id))
+    }
+}
+
+/// A random value from the randomly keyed hasher std seeds per process
+fn random_u64() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish()
+}
@@ -0,0 +1,94 @@
+//! Per-process resource limits
+//!
+//! Horizon counts a process' memory and kernel objects against a resource
+//! limit and fails creation with `LimitReached` once one would be exceeded.
+//! Memory, threads and events are counted here.
+
+use crate::kernel::result::{self, ResultCode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitableResource {
+    PhysicalMemory = 0,
+    Threads = 1,
+    Events = 2,
+    TransferMemories = 3,
+    Sessions = 4,
+}
+
+const RESOURCE_COUNT: usize = 5;
+
+/// Thread, event, transfer memory and session counts pm gives applications
+pub const APPLICATION_LIMITS: [u64; 4] = [96, 64, 32, 64];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceLimit {
+    limits: [u64; RESOURCE_COUNT],
+    current: [u64; RESOURCE_COUNT],
+    peak: [u64; RESOURCE_COUNT],
+}
+
+impl ResourceLimit {
+    /// A limit nothing reaches
+    pub fn unlimited() -> Self {
+        Self {
+            limits: [u64::MAX; RESOURCE_COUNT],
+            current: [0; RESOURCE_COUNT],
+            peak: [0; RESOURCE_COUNT],
+        }
+    }
+
+    /// The limit of an application allowed `memory` bytes
+    pub fn application(memory: u64) -> Self {
+        let [threads, events, transfer_memories, sessions] = APPLICATION_LIMITS;
+        Self {
+            limits: [memory, threads, events, transfer_memories, sessions],
+            ..Self::unlimited()
+        }
+    }
+
+    pub fn limit(&self, resource: LimitableResource) -> u64 {
+        self.limits[resource as usize]
+    }
+
+    pub fn current(&self, resource: LimitableResource) -> u64 {
+        self.current[resource as usize]
+    }
+
+    /// How much more of `resource` can be reserved
+    pub fn free(&self, resource: LimitableResource) -> u64 {
+        let i = resource as usize;
+        self.limits[i] - self.current[i]
+    }
+
+    /// Highest value [`Self::current`] has had
+    pub fn peak(&self, resource: LimitableResource) -> u64 {
+        self.peak[resource as usize]
+    }
+
+    /// Change a limit; it may not drop below what is in use
+    pub fn set_limit(&mut self, resource: LimitableResource, value: u64) -> Result<(), ResultCode> {
+        let i = resource as usize;
+        if value < self.current[i] {
+            return Err(result::INVALID_STATE);
+        }
+        self.limits[i] = value;
+        Ok(())
+    }
+
+    /// Count `amount` more of `resource` as in use
+    pub fn reserve(&mut self, resource: LimitableResource, amount: u64) -> Result<(), ResultCode> {
+        let i = resource as usize;
+        let total = self.current[i]
+            .checked_add(amount)
+            .filter(|&total| total <= self.limits[i])
+            .ok_or(result::LIMIT_REACHED)?;
+        self.current[i] = total;
+        self.peak[i] = self.peak[i].max(total);
+        Ok(())
+    }
+
+    pub fn release(&mut self, resource: LimitableResource, amount: u64) {
+        let i = resource as usize;
+        self.current[i] = self.current[i].saturating_sub(amount);
+    }
+}
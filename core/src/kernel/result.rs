@@ -0,0 +1,67 @@
+use std::fmt;
+
+/// A Horizon result code, packed as `module | (description << 9)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResultCode(pub u32);
+
+impl ResultCode {
+    pub const SUCCESS: Self = Self(0);
+
+    pub const fn new(module: u32, description: u32) -> Self {
+        Self((module & 0x1FF) | ((description & 0x1FFF) << 9))
+    }
+
+    pub const fn module(self) -> u32 {
+        self.0 & 0x1FF
+    }
+
+    pub const fn description(self) -> u32 {
+        (self.0 >> 9) & 0x1FFF
+    }
+
+    pub const fn is_success(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl fmt::Display for ResultCode {
+    /// Formats as the `2xxx-yyyy` error code shown by the system
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:04}", 2000 + self.module(), self.description())
+    }
+}
+
+pub const MODULE_KERNEL: u32 = 1;
+
+pub const OUT_OF_SESSIONS: ResultCode = ResultCode::new(MODULE_KERNEL, 7);
+pub const INVALID_ARGUMENT: ResultCode = ResultCode::new(MODULE_KERNEL, 14);
+pub const NOT_IMPLEMENTED: ResultCode = ResultCode::new(MODULE_KERNEL, 33);
+pub const INVALID_SIZE: ResultCode = ResultCode::new(MODULE_KERNEL, 101);
+pub const INVALID_ADDRESS: ResultCode = ResultCode::new(MODULE_KERNEL, 102);
+pub const OUT_OF_RESOURCE: ResultCode = ResultCode::new(MODULE_KERNEL, 103);
+pub const OUT_OF_MEMORY: ResultCode = ResultCode::new(MODULE_KERNEL, 104);
+pub const OUT_OF_HANDLES: ResultCode = ResultCode::new(MODULE_KERNEL, 105);
+pub const INVALID_CURRENT_MEMORY: ResultCode = ResultCode::new(MODULE_KERNEL, 106);
+pub const INVALID_NEW_MEMORY_PERMISSION: ResultCode = ResultCode::new(MODULE_KERNEL, 108);
+pub const INVALID_MEMORY_REGION: ResultCode = ResultCode::new(MODULE_KERNEL, 110);
+pub const INVALID_PRIORITY: ResultCode = ResultCode::new(MODULE_KERNEL, 112);
+pub const INVALID_CORE_ID: ResultCode = ResultCode::new(MODULE_KERNEL, 113);
+pub const INVALID_HANDLE: ResultCode = ResultCode::new(MODULE_KERNEL, 114);
+pub const INVALID_POINTER: ResultCode = ResultCode::new(MODULE_KERNEL, 115);
+pub const INVALID_COMBINATION: ResultCode = ResultCode::new(MODULE_KERNEL, 116);
+pub const TIMED_OUT: ResultCode = ResultCode::new(MODULE_KERNEL, 117);
+pub const CANCELLED: ResultCode = ResultCode::new(MODULE_KERNEL, 118);
+pub const OUT_OF_RANGE: ResultCode = ResultCode::new(MODULE_KERNEL, 119);
+pub const INVALID_ENUM_VALUE: ResultCode = ResultCode::new(MODULE_KERNEL, 120);
+pub const NOT_FOUND: ResultCode = ResultCode::new(MODULE_KERNEL, 121);
+pub const BUSY: ResultCode = ResultCode::new(MODULE_KERNEL, 122);
+pub const SESSION_CLOSED: ResultCode = ResultCode::new(MODULE_KERNEL, 123);
+pub const INVALID_STATE: ResultCode = ResultCode::new(MODULE_KERNEL, 125);
+pub const NOT_SUPPORTED: ResultCode = ResultCode::new(MODULE_KERNEL, 127);
+pub const PORT_CLOSED: ResultCode = ResultCode::new(MODULE_KERNEL, 131);
+pub const LIMIT_REACHED: ResultCode = ResultCode::new(MODULE_KERNEL, 132);
+pub const OUT_OF_ADDRESS_SPACE: ResultCode = ResultCode::new(MODULE_KERNEL, 259);
+pub const MESSAGE_TOO_LARGE: ResultCode = ResultCode::new(MODULE_KERNEL, 260);
+pub const INVALID_PROCESS_ID: ResultCode = ResultCode::new(MODULE_KERNEL, 517);
+pub const INVALID_THREAD_ID: ResultCode = ResultCode::new(MODULE_KERNEL, 518);
+pub const PROCESS_TERMINATED: ResultCode = ResultCode::new(MODULE_KERNEL, 520);
@@ -0,0 +1,371 @@
+//! Priority scheduler mapping guest threads onto the emulated cores
+//!
+//! Every runnable thread that is not currently on a core sits in one FIFO
+//! queue per priority level. A core runs the highest-priority thread whose
+//! active core it is; a running thread keeps its core until it blocks,
+//! yields or a strictly higher-priority thread becomes runnable for that
+//! core, in which case it is preempted back to the front of its queue. A core
+//! with nothing of its own to run takes a suggested thread from a busy core
+//! if the thread's affinity mask allows it.
+//!
+//! Time is emulated: the kernel advances [`Scheduler::now`] as slices run
+//! and skips straight to the next deadline when every core is idle, so
+//! sleeps and timeouts are deterministic.
+
+use crate::cpu::UnicornCPU;
+use crate::kernel::sync::Wait;
+use crate::kernel::thread::{PRIORITY_COUNT, Thread, ThreadId, ThreadState, YieldKind};
+use std::collections::{BTreeMap, VecDeque};
+
+pub struct Scheduler {
+    threads: BTreeMap<ThreadId, Thread>,
+    queues: [VecDeque<ThreadId>; PRIORITY_COUNT],
+    /// Thread currently loaded on each core
+    running: Vec<Option<ThreadId>>,
+    /// Yield requested by the running thread of each core
+    pending_yield: Vec<Option<YieldKind>>,
+    next_thread_id: ThreadId,
+    next_wait_seq: u64,
+    now: u64,
+    /// Emulated nanoseconds each core has spent without a thread
+    idle_time: Vec<u64>,
+}
+
+impl Scheduler {
+    pub fn new(core_count: usize) -> Self {
+        Self {
+            threads: BTreeMap::new(),
+            queues: std::array::from_fn(|_| VecDeque::new()),
+            running: vec![None; core_count],
+            pending_yield: vec![None; core_count],
+            next_thread_id: 1,
+            next_wait_seq: 0,
+            now: 0,
+            idle_time: vec![0; core_count],
+        }
+    }
+
+    /// Emulated time in nanoseconds
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Move emulated time forward by `ns`
+    pub fn advance(&mut self, ns: u64) {
+        self.now += ns;
+    }
+
+    /// Account `ns` of a round to the thread that ran on `core`, or to the
+    /// core's idle time if none did
+    pub fn charge(&mut self, core: usize, thread: Option<ThreadId>, ns: u64) {
+        match thread.and_then(|id| self.threads.get_mut(&id)) {
+            Some(thread) => thread.cpu_time += ns,
+            None => self.idle_time[core] += ns,
+        }
+    }
+
+    pub fn idle_time(&self, core: usize) -> u64 {
+        self.idle_time[core]
+    }
+
+    pub fn thread(&self, id: ThreadId) -> Option<&Thread> {
+        self.threads.get(&id)
+    }
+
+    pub fn thread_mut(&mut self, id: ThreadId) -> Option<&mut Thread> {
+        self.threads.get_mut(&id)
+    }
+
+    pub fn threads(&self) -> impl Iterator<Item = &Thread> {
+        self.threads.values()
+    }
+
+    /// Thread running on `core`
+    pub fn current(&self, core: usize) -> Option<ThreadId> {
+        self.running.get(core).copied().flatten()
+    }
+
+    /// Whether any thread can still make progress, now or after a wait
+    pub fn has_live_threads(&self) -> bool {
+        self.threads
+            .values()
+            .any(|t| matches!(t.state, ThreadState::Runnable | ThreadState::Waiting))
+    }
+
+    /// Earliest deadline among waiting threads
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.threads
+            .values()
+            .filter(|t| t.state == ThreadState::Waiting)
+            .filter_map(|t| t.wake_at)
+            .min()
+    }
+
+    /// Register a thread in the [`ThreadState::Created`] state
+    pub fn add_thread(&mut self, make: impl FnOnce(ThreadId) -> Thread) -> ThreadId {
+        let id = self.next_thread_id;
+        self.next_thread_id += 1;
+        self.threads.insert(id, make(id));
+        id
+    }
+
+    /// Forget a thread entirely, e.g. once it has exited and its last
+    /// handle is closed
+    pub fn remove_thread(&mut self, id: ThreadId) -> Option<Thread> {
+        self.dequeue(id);
+        self.threads.remove(&id)
+    }
+
+    /// Make a thread runnable, queuing it behind others of its priority
+    pub fn wake(&mut self, id: ThreadId) {
+        let Some(thread) = self.threads.get_mut(&id) else {
+            return;
+        };
+        thread.state = ThreadState::Runnable;
+        thread.wait = None;
+        thread.wake_at = None;
+        if !self.running.contains(&Some(id)) {
+            self.queues[thread.priority as usize].push_back(id);
+        }
+    }
+
+    /// Block a thread on `wait` until [`Self::wake`] or, if given, the
+    /// deadline `timeout_ns` from now
+    pub fn block(&mut self, id: ThreadId, wait: Option<Wait>, timeout_ns: Option<u64>) {
+        self.dequeue(id);
+        let now = self.now;
+        let seq = self.next_wait_seq;
+        self.next_wait_seq += 1;
+        if let Some(thread) = self.threads.get_mut(&id) {
+            thread.state = ThreadState::Waiting;
+            thread.wait = wait;
+            thread.wait_seq = seq;
+            thread.wake_at = timeout_ns.map(|ns| now.saturating_add(ns));
+        }
+    }
+
+    pub fn terminate(&mut self, id: ThreadId) {
+        self.dequeue(id);
+        if let Some(thread) = self.threads.get_mut(&id) {
+            thread.state = ThreadState::Terminated;
+            thread.wait = None;
+            thread.wake_at = None;
+        }
+    }
+
+    /// Ask for the thread running on `core` to be requeued after its SVC
+    pub fn yield_current(&mut self, core: usize, kind: YieldKind) {
+        self.pending_yield[core] = Some(kind);
+    }
+
+    pub fn set_priority(&mut self, id: ThreadId, priority: u32) {
+        let Some(thread) = self.threads.get(&id) else {
+            return;
+        };
+        let old = thread.priority as usize;
+        if let Some(pos) = self.queues[old].iter().position(|&t| t == id) {
+            self.queues[old].remove(pos);
+            self.queues[priority as usize].push_back(id);
+        }
+        self.threads.get_mut(&id).unwrap().priority = priority;
+    }
+
+    /// Update a thread's ideal core and affinity, moving it to a core in the
+    /// new mask if its current one is no longer allowed
+    pub fn set_core_mask(&mut self, id: ThreadId, ideal_core: i32, affinity_mask: u64) {
+        let Some(thread) = self.threads.get_mut(&id) else {
+            return;
+        };
+        thread.ideal_core = ideal_core;
+        thread.affinity_mask = affinity_mask;
+        if !thread.can_run_on(thread.active_core) {
+            thread.active_core = if ideal_core >= 0 {
+                ideal_core as usize
+            } else {
+                63 - affinity_mask.leading_zeros() as usize
+            };
+        }
+    }
+
+    /// Wake every waiting thread whose deadline has passed
+    pub fn wake_expired(&mut self) {
+        self.wake_due(self.now);
+    }
+
+    /// Wake every waiting thread whose deadline is at or before `time`, for
+    /// a timer that fires partway through a round
+    pub fn wake_due(&mut self, time: u64) {
+        let expired: Vec<ThreadId> = self
+            .threads
+            .values()
+            .filter(|t| t.state == ThreadState::Waiting && t.wake_at.is_some_and(|at| at <= time))
+            .map(|t| t.id)
+            .collect();
+        for id in expired {
+            self.wake(id);
+        }
+    }
+
+    /// Jump emulated time to the next deadline, if any thread has one
+    pub fn skip_to_next_deadline(&mut self) -> bool {
+        match self.next_deadline() {
+            Some(deadline) => {
+                let skipped = deadline.saturating_sub(self.now);
+                for idle in &mut self.idle_time {
+                    *idle += skipped;
+                }
+                self.now = self.now.max(deadline);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Choose the thread `core` should run next and load it, returning it
+    ///
+    /// The running thread is kept unless a strictly higher-priority thread
+    /// is available for the core.
+    pub fn dispatch(&mut self, core: &UnicornCPU) -> Option<ThreadId> {
+        let core_id = core.core_id as usize;
+        if let Some(current) = self.running[core_id] {
+            let priority = self.threads[&current].priority as usize;
+            let Some(next) = self.take_candidate(core_id, priority) else {
+                return Some(current);
+            };
+            self.threads.get_mut(&current).unwrap().context = core.save_context();
+            self.queues[priority].push_front(current);
+            self.switch_to(core, next);
+            return Some(next);
+        }
+
+        let next = self.take_candidate(core_id, PRIORITY_COUNT)?;
+        self.switch_to(core, next);
+        Some(next)
+    }
+
+    /// Take the running thread of `core` off it if it blocked, exited,
+    /// yielded or lost its affinity for the core during the last slice
+    pub fn update_current(&mut self, core: &UnicornCPU) {
+        let core_id = core.core_id as usize;
+        let Some(current) = self.running[core_id] else {
+            return;
+        };
+        let yielded = self.pending_yield[core_id].take();
+        let Some(thread) = self.threads.get_mut(&current) else {
+            self.running[core_id] = None;
+            return;
+        };
+
+        let stays = thread.state == ThreadState::Runnable
+            && thread.active_core == core_id
+            && yielded.is_none();
+        if stays {
+            return;
+        }
+
+        self.running[core_id] = None;
+        if thread.state == ThreadState::Terminated {
+            return;
+        }
+        thread.context = core.save_context();
+        if thread.state != ThreadState::Runnable {
+            return;
+        }
+
+        match yielded {
+            Some(YieldKind::ToAnyThread) => thread.yielded_to_any = true,
+            Some(YieldKind::WithMigration) => {
+                let mask = thread.affinity_mask;
+                if let Some(idle) = self.find_idle_core(core_id, mask) {
+                    self.threads.get_mut(&current).unwrap().active_core = idle;
+                }
+            }
+            _ => {}
+        }
+        let priority = self.threads[&current].priority as usize;
+        self.queues[priority].push_back(current);
+    }
+
+    fn switch_to(&mut self, core: &UnicornCPU, id: ThreadId) {
+        let core_id = core.core_id as usize;
+        let thread = self.threads.get_mut(&id).unwrap();
+        thread.active_core = core_id;
+        thread.yielded_to_any = false;
+        core.load_context(&thread.context);
+        self.running[core_id] = Some(id);
+    }
+
+    /// Remove and return the best queued thread for `core` with a priority
+    /// value below `limit`
+    ///
+    /// Threads that yielded to any thread are passed over while anything
+    /// else is available.
+    fn take_candidate(&mut self, core: usize, limit: usize) -> Option<ThreadId> {
+        let (priority, index) = self
+            .find_candidate(core, limit, false)
+            .or_else(|| self.find_candidate(core, limit, true))?;
+        self.queues[priority].remove(index)
+    }
+
+    fn find_candidate(
+        &self,
+        core: usize,
+        limit: usize,
+        allow_yielded: bool,
+    ) -> Option<(usize, usize)> {
+        let eligible = |t: &Thread| allow_yielded || !t.yielded_to_any;
+
+        let local = (0..limit).find_map(|priority| {
+            self.queues[priority]
+                .iter()
+                .position(|id| {
+                    let t = &self.threads[id];
+                    t.active_core == core && eligible(t)
+                })
+                .map(|index| (priority, index))
+        });
+        if local.is_some() {
+            return local;
+        }
+
+        // Only migrate threads whose own core is busy with something they
+        // cannot preempt
+        (0..limit).find_map(|priority| {
+            self.queues[priority]
+                .iter()
+                .position(|id| {
+                    let t = &self.threads[id];
+                    t.active_core != core
+                        && t.can_run_on(core)
+                        && eligible(t)
+                        && self
+                            .current(t.active_core)
+                            .is_some_and(|other| self.threads[&other].priority <= t.priority)
+                })
+                .map(|index| (priority, index))
+        })
+    }
+
+    /// Another core in `mask` with nothing running or queued
+    fn find_idle_core(&self, except: usize, mask: u64) -> Option<usize> {
+        (0..self.running.len()).find(|&c| {
+            c != except
+                && mask & (1 << c) != 0
+                && self.running[c].is_none()
+                && !self.has_local_work(c)
+        })
+    }
+
+    fn has_local_work(&self, core: usize) -> bool {
+        self.queues
+            .iter()
+            .flatten()
+            .any(|id| self.threads[id].active_core == core)
+    }
+
+    fn dequeue(&mut self, id: ThreadId) {
+        for queue in &mut self.queues {
+            queue.retain(|&t| t != id);
+        }
+    }
+}
@@ -0,0 +1,451 @@
+//! Shared memory, transfer memory and the SVCs that map them
+//!
+//! Shared memory is a set of pages owned by the kernel, typically created by
+//! a service to publish state such as HID input. Transfer memory lends a
+//! range of the creator's own memory to another party, which is how
+//! applications hand work buffers to services.
+//!
+//! RAM is identity mapped, so a mapping cannot alias other pages. As with
+//! `svcMapMemory`, the contents are copied into place on map and back out
+//! on unmap, and each object can be mapped once at a time. Host services,
+//! which share the guest's handle table, reach the pages through
+//! [`Kernel::read_memory_object`] and [`Kernel::write_memory_object`]
+//! wherever they currently live.
+
+use crate::kernel::Kernel;
+use crate::kernel::handle::{Handle, KernelObject};
+use crate::kernel::memory::{MemoryAttribute, MemoryPermission, MemoryState, PAGE_SIZE};
+use crate::kernel::resource_limit::LimitableResource;
+use crate::kernel::result::{self, ResultCode};
+
+pub type SharedMemoryId = u64;
+pub type TransferMemoryId = u64;
+
+/// Remote permission of shared memory the other party may map either way
+pub const PERMISSION_DONT_CARE: MemoryPermission = MemoryPermission(1 << 28);
+
+pub struct SharedMemory {
+    pub size: u64,
+    /// Permission the creator maps the memory with
+    pub owner_permission: MemoryPermission,
+    /// Permission anyone else maps it with, or [`PERMISSION_DONT_CARE`]
+    pub remote_permission: MemoryPermission,
+    /// Whether a host service created the memory, making the guest the
+    /// remote party
+    pub host_owned: bool,
+    /// The pages while they are not mapped
+    contents: Vec<u8>,
+    /// Where the guest has the pages mapped, if anywhere
+    mapped_at: Option<u64>,
+}
+
+pub struct TransferMemory {
+    /// The creator's pages being lent
+    pub address: u64,
+    pub size: u64,
+    /// Permission the creator keeps on its pages meanwhile
+    pub permission: MemoryPermission,
+    /// Where the pages are mapped for the borrower, if anywhere
+    mapped_at: Option<u64>,
+}
+
+fn check_map_args(addr: u64, size: u64) -> Result<(), ResultCode> {
+    if !addr.is_multiple_of(PAGE_SIZE) {
+        return Err(result::INVALID_ADDRESS);
+    }
+    if size == 0 || !size.is_multiple_of(PAGE_SIZE) {
+        return Err(result::INVALID_SIZE);
+    }
+    if addr.checked_add(size).is_none() {
+        return Err(result::INVALID_CURRENT_MEMORY);
+    }
+    Ok(())
+}
+
+impl Kernel {
+    /// `svcCreateSharedMemory`: zeroed pages the guest creates and maps with
+    /// `owner_permission`
+    pub fn create_shared_memory(
+        &mut self,
+        size: u64,
+        owner_permission: MemoryPermission,
+        remote_permission: MemoryPermission,
+    ) -> Result<Handle, ResultCode> {
+        if !matches!(
+            owner_permission,
+            MemoryPermission::READ | MemoryPermission::READ_WRITE
+        ) {
+            return Err(result::INVALID_NEW_MEMORY_PERMISSION);
+        }
+        if !matches!(
+            remote_permission,
+            MemoryPermission::READ | MemoryPermission::READ_WRITE | PERMISSION_DONT_CARE
+        ) {
+            return Err(result::INVALID_NEW_MEMORY_PERMISSION);
+        }
+        self.add_shared_memory(size, owner_permission, remote_permission, false)
+    }
+
+    /// Shared memory created by a host service, which the guest maps with
+    /// `guest_permission`; the service keeps the returned handle and copies
+    /// it to clients
+    pub fn create_host_shared_memory(
+        &mut self,
+        size: u64,
+        guest_permission: MemoryPermission,
+    ) -> Result<Handle, ResultCode> {
+        self.add_shared_memory(size, MemoryPermission::READ_WRITE, guest_permission, true)
+    }
+
+    fn add_shared_memory(
+        &mut self,
+        size: u64,
+        owner_permission: MemoryPermission,
+        remote_permission: MemoryPermission,
+        host_owned: bool,
+    ) -> Result<Handle, ResultCode> {
+        if size == 0 || !size.is_multiple_of(PAGE_SIZE) {
+            return Err(result::INVALID_SIZE);
+        }
+        if size > self.process.address_space.layout.address_space.size {
+            return Err(result::OUT_OF_RESOURCE);
+        }
+        // Host services allocate from their own pools
+        if !host_owned {
+            let limit = &mut self.process.resource_limit;
+            limit.reserve(LimitableResource::PhysicalMemory, size)?;
+        }
+        let id = self.process.add_shared_memory(SharedMemory {
+            size,
+            owner_permission,
+            remote_permission,
+            host_owned,
+            contents: vec![0; size as usize],
+            mapped_at: None,
+        });
+        self.process
+            .handles
+            .add(KernelObject::SharedMemory(id))
+            .inspect_err(|_| {
+                self.release_shared_memory(id);
+            })
+    }
+
+    fn shared_memory_from_handle(&self, handle: Handle) -> Result<SharedMemoryId, ResultCode> {
+        match self.process.handles.get(handle) {
+            Some(KernelObject::SharedMemory(id)) => Ok(id),
+            _ => Err(result::INVALID_HANDLE),
+        }
+    }
+
+    fn transfer_memory_from_handle(&self, handle: Handle) -> Result<TransferMemoryId, ResultCode> {
+        match self.process.handles.get(handle) {
+            Some(KernelObject::TransferMemory(id)) => Ok(id),
+            _ => Err(result::INVALID_HANDLE),
+        }
+    }
+
+    /// `svcMapSharedMemory`
+    pub fn map_shared_memory(
+        &mut self,
+        handle: Handle,
+        addr: u64,
+        size: u64,
+        permission: MemoryPermission,
+    ) -> Result<(), ResultCode> {
+        check_map_args(addr, size)?;
+        let id = self.shared_memory_from_handle(handle)?;
+        let shared = &self.process.shared_memory[&id];
+        if size != shared.size {
+            return Err(result::INVALID_SIZE);
+        }
+        let allowed = if shared.host_owned {
+            shared.remote_permission
+        } else {
+            shared.owner_permission
+        };
+        let valid = match allowed {
+            PERMISSION_DONT_CARE => matches!(
+                permission,
+                MemoryPermission::READ | MemoryPermission::READ_WRITE
+            ),
+            allowed => permission == allowed,
+        };
+        if !valid {
+            return Err(result::INVALID_NEW_MEMORY_PERMISSION);
+        }
+        if shared.mapped_at.is_some() {
+            return Err(result::INVALID_STATE);
+        }
+
+        let space = &mut self.process.address_space;
+        if !space.layout.address_space.contains(addr, size) {
+            return Err(result::INVALID_MEMORY_REGION);
+        }
+        space.check_range(addr, size, |b| b.state == MemoryState::Free)?;
+        space.memory().write(addr, &shared.contents);
+        space.update(
+            addr,
+            size,
+            MemoryState::Shared,
+            permission,
+            MemoryAttribute::NONE,
+        );
+
+        let shared = self.process.shared_memory.get_mut(&id).unwrap();
+        shared.mapped_at = Some(addr);
+        shared.contents = Vec::new();
+        Ok(())
+    }
+
+    /// `svcUnmapSharedMemory`
+    pub fn unmap_shared_memory(
+        &mut self,
+        handle: Handle,
+        addr: u64,
+        size: u64,
+    ) -> Result<(), ResultCode> {
+        check_map_args(addr, size)?;
+        let id = self.shared_memory_from_handle(handle)?;
+        let shared = self.process.shared_memory.get_mut(&id).unwrap();
+        if shared.mapped_at != Some(addr) {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        if size != shared.size {
+            return Err(result::INVALID_SIZE);
+        }
+
+        let space = &mut self.process.address_space;
+        shared.contents = space
+            .memory()
+            .read_vec(addr, size as usize)
+            .ok_or(result::INVALID_CURRENT_MEMORY)?;
+        shared.mapped_at = None;
+        space.unmap(addr, size);
+        Ok(())
+    }
+
+    /// `svcCreateTransferMemory`: lend `[addr, addr + size)`, keeping
+    /// `permission` on it until the transfer memory is closed
+    pub fn create_transfer_memory(
+        &mut self,
+        addr: u64,
+        size: u64,
+        permission: MemoryPermission,
+    ) -> Result<Handle, ResultCode> {
+        check_map_args(addr, size)?;
+        if !matches!(
+            permission,
+            MemoryPermission::NONE | MemoryPermission::READ | MemoryPermission::READ_WRITE
+        ) {
+            return Err(result::INVALID_NEW_MEMORY_PERMISSION);
+        }
+        let space = &mut self.process.address_space;
+        let block = space.check_range(addr, size, |b| {
+            b.state.can_alias()
+                && b.permission == MemoryPermission::READ_WRITE
+                && b.attribute == MemoryAttribute::NONE
+        })?;
+
+        let id = self.process.add_transfer_memory(TransferMemory {
+            address: addr,
+            size,
+            permission,
+            mapped_at: None,
+        });
+        let handle = self
+            .process
+            .handles
+            .add(KernelObject::TransferMemory(id))
+            .inspect_err(|_| {
+                self.process.transfer_memory.remove(&id);
+            })?;
+        self.process.address_space.update(
+            addr,
+            size,
+            block.state,
+            permission,
+            MemoryAttribute::LOCKED,
+        );
+        Ok(handle)
+    }
+
+    /// `svcMapTransferMemory`: map the lent pages read-write at `addr`
+    ///
+    /// `permission` has to match what the creator kept, which tells whether
+    /// the creator can still read the pages meanwhile.
+    pub fn map_transfer_memory(
+        &mut self,
+        handle: Handle,
+        addr: u64,
+        size: u64,
+        permission: MemoryPermission,
+    ) -> Result<(), ResultCode> {
+        check_map_args(addr, size)?;
+        let id = self.transfer_memory_from_handle(handle)?;
+        let transfer = &self.process.transfer_memory[&id];
+        if size != transfer.size {
+            return Err(result::INVALID_SIZE);
+        }
+        if permission != transfer.permission || transfer.mapped_at.is_some() {
+            return Err(result::INVALID_STATE);
+        }
+
+        let space = &mut self.process.address_space;
+        if !space.layout.address_space.contains(addr, size) {
+            return Err(result::INVALID_MEMORY_REGION);
+        }
+        space.check_range(addr, size, |b| b.state == MemoryState::Free)?;
+        space.memory().copy(addr, transfer.address, size);
+        let state = match permission {
+            MemoryPermission::NONE => MemoryState::Transfered,
+            _ => MemoryState::SharedTransfered,
+        };
+        space.update(
+            addr,
+            size,
+            state,
+            MemoryPermission::READ_WRITE,
+            MemoryAttribute::NONE,
+        );
+        self.process.transfer_memory.get_mut(&id).unwrap().mapped_at = Some(addr);
+        Ok(())
+    }
+
+    /// `svcUnmapTransferMemory`
+    pub fn unmap_transfer_memory(
+        &mut self,
+        handle: Handle,
+        addr: u64,
+        size: u64,
+    ) -> Result<(), ResultCode> {
+        check_map_args(addr, size)?;
+        let id = self.transfer_memory_from_handle(handle)?;
+        let transfer = self.process.transfer_memory.get_mut(&id).unwrap();
+        if transfer.mapped_at != Some(addr) {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        if size != transfer.size {
+            return Err(result::INVALID_SIZE);
+        }
+
+        let space = &mut self.process.address_space;
+        space.memory().copy(transfer.address, addr, size);
+        space.unmap(addr, size);
+        transfer.mapped_at = None;
+        Ok(())
+    }
+
+    /// Address and size the pages of a shared or transfer memory object
+    /// currently have in guest memory, if they are there
+    fn memory_object_pages(&self, handle: Handle) -> Result<(Option<u64>, u64), ResultCode> {
+        match self.process.handles.get(handle) {
+            Some(KernelObject::SharedMemory(id)) => {
+                let shared = &self.process.shared_memory[&id];
+                Ok((shared.mapped_at, shared.size))
+            }
+            Some(KernelObject::TransferMemory(id)) => {
+                let transfer = &self.process.transfer_memory[&id];
+                let addr = transfer.mapped_at.unwrap_or(transfer.address);
+                Ok((Some(addr), transfer.size))
+            }
+            _ => Err(result::INVALID_HANDLE),
+        }
+    }
+
+    /// Read from the pages of a shared or transfer memory object on behalf
+    /// of a host service
+    pub fn read_memory_object(
+        &self,
+        handle: Handle,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> Result<(), ResultCode> {
+        let (addr, size) = self.memory_object_pages(handle)?;
+        let end = offset.checked_add(buf.len() as u64);
+        if end.is_none_or(|end| end > size) {
+            return Err(result::OUT_OF_RANGE);
+        }
+        match addr {
+            Some(addr) => {
+                self.process.address_space.memory().read(addr + offset, buf);
+            }
+            None => {
+                let Some(KernelObject::SharedMemory(id)) = self.process.handles.get(handle) else {
+                    unreachable!("only shared memory leaves guest memory");
+                };
+                let contents = &self.process.shared_memory[&id].contents;
+                buf.copy_from_slice(&contents[offset as usize..][..buf.len()]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write to the pages of a shared or transfer memory object on behalf
+    /// of a host service
+    pub fn write_memory_object(
+        &mut self,
+        handle: Handle,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), ResultCode> {
+        let (addr, size) = self.memory_object_pages(handle)?;
+        let end = offset.checked_add(data.len() as u64);
+        if end.is_none_or(|end| end > size) {
+            return Err(result::OUT_OF_RANGE);
+        }
+        match addr {
+            Some(addr) => {
+                self.process
+                    .address_space
+                    .memory()
+                    .write(addr + offset, data);
+            }
+            None => {
+                let Some(KernelObject::SharedMemory(id)) = self.process.handles.get(handle) else {
+                    unreachable!("only shared memory leaves guest memory");
+                };
+                let contents = &mut self.process.shared_memory.get_mut(&id).unwrap().contents;
+                contents[offset as usize..][..data.len()].copy_from_slice(data);
+            }
+        }
+        Ok(())
+    }
+
+    /// Destroy shared memory nothing refers to or maps any more
+    pub(crate) fn release_shared_memory(&mut self, id: SharedMemoryId) {
+        if self
+            .process
+            .shared_memory
+            .get(&id)
+            .is_some_and(|s| s.mapped_at.is_none())
+        {
+            let shared = self.process.shared_memory.remove(&id).unwrap();
+            if !shared.host_owned {
+                let limit = &mut self.process.resource_limit;
+                limit.release(LimitableResource::PhysicalMemory, shared.size);
+            }
+        }
+    }
+
+    /// Destroy transfer memory nothing refers to or maps any more, giving
+    /// the creator its pages back
+    pub(crate) fn release_transfer_memory(&mut self, id: TransferMemoryId) {
+        let Some(transfer) = self.process.transfer_memory.get(&id) else {
+            return;
+        };
+        if transfer.mapped_at.is_some() {
+            return;
+        }
+        let transfer = self.process.transfer_memory.remove(&id).unwrap();
+        let space = &mut self.process.address_space;
+        let state = space.query(transfer.address).state;
+        space.update(
+            transfer.address,
+            transfer.size,
+            state,
+            MemoryPermission::READ_WRITE,
+            MemoryAttribute::NONE,
+        );
+    }
+}
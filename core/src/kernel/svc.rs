@@ -0,0 +1,613 @@
+//! Supervisor call dispatch
+//!
+//! Arguments arrive in X0-X7 following the Horizon SVC ABI. The result code
+//! is returned in W0 and any output values in X1 onwards.
+
+use crate::cpu::UnicornCPU;
+use crate::kernel::Kernel;
+use crate::kernel::handle::Handle;
+use crate::kernel::hipc::MESSAGE_BUFFER_SIZE;
+use crate::kernel::ipc::MAX_PORT_NAME_LENGTH;
+use crate::kernel::memory::{MemoryInfo, MemoryPermission, PAGE_SIZE};
+use crate::kernel::result::{self, ResultCode};
+use crate::kernel::sync::MAX_WAIT_OBJECTS;
+use crate::kernel::thread::{
+    IDEAL_CORE_DONT_CARE, IDEAL_CORE_NO_UPDATE, IDEAL_CORE_USE_PROCESS_VALUE, YieldKind,
+};
+
+pub const SET_HEAP_SIZE: u32 = 0x01;
+pub const SET_MEMORY_ATTRIBUTE: u32 = 0x03;
+pub const MAP_MEMORY: u32 = 0x04;
+pub const UNMAP_MEMORY: u32 = 0x05;
+pub const QUERY_MEMORY: u32 = 0x06;
+pub const CREATE_THREAD: u32 = 0x08;
+pub const START_THREAD: u32 = 0x09;
+pub const EXIT_THREAD: u32 = 0x0A;
+pub const SLEEP_THREAD: u32 = 0x0B;
+pub const GET_THREAD_PRIORITY: u32 = 0x0C;
+pub const SET_THREAD_PRIORITY: u32 = 0x0D;
+pub const GET_THREAD_CORE_MASK: u32 = 0x0E;
+pub const SET_THREAD_CORE_MASK: u32 = 0x0F;
+pub const GET_CURRENT_PROCESSOR_NUMBER: u32 = 0x10;
+pub const SIGNAL_EVENT: u32 = 0x11;
+pub const CLEAR_EVENT: u32 = 0x12;
+pub const MAP_SHARED_MEMORY: u32 = 0x13;
+pub const UNMAP_SHARED_MEMORY: u32 = 0x14;
+pub const CREATE_TRANSFER_MEMORY: u32 = 0x15;
+pub const CLOSE_HANDLE: u32 = 0x16;
+pub const RESET_SIGNAL: u32 = 0x17;
+pub const WAIT_SYNCHRONIZATION: u32 = 0x18;
+pub const CANCEL_SYNCHRONIZATION: u32 = 0x19;
+pub const ARBITRATE_LOCK: u32 = 0x1A;
+pub const ARBITRATE_UNLOCK: u32 = 0x1B;
+pub const WAIT_PROCESS_WIDE_KEY_ATOMIC: u32 = 0x1C;
+pub const SIGNAL_PROCESS_WIDE_KEY: u32 = 0x1D;
+pub const CONNECT_TO_NAMED_PORT: u32 = 0x1F;
+pub const SEND_SYNC_REQUEST: u32 = 0x21;
+pub const SEND_SYNC_REQUEST_WITH_USER_BUFFER: u32 = 0x22;
+pub const BREAK: u32 = 0x26;
+pub const OUTPUT_DEBUG_STRING: u32 = 0x27;
+pub const RETURN_FROM_EXCEPTION: u32 = 0x28;
+pub const GET_INFO: u32 = 0x29;
+pub const WAIT_FOR_ADDRESS: u32 = 0x34;
+pub const SIGNAL_TO_ADDRESS: u32 = 0x35;
+pub const CREATE_SESSION: u32 = 0x40;
+pub const ACCEPT_SESSION: u32 = 0x41;
+pub const REPLY_AND_RECEIVE: u32 = 0x43;
+pub const CREATE_EVENT: u32 = 0x45;
+pub const CREATE_SHARED_MEMORY: u32 = 0x50;
+pub const MAP_TRANSFER_MEMORY: u32 = 0x51;
+pub const UNMAP_TRANSFER_MEMORY: u32 = 0x52;
+pub const CREATE_INTERRUPT_EVENT: u32 = 0x53;
+pub const QUERY_MEMORY_MAPPING: u32 = 0x55;
+pub const GET_SYSTEM_INFO: u32 = 0x6F;
+
+/// Highest SVC ID, which bounds `svcGetInfo`'s permission queries
+pub const MAX_SVC_ID: u32 = 0xBF;
+
+/// Horizon's name for SVC `id`, e.g. `SendSyncRequest`, if it is serviced
+pub fn name(id: u32) -> Option<&'static str> {
+    Some(match id {
+        SET_HEAP_SIZE => "SetHeapSize",
+        SET_MEMORY_ATTRIBUTE => "SetMemoryAttribute",
+        MAP_MEMORY => "MapMemory",
+        UNMAP_MEMORY => "UnmapMemory",
+        QUERY_MEMORY => "QueryMemory",
+        CREATE_THREAD => "CreateThread",
+        START_THREAD => "StartThread",
+        EXIT_THREAD => "ExitThread",
+        SLEEP_THREAD => "SleepThread",
+        GET_THREAD_PRIORITY => "GetThreadPriority",
+        SET_THREAD_PRIORITY => "SetThreadPriority",
+        GET_THREAD_CORE_MASK => "GetThreadCoreMask",
+        SET_THREAD_CORE_MASK => "SetThreadCoreMask",
+        GET_CURRENT_PROCESSOR_NUMBER => "GetCurrentProcessorNumber",
+        SIGNAL_EVENT => "SignalEvent",
+        CLEAR_EVENT => "ClearEvent",
+        MAP_SHARED_MEMORY => "MapSharedMemory",
+        UNMAP_SHARED_MEMORY => "UnmapSharedMemory",
+        CREATE_TRANSFER_MEMORY => "CreateTransferMemory",
+        CLOSE_HANDLE => "CloseHandle",
+        RESET_SIGNAL => "ResetSignal",
+        WAIT_SYNCHRONIZATION => "WaitSynchronization",
+        CANCEL_SYNCHRONIZATION => "CancelSynchronization",
+        ARBITRATE_LOCK => "ArbitrateLock",
+        ARBITRATE_UNLOCK => "ArbitrateUnlock",
+        WAIT_PROCESS_WIDE_KEY_ATOMIC => "WaitProcessWideKeyAtomic",
+        SIGNAL_PROCESS_WIDE_KEY => "SignalProcessWideKey",
+        CONNECT_TO_NAMED_PORT => "ConnectToNamedPort",
+        SEND_SYNC_REQUEST => "SendSyncRequest",
+        SEND_SYNC_REQUEST_WITH_USER_BUFFER => "SendSyncRequestWithUserBuffer",
+        BREAK => "Break",
+        OUTPUT_DEBUG_STRING => "OutputDebugString",
+        RETURN_FROM_EXCEPTION => "ReturnFromException",
+        GET_INFO => "GetInfo",
+        WAIT_FOR_ADDRESS => "WaitForAddress",
+        SIGNAL_TO_ADDRESS => "SignalToAddress",
+        CREATE_SESSION => "CreateSession",
+        ACCEPT_SESSION => "AcceptSession",
+        REPLY_AND_RECEIVE => "ReplyAndReceive",
+        CREATE_EVENT => "CreateEvent",
+        CREATE_SHARED_MEMORY => "CreateSharedMemory",
+        MAP_TRANSFER_MEMORY => "MapTransferMemory",
+        UNMAP_TRANSFER_MEMORY => "UnmapTransferMemory",
+        CREATE_INTERRUPT_EVENT => "CreateInterruptEvent",
+        QUERY_MEMORY_MAPPING => "QueryMemoryMapping",
+        GET_SYSTEM_INFO => "GetSystemInfo",
+        _ => return None,
+    })
+}
+
+/// Service SVC `id` raised by `core`
+pub fn call(kernel: &mut Kernel, core: &UnicornCPU, id: u32) {
+    match id {
+        SET_HEAP_SIZE => {
+            let out = kernel.process.set_heap_size(core.get_x(1));
+            write_result(core, out.map(|addr| core.set_x(1, addr)));
+        }
+        SET_MEMORY_ATTRIBUTE => {
+            let out = kernel.process.address_space.set_memory_attribute(
+                core.get_x(0),
+                core.get_x(1),
+                core.get_x(2) as u32,
+                core.get_x(3) as u32,
+            );
+            write_result(core, out);
+        }
+        MAP_MEMORY => {
+            let out = kernel.process.address_space.map_memory(
+                core.get_x(0),
+                core.get_x(1),
+                core.get_x(2),
+            );
+            write_result(core, out);
+        }
+        UNMAP_MEMORY => {
+            let out = kernel.process.address_space.unmap_memory(
+                core.get_x(0),
+                core.get_x(1),
+                core.get_x(2),
+            );
+            write_result(core, out);
+        }
+        QUERY_MEMORY => {
+            let out = query_memory(kernel, core.get_x(0), core.get_x(2));
+            write_result(core, out.map(|page_info| core.set_x(1, page_info as u64)));
+        }
+        CREATE_THREAD => {
+            let out = kernel.create_thread(
+                core.get_x(1),
+                core.get_x(2),
+                core.get_x(3),
+                core.get_x(4) as u32,
+                core.get_x(5) as i32,
+            );
+            write_result(core, out.map(|handle| core.set_x(1, handle as u64)));
+        }
+        START_THREAD => {
+            let out = kernel.start_thread(core.get_x(0) as u32);
+            write_result(core, out);
+        }
+        EXIT_THREAD => {
+            if let Some(id) = kernel.scheduler.current(core.core_id as usize) {
+                kernel.exit_thread(id);
+            }
+        }
+        SLEEP_THREAD => sleep_thread(kernel, core, core.get_x(0) as i64),
+        GET_THREAD_PRIORITY => {
+            let out = current_thread_arg(kernel, core, 1).and_then(|id| {
+                let thread = kernel.scheduler.thread(id).ok_or(result::INVALID_HANDLE)?;
+                core.set_x(1, thread.priority as u64);
+                Ok(())
+            });
+            write_result(core, out);
+        }
+        SET_THREAD_PRIORITY => {
+            let priority = core.get_x(1) as u32;
+            let out = if kernel.process.is_valid_priority(priority) {
+                current_thread_arg(kernel, core, 0)
+                    .map(|id| kernel.scheduler.set_priority(id, priority))
+            } else {
+                Err(result::INVALID_PRIORITY)
+            };
+            write_result(core, out);
+        }
+        GET_THREAD_CORE_MASK => {
+            let out = current_thread_arg(kernel, core, 2).and_then(|id| {
+                let thread = kernel.scheduler.thread(id).ok_or(result::INVALID_HANDLE)?;
+                core.set_x(1, thread.ideal_core as u32 as u64);
+                core.set_x(2, thread.affinity_mask);
+                Ok(())
+            });
+            write_result(core, out);
+        }
+        SET_THREAD_CORE_MASK => {
+            let out = set_thread_core_mask(kernel, core, core.get_x(1) as i32, core.get_x(2));
+            write_result(core, out);
+        }
+        GET_CURRENT_PROCESSOR_NUMBER => core.set_x(0, core.core_id as u64),
+        SIGNAL_EVENT => {
+            let out = kernel.signal_event(core.get_x(0) as u32);
+            write_result(core, out);
+        }
+        CLEAR_EVENT => {
+            let out = kernel.clear_event(core.get_x(0) as u32);
+            write_result(core, out);
+        }
+        MAP_SHARED_MEMORY => {
+            let out = kernel.map_shared_memory(
+                core.get_x(0) as u32,
+                core.get_x(1),
+                core.get_x(2),
+                MemoryPermission(core.get_x(3) as u32),
+            );
+            write_result(core, out);
+        }
+        UNMAP_SHARED_MEMORY => {
+            let out =
+                kernel.unmap_shared_memory(core.get_x(0) as u32, core.get_x(1), core.get_x(2));
+            write_result(core, out);
+        }
+        CREATE_TRANSFER_MEMORY => {
+            let out = kernel.create_transfer_memory(
+                core.get_x(1),
+                core.get_x(2),
+                MemoryPermission(core.get_x(3) as u32),
+            );
+            write_result(core, out.map(|handle| core.set_x(1, handle as u64)));
+        }
+        CLOSE_HANDLE => {
+            let out = kernel.close_handle(core.get_x(0) as u32);
+            write_result(core, out);
+        }
+        RESET_SIGNAL => {
+            let out = kernel.reset_signal(core.get_x(0) as u32);
+            write_result(core, out);
+        }
+        WAIT_SYNCHRONIZATION => {
+            let out = wait_synchronization(kernel, core).map(|index| core.set_x(1, index));
+            write_result(core, out);
+        }
+        CANCEL_SYNCHRONIZATION => {
+            let out =
+                current_thread_arg(kernel, core, 0).map(|id| kernel.cancel_synchronization(id));
+            write_result(core, out);
+        }
+        ARBITRATE_LOCK => {
+            let out = current_thread(kernel, core).and_then(|id| {
+                kernel.arbitrate_lock(
+                    id,
+                    core.get_x(0) as u32,
+                    core.get_x(1),
+                    core.get_x(2) as u32,
+                )
+            });
+            write_result(core, out);
+        }
+        ARBITRATE_UNLOCK => {
+            let out = current_thread(kernel, core)
+                .and_then(|id| kernel.arbitrate_unlock(id, core.get_x(0)));
+            write_result(core, out);
+        }
+        WAIT_PROCESS_WIDE_KEY_ATOMIC => {
+            let out = current_thread(kernel, core).and_then(|id| {
+                kernel.wait_process_wide_key(
+                    id,
+                    core.get_x(0),
+                    core.get_x(1),
+                    core.get_x(2) as u32,
+                    core.get_x(3) as i64,
+                )
+            });
+            write_result(core, out);
+        }
+        SIGNAL_PROCESS_WIDE_KEY => {
+            kernel.signal_process_wide_key(core.get_x(0), core.get_x(1) as i32);
+            write_result(core, Ok(()));
+        }
+        CONNECT_TO_NAMED_PORT => {
+            let out = read_port_name(kernel, core.get_x(1))
+                .and_then(|name| kernel.connect_to_named_port(&name))
+                .map(|handle| core.set_x(1, handle as u64));
+            write_result(core, out);
+        }
+        SEND_SYNC_REQUEST => {
+            let out = current_thread(kernel, core).and_then(|id| {
+                let tls = kernel.scheduler.thread(id).map_or(0, |t| t.tls_address);
+                let size = MESSAGE_BUFFER_SIZE as u64;
+                kernel.send_sync_request(id, core.get_x(0) as u32, tls, size)
+            });
+            write_result(core, out);
+        }
+        SEND_SYNC_REQUEST_WITH_USER_BUFFER => {
+            let (buffer, size) = (core.get_x(0), core.get_x(1));
+            let out = if !buffer.is_multiple_of(PAGE_SIZE) {
+                Err(result::INVALID_ADDRESS)
+            } else if size == 0 || !size.is_multiple_of(PAGE_SIZE) {
+                Err(result::INVALID_SIZE)
+            } else {
+                current_thread(kernel, core)
+                    .and_then(|id| kernel.send_sync_request(id, core.get_x(2) as u32, buffer, size))
+            };
+            write_result(core, out);
+        }
+        BREAK => {
+            let out = kernel.break_process(
+                core.core_id as usize,
+                core.get_x(0) as u32,
+                core.get_x(1),
+                core.get_x(2),
+            );
+            write_result(core, out);
+        }
+        OUTPUT_DEBUG_STRING => {
+            let out = kernel.output_debug_string(core.get_x(0), core.get_x(1));
+            write_result(core, out);
+        }
+        RETURN_FROM_EXCEPTION => {
+            let result = ResultCode(core.get_x(0) as u32);
+            let out = kernel.return_from_exception(core.core_id as usize, result);
+            write_result(core, out);
+        }
+        GET_INFO => {
+            let out = kernel.get_info(
+                core.get_x(1) as u32,
+                core.get_x(2) as u32,
+                core.get_x(3),
+                core.core_id as usize,
+            );
+            write_result(core, out.map(|value| core.set_x(1, value)));
+        }
+        WAIT_FOR_ADDRESS => {
+            let out = current_thread(kernel, core).and_then(|id| {
+                kernel.wait_for_address(
+                    id,
+                    core.get_x(0),
+                    core.get_x(1) as u32,
+                    core.get_x(2) as i32,
+                    core.get_x(3) as i64,
+                )
+            });
+            write_result(core, out);
+        }
+        SIGNAL_TO_ADDRESS => {
+            let out = kernel.signal_to_address(
+                core.get_x(0),
+                core.get_x(1) as u32,
+                core.get_x(2) as i32,
+                core.get_x(3) as i32,
+            );
+            write_result(core, out);
+        }
+        CREATE_SESSION => {
+            let out = if core.get_x(2) as u32 != 0 {
+                // Light sessions pass messages in registers instead
+                Err(result::NOT_IMPLEMENTED)
+            } else {
+                kernel.create_session().map(|(server, client)| {
+                    core.set_x(1, server as u64);
+                    core.set_x(2, client as u64);
+                })
+            };
+            write_result(core, out);
+        }
+        ACCEPT_SESSION => {
+            let out = kernel
+                .accept_session(core.get_x(1) as u32)
+                .map(|handle| core.set_x(1, handle as u64));
+            write_result(core, out);
+        }
+        REPLY_AND_RECEIVE => {
+            let out = reply_and_receive(kernel, core);
+            let out = match out {
+                Ok(index) => {
+                    core.set_x(1, index);
+                    Ok(())
+                }
+                Err((code, index)) => {
+                    if let Some(index) = index {
+                        core.set_x(1, index);
+                    }
+                    Err(code)
+                }
+            };
+            write_result(core, out);
+        }
+        CREATE_EVENT => {
+            let out = kernel.create_event().map(|(writable, readable)| {
+                core.set_x(1, writable as u64);
+                core.set_x(2, readable as u64);
+            });
+            write_result(core, out);
+        }
+        CREATE_SHARED_MEMORY => {
+            let out = kernel.create_shared_memory(
+                core.get_x(1),
+                MemoryPermission(core.get_x(2) as u32),
+                MemoryPermission(core.get_x(3) as u32),
+            );
+            write_result(core, out.map(|handle| core.set_x(1, handle as u64)));
+        }
+        MAP_TRANSFER_MEMORY => {
+            let out = kernel.map_transfer_memory(
+                core.get_x(0) as u32,
+                core.get_x(1),
+                core.get_x(2),
+                MemoryPermission(core.get_x(3) as u32),
+            );
+            write_result(core, out);
+        }
+        UNMAP_TRANSFER_MEMORY => {
+            let out =
+                kernel.unmap_transfer_memory(core.get_x(0) as u32, core.get_x(1), core.get_x(2));
+            write_result(core, out);
+        }
+        CREATE_INTERRUPT_EVENT => {
+            let out = kernel.create_interrupt_event(core.get_x(1) as u32, core.get_x(2) as u32);
+            write_result(core, out.map(|handle| core.set_x(1, handle as u64)));
+        }
+        QUERY_MEMORY_MAPPING => {
+            let size = core.get_x(3);
+            let out = kernel.query_memory_mapping(core.get_x(2), size);
+            write_result(
+                core,
+                out.map(|address| {
+                    core.set_x(1, address);
+                    core.set_x(2, size);
+                }),
+            );
+        }
+        GET_SYSTEM_INFO => {
+            let out =
+                kernel.get_system_info(core.get_x(1) as u32, core.get_x(2) as u32, core.get_x(3));
+            write_result(core, out.map(|value| core.set_x(1, value)));
+        }
+        _ => {
+            log::warn!(
+                "Unimplemented SVC {id:#04x} at PC {:#018x}",
+                core.get_pc().wrapping_sub(4)
+            );
+            write_result(core, Err(result::NOT_IMPLEMENTED));
+        }
+    }
+}
+
+fn write_result(core: &UnicornCPU, out: Result<(), ResultCode>) {
+    let code = match out {
+        Ok(()) => ResultCode::SUCCESS,
+        Err(code) => code,
+    };
+    core.set_x(0, code.0 as u64);
+}
+
+/// `svcQueryMemory`: write the `MemoryInfo` for `addr` to `out_info` and
+/// return the page info
+fn query_memory(kernel: &Kernel, out_info: u64, addr: u64) -> Result<u32, ResultCode> {
+    let address_space = &kernel.process.address_space;
+    if !address_space.is_writable(out_info, MemoryInfo::SIZE as u64) {
+        return Err(result::INVALID_POINTER);
+    }
+    let info = address_space.query(addr);
+    address_space.memory().write(out_info, &info.to_bytes());
+    Ok(0)
+}
+
+/// The thread that issued the SVC on `core`
+///
+/// Only missing when a core is driven directly through
+/// [`Kernel::run_core`] rather than the scheduler.
+fn current_thread(kernel: &Kernel, core: &UnicornCPU) -> Result<u64, ResultCode> {
+    kernel
+        .scheduler
+        .current(core.core_id as usize)
+        .ok_or(result::INVALID_STATE)
+}
+
+/// `svcWaitSynchronization`: the handle array lives in guest memory
+fn wait_synchronization(kernel: &mut Kernel, core: &UnicornCPU) -> Result<u64, ResultCode> {
+    let current = current_thread(kernel, core)?;
+    let handles = read_handles(kernel, core.get_x(1), core.get_x(2) as u32 as usize)?;
+    kernel.wait_synchronization(current, &handles, core.get_x(3) as i64)
+}
+
+fn reply_and_receive(
+    kernel: &mut Kernel,
+    core: &UnicornCPU,
+) -> Result<u64, (ResultCode, Option<u64>)> {
+    let current = current_thread(kernel, core).map_err(|code| (code, None))?;
+    let handles = read_handles(kernel, core.get_x(1), core.get_x(2) as u32 as usize)
+        .map_err(|code| (code, None))?;
+    kernel.reply_and_receive(
+        current,
+        &handles,
+        core.get_x(3) as u32,
+        core.get_x(4) as i64,
+    )
+}
+
+/// Copy in an array of `count` handles for a multi-object wait
+fn read_handles(kernel: &Kernel, addr: u64, count: usize) -> Result<Vec<Handle>, ResultCode> {
+    if count > MAX_WAIT_OBJECTS {
+        return Err(result::OUT_OF_RANGE);
+    }
+    let space = &kernel.process.address_space;
+    if count > 0 && !space.is_readable(addr, count as u64 * 4) {
+        return Err(result::INVALID_POINTER);
+    }
+    let bytes = space
+        .memory()
+        .read_vec(addr, count * 4)
+        .ok_or(result::INVALID_POINTER)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .collect())
+}
+
+/// Copy in the NUL-terminated name passed to `svcConnectToNamedPort`
+fn read_port_name(kernel: &Kernel, addr: u64) -> Result<String, ResultCode> {
+    let space = &kernel.process.address_space;
+    let mut name = Vec::new();
+    for i in 0..MAX_PORT_NAME_LENGTH as u64 {
+        if !space.is_readable(addr + i, 1) {
+            return Err(result::INVALID_POINTER);
+        }
+        let mut byte = [0];
+        space.memory().read(addr + i, &mut byte);
+        if byte[0] == 0 {
+            return String::from_utf8(name).map_err(|_| result::NOT_FOUND);
+        }
+        name.push(byte[0]);
+    }
+    Err(result::OUT_OF_RANGE)
+}
+
+/// Resolve the thread handle in `Xreg`, accepting the current-thread
+/// pseudo-handle
+fn current_thread_arg(kernel: &Kernel, core: &UnicornCPU, reg: u32) -> Result<u64, ResultCode> {
+    let current = kernel.scheduler.current(core.core_id as usize);
+    kernel.thread_from_handle(core.get_x(reg) as u32, current)
+}
+
+/// `svcSleepThread`: positive values sleep, 0/-1/-2 are the three yield kinds
+fn sleep_thread(kernel: &mut Kernel, core: &UnicornCPU, ns: i64) {
+    let core_id = core.core_id as usize;
+    core.set_x(0, ResultCode::SUCCESS.0 as u64);
+    let Some(id) = kernel.scheduler.current(core_id) else {
+        return;
+    };
+    match ns {
+        0 => kernel
+            .scheduler
+            .yield_current(core_id, YieldKind::WithoutMigration),
+        -1 => kernel
+            .scheduler
+            .yield_current(core_id, YieldKind::WithMigration),
+        -2 => kernel
+            .scheduler
+            .yield_current(core_id, YieldKind::ToAnyThread),
+        ns if ns > 0 => kernel.scheduler.block(id, None, Some(ns as u64)),
+        _ => {}
+    }
+}
+
+/// `svcSetThreadCoreMask`, with the argument validation order of the real
+/// kernel
+fn set_thread_core_mask(
+    kernel: &mut Kernel,
+    core: &UnicornCPU,
+    core_id: i32,
+    affinity_mask: u64,
+) -> Result<(), ResultCode> {
+    let (mut core_id, affinity_mask) = if core_id == IDEAL_CORE_USE_PROCESS_VALUE {
+        let ideal = kernel.process.ideal_core;
+        (ideal as i32, 1u64 << ideal)
+    } else {
+        let process_mask = kernel.process.core_mask;
+        if affinity_mask | process_mask != process_mask {
+            return Err(result::INVALID_CORE_ID);
+        }
+        if affinity_mask == 0 {
+            return Err(result::INVALID_COMBINATION);
+        }
+        if (0..64).contains(&core_id) {
+            if affinity_mask & (1 << core_id) == 0 {
+                return Err(result::INVALID_COMBINATION);
+            }
+        } else if core_id != IDEAL_CORE_NO_UPDATE && core_id != IDEAL_CORE_DONT_CARE {
+            return Err(result::INVALID_CORE_ID);
+        }
+        (core_id, affinity_mask)
+    };
+
+    let id = current_thread_arg(kernel, core, 0)?;
+    let thread = kernel.scheduler.thread(id).ok_or(result::INVALID_HANDLE)?;
+    if core_id == IDEAL_CORE_NO_UPDATE {
+        core_id = thread.ideal_core;
+        if core_id >= 0 && affinity_mask & (1 << core_id) == 0 {
+            return Err(result::INVALID_COMBINATION);
+        }
+    }
+    kernel.scheduler.set_core_mask(id, core_id, affinity_mask);
+    Ok(())
+}
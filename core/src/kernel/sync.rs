@@ -0,0 +1,583 @@
+//! Synchronization objects and the SVCs that block on them
+//!
+//! A blocked thread records what it waits for in [`Thread::wait`]. Blocking
+//! calls return the result the guest sees if nothing wakes the thread before
+//! its timeout; whoever wakes it overwrites that result in its saved
+//! registers instead.
+//!
+//! Mutexes and condition variables follow the Horizon user-space protocol:
+//! the 32-bit mutex word holds the owner's thread handle, with
+//! [`HAS_WAITERS`] set once another thread has to sleep on it, and the
+//! condition variable word is 1 while threads wait on it. Priority
+//! inheritance is not modelled.
+//!
+//! [`Thread::wait`]: crate::kernel::thread::Thread::wait
+
+use crate::kernel::Kernel;
+use crate::kernel::handle::{Handle, KernelObject};
+use crate::kernel::ipc::SessionId;
+use crate::kernel::resource_limit::LimitableResource;
+use crate::kernel::result::{self, ResultCode};
+use crate::kernel::thread::{ThreadId, ThreadState};
+
+pub type EventId = u64;
+
+/// Set in a mutex word when threads are waiting for it
+pub const HAS_WAITERS: u32 = 0x4000_0000;
+/// Most handles `svcWaitSynchronization` accepts at once
+pub const MAX_WAIT_OBJECTS: usize = 0x40;
+
+#[derive(Debug, Default)]
+pub struct Event {
+    pub signaled: bool,
+}
+
+/// What a waiting thread is blocked on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Wait {
+    /// `svcWaitSynchronization` on any of these objects
+    Objects(Vec<KernelObject>),
+    /// `svcArbitrateLock`, queued on the thread owning the mutex
+    Mutex {
+        address: u64,
+        owner: ThreadId,
+        tag: u32,
+    },
+    /// `svcWaitProcessWideKeyAtomic`, to relock `mutex` once signaled
+    ConditionVariable { key: u64, mutex: u64, tag: u32 },
+    /// `svcWaitForAddress`
+    Address(u64),
+    /// `svcReplyAndReceive` on any of these objects, receiving the request
+    /// if a server session is signaled
+    Receive(Vec<KernelObject>),
+    /// `svcSendSyncRequest` on a session served by a guest thread
+    Reply(SessionId),
+}
+
+/// `svcWaitForAddress` arbitration types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArbitrationType {
+    WaitIfLessThan,
+    DecrementAndWaitIfLessThan,
+    WaitIfEqual,
+}
+
+impl ArbitrationType {
+    pub fn from_raw(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::WaitIfLessThan),
+            1 => Some(Self::DecrementAndWaitIfLessThan),
+            2 => Some(Self::WaitIfEqual),
+            _ => None,
+        }
+    }
+}
+
+/// `svcSignalToAddress` signal types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalType {
+    Signal,
+    SignalAndIncrementIfEqual,
+    SignalAndModifyByWaitingCountIfEqual,
+}
+
+impl SignalType {
+    pub fn from_raw(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Signal),
+            1 => Some(Self::SignalAndIncrementIfEqual),
+            2 => Some(Self::SignalAndModifyByWaitingCountIfEqual),
+            _ => None,
+        }
+    }
+}
+
+/// SVC timeouts are signed: negative waits forever, 0 only polls
+fn timeout_ns(timeout: i64) -> Option<u64> {
+    (timeout >= 0).then_some(timeout as u64)
+}
+
+impl Kernel {
+    /// Create an event, returning its writable and readable handles
+    pub fn create_event(&mut self) -> Result<(Handle, Handle), ResultCode> {
+        let limit = &mut self.process.resource_limit;
+        limit.reserve(LimitableResource::Events, 1)?;
+        let id = self.process.add_event();
+        let handles = &mut self.process.handles;
+        let writable = handles.add(KernelObject::WritableEvent(id));
+        let readable = handles.add(KernelObject::ReadableEvent(id));
+        match (writable, readable) {
+            (Ok(writable), Ok(readable)) => Ok((writable, readable)),
+            (writable, readable) => {
+                for handle in [writable, readable].into_iter().flatten() {
+                    handles.remove(handle);
+                }
+                self.process.remove_event(id);
+                Err(result::OUT_OF_HANDLES)
+            }
+        }
+    }
+
+    /// Signal an event through its writable handle, waking its waiters
+    pub fn signal_event(&mut self, handle: Handle) -> Result<(), ResultCode> {
+        let Some(KernelObject::WritableEvent(id)) = self.process.handles.get(handle) else {
+            return Err(result::INVALID_HANDLE);
+        };
+        let event = self
+            .process
+            .events
+            .get_mut(&id)
+            .ok_or(result::INVALID_HANDLE)?;
+        if !event.signaled {
+            event.signaled = true;
+            self.signal_object(KernelObject::ReadableEvent(id));
+        }
+        Ok(())
+    }
+
+    /// `svcClearEvent`: accepts either half of the event
+    pub fn clear_event(&mut self, handle: Handle) -> Result<(), ResultCode> {
+        let id = match self.process.handles.get(handle) {
+            Some(KernelObject::WritableEvent(id) | KernelObject::ReadableEvent(id)) => id,
+            _ => return Err(result::INVALID_HANDLE),
+        };
+        let event = self
+            .process
+            .events
+            .get_mut(&id)
+            .ok_or(result::INVALID_HANDLE)?;
+        event.signaled = false;
+        Ok(())
+    }
+
+    /// `svcResetSignal`: like clearing, but fails if nothing was signaled
+    pub fn reset_signal(&mut self, handle: Handle) -> Result<(), ResultCode> {
+        let Some(KernelObject::ReadableEvent(id)) = self.process.handles.get(handle) else {
+            return Err(result::INVALID_HANDLE);
+        };
+        let event = self
+            .process
+            .events
+            .get_mut(&id)
+            .ok_or(result::INVALID_HANDLE)?;
+        if !event.signaled {
+            return Err(result::INVALID_STATE);
+        }
+        event.signaled = false;
+        Ok(())
+    }
+
+    /// `svcWaitSynchronization`, returning the index of the signaled handle
+    pub fn wait_synchronization(
+        &mut self,
+        current: ThreadId,
+        handles: &[Handle],
+        timeout: i64,
+    ) -> Result<u64, ResultCode> {
+        let objects = self.waitable_objects(handles)?;
+        if let Some(index) = objects.iter().position(|&o| self.is_signaled(o)) {
+            return Ok(index as u64);
+        }
+        if timeout == 0 {
+            return Err(result::TIMED_OUT);
+        }
+        if self.take_wait_cancelled(current) {
+            return Err(result::CANCELLED);
+        }
+
+        self.scheduler
+            .block(current, Some(Wait::Objects(objects)), timeout_ns(timeout));
+        Err(result::TIMED_OUT)
+    }
+
+    /// `svcCancelSynchronization`
+    pub fn cancel_synchronization(&mut self, id: ThreadId) {
+        let Some(thread) = self.scheduler.thread_mut(id) else {
+            return;
+        };
+        if matches!(thread.wait, Some(Wait::Objects(_) | Wait::Receive(_))) {
+            self.end_wait(id, result::CANCELLED, None);
+        } else {
+            thread.wait_cancelled = true;
+        }
+    }
+
+    /// `svcArbitrateLock`: sleep until the owner hands over the mutex
+    pub fn arbitrate_lock(
+        &mut self,
+        current: ThreadId,
+        owner_handle: Handle,
+        address: u64,
+        tag: u32,
+    ) -> Result<(), ResultCode> {
+        self.check_arbiter_address(address)?;
+        if self.read_user_u32(address)? != owner_handle | HAS_WAITERS {
+            return Ok(());
+        }
+        let Some(KernelObject::Thread(owner)) = self.process.handles.get(owner_handle) else {
+            return Err(result::INVALID_HANDLE);
+        };
+        let wait = Wait::Mutex {
+            address,
+            owner,
+            tag,
+        };
+        self.scheduler.block(current, Some(wait), None);
+        Ok(())
+    }
+
+    /// `svcArbitrateUnlock`
+    pub fn arbitrate_unlock(&mut self, current: ThreadId, address: u64) -> Result<(), ResultCode> {
+        self.check_arbiter_address(address)?;
+        self.release_mutex(current, address)
+    }
+
+    /// `svcWaitProcessWideKeyAtomic`: release the mutex and wait on `key`
+    pub fn wait_process_wide_key(
+        &mut self,
+        current: ThreadId,
+        mutex: u64,
+        key: u64,
+        tag: u32,
+        timeout: i64,
+    ) -> Result<(), ResultCode> {
+        self.check_arbiter_address(mutex)?;
+        self.write_user_u32(key, 1)?;
+        self.release_mutex(current, mutex)?;
+        if timeout == 0 {
+            return Err(result::TIMED_OUT);
+        }
+        let wait = Wait::ConditionVariable { key, mutex, tag };
+        self.scheduler
+            .block(current, Some(wait), timeout_ns(timeout));
+        Err(result::TIMED_OUT)
+    }
+
+    /// `svcSignalProcessWideKey`: wake up to `count` waiters (all if
+    /// `count <= 0`), each of which then tries to relock its mutex
+    pub fn signal_process_wide_key(&mut self, key: u64, count: i32) {
+        let waiters =
+            self.waiters(|w| matches!(w, Wait::ConditionVariable { key: k, .. } if *k == key));
+        let woken = if count <= 0 {
+            waiters.len()
+        } else {
+            waiters.len().min(count as usize)
+        };
+        for &id in &waiters[..woken] {
+            self.relock_after_signal(id);
+        }
+        if woken == waiters.len() {
+            let _ = self.write_user_u32(key, 0);
+        }
+    }
+
+    /// `svcWaitForAddress`
+    pub fn wait_for_address(
+        &mut self,
+        current: ThreadId,
+        address: u64,
+        kind: u32,
+        value: i32,
+        timeout: i64,
+    ) -> Result<(), ResultCode> {
+        self.check_arbiter_address(address)?;
+        let kind = ArbitrationType::from_raw(kind).ok_or(result::INVALID_ENUM_VALUE)?;
+
+        let current_value = self.read_user_u32(address)? as i32;
+        let should_wait = match kind {
+            ArbitrationType::WaitIfLessThan => current_value < value,
+            ArbitrationType::DecrementAndWaitIfLessThan => {
+                if current_value < value {
+                    self.write_user_u32(address, current_value.wrapping_sub(1) as u32)?;
+                }
+                current_value < value
+            }
+            ArbitrationType::WaitIfEqual => current_value == value,
+        };
+        if !should_wait {
+            return Err(result::INVALID_STATE);
+        }
+        if timeout == 0 {
+            return Err(result::TIMED_OUT);
+        }
+
+        self.scheduler
+            .block(current, Some(Wait::Address(address)), timeout_ns(timeout));
+        Err(result::TIMED_OUT)
+    }
+
+    /// `svcSignalToAddress`: optionally update the value, then wake up to
+    /// `count` waiters (all if `count <= 0`)
+    pub fn signal_to_address(
+        &mut self,
+        address: u64,
+        kind: u32,
+        value: i32,
+        count: i32,
+    ) -> Result<(), ResultCode> {
+        self.check_arbiter_address(address)?;
+        let kind = SignalType::from_raw(kind).ok_or(result::INVALID_ENUM_VALUE)?;
+        let waiters = self.waiters(|w| *w == Wait::Address(address));
+
+        let new_value = match kind {
+            SignalType::Signal => None,
+            SignalType::SignalAndIncrementIfEqual => Some(value.wrapping_add(1)),
+            SignalType::SignalAndModifyByWaitingCountIfEqual => Some(if waiters.is_empty() {
+                value.wrapping_add(1)
+            } else if count <= 0 {
+                value.wrapping_sub(2)
+            } else if ((waiters.len() - 1) as i64) < count as i64 {
+                // Everyone waiting is about to be woken
+                value.wrapping_sub(1)
+            } else {
+                value
+            }),
+        };
+        if let Some(new_value) = new_value {
+            if self.read_user_u32(address)? as i32 != value {
+                return Err(result::INVALID_STATE);
+            }
+            if new_value != value {
+                self.write_user_u32(address, new_value as u32)?;
+            }
+        }
+
+        let woken = if count <= 0 {
+            waiters.len()
+        } else {
+            waiters.len().min(count as usize)
+        };
+        for &id in &waiters[..woken] {
+            self.end_wait(id, ResultCode::SUCCESS, None);
+        }
+        Ok(())
+    }
+
+    /// Wake the threads waiting for `object` through
+    /// `svcWaitSynchronization` or `svcReplyAndReceive`, best priority first
+    ///
+    /// Receiving on a server session takes its request, so later waiters
+    /// only wake while the session stays signaled.
+    pub(crate) fn signal_object(&mut self, object: KernelObject) {
+        let waiters = self.waiters(|w| match w {
+            Wait::Objects(objects) | Wait::Receive(objects) => objects.contains(&object),
+            _ => false,
+        });
+        for id in waiters {
+            if !self.is_signaled(object) {
+                break;
+            }
+            let Some((index, receive)) = self
+                .scheduler
+                .thread(id)
+                .and_then(|t| match &t.wait {
+                    Some(Wait::Objects(objects)) => Some((objects, false)),
+                    Some(Wait::Receive(objects)) => Some((objects, true)),
+                    _ => None,
+                })
+                .map(|(objects, receive)| {
+                    (objects.iter().position(|&o| o == object).unwrap(), receive)
+                })
+            else {
+                continue;
+            };
+            let out = match object {
+                KernelObject::ServerSession(session) if receive => {
+                    self.receive_request(id, session)
+                }
+                _ => Ok(()),
+            };
+            self.end_wait(
+                id,
+                out.err().unwrap_or(ResultCode::SUCCESS),
+                Some(index as u64),
+            );
+        }
+    }
+
+    /// Resolve the handles of a multi-object wait
+    pub(crate) fn waitable_objects(
+        &self,
+        handles: &[Handle],
+    ) -> Result<Vec<KernelObject>, ResultCode> {
+        if handles.len() > MAX_WAIT_OBJECTS {
+            return Err(result::OUT_OF_RANGE);
+        }
+        handles
+            .iter()
+            .map(|&handle| match self.process.handles.get(handle) {
+                Some(
+                    object @ (KernelObject::Thread(_)
+                    | KernelObject::ReadableEvent(_)
+                    | KernelObject::ServerSession(_)
+                    | KernelObject::ServerPort(_)),
+                ) => Ok(object),
+                _ => Err(result::INVALID_HANDLE),
+            })
+            .collect()
+    }
+
+    /// Consume a cancellation requested while the thread was not waiting
+    pub(crate) fn take_wait_cancelled(&mut self, id: ThreadId) -> bool {
+        match self.scheduler.thread_mut(id) {
+            Some(thread) => std::mem::take(&mut thread.wait_cancelled),
+            None => false,
+        }
+    }
+
+    pub(crate) fn is_signaled(&self, object: KernelObject) -> bool {
+        match object {
+            KernelObject::Thread(id) => self
+                .scheduler
+                .thread(id)
+                .is_none_or(|t| t.state == ThreadState::Terminated),
+            KernelObject::ReadableEvent(id) => {
+                self.process.events.get(&id).is_some_and(|e| e.signaled)
+            }
+            KernelObject::ServerSession(id) => self.is_session_signaled(id),
+            KernelObject::ServerPort(id) => self.is_port_signaled(id),
+            KernelObject::WritableEvent(_)
+            | KernelObject::ClientSession(_)
+            | KernelObject::SharedMemory(_)
+            | KernelObject::TransferMemory(_) => false,
+        }
+    }
+
+    /// Wake a waiting thread, making `result` (and `index` in X1) the return
+    /// value of the SVC it is blocked in
+    pub(crate) fn end_wait(&mut self, id: ThreadId, result: ResultCode, index: Option<u64>) {
+        let Some(thread) = self.scheduler.thread_mut(id) else {
+            return;
+        };
+        thread.context.x[0] = result.0 as u64;
+        if let Some(index) = index {
+            thread.context.x[1] = index;
+        }
+        self.scheduler.wake(id);
+    }
+
+    /// Threads whose wait matches, best priority first and FIFO within a
+    /// priority
+    fn waiters(&self, matches: impl Fn(&Wait) -> bool) -> Vec<ThreadId> {
+        let mut waiters: Vec<_> = self
+            .scheduler
+            .threads()
+            .filter(|t| t.state == ThreadState::Waiting && t.wait.as_ref().is_some_and(&matches))
+            .map(|t| (t.priority, t.wait_seq, t.id))
+            .collect();
+        waiters.sort_unstable();
+        waiters.into_iter().map(|(_, _, id)| id).collect()
+    }
+
+    /// Hand the mutex at `address` from `owner` to its best waiter, or
+    /// unlock it if nobody waits
+    fn release_mutex(&mut self, owner: ThreadId, address: u64) -> Result<(), ResultCode> {
+        let waiters = self.waiters(
+            |w| matches!(w, Wait::Mutex { address: a, owner: o, .. } if *a == address && *o == owner),
+        );
+
+        let Some((&next, rest)) = waiters.split_first() else {
+            return self.write_user_u32(address, 0);
+        };
+        let Some(Wait::Mutex { tag, .. }) =
+            self.scheduler.thread(next).and_then(|t| t.wait.clone())
+        else {
+            unreachable!("mutex waiters wait on a mutex");
+        };
+        // The remaining waiters now queue on the new owner
+        for &other in rest {
+            if let Some(Wait::Mutex { owner, .. }) = self
+                .scheduler
+                .thread_mut(other)
+                .and_then(|t| t.wait.as_mut())
+            {
+                *owner = next;
+            }
+        }
+
+        let value = if rest.is_empty() {
+            tag
+        } else {
+            tag | HAS_WAITERS
+        };
+        match self.write_user_u32(address, value) {
+            Ok(()) => {
+                self.end_wait(next, ResultCode::SUCCESS, None);
+                Ok(())
+            }
+            Err(code) => {
+                self.end_wait(next, code, None);
+                Err(code)
+            }
+        }
+    }
+
+    /// Try to give a signaled condition-variable waiter its mutex back,
+    /// queuing it on the current owner if the mutex is taken
+    fn relock_after_signal(&mut self, id: ThreadId) {
+        let Some(Wait::ConditionVariable { mutex, tag, .. }) =
+            self.scheduler.thread(id).and_then(|t| t.wait.clone())
+        else {
+            return;
+        };
+
+        let value = match self.read_user_u32(mutex) {
+            Ok(value) => value,
+            Err(code) => return self.end_wait(id, code, None),
+        };
+        if value == 0 {
+            let out = self.write_user_u32(mutex, tag);
+            return self.end_wait(id, out.err().unwrap_or(ResultCode::SUCCESS), None);
+        }
+        if let Err(code) = self.write_user_u32(mutex, value | HAS_WAITERS) {
+            return self.end_wait(id, code, None);
+        }
+        match self.process.handles.get(value & !HAS_WAITERS) {
+            Some(KernelObject::Thread(owner)) => {
+                if let Some(thread) = self.scheduler.thread_mut(id) {
+                    thread.wait = Some(Wait::Mutex {
+                        address: mutex,
+                        owner,
+                        tag,
+                    });
+                }
+            }
+            _ => self.end_wait(id, result::INVALID_STATE, None),
+        }
+    }
+
+    fn check_arbiter_address(&self, address: u64) -> Result<(), ResultCode> {
+        if !address.is_multiple_of(4) {
+            return Err(result::INVALID_ADDRESS);
+        }
+        if !self
+            .process
+            .address_space
+            .layout
+            .address_space
+            .contains(address, 4)
+        {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        Ok(())
+    }
+
+    fn read_user_u32(&self, address: u64) -> Result<u32, ResultCode> {
+        let space = &self.process.address_space;
+        if !space.is_readable(address, 4) {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        space
+            .memory()
+            .read_u32(address)
+            .ok_or(result::INVALID_CURRENT_MEMORY)
+    }
+
+    fn write_user_u32(&self, address: u64, value: u32) -> Result<(), ResultCode> {
+        let space = &self.process.address_space;
+        if !space.is_writable(address, 4) || !space.memory().write_u32(address, value) {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        Ok(())
+    }
+}
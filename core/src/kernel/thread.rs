@@ -0,0 +1,110 @@
+use crate::cpu::CpuContext;
+use crate::kernel::sync::Wait;
+
+pub type ThreadId = u64;
+
+/// Number of scheduler priority levels; 0 is the highest
+pub const PRIORITY_COUNT: usize = 64;
+pub const LOWEST_PRIORITY: u32 = PRIORITY_COUNT as u32 - 1;
+
+/// `svcSetThreadCoreMask` ideal core values with special meaning
+pub const IDEAL_CORE_DONT_CARE: i32 = -1;
+pub const IDEAL_CORE_USE_PROCESS_VALUE: i32 = -2;
+pub const IDEAL_CORE_NO_UPDATE: i32 = -3;
+
+/// Size of the per-thread region `TPIDRRO_EL0` points at; IPC messages are
+/// exchanged through its first 0x100 bytes
+pub const TLS_SIZE: u64 = 0x200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Created by `svcCreateThread` but not started yet
+    Created,
+    /// Running on a core or queued to run
+    Runnable,
+    /// Blocked until woken by another thread or its deadline
+    Waiting,
+    Terminated,
+}
+
+/// How a thread gave up its core through `svcSleepThread`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YieldKind {
+    /// 0: let other threads of the same priority on this core run
+    WithoutMigration,
+    /// -1: additionally allow moving to another core in the affinity mask
+    WithMigration,
+    /// -2: let any other thread run, even one of lower priority
+    ToAnyThread,
+}
+
+/// A guest thread and the state the scheduler keeps for it
+pub struct Thread {
+    pub id: ThreadId,
+    pub state: ThreadState,
+    pub priority: u32,
+    /// Core the thread prefers, or [`IDEAL_CORE_DONT_CARE`]
+    pub ideal_core: i32,
+    pub affinity_mask: u64,
+    /// Core whose queue the thread currently belongs to
+    pub active_core: usize,
+    /// Saved registers; only meaningful while the thread is off-core
+    pub context: CpuContext,
+    pub tls_address: u64,
+    /// What a [`ThreadState::Waiting`] thread is blocked on, if anything
+    /// other than the clock
+    pub wait: Option<Wait>,
+    /// Orders threads that started waiting at the same priority
+    pub wait_seq: u64,
+    /// Emulated time, in nanoseconds, at which a waiting thread times out
+    pub wake_at: Option<u64>,
+    /// Set by `svcCancelSynchronization` when the thread was not waiting,
+    /// so its next `svcWaitSynchronization` fails instead
+    pub wait_cancelled: bool,
+    /// Set by a `ToAnyThread` yield until the thread is next scheduled
+    pub yielded_to_any: bool,
+    /// Emulated nanoseconds the thread has spent on a core
+    pub cpu_time: u64,
+}
+
+impl Thread {
+    pub fn new(
+        id: ThreadId,
+        entry: u64,
+        arg: u64,
+        stack_top: u64,
+        priority: u32,
+        core: usize,
+        tls_address: u64,
+    ) -> Self {
+        let mut context = CpuContext {
+            pc: entry,
+            // The ABI requires a 16-byte aligned stack
+            sp: stack_top & !0xF,
+            tpidrro_el0: tls_address,
+            ..Default::default()
+        };
+        context.x[0] = arg;
+
+        Self {
+            id,
+            state: ThreadState::Created,
+            priority,
+            ideal_core: core as i32,
+            affinity_mask: 1 << core,
+            active_core: core,
+            context,
+            tls_address,
+            wait: None,
+            wait_seq: 0,
+            wake_at: None,
+            wait_cancelled: false,
+            yielded_to_any: false,
+            cpu_time: 0,
+        }
+    }
+
+    pub fn can_run_on(&self, core: usize) -> bool {
+        self.affinity_mask & (1 << core) != 0
+    }
+}
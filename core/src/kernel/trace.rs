@@ -0,0 +1,312 @@
+//! Timeline of kernel events, exported as a Chrome trace
+//!
+//! The kernel records SVCs and the waits they block in on each thread's
+//! track, which thread occupies each core, and the host IPC commands
+//! threads call. Timestamps are emulated time, so a trace of the same
+//! program is the same every run. The JSON opens in `chrome://tracing` or
+//! the Perfetto UI.
+//!
+//! Recording is off unless [`Tracer::start`] is called, or from the start
+//! when built with the `trace` feature; while off, every hook returns after
+//! a single check.
+
+use crate::kernel::svc;
+use crate::kernel::thread::ThreadId;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+/// Chrome trace process IDs grouping the two kinds of track
+const CORES_PID: u32 = 1;
+const THREADS_PID: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Track {
+    Core(usize),
+    Thread(ThreadId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Begin,
+    End,
+    /// A span whose length is known when it is recorded
+    Complete {
+        duration: u64,
+    },
+    Instant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub name: String,
+    pub category: &'static str,
+    pub phase: Phase,
+    pub track: Track,
+    /// Emulated nanoseconds
+    pub time: u64,
+    pub args: Vec<(&'static str, String)>,
+}
+
+/// An SVC a thread is in until it next runs
+struct OpenSvc {
+    /// Whether the thread blocked in it, opening a wait span as well
+    waiting: bool,
+}
+
+pub struct Tracer {
+    enabled: bool,
+    events: Vec<Event>,
+    /// Thread each core's open span belongs to, and when it started
+    on_core: Vec<Option<(ThreadId, u64)>>,
+    open_svcs: BTreeMap<ThreadId, OpenSvc>,
+    /// Thread and time of the SVC being serviced, for events the kernel
+    /// records on its behalf
+    current: Option<(ThreadId, u64)>,
+}
+
+impl Tracer {
+    pub fn new(core_count: usize) -> Self {
+        Self {
+            enabled: cfg!(feature = "trace"),
+            events: Vec::new(),
+            on_core: vec![None; core_count],
+            open_svcs: BTreeMap::new(),
+            current: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn start(&mut self) {
+        self.enabled = true;
+    }
+
+    /// Stop recording; spans still open stay out of the trace
+    pub fn stop(&mut self) {
+        self.enabled = false;
+        self.on_core.fill(None);
+        self.open_svcs.clear();
+        self.current = None;
+    }
+
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    /// `thread` occupies `core` from `time`, or nothing does; ends the span
+    /// of the thread that was there before
+    pub fn on_core(&mut self, core: usize, thread: Option<ThreadId>, time: u64) {
+        if !self.enabled {
+            return;
+        }
+        let open = self.on_core[core];
+        if open.map(|(id, _)| id) == thread {
+            return;
+        }
+        if let Some((id, start)) = open {
+            self.push(Event {
+                name: format!("thread {id}"),
+                category: "sched",
+                phase: Phase::Complete {
+                    duration: time - start,
+                },
+                track: Track::Core(core),
+                time: start,
+                args: vec![("thread", id.to_string())],
+            });
+        }
+        self.on_core[core] = thread.map(|id| (id, time));
+    }
+
+    /// `thread` resumed at `time`, ending the SVC and wait it was left in
+    pub fn resume(&mut self, thread: ThreadId, time: u64) {
+        if !self.enabled {
+            return;
+        }
+        if let Some(open) = self.open_svcs.remove(&thread) {
+            if open.waiting {
+                self.end(thread, "wait", time);
+            }
+            self.end(thread, "svc", time);
+        }
+    }
+
+    pub fn svc_enter(&mut self, thread: ThreadId, id: u32, time: u64) {
+        if !self.enabled {
+            return;
+        }
+        let name = svc::name(id).map_or_else(|| format!("Svc{id:#04x}"), String::from);
+        self.push(Event {
+            name,
+            category: "svc",
+            phase: Phase::Begin,
+            track: Track::Thread(thread),
+            time,
+            args: vec![("id", format!("{id:#04x}"))],
+        });
+        self.open_svcs.insert(thread, OpenSvc { waiting: false });
+        self.current = Some((thread, time));
+    }
+
+    /// The SVC returned without blocking; one that blocked in `wait` ends
+    /// when the thread next runs
+    pub fn svc_exit(&mut self, thread: ThreadId, time: u64, wait: Option<String>) {
+        if !self.enabled {
+            return;
+        }
+        self.current = None;
+        let Some(wait) = wait else {
+            self.open_svcs.remove(&thread);
+            self.end(thread, "svc", time);
+            return;
+        };
+        self.push(Event {
+            name: String::from("wait"),
+            category: "wait",
+            phase: Phase::Begin,
+            track: Track::Thread(thread),
+            time,
+            args: vec![("on", wait)],
+        });
+        self.open_svcs.insert(thread, OpenSvc { waiting: true });
+    }
+
+    /// A command on a host service, called by the thread whose SVC is being
+    /// serviced
+    pub fn ipc_request(&mut self, service: &str, interface: &str, command: u32) {
+        if !self.enabled {
+            return;
+        }
+        let Some((thread, time)) = self.current else {
+            return;
+        };
+        self.push(Event {
+            name: format!("{interface}#{command}"),
+            category: "ipc",
+            phase: Phase::Instant,
+            track: Track::Thread(thread),
+            time,
+            args: vec![
+                ("service", service.to_string()),
+                ("interface", interface.to_string()),
+                ("command", command.to_string()),
+            ],
+        });
+    }
+
+    fn end(&mut self, thread: ThreadId, category: &'static str, time: u64) {
+        self.push(Event {
+            name: String::new(),
+            category,
+            phase: Phase::End,
+            track: Track::Thread(thread),
+            time,
+            args: Vec::new(),
+        });
+    }
+
+    fn push(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    /// The events in Chrome's trace event JSON format
+    pub fn to_chrome_json(&self) -> String {
+        let mut tracks: Vec<Track> = self.events.iter().map(|e| e.track).collect();
+        tracks.sort();
+        tracks.dedup();
+
+        let mut entries = vec![
+            metadata(CORES_PID, None, "process_name", "CPU"),
+            metadata(THREADS_PID, None, "process_name", "Guest threads"),
+        ];
+        for track in tracks {
+            let (pid, tid) = ids(track);
+            let name = match track {
+                Track::Core(core) => format!("Core {core}"),
+                Track::Thread(id) => format!("Thread {id}"),
+            };
+            entries.push(metadata(pid, Some(tid), "thread_name", &name));
+        }
+        entries.extend(self.events.iter().map(chrome_event));
+
+        let mut json = String::from("{\"displayTimeUnit\":\"ns\",\"traceEvents\":[\n");
+        json.push_str(&entries.join(",\n"));
+        json.push_str("\n]}\n");
+        json
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.to_chrome_json())
+    }
+}
+
+fn ids(track: Track) -> (u32, u64) {
+    match track {
+        Track::Core(core) => (CORES_PID, core as u64),
+        Track::Thread(id) => (THREADS_PID, id),
+    }
+}
+
+fn metadata(pid: u32, tid: Option<u64>, kind: &str, name: &str) -> String {
+    let tid = tid.map_or(String::new(), |tid| format!(",\"tid\":{tid}"));
+    format!(
+        "{{\"ph\":\"M\",\"pid\":{pid}{tid},\"name\":\"{kind}\",\"args\":{{\"name\":{}}}}}",
+        quote(name)
+    )
+}
+
+fn chrome_event(event: &Event) -> String {
+    let (pid, tid) = ids(event.track);
+    // Timestamps are in microseconds
+    let micros = |ns: u64| format!("{}.{:03}", ns / 1000, ns % 1000);
+    let mut out = format!(
+        "{{\"name\":{},\"cat\":\"{}\",\"pid\":{pid},\"tid\":{tid},\"ts\":{}",
+        quote(&event.name),
+        event.category,
+        micros(event.time)
+    );
+    match event.phase {
+        Phase::Begin => out.push_str(",\"ph\":\"B\""),
+        Phase::End => out.push_str(",\"ph\":\"E\""),
+        Phase::Complete { duration } => {
+            write!(out, ",\"ph\":\"X\",\"dur\":{}", micros(duration)).unwrap();
+        }
+        // Scoped to the thread's track
+        Phase::Instant => out.push_str(",\"ph\":\"i\",\"s\":\"t\""),
+    }
+    if !event.args.is_empty() {
+        let args: Vec<String> = event
+            .args
+            .iter()
+            .map(|(key, value)| format!("\"{key}\":{}", quote(value)))
+            .collect();
+        write!(out, ",\"args\":{{{}}}", args.join(",")).unwrap();
+    }
+    out.push('}');
+    out
+}
+
+/// `text` as a JSON string literal
+fn quote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -1,6 +1,9 @@
 pub mod cpu;
+pub mod crypto;
 pub mod fs;
 pub mod gpu;
+pub mod kernel;
+pub mod loader;
 pub mod tests;
 pub mod nn;
 pub mod sys;
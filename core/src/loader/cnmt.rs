@@ -0,0 +1,233 @@
+//! CNMT content meta: the NCAs that make up a title
+//!
+//! Every installable title has a Meta NCA whose PFS0 section holds a single
+//! `.cnmt` file. It names the title, its version and type, and lists each
+//! of the title's other NCAs by content ID with its type and size, along
+//! with any further titles it depends on (a system update lists the
+//! system titles it installs). An extended header after the fixed one
+//! holds what only some types have, such as the system version an
+//! application needs.
+
+use crate::fs::{VirtualDir, invalid_data};
+use crate::kernel::result::ResultCode;
+use crate::loader::{RESULT_INVALID_PACKAGE_FORMAT, u32_at, u64_at};
+use std::io;
+
+const HEADER_SIZE: usize = 0x20;
+const CONTENT_RECORD_SIZE: usize = 0x38;
+const META_RECORD_SIZE: usize = 0x10;
+
+/// What kind of title a content meta describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentMetaType {
+    SystemProgram,
+    SystemData,
+    SystemUpdate,
+    BootImagePackage,
+    BootImagePackageSafe,
+    Application,
+    Patch,
+    AddOnContent,
+    Delta,
+    DataPatch,
+}
+
+impl ContentMetaType {
+    fn from_raw(raw: u8) -> Option<Self> {
+        Some(match raw {
+            0x01 => Self::SystemProgram,
+            0x02 => Self::SystemData,
+            0x03 => Self::SystemUpdate,
+            0x04 => Self::BootImagePackage,
+            0x05 => Self::BootImagePackageSafe,
+            0x80 => Self::Application,
+            0x81 => Self::Patch,
+            0x82 => Self::AddOnContent,
+            0x83 => Self::Delta,
+            0x84 => Self::DataPatch,
+            _ => return None,
+        })
+    }
+}
+
+/// What a title's NCA holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Meta,
+    Program,
+    Data,
+    Control,
+    HtmlDocument,
+    LegalInformation,
+    DeltaFragment,
+}
+
+impl ContentType {
+    fn from_raw(raw: u8) -> Option<Self> {
+        Some(match raw {
+            0 => Self::Meta,
+            1 => Self::Program,
+            2 => Self::Data,
+            3 => Self::Control,
+            4 => Self::HtmlDocument,
+            5 => Self::LegalInformation,
+            6 => Self::DeltaFragment,
+            _ => return None,
+        })
+    }
+}
+
+/// One of a title's NCAs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentRecord {
+    /// SHA-256 of the whole NCA
+    pub hash: [u8; 0x20],
+    /// The first half of `hash`, which the NCA is named after
+    pub content_id: [u8; 0x10],
+    pub size: u64,
+    pub content_type: ContentType,
+    /// Tells apart contents of the same type, such as a program's
+    /// per-program manuals
+    pub id_offset: u8,
+}
+
+impl ContentRecord {
+    /// The name the NCA is stored under: its content ID in hex
+    pub fn file_name(&self) -> String {
+        let id: String = self.content_id.iter().map(|b| format!("{b:02x}")).collect();
+        match self.content_type {
+            ContentType::Meta => format!("{id}.cnmt.nca"),
+            _ => format!("{id}.nca"),
+        }
+    }
+}
+
+/// A further title a content meta refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentMetaRecord {
+    pub title_id: u64,
+    pub version: u32,
+    pub meta_type: ContentMetaType,
+    pub attributes: u8,
+}
+
+/// A parsed `.cnmt` file
+#[derive(Debug, Clone)]
+pub struct Cnmt {
+    pub title_id: u64,
+    pub version: u32,
+    pub meta_type: ContentMetaType,
+    pub required_download_system_version: u32,
+    /// The system version needed to run an application or patch
+    pub required_system_version: Option<u32>,
+    /// The application a patch or add-on belongs to
+    pub application_id: Option<u64>,
+    /// The patch title of an application
+    pub patch_id: Option<u64>,
+    /// The application version an add-on needs, or an application's
+    /// own minimum
+    pub required_application_version: Option<u32>,
+    pub contents: Vec<ContentRecord>,
+    pub meta: Vec<ContentMetaRecord>,
+}
+
+impl Cnmt {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ResultCode> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(RESULT_INVALID_PACKAGE_FORMAT);
+        }
+        let half = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as usize;
+        let meta_type =
+            ContentMetaType::from_raw(bytes[0xC]).ok_or(RESULT_INVALID_PACKAGE_FORMAT)?;
+        let extended_size = half(0xE);
+        let content_count = half(0x10);
+        let meta_count = half(0x12);
+
+        let extended = bytes
+            .get(HEADER_SIZE..HEADER_SIZE + extended_size)
+            .ok_or(RESULT_INVALID_PACKAGE_FORMAT)?;
+        let contents_at = HEADER_SIZE + extended_size;
+        let meta_at = contents_at + content_count * CONTENT_RECORD_SIZE;
+        let end = meta_at + meta_count * META_RECORD_SIZE;
+        if bytes.len() < end {
+            return Err(RESULT_INVALID_PACKAGE_FORMAT);
+        }
+
+        let mut cnmt = Self {
+            title_id: u64_at(bytes, 0).unwrap(),
+            version: u32_at(bytes, 8).unwrap(),
+            meta_type,
+            required_download_system_version: u32_at(bytes, 0x18).unwrap(),
+            required_system_version: None,
+            application_id: None,
+            patch_id: None,
+            required_application_version: None,
+            contents: bytes[contents_at..meta_at]
+                .chunks_exact(CONTENT_RECORD_SIZE)
+                .map(parse_content_record)
+                .collect::<Result<_, _>>()?,
+            meta: bytes[meta_at..end]
+                .chunks_exact(META_RECORD_SIZE)
+                .map(parse_meta_record)
+                .collect::<Result<_, _>>()?,
+        };
+        let missing = RESULT_INVALID_PACKAGE_FORMAT;
+        match meta_type {
+            ContentMetaType::Application => {
+                cnmt.patch_id = Some(u64_at(extended, 0).ok_or(missing)?);
+                cnmt.required_system_version = Some(u32_at(extended, 8).ok_or(missing)?);
+                cnmt.required_application_version = u32_at(extended, 0xC);
+            }
+            ContentMetaType::Patch => {
+                cnmt.application_id = Some(u64_at(extended, 0).ok_or(missing)?);
+                cnmt.required_system_version = Some(u32_at(extended, 8).ok_or(missing)?);
+            }
+            ContentMetaType::AddOnContent => {
+                cnmt.application_id = Some(u64_at(extended, 0).ok_or(missing)?);
+                cnmt.required_application_version = Some(u32_at(extended, 8).ok_or(missing)?);
+            }
+            ContentMetaType::Delta => cnmt.application_id = u64_at(extended, 0),
+            _ => {}
+        }
+        Ok(cnmt)
+    }
+
+    /// Read the content meta out of the PFS0 section of a Meta NCA
+    pub fn open(section: &VirtualDir) -> io::Result<Self> {
+        let file = section
+            .files()?
+            .into_iter()
+            .find(|file| file.name().ends_with(".cnmt"))
+            .ok_or_else(|| invalid_data("Meta NCA section holds no .cnmt file"))?;
+        Self::parse(&file.read_all()?)
+            .map_err(|_| invalid_data(format!("{} is not a valid content meta", file.name())))
+    }
+
+    /// The first of the title's NCAs of type `content_type`
+    pub fn content(&self, content_type: ContentType) -> Option<&ContentRecord> {
+        self.contents
+            .iter()
+            .find(|record| record.content_type == content_type)
+    }
+}
+
+fn parse_content_record(bytes: &[u8]) -> Result<ContentRecord, ResultCode> {
+    let mut size = [0; 8];
+    size[..6].copy_from_slice(&bytes[0x30..0x36]);
+    Ok(ContentRecord {
+        hash: bytes[..0x20].try_into().unwrap(),
+        content_id: bytes[0x20..0x30].try_into().unwrap(),
+        size: u64::from_le_bytes(size),
+        content_type: ContentType::from_raw(bytes[0x36]).ok_or(RESULT_INVALID_PACKAGE_FORMAT)?,
+        id_offset: bytes[0x37],
+    })
+}
+
+fn parse_meta_record(bytes: &[u8]) -> Result<ContentMetaRecord, ResultCode> {
+    Ok(ContentMetaRecord {
+        title_id: u64_at(bytes, 0).unwrap(),
+        version: u32_at(bytes, 8).unwrap(),
+        meta_type: ContentMetaType::from_raw(bytes[0xC]).ok_or(RESULT_INVALID_PACKAGE_FORMAT)?,
+        attributes: bytes[0xD],
+    })
+}
@@ -0,0 +1,229 @@
+//! Dynamic linking of loaded modules
+//!
+//! The `.dynamic` section MOD0 points at leads to a module's symbol table,
+//! string table and relocations. Symbols a module defines are recorded on
+//! its [`Module`], so HLE targets, crash reports and a debugger can name
+//! guest functions, and relocations are applied the way the runtime's own
+//! loader applies them, with imports resolved against every module loaded
+//! so far. The guest's rtld or crt0 applies the same relocations again when
+//! it starts, which is harmless: RELA relocations take their addends from
+//! the table rather than from memory.
+//!
+//! [`Module`]: crate::kernel::process::Module
+
+use crate::cpu::GuestMemory;
+use crate::kernel::Kernel;
+use crate::loader::{Mod0, Segment};
+use std::collections::BTreeMap;
+
+const DT_NULL: u64 = 0;
+const DT_PLTRELSZ: u64 = 2;
+const DT_HASH: u64 = 4;
+const DT_STRTAB: u64 = 5;
+const DT_SYMTAB: u64 = 6;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_STRSZ: u64 = 10;
+const DT_JMPREL: u64 = 23;
+
+pub const R_AARCH64_ABS64: u32 = 257;
+pub const R_AARCH64_GLOB_DAT: u32 = 1025;
+pub const R_AARCH64_JUMP_SLOT: u32 = 1026;
+pub const R_AARCH64_RELATIVE: u32 = 1027;
+
+/// `Elf64_Sym` and `Elf64_Rela` are both three words long
+const ENTRY_SIZE: u64 = 0x18;
+const SHN_UNDEF: u16 = 0;
+const STB_WEAK: u8 = 2;
+/// Upper bound on `.dynamic` entries, in case DT_NULL is missing
+const MAX_DYNAMIC_ENTRIES: u64 = 0x200;
+
+/// The tables `.dynamic` points at, as offsets from the module's base
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dynamic {
+    pub symtab: u64,
+    pub strtab: Segment,
+    /// Entries in the symbol table, from DT_HASH or, without it, the gap
+    /// between the symbol and string tables that linkers leave
+    pub symbol_count: u64,
+    pub rela: Segment,
+    /// The PLT's relocations
+    pub jmprel: Segment,
+}
+
+struct Symbol {
+    name: String,
+    value: u64,
+    defined: bool,
+    weak: bool,
+}
+
+/// A module being linked, by where it was mapped
+struct Linked {
+    base: u64,
+    size: u64,
+    dynamic: Dynamic,
+    symbols: Vec<Symbol>,
+}
+
+impl Dynamic {
+    /// Read the `.dynamic` section `offset` bytes into the module at `base`,
+    /// `size` bytes long
+    pub fn read(memory: &GuestMemory, base: u64, size: u64, offset: u64) -> Option<Self> {
+        let mut tags = BTreeMap::new();
+        for i in 0..MAX_DYNAMIC_ENTRIES {
+            let at = offset + i * 0x10;
+            if at + 0x10 > size {
+                return None;
+            }
+            let tag = memory.read_u64(base + at)?;
+            if tag == DT_NULL {
+                break;
+            }
+            tags.insert(tag, memory.read_u64(base + at + 8)?);
+        }
+
+        let tag = |tag| tags.get(&tag).copied().unwrap_or(0);
+        let table = |offset, size| Segment {
+            offset: tag(offset),
+            size: tag(size),
+        };
+        let symtab = tag(DT_SYMTAB);
+        let strtab = table(DT_STRTAB, DT_STRSZ);
+        let symbol_count = match tags.get(&DT_HASH) {
+            Some(&hash) if hash.saturating_add(8) <= size => {
+                memory.read_u32(base + hash + 4)? as u64
+            }
+            Some(_) => return None,
+            None => strtab.offset.saturating_sub(symtab) / ENTRY_SIZE,
+        };
+        let dynamic = Self {
+            symtab,
+            strtab,
+            symbol_count,
+            rela: table(DT_RELA, DT_RELASZ),
+            jmprel: table(DT_JMPREL, DT_PLTRELSZ),
+        };
+        let symbols = Segment {
+            offset: symtab,
+            size: symbol_count * ENTRY_SIZE,
+        };
+        [symbols, strtab, dynamic.rela, dynamic.jmprel]
+            .iter()
+            .all(|table| {
+                table
+                    .offset
+                    .checked_add(table.size)
+                    .is_some_and(|end| end <= size)
+            })
+            .then_some(dynamic)
+    }
+}
+
+impl Linked {
+    fn read(memory: &GuestMemory, base: u64, size: u64, mod0: &Mod0) -> Option<Self> {
+        let dynamic = Dynamic::read(memory, base, size, mod0.dynamic)?;
+        let strtab = memory.read_vec(base + dynamic.strtab.offset, dynamic.strtab.size as usize)?;
+        let mut symbols = Vec::new();
+        for i in 0..dynamic.symbol_count {
+            let entry = memory.read_vec(base + dynamic.symtab + i * ENTRY_SIZE, 0x18)?;
+            let name = u32::from_le_bytes(entry[0..4].try_into().unwrap()) as usize;
+            let name = strtab.get(name..).map(super::c_str).unwrap_or_default();
+            let section = u16::from_le_bytes(entry[6..8].try_into().unwrap());
+            symbols.push(Symbol {
+                name,
+                value: u64::from_le_bytes(entry[8..16].try_into().unwrap()),
+                defined: section != SHN_UNDEF,
+                weak: entry[4] >> 4 == STB_WEAK,
+            });
+        }
+        Some(Self {
+            base,
+            size,
+            dynamic,
+            symbols,
+        })
+    }
+}
+
+impl Kernel {
+    /// Record the symbols of the freshly mapped modules at `modules`' base
+    /// addresses and apply their relocations
+    ///
+    /// This is best effort: a module whose dynamic section cannot be read
+    /// is left for the guest to link, and a relocation against a symbol no
+    /// module defines is left as it is.
+    pub fn link_modules(&mut self, modules: &[(u64, Mod0)]) {
+        let memory = self.cpu.memory();
+        let mut linked = Vec::new();
+        for (base, mod0) in modules {
+            let Some(module) = self.process.modules.iter_mut().find(|m| m.base == *base) else {
+                continue;
+            };
+            let Some(parsed) = Linked::read(&memory, module.base, module.size, mod0) else {
+                log::warn!("Cannot read the dynamic section of {}", module.name);
+                continue;
+            };
+            module.symbols = parsed
+                .symbols
+                .iter()
+                .filter(|s| s.defined && !s.name.is_empty())
+                .map(|s| (s.name.clone(), s.value))
+                .collect();
+            linked.push(parsed);
+        }
+
+        for module in &linked {
+            for table in [module.dynamic.rela, module.dynamic.jmprel] {
+                for i in 0..table.size / ENTRY_SIZE {
+                    let at = module.base + table.offset + i * ENTRY_SIZE;
+                    let (Some(offset), Some(info), Some(addend)) = (
+                        memory.read_u64(at),
+                        memory.read_u64(at + 8),
+                        memory.read_u64(at + 16),
+                    ) else {
+                        break;
+                    };
+                    self.relocate(&memory, module, offset, info, addend);
+                }
+            }
+        }
+    }
+
+    /// Apply one `Elf64_Rela`
+    fn relocate(&self, memory: &GuestMemory, module: &Linked, offset: u64, info: u64, addend: u64) {
+        if offset.saturating_add(8) > module.size {
+            log::warn!("Relocation at {offset:#x} lies outside its module");
+            return;
+        }
+        let value = match info as u32 {
+            R_AARCH64_RELATIVE => module.base.wrapping_add(addend),
+            R_AARCH64_ABS64 | R_AARCH64_GLOB_DAT | R_AARCH64_JUMP_SLOT => {
+                let Some(address) = self.resolve(module, (info >> 32) as usize) else {
+                    log::debug!("Leaving the relocation at {offset:#x} unresolved");
+                    return;
+                };
+                address.wrapping_add(addend)
+            }
+            kind => {
+                log::warn!("Unsupported relocation type {kind} at {offset:#x}");
+                return;
+            }
+        };
+        memory.write_u64(module.base + offset, value);
+    }
+
+    /// Where symbol `index` of `module` lives: the first module loaded that
+    /// defines it, or zero for the null symbol and missing weak ones
+    fn resolve(&self, module: &Linked, index: usize) -> Option<u64> {
+        if index == 0 {
+            return Some(0);
+        }
+        let symbol = module.symbols.get(index)?;
+        self.process
+            .symbol_address(&symbol.name)
+            .filter(|_| !symbol.name.is_empty())
+            .or(symbol.defined.then_some(module.base + symbol.value))
+            .or(symbol.weak.then_some(0))
+    }
+}
@@ -0,0 +1,173 @@
+//! Parsers for the executable and metadata formats titles ship in, and the
+//! code that turns them into a guest process
+
+pub mod cnmt;
+pub mod elf;
+pub mod nacp;
+pub mod npdm;
+pub mod nro;
+pub mod nso;
+
+use crate::kernel::Kernel;
+use crate::kernel::memory::{MemoryAttribute, MemoryPermission, MemoryState, PAGE_SIZE};
+use crate::kernel::process::Module;
+use crate::kernel::result::{self, ResultCode};
+
+const MODULE_NCM: u32 = 5;
+const MODULE_LOADER: u32 = 9;
+const MODULE_RO: u32 = 22;
+
+const MOD0_MAGIC: &[u8; 4] = b"MOD0";
+
+pub const RESULT_INVALID_META: ResultCode = ResultCode::new(MODULE_LOADER, 4);
+pub const RESULT_INVALID_NSO: ResultCode = ResultCode::new(MODULE_LOADER, 5);
+pub const RESULT_INVALID_PROGRAM_ID: ResultCode = ResultCode::new(MODULE_LOADER, 9);
+pub const RESULT_INVALID_NRO: ResultCode = ResultCode::new(MODULE_RO, 4);
+pub const RESULT_INVALID_PACKAGE_FORMAT: ResultCode = ResultCode::new(MODULE_NCM, 130);
+
+/// A range of an executable image, relative to where it is loaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Segment {
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl Segment {
+    pub fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+}
+
+/// How an executable's image is laid out once loaded: code, read-only data
+/// and data, each starting on a page, with the zeroed BSS after the data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Segments {
+    pub text: Segment,
+    pub ro: Segment,
+    pub data: Segment,
+    pub bss_size: u64,
+}
+
+impl Segments {
+    /// Bytes the image takes up in memory, BSS included
+    pub fn size(&self) -> u64 {
+        (self.data.end() + self.bss_size).next_multiple_of(PAGE_SIZE)
+    }
+
+    /// Whether the segments start on pages and follow each other in order
+    pub fn is_valid(&self) -> bool {
+        let aligned = [self.text, self.ro, self.data]
+            .iter()
+            .all(|s| s.offset.is_multiple_of(PAGE_SIZE));
+        aligned
+            && self.text.offset == 0
+            && self.text.end() <= self.ro.offset
+            && self.ro.end() <= self.data.offset
+    }
+}
+
+/// The MOD0 header the runtime finds through the word at offset 4 of text,
+/// with its offsets made relative to the start of the image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mod0 {
+    /// Where MOD0 itself is
+    pub offset: u64,
+    /// The `.dynamic` section
+    pub dynamic: u64,
+    pub bss_start: u64,
+    pub bss_end: u64,
+    pub eh_frame_hdr_start: u64,
+    pub eh_frame_hdr_end: u64,
+    /// Where the runtime keeps its module object, which links the loaded
+    /// modules together
+    pub module_object: u64,
+}
+
+impl Mod0 {
+    /// The MOD0 header of a loaded `image`, if it has one
+    pub fn parse(image: &[u8]) -> Option<Self> {
+        let offset = u32_at(image, 4)? as usize;
+        if image.get(offset..offset.checked_add(4)?)? != MOD0_MAGIC {
+            return None;
+        }
+        let field = |at: usize| {
+            let relative = u32_at(image, offset + at)? as i32 as i64;
+            (offset as u64).checked_add_signed(relative)
+        };
+        Some(Self {
+            offset: offset as u64,
+            dynamic: field(0x04)?,
+            bss_start: field(0x08)?,
+            bss_end: field(0x0C)?,
+            eh_frame_hdr_start: field(0x10)?,
+            eh_frame_hdr_end: field(0x14)?,
+            module_object: field(0x18)?,
+        })
+    }
+}
+
+impl Kernel {
+    /// Copy a module's `image`, laid out as `segments` describes, to `base`
+    /// and map it: text read-execute, read-only data read-only, and data
+    /// and BSS read-write
+    pub fn map_module(
+        &mut self,
+        name: &str,
+        build_id: [u8; 0x20],
+        base: u64,
+        image: &[u8],
+        segments: &Segments,
+    ) -> Result<(), ResultCode> {
+        let size = segments.size();
+        if !segments.is_valid() || image.len() as u64 > size {
+            return Err(result::INVALID_ARGUMENT);
+        }
+        let space = &mut self.process.address_space;
+        space.map(base, size, MemoryState::Code, MemoryPermission::READ)?;
+        space.memory().write(base, image);
+
+        let (text_end, data) = (segments.ro.offset, segments.data.offset);
+        if text_end > 0 {
+            let permission = MemoryPermission::READ_EXECUTE;
+            space.update(
+                base,
+                text_end,
+                MemoryState::Code,
+                permission,
+                MemoryAttribute::NONE,
+            );
+        }
+        if size > data {
+            let permission = MemoryPermission::READ_WRITE;
+            let (state, attribute) = (MemoryState::CodeData, MemoryAttribute::NONE);
+            space.update(base + data, size - data, state, permission, attribute);
+        }
+        self.cpu.invalidate_code(base, size);
+        self.process.modules.push(Module {
+            name: name.to_string(),
+            base,
+            size,
+            build_id,
+            ..Default::default()
+        });
+        Ok(())
+    }
+}
+
+/// The little-endian `u32` at `offset`, if `bytes` is long enough
+pub(crate) fn u32_at(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// The little-endian `u64` at `offset`, if `bytes` is long enough
+pub(crate) fn u64_at(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// A NUL-padded string field
+pub(crate) fn c_str(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
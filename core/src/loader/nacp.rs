@@ -0,0 +1,156 @@
+//! NACP application control properties
+//!
+//! The `control.nacp` in a title's Control NCA (or an NRO's ASET section)
+//! is a fixed 0x4000-byte block: the title's name and publisher in each of
+//! sixteen languages, then its display version, which languages it
+//! supports and the sizes of the save data `ns` creates for it. The icons
+//! sit beside it in the Control NCA as `icon_<Language>.dat` JPEGs.
+
+use crate::fs::{VirtualDir, VirtualFile};
+use crate::kernel::result::ResultCode;
+use crate::loader::{RESULT_INVALID_PACKAGE_FORMAT, c_str, u32_at, u64_at};
+
+pub const NACP_SIZE: usize = 0x4000;
+const TITLE_SIZE: usize = 0x300;
+const NAME_SIZE: usize = 0x200;
+
+/// The languages a title can be named in, in the order of the NACP's
+/// title table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    AmericanEnglish,
+    BritishEnglish,
+    Japanese,
+    French,
+    German,
+    LatinAmericanSpanish,
+    Spanish,
+    Italian,
+    Dutch,
+    CanadianFrench,
+    Portuguese,
+    Russian,
+    Korean,
+    TraditionalChinese,
+    SimplifiedChinese,
+    BrazilianPortuguese,
+}
+
+impl Language {
+    pub const ALL: [Self; 16] = [
+        Self::AmericanEnglish,
+        Self::BritishEnglish,
+        Self::Japanese,
+        Self::French,
+        Self::German,
+        Self::LatinAmericanSpanish,
+        Self::Spanish,
+        Self::Italian,
+        Self::Dutch,
+        Self::CanadianFrench,
+        Self::Portuguese,
+        Self::Russian,
+        Self::Korean,
+        Self::TraditionalChinese,
+        Self::SimplifiedChinese,
+        Self::BrazilianPortuguese,
+    ];
+
+    /// The name the language's icon is stored under in a Control NCA
+    pub fn icon_name(self) -> String {
+        format!("icon_{self:?}.dat")
+    }
+}
+
+/// A title's name and publisher in one language
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplicationTitle {
+    pub name: String,
+    pub publisher: String,
+}
+
+/// A parsed `control.nacp`
+#[derive(Debug, Clone)]
+pub struct Nacp {
+    /// Indexed like `Language::ALL`; empty where the title has no name in
+    /// that language
+    pub titles: [ApplicationTitle; 16],
+    pub isbn: String,
+    pub display_version: String,
+    /// Bit `n` set if the title supports `Language::ALL[n]`
+    pub supported_languages: u32,
+    /// Whether a user has to be picked before the title starts
+    pub startup_user_account: bool,
+    pub presence_group_id: u64,
+    pub add_on_content_base_id: u64,
+    pub save_data_owner_id: u64,
+    pub user_account_save_data_size: u64,
+    pub user_account_save_data_journal_size: u64,
+    pub device_save_data_size: u64,
+    pub device_save_data_journal_size: u64,
+    pub bcat_delivery_cache_storage_size: u64,
+    pub cache_storage_size: u64,
+    pub cache_storage_journal_size: u64,
+}
+
+impl Nacp {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ResultCode> {
+        if bytes.len() < NACP_SIZE {
+            return Err(RESULT_INVALID_PACKAGE_FORMAT);
+        }
+        let long = |offset| u64_at(bytes, offset).unwrap();
+        Ok(Self {
+            titles: std::array::from_fn(|index| {
+                let title = &bytes[index * TITLE_SIZE..(index + 1) * TITLE_SIZE];
+                ApplicationTitle {
+                    name: c_str(&title[..NAME_SIZE]),
+                    publisher: c_str(&title[NAME_SIZE..]),
+                }
+            }),
+            isbn: c_str(&bytes[0x3000..0x3025]),
+            display_version: c_str(&bytes[0x3060..0x3070]),
+            supported_languages: u32_at(bytes, 0x302C).unwrap(),
+            startup_user_account: bytes[0x3025] != 0,
+            presence_group_id: long(0x3038),
+            add_on_content_base_id: long(0x3070),
+            save_data_owner_id: long(0x3078),
+            user_account_save_data_size: long(0x3080),
+            user_account_save_data_journal_size: long(0x3088),
+            device_save_data_size: long(0x3090),
+            device_save_data_journal_size: long(0x3098),
+            bcat_delivery_cache_storage_size: long(0x30A0),
+            cache_storage_size: long(0x3170),
+            cache_storage_journal_size: long(0x3178),
+        })
+    }
+
+    pub fn supports(&self, language: Language) -> bool {
+        self.supported_languages & 1 << language as u32 != 0
+    }
+
+    /// The title in `language`, or else in the first language it has one
+    /// in, as the home menu falls back
+    pub fn title(&self, language: Language) -> Option<&ApplicationTitle> {
+        let preferred = &self.titles[language as usize];
+        if !preferred.name.is_empty() {
+            return Some(preferred);
+        }
+        self.titles.iter().find(|title| !title.name.is_empty())
+    }
+
+    /// The icon for `language` in the Control NCA's `control` section,
+    /// falling back like `title`
+    pub fn icon(&self, control: &VirtualDir, language: Language) -> Option<VirtualFile> {
+        let files = control.files().ok()?;
+        let find = |language: Language| {
+            let name = language.icon_name();
+            files.iter().find(|file| file.name() == name).cloned()
+        };
+        find(language).or_else(|| {
+            Language::ALL
+                .into_iter()
+                .filter(|&language| !self.titles[language as usize].name.is_empty())
+                .find_map(find)
+        })
+    }
+}
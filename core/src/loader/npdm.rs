@@ -0,0 +1,175 @@
+//! NPDM program metadata (`main.npdm`)
+//!
+//! A META header describing the main thread and address space, followed by
+//! two access control blocks: the ACID, which is signed and bounds what the
+//! title may ever be granted, and the ACI0, which is what it asks for.
+
+use crate::kernel::capabilities::Capabilities;
+use crate::kernel::memory::AddressSpaceType;
+use crate::kernel::result::ResultCode;
+use crate::loader::{RESULT_INVALID_META, RESULT_INVALID_PROGRAM_ID, c_str, u32_at, u64_at};
+
+const META_MAGIC: &[u8; 4] = b"META";
+const ACID_MAGIC: &[u8; 4] = b"ACID";
+const ACI0_MAGIC: &[u8; 4] = b"ACI0";
+const META_SIZE: usize = 0x80;
+/// The ACID header follows a 0x100-byte signature and 0x100-byte key
+const ACID_HEADER: usize = 0x200;
+const ACID_SIZE: usize = 0x240;
+const ACI0_SIZE: usize = 0x40;
+
+/// A service a title may connect to, or register when `is_server` is set;
+/// names may end in `*` to match a prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceAccess {
+    pub name: String,
+    pub is_server: bool,
+}
+
+/// The access control descriptor: the limits signed by the title's issuer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Acid {
+    /// Signed with the production rather than development key
+    pub production: bool,
+    pub program_id_min: u64,
+    pub program_id_max: u64,
+    /// Raw filesystem access control
+    pub fs_access: Vec<u8>,
+    pub service_access: Vec<ServiceAccess>,
+    pub kernel_capabilities: Vec<u32>,
+}
+
+/// The access control info: what the title requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Aci {
+    pub program_id: u64,
+    /// Raw filesystem access header
+    pub fs_access: Vec<u8>,
+    pub service_access: Vec<ServiceAccess>,
+    pub kernel_capabilities: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Npdm {
+    pub name: String,
+    pub product_code: String,
+    /// Whether the process runs AArch64 code
+    pub is_64bit: bool,
+    pub address_space_type: AddressSpaceType,
+    pub main_thread_priority: u8,
+    pub main_thread_core: u8,
+    pub main_thread_stack_size: u32,
+    pub system_resource_size: u32,
+    pub version: u32,
+    pub acid: Acid,
+    pub aci: Aci,
+}
+
+impl Npdm {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ResultCode> {
+        let header = bytes.get(..META_SIZE).ok_or(RESULT_INVALID_META)?;
+        if &header[..4] != META_MAGIC {
+            return Err(RESULT_INVALID_META);
+        }
+        let flags = header[0xC];
+        let address_space_type =
+            AddressSpaceType::from_raw((flags >> 1) & 7).ok_or(RESULT_INVALID_META)?;
+        let word = |offset| u32_at(header, offset).unwrap();
+
+        let aci = section(bytes, word(0x70), word(0x74))?;
+        let acid = section(bytes, word(0x78), word(0x7C))?;
+        let npdm = Self {
+            name: c_str(&header[0x20..0x30]),
+            product_code: c_str(&header[0x30..0x40]),
+            is_64bit: flags & 1 != 0,
+            address_space_type,
+            main_thread_priority: header[0xE],
+            main_thread_core: header[0xF],
+            main_thread_stack_size: word(0x1C),
+            system_resource_size: word(0x14),
+            version: word(0x18),
+            acid: parse_acid(acid)?,
+            aci: parse_aci(aci)?,
+        };
+
+        let allowed = npdm.acid.program_id_min..=npdm.acid.program_id_max;
+        if !allowed.contains(&npdm.aci.program_id) {
+            return Err(RESULT_INVALID_PROGRAM_ID);
+        }
+        Ok(npdm)
+    }
+
+    /// The kernel capabilities the process gets, after checking that the
+    /// ACID allows all of them
+    pub fn capabilities(&self, core_count: usize) -> Result<Capabilities, ResultCode> {
+        let allowed = Capabilities::parse(&self.acid.kernel_capabilities, core_count)?;
+        let requested = Capabilities::parse(&self.aci.kernel_capabilities, core_count)?;
+        if !allowed.permits(&requested) {
+            return Err(RESULT_INVALID_META);
+        }
+        Ok(requested)
+    }
+}
+
+fn section(bytes: &[u8], offset: u32, size: u32) -> Result<&[u8], ResultCode> {
+    let start = offset as usize;
+    let end = start
+        .checked_add(size as usize)
+        .ok_or(RESULT_INVALID_META)?;
+    bytes.get(start..end).ok_or(RESULT_INVALID_META)
+}
+
+fn parse_acid(bytes: &[u8]) -> Result<Acid, ResultCode> {
+    if bytes.len() < ACID_SIZE || &bytes[ACID_HEADER..ACID_HEADER + 4] != ACID_MAGIC {
+        return Err(RESULT_INVALID_META);
+    }
+    let header = &bytes[ACID_HEADER..];
+    let word = |offset| u32_at(header, offset).unwrap();
+    Ok(Acid {
+        production: word(0xC) & 1 != 0,
+        program_id_min: u64_at(header, 0x10).unwrap(),
+        program_id_max: u64_at(header, 0x18).unwrap(),
+        fs_access: section(bytes, word(0x20), word(0x24))?.to_vec(),
+        service_access: parse_service_access(section(bytes, word(0x28), word(0x2C))?)?,
+        kernel_capabilities: parse_kernel_capabilities(section(bytes, word(0x30), word(0x34))?)?,
+    })
+}
+
+fn parse_aci(bytes: &[u8]) -> Result<Aci, ResultCode> {
+    if bytes.len() < ACI0_SIZE || &bytes[..4] != ACI0_MAGIC {
+        return Err(RESULT_INVALID_META);
+    }
+    let word = |offset| u32_at(bytes, offset).unwrap();
+    Ok(Aci {
+        program_id: u64_at(bytes, 0x10).unwrap(),
+        fs_access: section(bytes, word(0x20), word(0x24))?.to_vec(),
+        service_access: parse_service_access(section(bytes, word(0x28), word(0x2C))?)?,
+        kernel_capabilities: parse_kernel_capabilities(section(bytes, word(0x30), word(0x34))?)?,
+    })
+}
+
+/// Entries are a control byte, holding the name length minus one and the
+/// server flag in bit 7, followed by the name
+fn parse_service_access(mut bytes: &[u8]) -> Result<Vec<ServiceAccess>, ResultCode> {
+    let mut services = Vec::new();
+    while let Some((&control, rest)) = bytes.split_first() {
+        let len = (control & 7) as usize + 1;
+        let name = rest.get(..len).ok_or(RESULT_INVALID_META)?;
+        services.push(ServiceAccess {
+            name: String::from_utf8_lossy(name).into_owned(),
+            is_server: control & 0x80 != 0,
+        });
+        bytes = &rest[len..];
+    }
+    Ok(services)
+}
+
+fn parse_kernel_capabilities(bytes: &[u8]) -> Result<Vec<u32>, ResultCode> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(RESULT_INVALID_META);
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+        .collect())
+}
@@ -0,0 +1,210 @@
+//! NRO homebrew executables
+//!
+//! An NRO is loaded as it is laid out in the file: text, read-only data and
+//! data, each page aligned, with the header inside the first page of text
+//! and the BSS size given in it. Homebrew tools append an ASET section after
+//! the image holding the title's icon, NACP and RomFS.
+//!
+//! Homebrew starts under the homebrew loader ABI: X0 points at a table of
+//! config entries, including the main thread handle, and X1 is `u64::MAX`,
+//! which is how the runtime tells an NRO start from an NSO start.
+
+use crate::fs::{OffsetFile, VirtualFile};
+use crate::kernel::Kernel;
+use crate::kernel::handle::Handle;
+use crate::kernel::memory::{MemoryPermission, MemoryState, PAGE_SIZE};
+use crate::kernel::process::Process;
+use crate::kernel::result::{self, ResultCode};
+use crate::kernel::svc::MAX_SVC_ID;
+use crate::loader::{Mod0, RESULT_INVALID_NRO, Segment, Segments, u32_at, u64_at};
+use std::sync::Arc;
+
+const NRO_MAGIC: &[u8; 4] = b"NRO0";
+const ASET_MAGIC: &[u8; 4] = b"ASET";
+const HEADER_SIZE: usize = 0x80;
+const ASET_HEADER_SIZE: usize = 0x38;
+
+/// Keys of the homebrew loader config entries
+const ENTRY_END_OF_LIST: u32 = 0;
+const ENTRY_MAIN_THREAD_HANDLE: u32 = 1;
+const ENTRY_ARGV: u32 = 5;
+const ENTRY_SYSCALL_AVAILABLE_HINT: u32 = 6;
+const ENTRY_APPLET_TYPE: u32 = 7;
+const ENTRY_RANDOM_SEED: u32 = 14;
+const ENTRY_SYSCALL_AVAILABLE_HINT2: u32 = 17;
+/// Key, flags and two values
+const ENTRY_SIZE: usize = 0x18;
+const CONFIG_ENTRIES: usize = 7;
+
+/// `AppletType_Application`
+const APPLET_TYPE_APPLICATION: u64 = 0;
+
+/// The sections of the ASET trailer; any of them may be missing
+#[derive(Default)]
+pub struct Assets {
+    /// JPEG, 256x256
+    pub icon: Option<VirtualFile>,
+    pub nacp: Option<VirtualFile>,
+    pub romfs: Option<VirtualFile>,
+}
+
+pub struct Nro {
+    pub name: String,
+    pub segments: Segments,
+    pub build_id: [u8; 0x20],
+    /// Text, read-only data and data, as they are loaded
+    pub image: Vec<u8>,
+    pub mod0: Option<Mod0>,
+    pub assets: Option<Assets>,
+}
+
+impl Nro {
+    pub fn parse(file: &VirtualFile) -> Result<Self, ResultCode> {
+        let header = file
+            .read_vec(0, HEADER_SIZE)
+            .map_err(|_| RESULT_INVALID_NRO)?;
+        if &header[0x10..0x14] != NRO_MAGIC {
+            return Err(RESULT_INVALID_NRO);
+        }
+        let word = |offset| u32_at(&header, offset).unwrap() as u64;
+        let segment = |offset| Segment {
+            offset: word(offset),
+            size: word(offset + 4),
+        };
+        let segments = Segments {
+            text: segment(0x20),
+            ro: segment(0x28),
+            data: segment(0x30),
+            bss_size: word(0x38),
+        };
+        let size = word(0x18);
+        if !segments.is_valid() || segments.data.end() > size || size > file.size() {
+            return Err(RESULT_INVALID_NRO);
+        }
+
+        let image = file
+            .read_vec(0, segments.data.end() as usize)
+            .map_err(|_| RESULT_INVALID_NRO)?;
+        Ok(Self {
+            name: file.name(),
+            segments,
+            build_id: header[0x40..0x60].try_into().unwrap(),
+            mod0: Mod0::parse(&image),
+            image,
+            assets: Assets::parse(file, size)?,
+        })
+    }
+}
+
+impl Assets {
+    /// The ASET trailer at `offset`, if there is one
+    fn parse(file: &VirtualFile, offset: u64) -> Result<Option<Self>, ResultCode> {
+        if file.size() < offset + ASET_HEADER_SIZE as u64 {
+            return Ok(None);
+        }
+        let header = file
+            .read_vec(offset, ASET_HEADER_SIZE)
+            .map_err(|_| RESULT_INVALID_NRO)?;
+        if &header[..4] != ASET_MAGIC {
+            return Ok(None);
+        }
+        let section = |at: usize, name: &str| -> Result<Option<VirtualFile>, ResultCode> {
+            let start = u64_at(&header, at).unwrap();
+            let size = u64_at(&header, at + 8).unwrap();
+            if size == 0 {
+                return Ok(None);
+            }
+            let start = offset.checked_add(start).ok_or(RESULT_INVALID_NRO)?;
+            if start.checked_add(size).is_none_or(|end| end > file.size()) {
+                return Err(RESULT_INVALID_NRO);
+            }
+            Ok(Some(Arc::new(OffsetFile::new(
+                file.clone(),
+                name,
+                start,
+                size,
+            ))))
+        };
+        Ok(Some(Self {
+            icon: section(0x08, "icon.jpg")?,
+            nacp: section(0x18, "control.nacp")?,
+            romfs: section(0x28, "romfs.bin")?,
+        }))
+    }
+}
+
+impl Kernel {
+    /// Start `nro` in a fresh process, before any thread has been created,
+    /// with `args` as its command line; returns the main thread's handle
+    ///
+    /// Without metadata the process may use every SVC and core. By
+    /// convention `args` starts with the path the NRO was loaded from.
+    pub fn load_nro(&mut self, nro: &Nro, args: &str) -> Result<Handle, ResultCode> {
+        if self.scheduler.threads().next().is_some() {
+            return Err(result::INVALID_STATE);
+        }
+        self.process = Process::new(self.cpu.memory());
+
+        let size = nro.segments.size();
+        let config_size = (CONFIG_ENTRIES * ENTRY_SIZE + args.len() + 1) as u64;
+        let config_size = config_size.next_multiple_of(PAGE_SIZE);
+        let space = &self.process.address_space;
+        let base = space
+            .find_free(space.layout.code, size + config_size)
+            .ok_or(result::OUT_OF_MEMORY)?;
+        self.map_module(&nro.name, nro.build_id, base, &nro.image, &nro.segments)?;
+        let config = base + size;
+        self.process.address_space.map(
+            config,
+            config_size,
+            MemoryState::CodeData,
+            MemoryPermission::READ_WRITE,
+        )?;
+
+        let handle = self.start_main_thread(base)?;
+        self.write_loader_config(config, handle, args);
+        let id = self.thread_from_handle(handle, None)?;
+        let context = &mut self.scheduler.thread_mut(id).unwrap().context;
+        context.x[0] = config;
+        context.x[1] = u64::MAX;
+        if let Some(mod0) = nro.mod0 {
+            self.link_modules(&[(base, mod0)]);
+        }
+        self.install_replacements();
+        Ok(handle)
+    }
+
+    /// Write the homebrew loader config entries at `address`, with the
+    /// command line after them
+    fn write_loader_config(&self, address: u64, main_thread: Handle, args: &str) {
+        let capabilities = &self.process.capabilities;
+        let svc_mask = |first: u32| {
+            (0..64)
+                .filter(|bit| first + bit <= MAX_SVC_ID)
+                .filter(|bit| capabilities.is_svc_permitted(first + bit))
+                .fold(0u64, |mask, bit| mask | 1 << bit)
+        };
+        let argv = address + (CONFIG_ENTRIES * ENTRY_SIZE) as u64;
+        let seed = &self.process.random_entropy;
+        let entries: [(u32, [u64; 2]); CONFIG_ENTRIES] = [
+            (ENTRY_MAIN_THREAD_HANDLE, [main_thread as u64, 0]),
+            (ENTRY_APPLET_TYPE, [APPLET_TYPE_APPLICATION, 0]),
+            (ENTRY_SYSCALL_AVAILABLE_HINT, [svc_mask(0), svc_mask(64)]),
+            (ENTRY_SYSCALL_AVAILABLE_HINT2, [svc_mask(128), 0]),
+            (ENTRY_RANDOM_SEED, [seed[0], seed[1]]),
+            (ENTRY_ARGV, [0, argv]),
+            (ENTRY_END_OF_LIST, [0, 0]),
+        ];
+
+        let mut bytes = Vec::with_capacity(CONFIG_ENTRIES * ENTRY_SIZE + args.len() + 1);
+        for (key, [first, second]) in entries {
+            bytes.extend(key.to_le_bytes());
+            bytes.extend(0u32.to_le_bytes());
+            bytes.extend(first.to_le_bytes());
+            bytes.extend(second.to_le_bytes());
+        }
+        bytes.extend(args.as_bytes());
+        bytes.push(0);
+        self.cpu.memory().write(address, &bytes);
+    }
+}
@@ -0,0 +1,134 @@
+//! NSO executables, the modules a title's ExeFS is made of
+//!
+//! Each of text, read-only data and data is stored on its own, optionally
+//! LZ4 compressed and with a SHA-256 hash of its decompressed contents, and
+//! placed at a page-aligned offset when loaded. A title's modules are mapped
+//! one after another in load order; the first, usually `rtld`, is where the
+//! main thread starts and it goes on to call into the others.
+
+use crate::fs::{VirtualDir, VirtualFile};
+use crate::kernel::Kernel;
+use crate::kernel::result::{self, ResultCode};
+use crate::loader::{Mod0, RESULT_INVALID_NSO, Segment, Segments, u32_at};
+use sha2::{Digest, Sha256};
+use std::io::ErrorKind;
+
+const NSO_MAGIC: &[u8; 4] = b"NSO0";
+const HEADER_SIZE: usize = 0x100;
+
+/// The modules an ExeFS may hold, in the order they are mapped
+pub const MODULE_NAMES: [&str; 13] = [
+    "rtld", "main", "subsdk0", "subsdk1", "subsdk2", "subsdk3", "subsdk4", "subsdk5", "subsdk6",
+    "subsdk7", "subsdk8", "subsdk9", "sdk",
+];
+
+pub struct Nso {
+    pub name: String,
+    pub segments: Segments,
+    pub build_id: [u8; 0x20],
+    /// Text, read-only data and data, decompressed and placed as they are
+    /// loaded
+    pub image: Vec<u8>,
+    pub mod0: Option<Mod0>,
+}
+
+impl Nso {
+    pub fn parse(file: &VirtualFile) -> Result<Self, ResultCode> {
+        let header = file
+            .read_vec(0, HEADER_SIZE)
+            .map_err(|_| RESULT_INVALID_NSO)?;
+        if &header[..4] != NSO_MAGIC {
+            return Err(RESULT_INVALID_NSO);
+        }
+        let word = |offset| u32_at(&header, offset).unwrap();
+        let flags = word(0x0C);
+        let segment = |i: usize| Segment {
+            offset: word(0x14 + i * 0x10) as u64,
+            size: word(0x18 + i * 0x10) as u64,
+        };
+        let segments = Segments {
+            text: segment(0),
+            ro: segment(1),
+            data: segment(2),
+            bss_size: word(0x3C) as u64,
+        };
+        if !segments.is_valid() {
+            return Err(RESULT_INVALID_NSO);
+        }
+
+        let mut image = vec![0; segments.data.end() as usize];
+        for (i, segment) in [segments.text, segments.ro, segments.data]
+            .into_iter()
+            .enumerate()
+        {
+            let file_offset = word(0x10 + i * 0x10) as u64;
+            let dest = &mut image[segment.offset as usize..segment.end() as usize];
+            if flags & (1 << i) != 0 {
+                let stored_size = word(0x60 + i * 4) as usize;
+                let compressed = file
+                    .read_vec(file_offset, stored_size)
+                    .map_err(|_| RESULT_INVALID_NSO)?;
+                let len = lz4_flex::block::decompress_into(&compressed, dest)
+                    .map_err(|_| RESULT_INVALID_NSO)?;
+                if len != dest.len() {
+                    return Err(RESULT_INVALID_NSO);
+                }
+            } else {
+                file.read_exact_at(file_offset, dest)
+                    .map_err(|_| RESULT_INVALID_NSO)?;
+            }
+            let hash = &header[0xA0 + i * 0x20..0xC0 + i * 0x20];
+            if flags & (1 << (i + 3)) != 0 && Sha256::digest(dest).as_slice() != hash {
+                return Err(RESULT_INVALID_NSO);
+            }
+        }
+
+        Ok(Self {
+            name: file.name(),
+            segments,
+            build_id: header[0x40..0x60].try_into().unwrap(),
+            mod0: Mod0::parse(&image),
+            image,
+        })
+    }
+
+    /// The modules in `exefs`, in load order; `main` is required
+    pub fn parse_exefs(exefs: &VirtualDir) -> Result<Vec<Self>, ResultCode> {
+        let mut modules = Vec::new();
+        for name in MODULE_NAMES {
+            match exefs.file(name) {
+                Ok(file) => modules.push(Self::parse(&file)?),
+                Err(err) if err.kind() == ErrorKind::NotFound && name != "main" => {}
+                Err(_) => return Err(RESULT_INVALID_NSO),
+            }
+        }
+        Ok(modules)
+    }
+}
+
+impl Kernel {
+    /// Map `modules` one after another in the code region of the current
+    /// process and link them, returning where the first was placed, which is
+    /// where the main thread starts
+    pub fn load_nsos(&mut self, modules: &[Nso]) -> Result<u64, ResultCode> {
+        if modules.is_empty() {
+            return Err(result::INVALID_ARGUMENT);
+        }
+        let size = modules.iter().map(|nso| nso.segments.size()).sum();
+        let space = &self.process.address_space;
+        let base = space
+            .find_free(space.layout.code, size)
+            .ok_or(result::OUT_OF_MEMORY)?;
+
+        let mut address = base;
+        let mut dynamic = Vec::new();
+        for nso in modules {
+            self.map_module(&nso.name, nso.build_id, address, &nso.image, &nso.segments)?;
+            dynamic.extend(nso.mod0.map(|mod0| (address, mod0)));
+            address += nso.segments.size();
+        }
+        self.link_modules(&dynamic);
+        self.install_replacements();
+        Ok(base)
+    }
+}
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.acc = Some(State::new(state));
+        state.register_host(&["acc:u0", "acc:u1", "acc:su", "acc:aa"], State::new);
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.audin = Some(State::new(state));
+        state.register_host(&["audin:u", "audin:a", "audin:d"], State::new);
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.bcat = Some(State::new(state));
+        state.register_host(&["bcat:a", "bcat:m", "bcat:u", "bcat:s"], State::new);
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.bgtc = Some(State::new(state));
+        state.register_host(&["bgtc:t", "bgtc:sc"], State::new);
     }
 }
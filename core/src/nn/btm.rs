@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.btm = Some(State::new(state));
+        state.register_host(&["btm", "btm:dbg", "btm:sys", "btm:u"], State::new);
     }
 }
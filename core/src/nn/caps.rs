@@ -8,6 +8,11 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.caps = Some(State::new(state));
+        state.register_host(
+            &[
+                "caps:a", "caps:c", "caps:u", "caps:sc", "caps:ss", "caps:su",
+            ],
+            State::new,
+        );
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.cec_mgr = Some(State::new(state));
+        state.register_host(&["cec-mgr"], State::new);
     }
 }
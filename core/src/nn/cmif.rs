@@ -0,0 +1,357 @@
+//! CMIF, the command protocol most services speak over HIPC
+//!
+//! A request's raw data starts at the first 16-byte aligned word of the
+//! message with an `SFCI` header carrying the command ID, followed by the
+//! arguments; the reply starts with an `SFCO` header carrying the result.
+//! Requests reserve 16 bytes more than they need so that alignment can be
+//! made up either way.
+//!
+//! A session can be converted into a domain, which multiplexes objects over
+//! it: requests then begin with a domain header naming the target object,
+//! and interfaces a command returns become new objects of the domain rather
+//! than new sessions.
+
+use crate::kernel::Kernel;
+use crate::kernel::hipc::Message;
+use crate::kernel::ipc::SessionHandler;
+use crate::kernel::result::ResultCode;
+use crate::nn::sf::{
+    self, Context, ObjectRef, Output, RESULT_INVALID_IN_HEADER, RESULT_INVALID_IN_OBJECT,
+    RESULT_TARGET_NOT_FOUND, RESULT_UNKNOWN_COMMAND_ID,
+};
+use crate::nn::{ServiceName, ServiceTrait, tipc, unimplemented};
+use std::collections::BTreeMap;
+
+/// "SFCI" and "SFCO", the magic of request and response headers
+pub const IN_MAGIC: u32 = 0x4943_4653;
+pub const OUT_MAGIC: u32 = 0x4F43_4653;
+
+/// Message types of the HIPC header
+pub const TYPE_CLOSE: u16 = 2;
+pub const TYPE_REQUEST: u16 = 4;
+pub const TYPE_CONTROL: u16 = 5;
+pub const TYPE_REQUEST_WITH_CONTEXT: u16 = 6;
+pub const TYPE_CONTROL_WITH_CONTEXT: u16 = 7;
+
+/// Types of a domain request
+pub const DOMAIN_SEND_MESSAGE: u8 = 1;
+pub const DOMAIN_CLOSE: u8 = 2;
+
+/// Commands of control requests, which act on the session itself
+const CONVERT_CURRENT_OBJECT_TO_DOMAIN: u32 = 0;
+const COPY_FROM_CURRENT_DOMAIN: u32 = 1;
+const CLONE_CURRENT_OBJECT: u32 = 2;
+const QUERY_POINTER_BUFFER_SIZE: u32 = 3;
+const CLONE_CURRENT_OBJECT_EX: u32 = 4;
+
+/// ID a session's own object gets when it becomes a domain
+const FIRST_OBJECT_ID: u32 = 1;
+
+/// The pointer buffer size reported to clients. Host services read X
+/// buffers and write C buffers in place, so this only decides which
+/// transfer mode clients pick for small buffers.
+const POINTER_BUFFER_SIZE: u16 = 0x8000;
+
+/// Words of padding that align a CMIF header at `data_offset` to 16 bytes
+pub fn padding(data_offset: usize) -> usize {
+    (16 - data_offset % 16) % 16 / 4
+}
+
+/// A session to a host interface, and the domain it may have become
+///
+/// Each request is decoded by its type, so the same session answers both
+/// CMIF and [`tipc`] clients.
+pub struct Session {
+    object: ObjectRef,
+    domain: Option<Domain>,
+    /// Name the session was opened under through `sm:`, which sessions it
+    /// opens to sub-interfaces inherit for reports
+    service: Option<ServiceName>,
+}
+
+struct Domain {
+    objects: BTreeMap<u32, ObjectRef>,
+    next_id: u32,
+}
+
+impl Session {
+    pub fn new(object: ObjectRef) -> Self {
+        Self {
+            object,
+            domain: None,
+            service: None,
+        }
+    }
+
+    /// A session to `object` that `sm:` handed out as `service`
+    pub fn for_service(object: ObjectRef, service: ServiceName) -> Self {
+        Self {
+            service: Some(service),
+            ..Self::new(object)
+        }
+    }
+}
+
+/// Record a command about to run on `object` in the kernel trace
+pub(crate) fn trace_request(
+    kernel: &mut Kernel,
+    service: Option<ServiceName>,
+    object: &ObjectRef,
+    command: u32,
+) {
+    if kernel.trace.is_enabled() {
+        let service = service.map(|name| unimplemented::display_name(&name));
+        let interface = object.borrow().name();
+        let service = service.as_deref().unwrap_or(interface);
+        kernel.trace.ipc_request(service, interface, command);
+    }
+}
+
+/// Handler for a new session to `service`, for port factories
+pub fn serve(service: impl ServiceTrait + 'static) -> Box<dyn SessionHandler> {
+    Box::new(Session::new(sf::object(service)))
+}
+
+impl SessionHandler for Session {
+    fn handle_request(
+        &mut self,
+        kernel: &mut Kernel,
+        request: &Message,
+    ) -> Result<Message, ResultCode> {
+        let payload = request
+            .data
+            .get(padding(request.data_offset())..)
+            .unwrap_or_default();
+        match request.kind {
+            TYPE_CLOSE => Ok(Message::default()),
+            TYPE_REQUEST | TYPE_REQUEST_WITH_CONTEXT if self.domain.is_some() => {
+                Ok(self.handle_domain_request(kernel, request, payload))
+            }
+            TYPE_REQUEST | TYPE_REQUEST_WITH_CONTEXT => {
+                let object = self.object.clone();
+                Ok(self.invoke(kernel, request, &object, payload, Vec::new()))
+            }
+            TYPE_CONTROL | TYPE_CONTROL_WITH_CONTEXT => {
+                Ok(self.handle_control(kernel, request, payload))
+            }
+            tipc::TYPE_CLOSE => Ok(Message::default()),
+            kind if kind >= tipc::TYPE_FIRST_COMMAND => {
+                Ok(tipc::invoke(kernel, request, &self.object, self.service))
+            }
+            _ => Ok(self.reply(kernel, Err(RESULT_INVALID_IN_HEADER), Output::default())),
+        }
+    }
+}
+
+impl Session {
+    fn handle_domain_request(
+        &mut self,
+        kernel: &mut Kernel,
+        request: &Message,
+        payload: &[u32],
+    ) -> Message {
+        let &[header, object_id, _, _, ref rest @ ..] = payload else {
+            return self.reply(kernel, Err(RESULT_INVALID_IN_HEADER), Output::default());
+        };
+        let kind = header as u8;
+        let in_object_count = (header >> 8) as u8 as usize;
+        let data_words = (header >> 16) as usize / 4;
+        let Some(in_object_ids) = rest.get(data_words..data_words + in_object_count) else {
+            return self.reply(kernel, Err(RESULT_INVALID_IN_HEADER), Output::default());
+        };
+
+        let domain = self.domain.as_mut().unwrap();
+        let result = match kind {
+            DOMAIN_SEND_MESSAGE => {
+                let Some(object) = domain.objects.get(&object_id).cloned() else {
+                    return self.reply(kernel, Err(RESULT_TARGET_NOT_FOUND), Output::default());
+                };
+                let in_objects: Option<Vec<_>> = in_object_ids
+                    .iter()
+                    .map(|id| domain.objects.get(id).cloned())
+                    .collect();
+                let Some(in_objects) = in_objects else {
+                    return self.reply(kernel, Err(RESULT_INVALID_IN_OBJECT), Output::default());
+                };
+                return self.invoke(kernel, request, &object, &rest[..data_words], in_objects);
+            }
+            DOMAIN_CLOSE => match domain.objects.remove(&object_id) {
+                Some(_) => Ok(()),
+                None => Err(RESULT_TARGET_NOT_FOUND),
+            },
+            _ => Err(RESULT_INVALID_IN_HEADER),
+        };
+        self.reply(kernel, result, Output::default())
+    }
+
+    /// Run a command on `object` and frame its reply
+    fn invoke(
+        &mut self,
+        kernel: &mut Kernel,
+        request: &Message,
+        object: &ObjectRef,
+        words: &[u32],
+        in_objects: Vec<ObjectRef>,
+    ) -> Message {
+        let Some((command, args)) = parse_in_header(words) else {
+            return self.reply(kernel, Err(RESULT_INVALID_IN_HEADER), Output::default());
+        };
+        trace_request(kernel, self.service, object, command);
+        let input = sf::to_bytes(args);
+        let mut ctx = Context::new(kernel, request, &input, in_objects);
+        let result = object.borrow_mut().invoke(command, &mut ctx);
+        let output = ctx.finish();
+        let result = result.unwrap_or_else(|| {
+            let interface = object.borrow().name();
+            unimplemented::command(kernel, self.service, interface, command, args)
+        });
+        self.reply(kernel, result, output)
+    }
+
+    /// Requests on the session itself. These never carry a domain header,
+    /// even once the session is a domain.
+    fn handle_control(
+        &mut self,
+        kernel: &mut Kernel,
+        request: &Message,
+        payload: &[u32],
+    ) -> Message {
+        let Some((command, args)) = parse_in_header(payload) else {
+            return reply_message(Err(RESULT_INVALID_IN_HEADER), Output::default(), None);
+        };
+        let input = sf::to_bytes(args);
+        let mut ctx = Context::new(kernel, request, &input, Vec::new());
+        let result = match command {
+            CONVERT_CURRENT_OBJECT_TO_DOMAIN => {
+                self.domain.get_or_insert_with(|| Domain {
+                    objects: BTreeMap::from([(FIRST_OBJECT_ID, self.object.clone())]),
+                    next_id: FIRST_OBJECT_ID + 1,
+                });
+                ctx.push(FIRST_OBJECT_ID);
+                Ok(())
+            }
+            COPY_FROM_CURRENT_DOMAIN => self.copy_from_current_domain(&mut ctx),
+            CLONE_CURRENT_OBJECT | CLONE_CURRENT_OBJECT_EX => {
+                open_session(&mut ctx, self.object.clone(), self.service)
+            }
+            QUERY_POINTER_BUFFER_SIZE => {
+                ctx.push(POINTER_BUFFER_SIZE);
+                Ok(())
+            }
+            _ => Err(RESULT_UNKNOWN_COMMAND_ID),
+        };
+        let output = ctx.finish();
+        // Control replies have no domain header either
+        reply_message(result, output, None)
+    }
+
+    /// A new session to one of the domain's objects
+    fn copy_from_current_domain(&self, ctx: &mut Context<'_>) -> Result<(), ResultCode> {
+        let id: u32 = ctx.pop()?;
+        let object = self
+            .domain
+            .as_ref()
+            .and_then(|domain| domain.objects.get(&id))
+            .ok_or(RESULT_TARGET_NOT_FOUND)?;
+        open_session(ctx, object.clone(), self.service)
+    }
+
+    /// Frame a reply, handing out the interfaces the command returned as
+    /// domain objects or new sessions
+    fn reply(
+        &mut self,
+        kernel: &mut Kernel,
+        result: Result<(), ResultCode>,
+        mut output: Output,
+    ) -> Message {
+        if result.is_err() {
+            output = Output::default();
+        }
+        let Some(domain) = &mut self.domain else {
+            return match open_sessions(kernel, &mut output, self.service) {
+                Ok(()) => reply_message(result, output, None),
+                Err(error) => reply_message(Err(error), Output::default(), None),
+            };
+        };
+        let objects = std::mem::take(&mut output.objects);
+        let ids = objects
+            .into_iter()
+            .map(|object| {
+                let id = domain.next_id;
+                domain.next_id += 1;
+                domain.objects.insert(id, object);
+                id
+            })
+            .collect();
+        reply_message(result, output, Some(ids))
+    }
+}
+
+/// Return a new session to `object` as a moved handle
+fn open_session(
+    ctx: &mut Context<'_>,
+    object: ObjectRef,
+    service: Option<ServiceName>,
+) -> Result<(), ResultCode> {
+    let session = Session {
+        service,
+        ..Session::new(object)
+    };
+    let handle = ctx.kernel.create_host_session(Box::new(session))?;
+    ctx.move_handle(handle);
+    Ok(())
+}
+
+/// Send the interfaces a command returned as new sessions, which is how
+/// they are returned outside a domain
+pub(crate) fn open_sessions(
+    kernel: &mut Kernel,
+    output: &mut Output,
+    service: Option<ServiceName>,
+) -> Result<(), ResultCode> {
+    for object in std::mem::take(&mut output.objects) {
+        let session = Session {
+            service,
+            ..Session::new(object)
+        };
+        let handle = kernel.create_host_session(Box::new(session))?;
+        output.move_handles.push(handle);
+    }
+    Ok(())
+}
+
+/// Command ID and argument words of a request
+fn parse_in_header(words: &[u32]) -> Option<(u32, &[u32])> {
+    match words {
+        [IN_MAGIC, _version, command, _token, args @ ..] => Some((*command, args)),
+        _ => None,
+    }
+}
+
+/// A reply message; `object_ids` is present for replies on a domain
+fn reply_message(
+    result: Result<(), ResultCode>,
+    output: Output,
+    object_ids: Option<Vec<u32>>,
+) -> Message {
+    let result = result.err().unwrap_or(ResultCode::SUCCESS);
+    let mut reply = Message {
+        copy_handles: output.copy_handles,
+        move_handles: output.move_handles,
+        ..Default::default()
+    };
+    let mut payload = Vec::new();
+    if let Some(ids) = &object_ids {
+        payload.extend([ids.len() as u32, 0, 0, 0]);
+    }
+    payload.extend([OUT_MAGIC, 0, result.0, 0]);
+    payload.extend(sf::to_words(&output.data));
+    payload.extend(object_ids.into_iter().flatten());
+
+    // Alignment comes out of the 16 spare bytes; the rest is returned as is
+    let payload_len = payload.len();
+    reply.data = vec![0; padding(reply.data_offset())];
+    reply.data.extend(payload);
+    reply.data.resize(payload_len + 4, 0);
+    reply
+}
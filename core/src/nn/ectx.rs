@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.ectx = Some(State::new(state));
+        state.register_host(&["ectx:aw", "ectx:r", "ectx:w"], State::new);
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.fgm = Some(State::new(state));
+        state.register_host(&["fgm", "fgm:0", "fgm:9"], State::new);
     }
 }
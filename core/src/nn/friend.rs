@@ -8,6 +8,9 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.friend = Some(State::new(state));
+        state.register_host(
+            &["friend:a", "friend:m", "friend:s", "friend:u", "friend:v"],
+            State::new,
+        );
     }
 }
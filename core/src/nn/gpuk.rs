@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.gpuk = Some(State::new(state));
+        state.register_host(&["gpuk"], State::new);
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.hid = Some(State::new(state));
+        state.register_host(&["hid", "hid:dbg", "hid:sys", "hid:tmp"], State::new);
     }
 }
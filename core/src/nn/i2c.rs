@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.i2c = Some(State::new(state));
+        state.register_host(&["i2c", "i2c:pcv"], State::new);
     }
 }
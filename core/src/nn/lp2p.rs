@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.lp2p = Some(State::new(state));
+        state.register_host(&["lp2p:app", "lp2p:sys"], State::new);
     }
 }
@@ -1,5 +1,9 @@
+use crate::kernel::ipc::PortId;
+use crate::kernel::result::ResultCode;
 use crate::nn;
+use crate::nn::sf::{Command, ObjectRef};
 use crate::sys;
+use std::collections::BTreeMap;
 
 mod acc;
 mod adraw;
@@ -38,6 +42,7 @@ mod caps2;
 mod cec_mgr;
 mod chat;
 mod clkrst;
+pub mod cmif;
 mod codecctl;
 mod csrng;
 mod dauth;
@@ -137,8 +142,10 @@ mod ro;
 mod rtc;
 mod sasbus;
 mod set;
+pub mod sf;
 mod sf_uds;
 mod sfdnsres;
+pub mod sm;
 mod spbg;
 mod spi;
 mod spl;
@@ -150,11 +157,13 @@ mod syncpt;
 mod tc;
 mod tcap;
 mod time;
+pub mod tipc;
 mod tma_log;
 mod tmagent;
 mod ts;
 mod tspm;
 mod uart;
+pub mod unimplemented;
 mod usb;
 mod vi;
 mod vi2;
@@ -163,336 +172,251 @@ mod wlan;
 mod xcd;
 
 pub trait ServiceTrait {
-    fn run(_state: &mut sys::State) -> () {
-        todo!();
+    /// Register the interface with the service manager; interfaces that
+    /// are only returned by other commands have nothing to register
+    fn run(_state: &mut sys::State) {}
+
+    /// Name of the interface in logs
+    fn name() -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Commands the interface answers, looked up by ID for each request
+    fn commands() -> &'static [Command<Self>]
+    where
+        Self: Sized,
+    {
+        &[]
     }
 }
 
+/// A service name as `sm:` passes it: up to 8 bytes, padded with zeros
+pub type ServiceName = [u8; 8];
+
+/// Sessions a host service allows at once
+pub const DEFAULT_MAX_SESSIONS: u32 = 0x40;
+
+/// The services `sm:` hands out, by name
+#[derive(Default)]
 pub struct ServiceManager {
-    acc: Option<nn::acc::State>,
-    adraw: Option<nn::adraw::State>,
-    ahid: Option<nn::ahid::State>,
-    aoc: Option<nn::aoc::State>,
-    apm: Option<nn::apm::State>,
-    applet_ae: Option<nn::applet_ae::State>,
-    applet_oe: Option<nn::applet_oe::State>,
-    arp: Option<nn::arp::State>,
-    aud: Option<nn::aud::State>,
-    audctl: Option<nn::audctl::State>,
-    auddebug: Option<nn::auddebug::State>,
-    auddev: Option<nn::auddev::State>,
-    auddmg: Option<nn::auddmg::State>,
-    audin: Option<nn::audin::State>,
-    audout: Option<nn::audout::State>,
-    audrec: Option<nn::audrec::State>,
-    audren: Option<nn::audren::State>,
-    audsmx: Option<nn::audsmx::State>,
-    avm: Option<nn::avm::State>,
-    banana: Option<nn::banana::State>,
-    batlog: Option<nn::batlog::State>,
-    bcat: Option<nn::bcat::State>,
-    bgtc: Option<nn::bgtc::State>,
-    bpc: Option<nn::bpc::State>,
-    bpmpmr: Option<nn::bpmpmr::State>,
-    bsd: Option<nn::bsd::State>,
-    bsdcfg: Option<nn::bsdcfg::State>,
-    bt: Option<nn::bt::State>,
-    btdrv: Option<nn::btdrv::State>,
-    btm: Option<nn::btm::State>,
-    btp: Option<nn::btp::State>,
-    capmtp: Option<nn::capmtp::State>,
-    caps: Option<nn::caps::State>,
-    caps2: Option<nn::caps2::State>,
-    cec_mgr: Option<nn::cec_mgr::State>,
-    chat: Option<nn::chat::State>,
-    clkrst: Option<nn::clkrst::State>,
-    codecctl: Option<nn::codecctl::State>,
-    csrng: Option<nn::csrng::State>,
-    dauth: Option<nn::dauth::State>,
-    disp: Option<nn::disp::State>,
-    dispdrv: Option<nn::dispdrv::State>,
-    dmnt: Option<nn::dmnt::State>,
-    dns: Option<nn::dns::State>,
-    dt: Option<nn::dt::State>,
-    ectx: Option<nn::ectx::State>,
-    erpt: Option<nn::erpt::State>,
-    es: Option<nn::es::State>,
-    eth: Option<nn::eth::State>,
-    ethc: Option<nn::ethc::State>,
-    eupld: Option<nn::eupld::State>,
-    fan: Option<nn::fan::State>,
-    fatal: Option<nn::fatal::State>,
-    fgm: Option<nn::fgm::State>,
-    file_io: Option<nn::file_io::State>,
-    friend: Option<nn::friend::State>,
-    fs: Option<nn::fs::State>,
-    fsp_ldr: Option<nn::fsp_ldr::State>,
-    fsp_pr: Option<nn::fsp_pr::State>,
-    fsp_srv: Option<nn::fsp_srv::State>,
-    gds: Option<nn::gds::State>,
-    gpio: Option<nn::gpio::State>,
-    gpuk: Option<nn::gpuk::State>,
-    grc: Option<nn::grc::State>,
-    gsv: Option<nn::gsv::State>,
-    hdcp: Option<nn::hdcp::State>,
-    hid: Option<nn::hid::State>,
-    hidbus: Option<nn::hidbus::State>,
-    host1x: Option<nn::host1x::State>,
-    hshl: Option<nn::hshl::State>,
-    htc: Option<nn::htc::State>,
-    htcs: Option<nn::htcs::State>,
-    hwopus: Option<nn::hwopus::State>,
-    i2c: Option<nn::i2c::State>,
-    idle: Option<nn::idle::State>,
-    ifcfg: Option<nn::ifcfg::State>,
-    imf: Option<nn::imf::State>,
-    ins: Option<nn::ins::State>,
-    irs: Option<nn::irs::State>,
-    jit: Option<nn::jit::State>,
-    lbl: Option<nn::lbl::State>,
-    ldn: Option<nn::ldn::State>,
-    ldr: Option<nn::ldr::State>,
-    led: Option<nn::led::State>,
-    lm: Option<nn::lm::State>,
-    lp2p: Option<nn::lp2p::State>,
-    lr: Option<nn::lr::State>,
-    manu: Option<nn::manu::State>,
-    mig: Option<nn::mig::State>,
-    mii: Option<nn::mii::State>,
-    miiimg: Option<nn::miiimg::State>,
-    mm: Option<nn::mm::State>,
-    mnpp: Option<nn::mnpp::State>,
-    ncm: Option<nn::ncm::State>,
-    nd: Option<nn::nd::State>,
-    ndd: Option<nn::ndd::State>,
-    ndrm: Option<nn::ndrm::State>,
-    news: Option<nn::news::State>,
-    nfc: Option<nn::nfc::State>,
-    nfp: Option<nn::nfp::State>,
-    ngc: Option<nn::ngc::State>,
-    ngct: Option<nn::ngct::State>,
-    nifm: Option<nn::nifm::State>,
-    nim: Option<nn::nim::State>,
-    notif: Option<nn::notif::State>,
-    npns: Option<nn::npns::State>,
-    ns: Option<nn::ns::State>,
-    nsd: Option<nn::nsd::State>,
-    ntc: Option<nn::ntc::State>,
-    nvdbg: Option<nn::nvdbg::State>,
-    nvdrv: Option<nn::nvdrv::State>,
-    nvdrvdbg: Option<nn::nvdrvdbg::State>,
-    nvgem: Option<nn::nvgem::State>,
-    nvmemp: Option<nn::nvmemp::State>,
-    olsc: Option<nn::olsc::State>,
-    omm: Option<nn::omm::State>,
-    ommdisp: Option<nn::ommdisp::State>,
-    ovln: Option<nn::ovln::State>,
-    pcie: Option<nn::pcie::State>,
-    pcm: Option<nn::pcm::State>,
-    pctl: Option<nn::pctl::State>,
-    pcv: Option<nn::pcv::State>,
-    pdm: Option<nn::pdm::State>,
-    pgl: Option<nn::pgl::State>,
-    pinmux: Option<nn::pinmux::State>,
-    pl: Option<nn::pl::State>,
-    pm: Option<nn::pm::State>,
-    prepo: Option<nn::prepo::State>,
-    psc: Option<nn::psc::State>,
-    psm: Option<nn::psm::State>,
-    pwm: Option<nn::pwm::State>,
-    rgltr: Option<nn::rgltr::State>,
-    ro: Option<nn::ro::State>,
-    rtc: Option<nn::rtc::State>,
-    sasbus: Option<nn::sasbus::State>,
-    set: Option<nn::set::State>,
-    sf_uds: Option<nn::sf_uds::State>,
-    sfdnsres: Option<nn::sfdnsres::State>,
-    spbg: Option<nn::spbg::State>,
-    spi: Option<nn::spi::State>,
-    spl: Option<nn::spl::State>,
-    sprof: Option<nn::sprof::State>,
-    spsm: Option<nn::spsm::State>,
-    srepo: Option<nn::srepo::State>,
-    ssl: Option<nn::ssl::State>,
-    syncpt: Option<nn::syncpt::State>,
-    tc: Option<nn::tc::State>,
-    tcap: Option<nn::tcap::State>,
-    time: Option<nn::time::State>,
-    tma_log: Option<nn::tma_log::State>,
-    tmagent: Option<nn::tmagent::State>,
-    ts: Option<nn::ts::State>,
-    tspm: Option<nn::tspm::State>,
-    uart: Option<nn::uart::State>,
-    usb: Option<nn::usb::State>,
-    vi: Option<nn::vi::State>,
-    vi2: Option<nn::vi2::State>,
-    vic: Option<nn::vic::State>,
-    wlan: Option<nn::wlan::State>,
-    xcd: Option<nn::xcd::State>,
+    services: BTreeMap<ServiceName, Service>,
 }
+
+pub struct Service {
+    /// The interface a host service opens sessions to; `None` for a service
+    /// a guest process serves through its port
+    pub object: Option<ObjectRef>,
+    pub max_sessions: u32,
+    /// Port sessions are counted against, created on first use for host
+    /// services
+    pub port: Option<PortId>,
+}
+
+/// `name` as a [`ServiceName`], if it fits
+pub fn service_name(name: &str) -> Option<ServiceName> {
+    let mut out = [0; 8];
+    out.get_mut(..name.len())?.copy_from_slice(name.as_bytes());
+    Some(out)
+}
+
 impl ServiceManager {
-    pub fn start_host_services<'a>(_state: &mut sys::State) {
-        let _entries: [(&str, &'a dyn FnMut(&mut sys::State) -> ()); 160] = [
-            ("acc", &nn::acc::State::run),
-            ("adraw", &nn::adraw::State::run),
-            ("ahid", &nn::ahid::State::run),
-            ("aoc", &nn::aoc::State::run),
-            ("apm", &nn::apm::State::run),
-            ("applet_ae", &nn::applet_ae::State::run),
-            ("applet_oe", &nn::applet_oe::State::run),
-            ("arp", &nn::arp::State::run),
-            ("aud", &nn::aud::State::run),
-            ("audctl", &nn::audctl::State::run),
-            ("auddebug", &nn::auddebug::State::run),
-            ("auddev", &nn::auddev::State::run),
-            ("auddmg", &nn::auddmg::State::run),
-            ("audin", &nn::audin::State::run),
-            ("audout", &nn::audout::State::run),
-            ("audrec", &nn::audrec::State::run),
-            ("audren", &nn::audren::State::run),
-            ("audsmx", &nn::audsmx::State::run),
-            ("avm", &nn::avm::State::run),
-            ("banana", &nn::banana::State::run),
-            ("batlog", &nn::batlog::State::run),
-            ("bcat", &nn::bcat::State::run),
-            ("bgtc", &nn::bgtc::State::run),
-            ("bpc", &nn::bpc::State::run),
-            ("bpmpmr", &nn::bpmpmr::State::run),
-            ("bsd", &nn::bsd::State::run),
-            ("bsdcfg", &nn::bsdcfg::State::run),
-            ("bt", &nn::bt::State::run),
-            ("btdrv", &nn::btdrv::State::run),
-            ("btm", &nn::btm::State::run),
-            ("btp", &nn::btp::State::run),
-            ("capmtp", &nn::capmtp::State::run),
-            ("caps", &nn::caps::State::run),
-            ("caps2", &nn::caps2::State::run),
-            ("cec_mgr", &nn::cec_mgr::State::run),
-            ("chat", &nn::chat::State::run),
-            ("clkrst", &nn::clkrst::State::run),
-            ("codecctl", &nn::codecctl::State::run),
-            ("csrng", &nn::csrng::State::run),
-            ("dauth", &nn::dauth::State::run),
-            ("disp", &nn::disp::State::run),
-            ("dispdrv", &nn::dispdrv::State::run),
-            ("dmnt", &nn::dmnt::State::run),
-            ("dns", &nn::dns::State::run),
-            ("dt", &nn::dt::State::run),
-            ("ectx", &nn::ectx::State::run),
-            ("erpt", &nn::erpt::State::run),
-            ("es", &nn::es::State::run),
-            ("eth", &nn::eth::State::run),
-            ("ethc", &nn::ethc::State::run),
-            ("eupld", &nn::eupld::State::run),
-            ("fan", &nn::fan::State::run),
-            ("fatal", &nn::fatal::State::run),
-            ("fgm", &nn::fgm::State::run),
-            ("file_io", &nn::file_io::State::run),
-            ("friend", &nn::friend::State::run),
-            ("fs", &nn::fs::State::run),
-            ("fsp_ldr", &nn::fsp_ldr::State::run),
-            ("fsp_pr", &nn::fsp_pr::State::run),
-            ("fsp_srv", &nn::fsp_srv::State::run),
-            ("gds", &nn::gds::State::run),
-            ("gpio", &nn::gpio::State::run),
-            ("gpuk", &nn::gpuk::State::run),
-            ("grc", &nn::grc::State::run),
-            ("gsv", &nn::gsv::State::run),
-            ("hdcp", &nn::hdcp::State::run),
-            ("hid", &nn::hid::State::run),
-            ("hidbus", &nn::hidbus::State::run),
-            ("host1x", &nn::host1x::State::run),
-            ("hshl", &nn::hshl::State::run),
-            ("htc", &nn::htc::State::run),
-            ("htcs", &nn::htcs::State::run),
-            ("hwopus", &nn::hwopus::State::run),
-            ("i2c", &nn::i2c::State::run),
-            ("idle", &nn::idle::State::run),
-            ("ifcfg", &nn::ifcfg::State::run),
-            ("imf", &nn::imf::State::run),
-            ("ins", &nn::ins::State::run),
-            ("irs", &nn::irs::State::run),
-            ("jit", &nn::jit::State::run),
-            ("lbl", &nn::lbl::State::run),
-            ("ldn", &nn::ldn::State::run),
-            ("ldr", &nn::ldr::State::run),
-            ("led", &nn::led::State::run),
-            ("lm", &nn::lm::State::run),
-            ("lp2p", &nn::lp2p::State::run),
-            ("lr", &nn::lr::State::run),
-            ("manu", &nn::manu::State::run),
-            ("mig", &nn::mig::State::run),
-            ("mii", &nn::mii::State::run),
-            ("miiimg", &nn::miiimg::State::run),
-            ("mm", &nn::mm::State::run),
-            ("mnpp", &nn::mnpp::State::run),
-            ("ncm", &nn::ncm::State::run),
-            ("nd", &nn::nd::State::run),
-            ("ndd", &nn::ndd::State::run),
-            ("ndrm", &nn::ndrm::State::run),
-            ("news", &nn::news::State::run),
-            ("nfc", &nn::nfc::State::run),
-            ("nfp", &nn::nfp::State::run),
-            ("ngc", &nn::ngc::State::run),
-            ("ngct", &nn::ngct::State::run),
-            ("nifm", &nn::nifm::State::run),
-            ("nim", &nn::nim::State::run),
-            ("notif", &nn::notif::State::run),
-            ("npns", &nn::npns::State::run),
-            ("ns", &nn::ns::State::run),
-            ("nsd", &nn::nsd::State::run),
-            ("ntc", &nn::ntc::State::run),
-            ("nvdbg", &nn::nvdbg::State::run),
-            ("nvdrv", &nn::nvdrv::State::run),
-            ("nvdrvdbg", &nn::nvdrvdbg::State::run),
-            ("nvgem", &nn::nvgem::State::run),
-            ("nvmemp", &nn::nvmemp::State::run),
-            ("olsc", &nn::olsc::State::run),
-            ("omm", &nn::omm::State::run),
-            ("ommdisp", &nn::ommdisp::State::run),
-            ("ovln", &nn::ovln::State::run),
-            ("pcie", &nn::pcie::State::run),
-            ("pcm", &nn::pcm::State::run),
-            ("pctl", &nn::pctl::State::run),
-            ("pcv", &nn::pcv::State::run),
-            ("pdm", &nn::pdm::State::run),
-            ("pgl", &nn::pgl::State::run),
-            ("pinmux", &nn::pinmux::State::run),
-            ("pl", &nn::pl::State::run),
-            ("pm", &nn::pm::State::run),
-            ("prepo", &nn::prepo::State::run),
-            ("psc", &nn::psc::State::run),
-            ("psm", &nn::psm::State::run),
-            ("pwm", &nn::pwm::State::run),
-            ("rgltr", &nn::rgltr::State::run),
-            ("ro", &nn::ro::State::run),
-            ("rtc", &nn::rtc::State::run),
-            ("sasbus", &nn::sasbus::State::run),
-            ("set", &nn::set::State::run),
-            ("sf_uds", &nn::sf_uds::State::run),
-            ("sfdnsres", &nn::sfdnsres::State::run),
-            ("spbg", &nn::spbg::State::run),
-            ("spi", &nn::spi::State::run),
-            ("spl", &nn::spl::State::run),
-            ("sprof", &nn::sprof::State::run),
-            ("spsm", &nn::spsm::State::run),
-            ("srepo", &nn::srepo::State::run),
-            ("ssl", &nn::ssl::State::run),
-            ("syncpt", &nn::syncpt::State::run),
-            ("tc", &nn::tc::State::run),
-            ("tcap", &nn::tcap::State::run),
-            ("time", &nn::time::State::run),
-            ("tma_log", &nn::tma_log::State::run),
-            ("tmagent", &nn::tmagent::State::run),
-            ("ts", &nn::ts::State::run),
-            ("tspm", &nn::tspm::State::run),
-            ("uart", &nn::uart::State::run),
-            ("usb", &nn::usb::State::run),
-            ("vi", &nn::vi::State::run),
-            ("vi2", &nn::vi2::State::run),
-            ("vic", &nn::vic::State::run),
-            ("wlan", &nn::wlan::State::run),
-            ("xcd", &nn::xcd::State::run),
+    /// Call every module's [`ServiceTrait::run`]; a new module only needs
+    /// adding here
+    pub fn start_host_services(state: &mut sys::State) {
+        let modules: &[fn(&mut sys::State)] = &[
+            nn::acc::State::run,
+            nn::adraw::State::run,
+            nn::ahid::State::run,
+            nn::aoc::State::run,
+            nn::apm::State::run,
+            nn::applet_ae::State::run,
+            nn::applet_oe::State::run,
+            nn::arp::State::run,
+            nn::aud::State::run,
+            nn::audctl::State::run,
+            nn::auddebug::State::run,
+            nn::auddev::State::run,
+            nn::auddmg::State::run,
+            nn::audin::State::run,
+            nn::audout::State::run,
+            nn::audrec::State::run,
+            nn::audren::State::run,
+            nn::audsmx::State::run,
+            nn::avm::State::run,
+            nn::banana::State::run,
+            nn::batlog::State::run,
+            nn::bcat::State::run,
+            nn::bgtc::State::run,
+            nn::bpc::State::run,
+            nn::bpmpmr::State::run,
+            nn::bsd::State::run,
+            nn::bsdcfg::State::run,
+            nn::bt::State::run,
+            nn::btdrv::State::run,
+            nn::btm::State::run,
+            nn::btp::State::run,
+            nn::capmtp::State::run,
+            nn::caps::State::run,
+            nn::caps2::State::run,
+            nn::cec_mgr::State::run,
+            nn::chat::State::run,
+            nn::clkrst::State::run,
+            nn::codecctl::State::run,
+            nn::csrng::State::run,
+            nn::dauth::State::run,
+            nn::disp::State::run,
+            nn::dispdrv::State::run,
+            nn::dmnt::State::run,
+            nn::dns::State::run,
+            nn::dt::State::run,
+            nn::ectx::State::run,
+            nn::erpt::State::run,
+            nn::es::State::run,
+            nn::eth::State::run,
+            nn::ethc::State::run,
+            nn::eupld::State::run,
+            nn::fan::State::run,
+            nn::fatal::State::run,
+            nn::fgm::State::run,
+            nn::file_io::State::run,
+            nn::friend::State::run,
+            nn::fs::State::run,
+            nn::fsp_ldr::State::run,
+            nn::fsp_pr::State::run,
+            nn::fsp_srv::State::run,
+            nn::gds::State::run,
+            nn::gpio::State::run,
+            nn::gpuk::State::run,
+            nn::grc::State::run,
+            nn::gsv::State::run,
+            nn::hdcp::State::run,
+            nn::hid::State::run,
+            nn::hidbus::State::run,
+            nn::host1x::State::run,
+            nn::hshl::State::run,
+            nn::htc::State::run,
+            nn::htcs::State::run,
+            nn::hwopus::State::run,
+            nn::i2c::State::run,
+            nn::idle::State::run,
+            nn::ifcfg::State::run,
+            nn::imf::State::run,
+            nn::ins::State::run,
+            nn::irs::State::run,
+            nn::jit::State::run,
+            nn::lbl::State::run,
+            nn::ldn::State::run,
+            nn::ldr::State::run,
+            nn::led::State::run,
+            nn::lm::State::run,
+            nn::lp2p::State::run,
+            nn::lr::State::run,
+            nn::manu::State::run,
+            nn::mig::State::run,
+            nn::mii::State::run,
+            nn::miiimg::State::run,
+            nn::mm::State::run,
+            nn::mnpp::State::run,
+            nn::ncm::State::run,
+            nn::nd::State::run,
+            nn::ndd::State::run,
+            nn::ndrm::State::run,
+            nn::news::State::run,
+            nn::nfc::State::run,
+            nn::nfp::State::run,
+            nn::ngc::State::run,
+            nn::ngct::State::run,
+            nn::nifm::State::run,
+            nn::nim::State::run,
+            nn::notif::State::run,
+            nn::npns::State::run,
+            nn::ns::State::run,
+            nn::nsd::State::run,
+            nn::ntc::State::run,
+            nn::nvdbg::State::run,
+            nn::nvdrv::State::run,
+            nn::nvdrvdbg::State::run,
+            nn::nvgem::State::run,
+            nn::nvmemp::State::run,
+            nn::olsc::State::run,
+            nn::omm::State::run,
+            nn::ommdisp::State::run,
+            nn::ovln::State::run,
+            nn::pcie::State::run,
+            nn::pcm::State::run,
+            nn::pctl::State::run,
+            nn::pcv::State::run,
+            nn::pdm::State::run,
+            nn::pgl::State::run,
+            nn::pinmux::State::run,
+            nn::pl::State::run,
+            nn::pm::State::run,
+            nn::prepo::State::run,
+            nn::psc::State::run,
+            nn::psm::State::run,
+            nn::pwm::State::run,
+            nn::rgltr::State::run,
+            nn::ro::State::run,
+            nn::rtc::State::run,
+            nn::sasbus::State::run,
+            nn::set::State::run,
+            nn::sf_uds::State::run,
+            nn::sfdnsres::State::run,
+            nn::spbg::State::run,
+            nn::spi::State::run,
+            nn::spl::State::run,
+            nn::sprof::State::run,
+            nn::spsm::State::run,
+            nn::srepo::State::run,
+            nn::ssl::State::run,
+            nn::syncpt::State::run,
+            nn::tc::State::run,
+            nn::tcap::State::run,
+            nn::time::State::run,
+            nn::tma_log::State::run,
+            nn::tmagent::State::run,
+            nn::ts::State::run,
+            nn::tspm::State::run,
+            nn::uart::State::run,
+            nn::usb::State::run,
+            nn::vi::State::run,
+            nn::vi2::State::run,
+            nn::vic::State::run,
+            nn::wlan::State::run,
+            nn::xcd::State::run,
         ];
+        for run in modules {
+            run(state);
+        }
+    }
+
+    pub fn register(&mut self, name: ServiceName, service: Service) -> Result<(), ResultCode> {
+        if self.services.contains_key(&name) {
+            return Err(nn::sm::RESULT_ALREADY_REGISTERED);
+        }
+        self.services.insert(name, service);
+        Ok(())
+    }
+
+    pub fn unregister(&mut self, name: ServiceName) -> Result<Service, ResultCode> {
+        self.services
+            .remove(&name)
+            .ok_or(nn::sm::RESULT_NOT_REGISTERED)
+    }
+
+    pub fn get(&self, name: ServiceName) -> Option<&Service> {
+        self.services.get(&name)
+    }
+
+    pub fn get_mut(&mut self, name: ServiceName) -> Option<&mut Service> {
+        self.services.get_mut(&name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.services.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
     }
 }
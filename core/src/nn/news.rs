@@ -8,6 +8,9 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.news = Some(State::new(state));
+        state.register_host(
+            &["news:a", "news:c", "news:m", "news:p", "news:v"],
+            State::new,
+        );
     }
 }
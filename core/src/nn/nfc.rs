@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.nfc = Some(State::new(state));
+        state.register_host(&["nfc:am", "nfc:mf:u", "nfc:user", "nfc:sys"], State::new);
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.nifm = Some(State::new(state));
+        state.register_host(&["nifm:a", "nifm:s", "nifm:u"], State::new);
     }
 }
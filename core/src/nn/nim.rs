@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.nim = Some(State::new(state));
+        state.register_host(&["nim", "nim:eca", "nim:ecas", "nim:shp"], State::new);
     }
 }
@@ -8,6 +8,12 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.ns = Some(State::new(state));
+        state.register_host(
+            &[
+                "ns:am2", "ns:dev", "ns:ec", "ns:rid", "ns:ro", "ns:rt", "ns:su", "ns:sweb",
+                "ns:vm", "ns:web",
+            ],
+            State::new,
+        );
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.nsd = Some(State::new(state));
+        state.register_host(&["nsd:a", "nsd:u"], State::new);
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.nvdrv = Some(State::new(state));
+        state.register_host(&["nvdrv", "nvdrv:a", "nvdrv:s", "nvdrv:t"], State::new);
     }
 }
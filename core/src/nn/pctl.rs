@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.pctl = Some(State::new(state));
+        state.register_host(&["pctl", "pctl:a", "pctl:r", "pctl:s"], State::new);
     }
 }
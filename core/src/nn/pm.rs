@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.pm = Some(State::new(state));
+        state.register_host(&["pm:bm", "pm:dmnt", "pm:info", "pm:shell"], State::new);
     }
 }
@@ -8,6 +8,9 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.prepo = Some(State::new(state));
+        state.register_host(
+            &["prepo:a", "prepo:a2", "prepo:m", "prepo:s", "prepo:u"],
+            State::new,
+        );
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.ro = Some(State::new(state));
+        state.register_host(&["ldr:ro", "ro:1", "ro:dmnt"], State::new);
     }
 }
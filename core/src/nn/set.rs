@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.set = Some(State::new(state));
+        state.register_host(&["set", "set:cal", "set:fd", "set:sys"], State::new);
     }
 }
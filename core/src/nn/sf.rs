@@ -0,0 +1,295 @@
+//! The service framework: host interfaces as tables of commands
+//!
+//! A service implements [`ServiceTrait`] by listing its commands. The
+//! protocol layer ([`cmif`](crate::nn::cmif) or [`tipc`](crate::nn::tipc))
+//! unpacks each request into a [`Context`], finds the command by ID and
+//! frames the reply from whatever the handler pushed back into the context:
+//! raw data, handles and sub-interfaces.
+
+use crate::kernel::Kernel;
+use crate::kernel::handle::Handle;
+use crate::kernel::hipc::{BufferDescriptor, Message};
+use crate::kernel::result::{self, ResultCode};
+use crate::nn::ServiceTrait;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub const MODULE_SF: u32 = 10;
+
+pub const RESULT_INVALID_HEADER_SIZE: ResultCode = ResultCode::new(MODULE_SF, 202);
+pub const RESULT_INVALID_IN_HEADER: ResultCode = ResultCode::new(MODULE_SF, 211);
+pub const RESULT_UNKNOWN_COMMAND_ID: ResultCode = ResultCode::new(MODULE_SF, 221);
+pub const RESULT_INVALID_IN_OBJECT: ResultCode = ResultCode::new(MODULE_SF, 239);
+pub const RESULT_TARGET_NOT_FOUND: ResultCode = ResultCode::new(MODULE_SF, 261);
+
+/// Runs one command of interface `S`
+pub type Handler<S> = fn(&mut S, &mut Context<'_>) -> Result<(), ResultCode>;
+
+/// An entry of an interface's command table
+pub struct Command<S> {
+    pub id: u32,
+    pub name: &'static str,
+    pub handler: Handler<S>,
+}
+
+impl<S> Command<S> {
+    pub const fn new(id: u32, name: &'static str, handler: Handler<S>) -> Self {
+        Self { id, name, handler }
+    }
+}
+
+/// An interface as the protocol layer sees it, whatever its type
+pub trait Object {
+    fn name(&self) -> &'static str;
+
+    /// Run command `id`, or return `None` if the interface has no such
+    /// command
+    fn invoke(&mut self, id: u32, ctx: &mut Context<'_>) -> Option<Result<(), ResultCode>>;
+}
+
+impl<S: ServiceTrait + 'static> Object for S {
+    fn name(&self) -> &'static str {
+        S::name()
+    }
+
+    fn invoke(&mut self, id: u32, ctx: &mut Context<'_>) -> Option<Result<(), ResultCode>> {
+        let command = S::commands().iter().find(|c| c.id == id)?;
+        Some((command.handler)(self, ctx))
+    }
+}
+
+/// An interface shared between the sessions and domains that expose it
+pub type ObjectRef = Rc<RefCell<dyn Object>>;
+
+pub fn object(service: impl ServiceTrait + 'static) -> ObjectRef {
+    Rc::new(RefCell::new(service))
+}
+
+/// A value that can be read from or written to a command's raw data
+///
+/// Fields are laid out in order, each at its natural alignment, as the
+/// guest's generated interface code does.
+pub trait RawData: Sized {
+    const SIZE: usize;
+    const ALIGN: usize;
+
+    fn read(bytes: &[u8]) -> Self;
+    fn write(&self, bytes: &mut [u8]);
+}
+
+macro_rules! impl_raw_data {
+    ($($ty:ty),*) => {
+        $(
+            impl RawData for $ty {
+                const SIZE: usize = size_of::<$ty>();
+                const ALIGN: usize = size_of::<$ty>();
+
+                fn read(bytes: &[u8]) -> Self {
+                    <$ty>::from_le_bytes(bytes.try_into().unwrap())
+                }
+
+                fn write(&self, bytes: &mut [u8]) {
+                    bytes.copy_from_slice(&self.to_le_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_raw_data!(u8, u16, u32, u64, u128, i8, i16, i32, i64);
+
+impl RawData for bool {
+    const SIZE: usize = 1;
+    const ALIGN: usize = 1;
+
+    fn read(bytes: &[u8]) -> Self {
+        bytes[0] != 0
+    }
+
+    fn write(&self, bytes: &mut [u8]) {
+        bytes[0] = *self as u8;
+    }
+}
+
+/// Byte arrays, such as names and UUIDs, have no alignment
+impl<const N: usize> RawData for [u8; N] {
+    const SIZE: usize = N;
+    const ALIGN: usize = 1;
+
+    fn read(bytes: &[u8]) -> Self {
+        bytes.try_into().unwrap()
+    }
+
+    fn write(&self, bytes: &mut [u8]) {
+        bytes.copy_from_slice(self);
+    }
+}
+
+/// Raw data words as bytes, for [`Context`]
+pub(crate) fn to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+/// Raw data bytes as words, padding the last one with zeros
+pub(crate) fn to_words(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(4)
+        .map(|chunk| {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u32::from_le_bytes(word)
+        })
+        .collect()
+}
+
+/// What a command sends back, collected by [`Context`]
+#[derive(Default)]
+pub(crate) struct Output {
+    pub data: Vec<u8>,
+    pub copy_handles: Vec<Handle>,
+    pub move_handles: Vec<Handle>,
+    pub objects: Vec<ObjectRef>,
+}
+
+/// A command's arguments and the reply it is building
+pub struct Context<'a> {
+    pub kernel: &'a mut Kernel,
+    pub request: &'a Message,
+    input: &'a [u8],
+    input_offset: usize,
+    in_objects: Vec<ObjectRef>,
+    output: Output,
+}
+
+impl<'a> Context<'a> {
+    pub(crate) fn new(
+        kernel: &'a mut Kernel,
+        request: &'a Message,
+        input: &'a [u8],
+        in_objects: Vec<ObjectRef>,
+    ) -> Self {
+        Self {
+            kernel,
+            request,
+            input,
+            input_offset: 0,
+            in_objects,
+            output: Output::default(),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Output {
+        self.output
+    }
+
+    /// Read the next argument from the raw data
+    pub fn pop<T: RawData>(&mut self) -> Result<T, ResultCode> {
+        let start = self.input_offset.next_multiple_of(T::ALIGN);
+        let bytes = self
+            .input
+            .get(start..start + T::SIZE)
+            .ok_or(RESULT_INVALID_HEADER_SIZE)?;
+        self.input_offset = start + T::SIZE;
+        Ok(T::read(bytes))
+    }
+
+    /// Append a value to the reply's raw data
+    pub fn push<T: RawData>(&mut self, value: T) {
+        let data = &mut self.output.data;
+        let start = data.len().next_multiple_of(T::ALIGN);
+        data.resize(start + T::SIZE, 0);
+        value.write(&mut data[start..]);
+    }
+
+    /// Give the client `handle`, which it then owns
+    pub fn copy_handle(&mut self, handle: Handle) {
+        self.output.copy_handles.push(handle);
+    }
+
+    pub fn move_handle(&mut self, handle: Handle) {
+        self.output.move_handles.push(handle);
+    }
+
+    /// A handle of the service's own to the object the client copies as
+    /// handle `index`
+    pub fn take_copy_handle(&mut self, index: usize) -> Result<Handle, ResultCode> {
+        let handle = self.request.copy_handles.get(index);
+        let handle = *handle.ok_or(result::OUT_OF_RANGE)?;
+        self.kernel.take_copied_handle(handle)
+    }
+
+    /// The handle the client moves as handle `index`, which the service then
+    /// owns; handles not taken are closed after the command
+    pub fn take_move_handle(&mut self, index: usize) -> Result<Handle, ResultCode> {
+        let handle = self.request.move_handles.get(index);
+        let handle = *handle.ok_or(result::OUT_OF_RANGE)?;
+        self.kernel.take_moved_handle(handle)
+    }
+
+    /// Return a sub-interface, as a new session or, on a domain, as a new
+    /// object in it
+    pub fn push_object(&mut self, service: impl ServiceTrait + 'static) {
+        self.output.objects.push(object(service));
+    }
+
+    /// An interface the client passed in, by position; only domains can
+    /// send objects
+    pub fn in_object(&self, index: usize) -> Result<ObjectRef, ResultCode> {
+        self.in_objects
+            .get(index)
+            .cloned()
+            .ok_or(RESULT_INVALID_IN_OBJECT)
+    }
+
+    /// Contents of input buffer `index`: its A descriptor, or the X
+    /// descriptor in the same position when the client sent it that way
+    pub fn read_buffer(&self, index: usize) -> Result<Vec<u8>, ResultCode> {
+        let (address, size) = match self.request.a_buffers.get(index) {
+            Some(buffer) if buffer.size != 0 => (buffer.address, buffer.size),
+            _ => {
+                let buffer = self
+                    .request
+                    .x_buffers
+                    .get(index)
+                    .ok_or(result::OUT_OF_RANGE)?;
+                (buffer.address, buffer.size as u64)
+            }
+        };
+        let space = &self.kernel.process.address_space;
+        if !space.is_readable(address, size) {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        space
+            .memory()
+            .read_vec(address, size as usize)
+            .ok_or(result::INVALID_CURRENT_MEMORY)
+    }
+
+    /// Capacity of output buffer `index`
+    pub fn out_buffer_size(&self, index: usize) -> u64 {
+        self.out_buffer(index).map_or(0, |(_, size)| size)
+    }
+
+    /// Copy `data` into output buffer `index`, its B descriptor or else the
+    /// C buffer in the same position, returning how much fit
+    pub fn write_buffer(&mut self, index: usize, data: &[u8]) -> Result<usize, ResultCode> {
+        let (address, size) = self.out_buffer(index).ok_or(result::OUT_OF_RANGE)?;
+        let len = data.len().min(size as usize);
+        let space = &self.kernel.process.address_space;
+        if !space.is_writable(address, len as u64) {
+            return Err(result::INVALID_CURRENT_MEMORY);
+        }
+        space.memory().write(address, &data[..len]);
+        Ok(len)
+    }
+
+    fn out_buffer(&self, index: usize) -> Option<(u64, u64)> {
+        match self.request.b_buffers.get(index) {
+            Some(&BufferDescriptor { address, size, .. }) if size != 0 => Some((address, size)),
+            _ => {
+                let buffer = self.request.c_buffers.get(index)?;
+                Some((buffer.address, buffer.size as u64))
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use crate::kernel::ipc::SessionHandler;
+use crate::kernel::result::{self, ResultCode};
+use crate::nn::sf::{Command, Context};
+use crate::nn::{self, ServiceName, ServiceTrait, cmif, unimplemented};
+
+const MODULE_SM: u32 = 21;
+
+pub const RESULT_INVALID_CLIENT: ResultCode = ResultCode::new(MODULE_SM, 2);
+pub const RESULT_ALREADY_REGISTERED: ResultCode = ResultCode::new(MODULE_SM, 4);
+pub const RESULT_INVALID_SERVICE_NAME: ResultCode = ResultCode::new(MODULE_SM, 6);
+pub const RESULT_NOT_REGISTERED: ResultCode = ResultCode::new(MODULE_SM, 7);
+
+/// `sm:`, the service manager every process connects to first
+///
+/// Looks services up in the kernel's [`ServiceManager`](nn::ServiceManager).
+/// Host services get a new session to their shared interface; services a
+/// guest registered get a session queued on the guest's port.
+pub struct State {
+    registered: bool,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self { registered: false }
+    }
+
+    fn register_client(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+        // The kernel fills in the PID the client asks it to send
+        if ctx.request.pid.is_none() {
+            return Err(RESULT_INVALID_CLIENT);
+        }
+        self.registered = true;
+        Ok(())
+    }
+
+    fn get_service_handle(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+        let name = pop_name(ctx)?;
+        if !self.registered {
+            return Err(RESULT_INVALID_CLIENT);
+        }
+        let kernel = &mut *ctx.kernel;
+        if kernel.sys.services.get(name).is_none() {
+            unimplemented::service(kernel, name)?;
+        }
+        let service = kernel.sys.services.get_mut(name).unwrap();
+        let port = *service
+            .port
+            .get_or_insert_with(|| kernel.ipc.new_port(service.max_sessions));
+        // Without a host interface, the guest owning the port serves it
+        let handler: Option<Box<dyn SessionHandler>> = match &service.object {
+            Some(object) => Some(Box::new(cmif::Session::for_service(object.clone(), name))),
+            None => None,
+        };
+        let handle = kernel.connect_port(port, handler)?;
+        ctx.move_handle(handle);
+        Ok(())
+    }
+
+    fn register_service(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+        let name = pop_name(ctx)?;
+        let is_light: bool = ctx.pop()?;
+        let max_sessions: u32 = ctx.pop()?;
+        if !self.registered {
+            return Err(RESULT_INVALID_CLIENT);
+        }
+        if is_light {
+            return Err(result::NOT_IMPLEMENTED);
+        }
+        if ctx.kernel.sys.services.get(name).is_some() {
+            return Err(RESULT_ALREADY_REGISTERED);
+        }
+        let (server, port) = ctx.kernel.create_server_port(max_sessions)?;
+        let service = nn::Service {
+            object: None,
+            max_sessions,
+            port: Some(port),
+        };
+        ctx.kernel.sys.services.register(name, service)?;
+        ctx.move_handle(server);
+        Ok(())
+    }
+
+    fn unregister_service(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+        let name = pop_name(ctx)?;
+        if !self.registered {
+            return Err(RESULT_INVALID_CLIENT);
+        }
+        ctx.kernel.sys.services.unregister(name)?;
+        Ok(())
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A service name argument: not empty, and nothing but padding after the
+/// first zero
+fn pop_name(ctx: &mut Context) -> Result<ServiceName, ResultCode> {
+    let name: ServiceName = ctx.pop()?;
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    if len == 0 || name[len..].iter().any(|&b| b != 0) {
+        return Err(RESULT_INVALID_SERVICE_NAME);
+    }
+    Ok(name)
+}
+
+const COMMANDS: &[Command<State>] = &[
+    Command::new(0, "RegisterClient", State::register_client),
+    Command::new(1, "GetServiceHandle", State::get_service_handle),
+    Command::new(2, "RegisterService", State::register_service),
+    Command::new(3, "UnregisterService", State::unregister_service),
+];
+
+impl ServiceTrait for State {
+    fn name() -> &'static str {
+        "sm:"
+    }
+
+    fn commands() -> &'static [Command<Self>] {
+        COMMANDS
+    }
+}
@@ -8,6 +8,9 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.spl = Some(State::new(state));
+        state.register_host(
+            &["spl:", "spl:mig", "spl:fs", "spl:ssl", "spl:es", "spl:manu"],
+            State::new,
+        );
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.srepo = Some(State::new(state));
+        state.register_host(&["srepo:a", "srepo:u"], State::new);
     }
 }
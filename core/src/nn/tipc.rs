@@ -0,0 +1,63 @@
+//! TIPC, the lighter command protocol `sm:` and other services use since
+//! 12.0.0
+//!
+//! The message type is the command ID plus 16 and the raw data holds only
+//! the arguments, with no header or alignment padding. A reply starts with
+//! the result, followed by the output. TIPC has no domains, so interfaces a
+//! command returns are always sent as new sessions.
+
+use crate::kernel::Kernel;
+use crate::kernel::hipc::Message;
+use crate::kernel::result::ResultCode;
+use crate::nn::sf::{self, Context, ObjectRef, Output};
+use crate::nn::{ServiceName, cmif, unimplemented};
+
+/// Message type that closes the session
+pub const TYPE_CLOSE: u16 = 15;
+/// Message type of command 0; higher commands follow on from it
+pub const TYPE_FIRST_COMMAND: u16 = 16;
+
+/// Run the command a TIPC request names on `object` and frame its reply
+///
+/// `service` is the name the session was opened under, for reports of
+/// unimplemented commands.
+pub(crate) fn invoke(
+    kernel: &mut Kernel,
+    request: &Message,
+    object: &ObjectRef,
+    service: Option<ServiceName>,
+) -> Message {
+    let command = (request.kind - TYPE_FIRST_COMMAND) as u32;
+    cmif::trace_request(kernel, service, object, command);
+    let input = sf::to_bytes(&request.data);
+    let mut ctx = Context::new(kernel, request, &input, Vec::new());
+    let result = object.borrow_mut().invoke(command, &mut ctx);
+    let mut output = ctx.finish();
+    let result = result.unwrap_or_else(|| {
+        let interface = object.borrow().name();
+        unimplemented::command(kernel, service, interface, command, &request.data)
+    });
+    let result = result.and_then(|()| cmif::open_sessions(kernel, &mut output, service));
+    reply_message(request.kind, result, output)
+}
+
+fn reply_message(kind: u16, result: Result<(), ResultCode>, output: Output) -> Message {
+    match result {
+        Ok(()) => {
+            let mut data = vec![ResultCode::SUCCESS.0];
+            data.extend(sf::to_words(&output.data));
+            Message {
+                kind,
+                copy_handles: output.copy_handles,
+                move_handles: output.move_handles,
+                data,
+                ..Default::default()
+            }
+        }
+        Err(error) => Message {
+            kind,
+            data: vec![error.0],
+            ..Default::default()
+        },
+    }
+}
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.tma_log = Some(State::new(state));
+        state.register_host(&["tma_log"], State::new);
     }
 }
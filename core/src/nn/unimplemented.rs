@@ -0,0 +1,191 @@
+//! Reports of the services and commands the guest uses that oboromi does
+//! not implement
+//!
+//! Each site (the same command of the same interface, called from the same
+//! address) is logged the first time it is hit and counted afterwards. The
+//! summary ranks sites by hits, which shows which stubs are worth filling
+//! in first. What the guest gets back is decided by the [`Policy`].
+
+use crate::kernel::Kernel;
+use crate::kernel::result::ResultCode;
+use crate::nn::sf::{self, RESULT_UNKNOWN_COMMAND_ID};
+use crate::nn::{self, ServiceName, ServiceTrait, sm};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::path::Path;
+use std::{fs, io};
+
+/// What the guest gets back from something unimplemented
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Succeed without output, as if commands were no-ops and missing
+    /// services had no commands yet
+    Stub,
+    /// Fail as Horizon would: `UnknownCommandId` for a command,
+    /// `NotRegistered` for a service
+    #[default]
+    Error,
+    /// Fail as with [`Policy::Error`], then stop the kernel with
+    /// [`KernelExit::Unimplemented`](crate::kernel::KernelExit::Unimplemented)
+    Halt,
+}
+
+/// One use of a missing service or command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Name the session was opened under through `sm:`, if it was
+    pub service: Option<ServiceName>,
+    /// Interface the command was sent to; `None` if the service is missing
+    pub interface: Option<&'static str>,
+    pub command: Option<u32>,
+    /// Raw argument words of the request
+    pub input: Vec<u32>,
+    /// Address of the SVC that sent the request, when a guest thread did
+    pub pc: Option<u64>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.interface, self.command) {
+            (Some(interface), Some(command)) => write!(f, "command {command} of {interface}")?,
+            (Some(interface), None) => write!(f, "{interface}")?,
+            _ => write!(f, "service")?,
+        }
+        if let Some(service) = &self.service {
+            write!(f, " ({})", display_name(service))?;
+        }
+        if let Some(pc) = self.pc {
+            write!(f, " at PC {pc:#018x}")?;
+        }
+        if !self.input.is_empty() {
+            write!(f, ", input {:08x?}", self.input)?;
+        }
+        Ok(())
+    }
+}
+
+type SiteKey = (
+    Option<ServiceName>,
+    Option<&'static str>,
+    Option<u32>,
+    Option<u64>,
+);
+
+struct Site {
+    /// The first report from the site; later ones may differ in input only
+    first: Report,
+    hits: u64,
+}
+
+/// Collects [`Report`]s for the whole run
+#[derive(Default)]
+pub struct Reporter {
+    pub policy: Policy,
+    sites: BTreeMap<SiteKey, Site>,
+    halt_requested: bool,
+}
+
+impl Reporter {
+    /// Record `report`, logging it if its site is new, and return the
+    /// policy to apply
+    pub fn report(&mut self, report: Report) -> Policy {
+        let key = (report.service, report.interface, report.command, report.pc);
+        let site = self.sites.entry(key).or_insert_with(|| {
+            log::warn!("Unimplemented {report}");
+            Site {
+                first: report,
+                hits: 0,
+            }
+        });
+        site.hits += 1;
+        if self.policy == Policy::Halt {
+            self.halt_requested = true;
+        }
+        self.policy
+    }
+
+    /// Whether a report asked to halt since the last call
+    pub(crate) fn take_halt(&mut self) -> bool {
+        std::mem::take(&mut self.halt_requested)
+    }
+
+    /// Every site with its first report and hit count, most hit first
+    pub fn sites(&self) -> Vec<(&Report, u64)> {
+        let mut sites: Vec<_> = self.sites.values().map(|s| (&s.first, s.hits)).collect();
+        sites.sort_by_key(|&(_, hits)| std::cmp::Reverse(hits));
+        sites
+    }
+
+    /// A plain-text table of [`Self::sites`]
+    pub fn summary(&self) -> String {
+        let mut out = format!("{} unimplemented sites\n", self.sites.len());
+        for (report, hits) in self.sites() {
+            writeln!(out, "{hits:>8}  {report}").unwrap();
+        }
+        out
+    }
+
+    /// Write the [`Self::summary`] to `path`, e.g. at shutdown
+    pub fn write_summary(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.summary())
+    }
+}
+
+/// Interface of a service the [`Policy::Stub`] policy stands in for; every
+/// command sent to it is reported in turn
+pub struct Placeholder;
+
+impl ServiceTrait for Placeholder {
+    fn name() -> &'static str {
+        "unimplemented service"
+    }
+}
+
+/// Report command `command` that `interface` lacks and apply the policy
+pub(crate) fn command(
+    kernel: &mut Kernel,
+    service: Option<ServiceName>,
+    interface: &'static str,
+    command: u32,
+    input: &[u32],
+) -> Result<(), ResultCode> {
+    let report = Report {
+        service,
+        interface: Some(interface),
+        command: Some(command),
+        input: input.to_vec(),
+        pc: kernel.request_pc(),
+    };
+    match kernel.sys.unimplemented.report(report) {
+        Policy::Stub => Ok(()),
+        Policy::Error | Policy::Halt => Err(RESULT_UNKNOWN_COMMAND_ID),
+    }
+}
+
+/// Report that nothing is registered as `name` and apply the policy; a
+/// stubbed service is registered as a [`Placeholder`]
+pub(crate) fn service(kernel: &mut Kernel, name: ServiceName) -> Result<(), ResultCode> {
+    let report = Report {
+        service: Some(name),
+        interface: None,
+        command: None,
+        input: Vec::new(),
+        pc: kernel.request_pc(),
+    };
+    match kernel.sys.unimplemented.report(report) {
+        Policy::Stub => {
+            let service = nn::Service {
+                object: Some(sf::object(Placeholder)),
+                max_sessions: nn::DEFAULT_MAX_SESSIONS,
+                port: None,
+            };
+            kernel.sys.services.register(name, service)
+        }
+        Policy::Error | Policy::Halt => Err(sm::RESULT_NOT_REGISTERED),
+    }
+}
+
+pub(crate) fn display_name(name: &ServiceName) -> String {
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
@@ -8,6 +8,9 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.usb = Some(State::new(state));
+        state.register_host(
+            &["usb:ds", "usb:hs", "usb:pd", "usb:pd:c", "usb:pm"],
+            State::new,
+        );
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.vi = Some(State::new(state));
+        state.register_host(&["vi:m", "vi:s", "vi:u"], State::new);
     }
 }
@@ -8,6 +8,6 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.vi2 = Some(State::new(state));
+        state.register_host(&["vi2:m", "vi2:u"], State::new);
     }
 }
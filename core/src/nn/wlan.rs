@@ -8,6 +8,11 @@ impl State {
 }
 impl ServiceTrait for State {
     fn run(state: &mut sys::State) {
-        state.services.wlan = Some(State::new(state));
+        state.register_host(
+            &[
+                "wlan:inf", "wlan:lcl", "wlan:lg", "wlan:lga", "wlan:sg", "wlan:soc",
+            ],
+            State::new,
+        );
     }
 }
@@ -1,5 +1,48 @@
 use crate::nn;
+use crate::nn::ServiceTrait;
+use crate::nn::sf;
 
 pub struct State {
     pub services: nn::ServiceManager,
+    /// Services and commands the guest used that have no implementation
+    pub unimplemented: nn::unimplemented::Reporter,
+}
+
+impl State {
+    /// System state with every host service registered
+    pub fn new() -> Self {
+        let mut state = Self {
+            services: nn::ServiceManager::default(),
+            unimplemented: nn::unimplemented::Reporter::default(),
+        };
+        nn::ServiceManager::start_host_services(&mut state);
+        state
+    }
+
+    /// Register the interface `new` creates under each of `names`, sharing
+    /// it between all their sessions
+    pub fn register_host<S: ServiceTrait + 'static>(
+        &mut self,
+        names: &[&str],
+        new: fn(&mut State) -> S,
+    ) {
+        let object = sf::object(new(self));
+        for name in names {
+            let name = nn::service_name(name).expect("service names are at most 8 bytes");
+            let service = nn::Service {
+                object: Some(object.clone()),
+                max_sessions: nn::DEFAULT_MAX_SESSIONS,
+                port: None,
+            };
+            self.services
+                .register(name, service)
+                .expect("service registered twice");
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
 }
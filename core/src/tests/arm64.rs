@@ -0,0 +1,99 @@
+//! Minimal AArch64 encoders for hand-written guest test programs
+
+pub fn add_imm(rd: u8, rn: u8, imm12: u16) -> u32 {
+    0x91000000 | ((imm12 as u32) << 10) | ((rn as u32) << 5) | (rd as u32)
+}
+
+pub fn sub_imm(rd: u8, rn: u8, imm12: u16) -> u32 {
+    0xD1000000 | ((imm12 as u32) << 10) | ((rn as u32) << 5) | (rd as u32)
+}
+
+pub fn add_reg(rd: u8, rn: u8, rm: u8) -> u32 {
+    0x8B000000 | ((rm as u32) << 16) | ((rn as u32) << 5) | (rd as u32)
+}
+
+pub fn mov_reg(rd: u8, rm: u8) -> u32 {
+    0xAA0003E0 | ((rm as u32) << 16) | (rd as u32)
+}
+
+pub fn branch(offset: i32) -> u32 {
+    let imm26 = (offset >> 2) & 0x3FFFFFF;
+    0x14000000 | (imm26 as u32)
+}
+
+pub fn blr(rn: u8) -> u32 {
+    0xD63F0000 | ((rn as u32) << 5)
+}
+
+pub fn ret() -> u32 {
+    0xD65F03C0
+}
+
+pub fn nop() -> u32 {
+    0xD503201F
+}
+
+pub fn brk(imm16: u16) -> u32 {
+    0xD4200000 | ((imm16 as u32) << 5)
+}
+
+pub fn svc(imm16: u16) -> u32 {
+    0xD4000001 | ((imm16 as u32) << 5)
+}
+
+/// MOVZ Xd, #imm16, LSL #(hw * 16)
+pub fn movz(rd: u8, imm16: u16, hw: u8) -> u32 {
+    0xD2800000 | ((hw as u32) << 21) | ((imm16 as u32) << 5) | (rd as u32)
+}
+
+/// MOVK Xd, #imm16, LSL #(hw * 16)
+pub fn movk(rd: u8, imm16: u16, hw: u8) -> u32 {
+    0xF2800000 | ((hw as u32) << 21) | ((imm16 as u32) << 5) | (rd as u32)
+}
+
+/// Load an arbitrary 64-bit constant into Xd (MOVZ + 3x MOVK)
+pub fn mov_imm64(rd: u8, value: u64) -> [u32; 4] {
+    [
+        movz(rd, value as u16, 0),
+        movk(rd, (value >> 16) as u16, 1),
+        movk(rd, (value >> 32) as u16, 2),
+        movk(rd, (value >> 48) as u16, 3),
+    ]
+}
+
+/// STR Xt, [Xn, #offset] (offset must be a multiple of 8)
+pub fn str_imm(rt: u8, rn: u8, offset: u16) -> u32 {
+    0xF9000000 | (((offset / 8) as u32) << 10) | ((rn as u32) << 5) | (rt as u32)
+}
+
+/// LDR Xt, [Xn, #offset] (offset must be a multiple of 8)
+pub fn ldr_imm(rt: u8, rn: u8, offset: u16) -> u32 {
+    0xF9400000 | (((offset / 8) as u32) << 10) | ((rn as u32) << 5) | (rt as u32)
+}
+
+/// CBZ Xt, offset (byte offset from this instruction)
+pub fn cbz(rt: u8, offset: i32) -> u32 {
+    let imm19 = ((offset >> 2) & 0x7FFFF) as u32;
+    0xB4000000 | (imm19 << 5) | (rt as u32)
+}
+
+/// CBNZ Xt, offset (byte offset from this instruction)
+pub fn cbnz(rt: u8, offset: i32) -> u32 {
+    let imm19 = ((offset >> 2) & 0x7FFFF) as u32;
+    0xB5000000 | (imm19 << 5) | (rt as u32)
+}
+
+/// MRS Xt, TPIDRRO_EL0
+pub fn mrs_tpidrro_el0(rt: u8) -> u32 {
+    0xD53BD060 | (rt as u32)
+}
+
+/// LDR Wt, [Xn, #offset] (offset must be a multiple of 4)
+pub fn ldr_w_imm(rt: u8, rn: u8, offset: u16) -> u32 {
+    0xB9400000 | (((offset / 4) as u32) << 10) | ((rn as u32) << 5) | (rt as u32)
+}
+
+/// STR Wt, [Xn, #offset] (offset must be a multiple of 4)
+pub fn str_w_imm(rt: u8, rn: u8, offset: u16) -> u32 {
+    0xB9000000 | (((offset / 4) as u32) << 10) | ((rn as u32) << 5) | (rt as u32)
+}
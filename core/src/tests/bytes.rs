@@ -0,0 +1,13 @@
+//! Byte-poking helpers for tests that build binary formats by hand
+
+pub fn put(bytes: &mut [u8], offset: usize, value: &[u8]) {
+    bytes[offset..offset + value.len()].copy_from_slice(value);
+}
+
+pub fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    put(bytes, offset, &value.to_le_bytes());
+}
+
+pub fn put_u64(bytes: &mut [u8], offset: usize, value: u64) {
+    put(bytes, offset, &value.to_le_bytes());
+}
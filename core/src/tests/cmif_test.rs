@@ -0,0 +1,402 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::KernelExit;
+    use crate::kernel::handle::KernelObject;
+    use crate::kernel::hipc::{
+        BufferDescriptor, MESSAGE_BUFFER_SIZE, Message, ReceiveListEntry, StaticDescriptor,
+    };
+    use crate::kernel::ipc::SessionHandler;
+    use crate::kernel::result::ResultCode;
+    use crate::nn::ServiceTrait;
+    use crate::nn::cmif::{self, Session};
+    use crate::nn::sf::{self, Command, Context};
+    use crate::tests::guest::Guest;
+
+    const ERROR: ResultCode = ResultCode::new(2, 1);
+
+    struct Counter {
+        total: u64,
+    }
+
+    impl Counter {
+        fn add(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+            let small: u8 = ctx.pop()?;
+            let large: u64 = ctx.pop()?;
+            self.total += small as u64 + large;
+            ctx.push(true);
+            ctx.push(self.total);
+            Ok(())
+        }
+
+        fn open_child(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+            ctx.push_object(Child {
+                value: self.total as u32,
+            });
+            Ok(())
+        }
+
+        fn fail(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+            ctx.push(1u32);
+            Err(ERROR)
+        }
+
+        fn reverse_buffer(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+            let mut data = ctx.read_buffer(0)?;
+            data.reverse();
+            let written = ctx.write_buffer(0, &data)?;
+            ctx.push(written as u32);
+            Ok(())
+        }
+
+        fn take_handles(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+            let copied = ctx.take_copy_handle(0)?;
+            let moved = ctx.take_move_handle(0)?;
+            ctx.push(copied);
+            ctx.push(moved);
+            Ok(())
+        }
+
+        fn count_objects(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+            let count = (0..).take_while(|&i| ctx.in_object(i).is_ok()).count();
+            ctx.push(count as u32);
+            Ok(())
+        }
+    }
+
+    impl ServiceTrait for Counter {
+        fn commands() -> &'static [Command<Self>] {
+            const COMMANDS: &[Command<Counter>] = &[
+                Command::new(0, "Add", Counter::add),
+                Command::new(1, "OpenChild", Counter::open_child),
+                Command::new(2, "Fail", Counter::fail),
+                Command::new(3, "ReverseBuffer", Counter::reverse_buffer),
+                Command::new(4, "CountObjects", Counter::count_objects),
+                Command::new(5, "TakeHandles", Counter::take_handles),
+            ];
+            COMMANDS
+        }
+    }
+
+    struct Child {
+        value: u32,
+    }
+
+    impl Child {
+        fn get(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+            ctx.push(self.value);
+            Ok(())
+        }
+    }
+
+    impl ServiceTrait for Child {
+        fn commands() -> &'static [Command<Self>] {
+            &[Command {
+                id: 0,
+                name: "Get",
+                handler: Child::get,
+            }]
+        }
+    }
+
+    fn session() -> Session {
+        Session::new(sf::object(Counter { total: 0 }))
+    }
+
+    /// Wrap a CMIF payload as libnx does: aligned to 16 bytes, with the
+    /// unused alignment words after it
+    fn frame(message: &mut Message, payload: &[u32]) {
+        let padding = cmif::padding(message.data_offset());
+        message.data = vec![0; padding];
+        message.data.extend(payload);
+        message.data.resize(payload.len() + 4, 0);
+    }
+
+    fn request(kind: u16, command: u32, args: &[u32]) -> Message {
+        let mut message = Message {
+            kind,
+            ..Default::default()
+        };
+        let mut payload = vec![cmif::IN_MAGIC, 1, command, 0];
+        payload.extend(args);
+        frame(&mut message, &payload);
+        message
+    }
+
+    fn domain_request(
+        kind: u8,
+        object: u32,
+        command: u32,
+        args: &[u32],
+        objects: &[u32],
+    ) -> Message {
+        let data_size = (4 + args.len() as u32) * 4;
+        let header = kind as u32 | (objects.len() as u32) << 8 | data_size << 16;
+        let mut payload = vec![header, object, 0, 0, cmif::IN_MAGIC, 1, command, 0];
+        payload.extend(args);
+        payload.extend(objects);
+        let mut message = Message {
+            kind: cmif::TYPE_REQUEST,
+            ..Default::default()
+        };
+        frame(&mut message, &payload);
+        message
+    }
+
+    /// Result, raw data words and returned object IDs of a reply
+    fn parse_reply(reply: &Message, domain: bool) -> (ResultCode, Vec<u32>, Vec<u32>) {
+        let padding = cmif::padding(reply.data_offset());
+        let payload = &reply.data[padding..reply.data.len() - (4 - padding)];
+        let (objects, header) = if domain {
+            (payload[0] as usize, &payload[4..])
+        } else {
+            (0, payload)
+        };
+        assert_eq!(header[0], cmif::OUT_MAGIC);
+        let raw = &header[4..];
+        let (raw, ids) = raw.split_at(raw.len() - objects);
+        (ResultCode(header[2]), raw.to_vec(), ids.to_vec())
+    }
+
+    fn call(guest: &mut Guest, session: &mut Session, message: &Message) -> Message {
+        session.handle_request(&mut guest.kernel, message).unwrap()
+    }
+
+    #[test]
+    fn test_command_table_dispatch() {
+        let mut guest = Guest::new();
+        let mut session = session();
+
+        // u8 then u64: the u64 is aligned to 8 bytes
+        let add = request(cmif::TYPE_REQUEST, 0, &[5, 0, 0x10, 1]);
+        let reply = call(&mut guest, &mut session, &add);
+        assert_eq!(reply.kind, 0);
+        assert_eq!(
+            parse_reply(&reply, false),
+            (ResultCode::SUCCESS, vec![1, 0, 0x15, 1], vec![])
+        );
+        let reply = call(&mut guest, &mut session, &add);
+        assert_eq!(parse_reply(&reply, false).1, [1, 0, 0x2A, 2], "state kept");
+
+        let with_context = request(cmif::TYPE_REQUEST_WITH_CONTEXT, 0, &[1, 0, 0, 0]);
+        let reply = call(&mut guest, &mut session, &with_context);
+        assert_eq!(parse_reply(&reply, false).1, [1, 0, 0x2B, 2]);
+
+        // An empty special header still moves the CMIF header along
+        let mut empty_special = Message {
+            kind: cmif::TYPE_REQUEST,
+            special_header: true,
+            ..Default::default()
+        };
+        frame(&mut empty_special, &[cmif::IN_MAGIC, 1, 0, 0, 1, 0, 0, 0]);
+        let mut bytes = vec![0; MESSAGE_BUFFER_SIZE];
+        empty_special.write(&mut bytes).unwrap();
+        let empty_special = Message::parse(&bytes).unwrap();
+        let reply = call(&mut guest, &mut session, &empty_special);
+        assert_eq!(parse_reply(&reply, false).1, [1, 0, 0x2C, 2]);
+
+        for (message, expected) in [
+            (request(cmif::TYPE_REQUEST, 2, &[]), ERROR),
+            (
+                request(cmif::TYPE_REQUEST, 9, &[]),
+                sf::RESULT_UNKNOWN_COMMAND_ID,
+            ),
+            (
+                request(cmif::TYPE_REQUEST, 0, &[1]),
+                sf::RESULT_INVALID_HEADER_SIZE,
+            ),
+            (request(3, 0, &[]), sf::RESULT_INVALID_IN_HEADER),
+        ] {
+            let reply = call(&mut guest, &mut session, &message);
+            assert_eq!(parse_reply(&reply, false), (expected, vec![], vec![]));
+        }
+
+        let mut bad_magic = add.clone();
+        let padding = cmif::padding(bad_magic.data_offset());
+        bad_magic.data[padding] = 0;
+        let reply = call(&mut guest, &mut session, &bad_magic);
+        assert_eq!(parse_reply(&reply, false).0, sf::RESULT_INVALID_IN_HEADER);
+
+        assert!(Counter::name().ends_with("Counter"));
+    }
+
+    #[test]
+    fn test_sub_interface_opens_session() {
+        let mut guest = Guest::new();
+        let mut session = session();
+
+        let reply = call(
+            &mut guest,
+            &mut session,
+            &request(cmif::TYPE_REQUEST, 1, &[]),
+        );
+        assert_eq!(parse_reply(&reply, false).0, ResultCode::SUCCESS);
+        assert_eq!(reply.move_handles.len(), 1);
+        let object = guest.kernel.process.handles.get(reply.move_handles[0]);
+        assert!(matches!(object, Some(KernelObject::ClientSession(_))));
+        assert_eq!(guest.kernel.ipc.session_count(), 1);
+
+        let clone = request(cmif::TYPE_CONTROL, 2, &[]);
+        let reply = call(&mut guest, &mut session, &clone);
+        assert_eq!(parse_reply(&reply, false).0, ResultCode::SUCCESS);
+        assert_eq!(reply.move_handles.len(), 1);
+        assert_eq!(guest.kernel.ipc.session_count(), 2);
+    }
+
+    #[test]
+    fn test_domain_objects() {
+        let mut guest = Guest::new();
+        let mut session = session();
+        let send = cmif::DOMAIN_SEND_MESSAGE;
+
+        let convert = request(cmif::TYPE_CONTROL, 0, &[]);
+        let reply = call(&mut guest, &mut session, &convert);
+        assert_eq!(
+            parse_reply(&reply, false),
+            (ResultCode::SUCCESS, vec![1], vec![])
+        );
+
+        let add = domain_request(send, 1, 0, &[2, 0, 0, 0], &[]);
+        let reply = call(&mut guest, &mut session, &add);
+        assert_eq!(
+            parse_reply(&reply, true),
+            (ResultCode::SUCCESS, vec![1, 0, 2, 0], vec![])
+        );
+
+        // Returned interfaces become objects of the domain
+        let open = domain_request(send, 1, 1, &[], &[]);
+        let reply = call(&mut guest, &mut session, &open);
+        assert!(reply.move_handles.is_empty());
+        assert_eq!(
+            parse_reply(&reply, true),
+            (ResultCode::SUCCESS, vec![], vec![2])
+        );
+        let reply = call(&mut guest, &mut session, &open);
+        assert_eq!(parse_reply(&reply, true).2, [3]);
+
+        let get = domain_request(send, 2, 0, &[], &[]);
+        let reply = call(&mut guest, &mut session, &get);
+        assert_eq!(
+            parse_reply(&reply, true),
+            (ResultCode::SUCCESS, vec![2], vec![])
+        );
+
+        let count = domain_request(send, 1, 4, &[], &[3, 2]);
+        let reply = call(&mut guest, &mut session, &count);
+        assert_eq!(parse_reply(&reply, true).1, [2]);
+        let count = domain_request(send, 1, 4, &[], &[7]);
+        let reply = call(&mut guest, &mut session, &count);
+        assert_eq!(parse_reply(&reply, true).0, sf::RESULT_INVALID_IN_OBJECT);
+
+        // Control requests keep the plain format on a domain
+        let reply = call(&mut guest, &mut session, &convert);
+        assert_eq!(parse_reply(&reply, false).1, [1]);
+        let query = request(cmif::TYPE_CONTROL, 3, &[]);
+        let reply = call(&mut guest, &mut session, &query);
+        assert_eq!(parse_reply(&reply, false).1, [0x8000]);
+        let copy = request(cmif::TYPE_CONTROL, 1, &[2]);
+        let reply = call(&mut guest, &mut session, &copy);
+        assert_eq!(parse_reply(&reply, false).0, ResultCode::SUCCESS);
+        assert_eq!(reply.move_handles.len(), 1);
+        let copy = request(cmif::TYPE_CONTROL, 1, &[9]);
+        let reply = call(&mut guest, &mut session, &copy);
+        assert_eq!(parse_reply(&reply, false).0, sf::RESULT_TARGET_NOT_FOUND);
+
+        let close = domain_request(cmif::DOMAIN_CLOSE, 2, 0, &[], &[]);
+        let reply = call(&mut guest, &mut session, &close);
+        assert_eq!(parse_reply(&reply, true).0, ResultCode::SUCCESS);
+        for message in [get, close] {
+            let reply = call(&mut guest, &mut session, &message);
+            assert_eq!(parse_reply(&reply, true).0, sf::RESULT_TARGET_NOT_FOUND);
+        }
+    }
+
+    #[test]
+    fn test_command_buffers() {
+        let mut guest = Guest::new();
+        let mut session = session();
+        let memory = guest.kernel.cpu.memory();
+        let (input, output) = (guest.slot(0x100), guest.slot(0x180));
+
+        // Mapped buffers, with an output too small for the whole reply
+        memory.write(input, b"abc");
+        let mut message = request(cmif::TYPE_REQUEST, 3, &[]);
+        message.a_buffers.push(BufferDescriptor {
+            address: input,
+            size: 3,
+            mode: 0,
+        });
+        message.b_buffers.push(BufferDescriptor {
+            address: output,
+            size: 2,
+            mode: 0,
+        });
+        frame(&mut message, &[cmif::IN_MAGIC, 1, 3, 0]);
+        let reply = call(&mut guest, &mut session, &message);
+        assert_eq!(parse_reply(&reply, false).1, [2]);
+        assert_eq!(memory.read_vec(output, 3).unwrap(), b"cb\0");
+
+        // Pointer buffers, with empty mapped ones alongside as libnx sends
+        // them
+        memory.write(input, b"xyz");
+        let mut message = request(cmif::TYPE_REQUEST, 3, &[]);
+        message.a_buffers.push(BufferDescriptor::default());
+        message.b_buffers.push(BufferDescriptor::default());
+        message.x_buffers.push(StaticDescriptor {
+            index: 0,
+            address: input,
+            size: 3,
+        });
+        message.c_buffers.push(ReceiveListEntry {
+            address: output,
+            size: 0x10,
+        });
+        frame(&mut message, &[cmif::IN_MAGIC, 1, 3, 0]);
+        let reply = call(&mut guest, &mut session, &message);
+        assert_eq!(parse_reply(&reply, false).1, [3]);
+        assert_eq!(memory.read_vec(output, 3).unwrap(), b"zyx");
+
+        let reply = call(
+            &mut guest,
+            &mut session,
+            &request(cmif::TYPE_REQUEST, 3, &[]),
+        );
+        assert_eq!(
+            parse_reply(&reply, false).0,
+            crate::kernel::result::OUT_OF_RANGE
+        );
+    }
+
+    #[test]
+    fn test_commands_take_request_handles() {
+        let mut guest = Guest::new();
+        let handle = guest
+            .kernel
+            .create_host_session(Box::new(session()))
+            .unwrap();
+        let (_, copied) = guest.kernel.create_event().unwrap();
+        let (_, moved) = guest.kernel.create_event().unwrap();
+        let moved_object = guest.kernel.process.handles.get(moved);
+        let mut take = Message {
+            kind: cmif::TYPE_REQUEST,
+            copy_handles: vec![copied],
+            move_handles: vec![moved],
+            ..Default::default()
+        };
+        frame(&mut take, &[cmif::IN_MAGIC, 1, 5, 0]);
+        let thread = guest.send(handle, &take, 1);
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+
+        let id = guest.kernel.thread_from_handle(thread, None).unwrap();
+        let tls = guest.kernel.scheduler.thread(id).unwrap().tls_address;
+        let bytes = guest.kernel.cpu.memory().read_vec(tls, MESSAGE_BUFFER_SIZE);
+        let reply = Message::parse(&bytes.unwrap()).unwrap();
+        let (result, raw, _) = parse_reply(&reply, false);
+        assert_eq!(result, ResultCode::SUCCESS);
+        let handles = &guest.kernel.process.handles;
+        assert_ne!(raw[0], copied, "the service's own handle");
+        assert_eq!(handles.get(raw[0]), handles.get(copied));
+        assert_eq!(handles.get(raw[1]), moved_object, "kept after the command");
+        assert_eq!(handles.get(moved), None);
+    }
+}
@@ -0,0 +1,128 @@
+#[cfg(test)]
+mod tests {
+    use crate::fs::{MemoryDirectory, MemoryFile, VirtualDir};
+    use crate::loader::RESULT_INVALID_PACKAGE_FORMAT;
+    use crate::loader::cnmt::{Cnmt, ContentMetaType, ContentType};
+    use crate::tests::bytes::{put, put_u32, put_u64};
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+
+    const TITLE_ID: u64 = 0x0100_0000_0000_1000;
+    const PATCH_ID: u64 = 0x0100_0000_0000_1800;
+
+    /// A content meta of type `meta_type` with `extended` after the header,
+    /// listing `contents` as (type, first byte of the hash, size)
+    fn build_cnmt(meta_type: u8, extended: &[u8], contents: &[(u8, u8, u64)]) -> Vec<u8> {
+        let mut bytes = vec![0; 0x20];
+        put_u64(&mut bytes, 0, TITLE_ID);
+        put_u32(&mut bytes, 8, 0x0001_0000);
+        bytes[0xC] = meta_type;
+        put(&mut bytes, 0xE, &(extended.len() as u16).to_le_bytes());
+        put(&mut bytes, 0x10, &(contents.len() as u16).to_le_bytes());
+        put_u32(&mut bytes, 0x18, 0x0C00_0000);
+        bytes.extend_from_slice(extended);
+        for &(content_type, hash, size) in contents {
+            let mut record = [hash; 0x38];
+            record[0x30..0x36].copy_from_slice(&size.to_le_bytes()[..6]);
+            record[0x36] = content_type;
+            record[0x37] = 0;
+            bytes.extend_from_slice(&record);
+        }
+        bytes
+    }
+
+    fn application_extended() -> Vec<u8> {
+        let mut extended = vec![0; 0x10];
+        put_u64(&mut extended, 0, PATCH_ID);
+        put_u32(&mut extended, 8, 0x0410_0000);
+        extended
+    }
+
+    #[test]
+    fn test_cnmt_application() {
+        let bytes = build_cnmt(
+            0x80,
+            &application_extended(),
+            &[(1, 0xAB, 0x1_2345_6789), (3, 0xCD, 0x8000)],
+        );
+        let cnmt = Cnmt::parse(&bytes).unwrap();
+        assert_eq!(cnmt.title_id, TITLE_ID);
+        assert_eq!(cnmt.version, 0x0001_0000);
+        assert_eq!(cnmt.meta_type, ContentMetaType::Application);
+        assert_eq!(cnmt.required_download_system_version, 0x0C00_0000);
+        assert_eq!(cnmt.required_system_version, Some(0x0410_0000));
+        assert_eq!(cnmt.patch_id, Some(PATCH_ID));
+        assert_eq!(cnmt.application_id, None);
+        assert_eq!(cnmt.contents.len(), 2);
+
+        let program = cnmt.content(ContentType::Program).unwrap();
+        assert_eq!(program.size, 0x1_2345_6789);
+        assert_eq!(program.content_id, [0xAB; 0x10]);
+        assert_eq!(program.file_name(), format!("{}.nca", "ab".repeat(0x10)));
+        let control = cnmt.content(ContentType::Control).unwrap();
+        assert_eq!(control.size, 0x8000);
+        assert!(cnmt.content(ContentType::HtmlDocument).is_none());
+    }
+
+    #[test]
+    fn test_cnmt_patch_and_add_on() {
+        let mut extended = vec![0; 0x18];
+        put_u64(&mut extended, 0, TITLE_ID);
+        put_u32(&mut extended, 8, 0x0500_0000);
+        let patch = Cnmt::parse(&build_cnmt(0x81, &extended, &[(0, 0x11, 0x1000)])).unwrap();
+        assert_eq!(patch.meta_type, ContentMetaType::Patch);
+        assert_eq!(patch.application_id, Some(TITLE_ID));
+        assert_eq!(patch.required_system_version, Some(0x0500_0000));
+        assert_eq!(
+            patch.contents[0].file_name(),
+            format!("{}.cnmt.nca", "11".repeat(0x10))
+        );
+
+        let add_on = Cnmt::parse(&build_cnmt(0x82, &extended[..0x10], &[])).unwrap();
+        assert_eq!(add_on.meta_type, ContentMetaType::AddOnContent);
+        assert_eq!(add_on.application_id, Some(TITLE_ID));
+        assert_eq!(add_on.required_application_version, Some(0x0500_0000));
+        assert_eq!(add_on.required_system_version, None);
+    }
+
+    #[test]
+    fn test_cnmt_rejects_malformed() {
+        let bytes = build_cnmt(0x80, &application_extended(), &[(1, 0xAB, 0x1000)]);
+        assert_eq!(
+            Cnmt::parse(&bytes[..bytes.len() - 1]).unwrap_err(),
+            RESULT_INVALID_PACKAGE_FORMAT
+        );
+        assert_eq!(
+            Cnmt::parse(&build_cnmt(0x42, &[], &[])).unwrap_err(),
+            RESULT_INVALID_PACKAGE_FORMAT
+        );
+        assert_eq!(
+            Cnmt::parse(&build_cnmt(0x80, &application_extended(), &[(9, 0, 0)])).unwrap_err(),
+            RESULT_INVALID_PACKAGE_FORMAT
+        );
+        // An application's extended header has to hold its patch ID
+        assert_eq!(
+            Cnmt::parse(&build_cnmt(0x80, &[0; 4], &[])).unwrap_err(),
+            RESULT_INVALID_PACKAGE_FORMAT
+        );
+    }
+
+    #[test]
+    fn test_cnmt_open_meta_section() {
+        let section = MemoryDirectory::new("section0");
+        let bytes = build_cnmt(0x80, &application_extended(), &[(1, 0xAB, 0x1000)]);
+        section.add_file(Arc::new(MemoryFile::read_only(
+            "Application_0100000000001000.cnmt",
+            bytes,
+        )));
+        let section: VirtualDir = Arc::new(section);
+        let cnmt = Cnmt::open(&section).unwrap();
+        assert_eq!(cnmt.title_id, TITLE_ID);
+
+        let empty: VirtualDir = Arc::new(MemoryDirectory::new("section0"));
+        assert_eq!(
+            Cnmt::open(&empty).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
@@ -0,0 +1,229 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::debug::Cause;
+    use crate::kernel::hle::Target;
+    use crate::kernel::{KernelExit, svc};
+    use crate::loader::elf::{
+        R_AARCH64_ABS64, R_AARCH64_GLOB_DAT, R_AARCH64_JUMP_SLOT, R_AARCH64_RELATIVE,
+    };
+    use crate::loader::nso::Nso;
+    use crate::loader::{Mod0, Segment, Segments};
+    use crate::tests::arm64;
+    use crate::tests::bytes::put;
+    use crate::tests::guest::{Guest, call};
+
+    // Where the builder puts things, as offsets from the module's base
+    const MOD0: usize = 0x10;
+    const FUNCTION: u64 = 0x100;
+    const SYMTAB: usize = 0x1000;
+    const STRTAB: usize = 0x1400;
+    const RELA: usize = 0x1800;
+    const JMPREL: usize = 0x1C00;
+    const DYNAMIC: usize = 0x2000;
+    const GOT: u64 = 0x2800;
+    const DATA_SIZE: usize = 0x1000;
+
+    const DT_PLTRELSZ: u64 = 2;
+    const DT_STRTAB: u64 = 5;
+    const DT_SYMTAB: u64 = 6;
+    const DT_RELA: u64 = 7;
+    const DT_RELASZ: u64 = 8;
+    const DT_STRSZ: u64 = 10;
+    const DT_JMPREL: u64 = 23;
+
+    /// Global, or weak for imports that may be missing
+    const STB_GLOBAL: u8 = 1;
+    const STB_WEAK: u8 = 2;
+    const STT_FUNC: u8 = 2;
+
+    /// `Elf64_Rela` at `offset`: type, symbol index and addend
+    type Relocation = (u64, u32, u32, u64);
+
+    /// A module defining `exports` at their offsets and importing
+    /// `imports`, whose symbol indices follow the exports' from 1, with
+    /// `program` at [`FUNCTION`]
+    fn module(
+        name: &str,
+        exports: &[(&str, u64)],
+        imports: &[(&str, u8)],
+        rela: &[Relocation],
+        jmprel: &[Relocation],
+        program: &[u32],
+    ) -> Nso {
+        let mut image = vec![0; DYNAMIC + DATA_SIZE];
+        put(&mut image, 0, &arm64::branch(FUNCTION as i32).to_le_bytes());
+        put(&mut image, 4, &(MOD0 as u32).to_le_bytes());
+        put(&mut image, MOD0, b"MOD0");
+        put(
+            &mut image,
+            MOD0 + 4,
+            &((DYNAMIC - MOD0) as u32).to_le_bytes(),
+        );
+        for (i, instr) in program.iter().enumerate() {
+            put(&mut image, FUNCTION as usize + i * 4, &instr.to_le_bytes());
+        }
+
+        let mut strtab = vec![0];
+        let mut symbols = vec![[0; 0x18]];
+        let exported = exports
+            .iter()
+            .map(|&(name, value)| (name, value, STB_GLOBAL, 1));
+        let imported = imports.iter().map(|&(name, bind)| (name, 0, bind, 0));
+        for (name, value, bind, section) in exported.chain(imported) {
+            let mut symbol = [0; 0x18];
+            put(&mut symbol, 0, &(strtab.len() as u32).to_le_bytes());
+            symbol[4] = bind << 4 | STT_FUNC;
+            put(&mut symbol, 6, &(section as u16).to_le_bytes());
+            put(&mut symbol, 8, &value.to_le_bytes());
+            symbols.push(symbol);
+            strtab.extend(name.as_bytes());
+            strtab.push(0);
+        }
+        put(&mut image, SYMTAB, &symbols.concat());
+        put(&mut image, STRTAB, &strtab);
+
+        for (at, relocations) in [(RELA, rela), (JMPREL, jmprel)] {
+            for (i, &(offset, kind, symbol, addend)) in relocations.iter().enumerate() {
+                let info = (symbol as u64) << 32 | kind as u64;
+                let entry = [offset, info, addend].map(u64::to_le_bytes).concat();
+                put(&mut image, at + i * 0x18, &entry);
+            }
+        }
+        let tags = [
+            (DT_SYMTAB, SYMTAB as u64),
+            (DT_STRTAB, STRTAB as u64),
+            (DT_STRSZ, strtab.len() as u64),
+            (DT_RELA, RELA as u64),
+            (DT_RELASZ, rela.len() as u64 * 0x18),
+            (DT_JMPREL, JMPREL as u64),
+            (DT_PLTRELSZ, jmprel.len() as u64 * 0x18),
+        ];
+        for (i, (tag, value)) in tags.into_iter().enumerate() {
+            put(&mut image, DYNAMIC + i * 0x10, &tag.to_le_bytes());
+            put(&mut image, DYNAMIC + i * 0x10 + 8, &value.to_le_bytes());
+        }
+
+        Nso {
+            name: name.to_string(),
+            segments: Segments {
+                text: Segment {
+                    offset: 0,
+                    size: 0x1000,
+                },
+                ro: Segment {
+                    offset: 0x1000,
+                    size: 0x1000,
+                },
+                data: Segment {
+                    offset: DYNAMIC as u64,
+                    size: DATA_SIZE as u64,
+                },
+                bss_size: 0,
+            },
+            build_id: [0; 0x20],
+            mod0: Mod0::parse(&image),
+            image,
+        }
+    }
+
+    /// `sdk` exports a function that breaks and a variable; `main` calls
+    /// the function through its PLT slot
+    fn modules() -> [Nso; 2] {
+        let main = module(
+            "main",
+            &[("nnMain", FUNCTION)],
+            &[("sdk_function", STB_GLOBAL), ("sdk_variable", STB_GLOBAL)],
+            &[
+                (GOT, R_AARCH64_RELATIVE, 0, 0x40),
+                (GOT + 8, R_AARCH64_GLOB_DAT, 3, 0),
+                (GOT + 0x18, R_AARCH64_ABS64, 2, 8),
+                (GOT + 0x20, R_AARCH64_ABS64, 1, 0),
+            ],
+            &[(GOT + 0x10, R_AARCH64_JUMP_SLOT, 2, 0)],
+            &[],
+        );
+        let mut program = call(svc::BREAK, &[0, 0, 0]);
+        program.push(arm64::ret());
+        let sdk = module(
+            "sdk",
+            &[("sdk_function", FUNCTION), ("sdk_variable", 0x2F00)],
+            &[("missing_weak", STB_WEAK), ("missing", STB_GLOBAL)],
+            &[
+                (GOT, R_AARCH64_GLOB_DAT, 3, 0),
+                (GOT + 8, R_AARCH64_GLOB_DAT, 4, 0),
+            ],
+            &[],
+            &program,
+        );
+        [main, sdk]
+    }
+
+    #[test]
+    fn test_link_records_symbols_and_relocates() {
+        let mut guest = Guest::new();
+        let mut modules = modules();
+        // The unresolvable import keeps whatever was there, while the
+        // missing weak one is cleared
+        modules[1].image[GOT as usize] = 0xAA;
+        modules[1].image[GOT as usize + 8] = 0xAA;
+        let main = guest.kernel.load_nsos(&modules).unwrap();
+        let sdk = guest.kernel.process.modules[1].base;
+
+        let process = &guest.kernel.process;
+        assert_eq!(
+            process.modules[0].symbols,
+            [(String::from("nnMain"), FUNCTION)].into()
+        );
+        assert_eq!(process.symbol_address("sdk_variable"), Some(sdk + 0x2F00));
+
+        let memory = guest.kernel.cpu.memory();
+        let got = |base: u64, i: u64| memory.read_u64(base + GOT + i * 8).unwrap();
+        assert_eq!(got(main, 0), main + 0x40, "RELATIVE");
+        assert_eq!(got(main, 1), sdk + 0x2F00, "GLOB_DAT");
+        assert_eq!(got(main, 2), sdk + FUNCTION, "JUMP_SLOT");
+        assert_eq!(got(main, 3), sdk + FUNCTION + 8, "ABS64 with an addend");
+        assert_eq!(got(main, 4), main + FUNCTION, "ABS64 to its own symbol");
+        assert_eq!(got(sdk, 0), 0, "missing weak symbols are null");
+        assert_eq!(got(sdk, 1), 0xAA);
+
+        let module = process.module_at(sdk + FUNCTION + 4).unwrap();
+        assert_eq!(
+            module.symbol_at(sdk + FUNCTION + 4),
+            Some(("sdk_function", 4))
+        );
+        assert_eq!(module.symbol_at(sdk + 0x10), None, "before any symbol");
+        assert_eq!(module.symbol_at(main), None, "outside the module");
+    }
+
+    #[test]
+    fn test_symbols_name_crashes_and_hle_targets() {
+        let mut guest = Guest::new();
+        guest
+            .kernel
+            .replace_function(Target::Symbol(String::from("nnMain")), |_, _| {})
+            .unwrap();
+        let modules = modules();
+        let main = guest.kernel.load_nsos(&modules).unwrap();
+        let sdk = guest.kernel.process.modules[1].base;
+        let installed: Vec<_> = guest.kernel.hle.installed().collect();
+        assert_eq!(
+            installed,
+            [(&Target::Symbol(String::from("nnMain")), main + FUNCTION)]
+        );
+
+        // Call sdk_function through main's PLT slot
+        let mut code = arm64::mov_imm64(16, main + GOT + 0x10).to_vec();
+        code.push(arm64::ldr_imm(16, 16, 0));
+        code.push(arm64::blr(16));
+        let entry = guest.load(&code);
+        guest.spawn(entry, 0, 44, 0);
+
+        assert!(matches!(guest.run(), KernelExit::Crashed { .. }));
+        let report = guest.kernel.debug.last_report.clone().unwrap();
+        assert!(matches!(report.cause, Cause::Break { .. }));
+        let pc = report.threads[0].context.pc;
+        let offset = pc - sdk - FUNCTION;
+        let text = report.to_string();
+        assert!(text.contains(&format!("sdk+{:#x} (sdk_function+{offset:#x})", pc - sdk)));
+    }
+}
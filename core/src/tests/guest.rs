@@ -0,0 +1,200 @@
+//! Harness for tests that run guest threads under the kernel scheduler
+
+use crate::kernel::handle::Handle;
+use crate::kernel::hipc::{MESSAGE_BUFFER_SIZE, Message};
+use crate::kernel::memory::{MemoryPermission, MemoryState};
+use crate::kernel::result::ResultCode;
+use crate::kernel::{Kernel, KernelExit, svc};
+use crate::nn::cmif;
+use crate::tests::arm64;
+
+const CODE_SIZE: u64 = 0x10000;
+const STACK_SIZE: u64 = 0x4000;
+const MAX_ROUNDS: usize = 100_000;
+
+/// A kernel with a code region, a heap for stacks and a data area
+pub struct Guest {
+    pub kernel: Kernel,
+    next_code: u64,
+    next_stack: u64,
+    data: u64,
+}
+
+impl Guest {
+    pub fn new() -> Self {
+        Self::with_kernel(Kernel::new())
+    }
+
+    /// Set up the regions in `kernel`'s current process
+    pub fn with_kernel(mut kernel: Kernel) -> Self {
+        let code = kernel.process.address_space.layout.code.base;
+        kernel
+            .process
+            .address_space
+            .map(
+                code,
+                CODE_SIZE,
+                MemoryState::Code,
+                MemoryPermission::READ_EXECUTE,
+            )
+            .unwrap();
+        let heap = kernel.process.set_heap_size(0x20_0000).unwrap();
+        Self {
+            kernel,
+            next_code: code,
+            next_stack: heap + 0x10_0000,
+            data: heap,
+        }
+    }
+
+    /// Copy `program` into the code region, returning its entry point
+    pub fn load(&mut self, program: &[u32]) -> u64 {
+        let entry = self.next_code;
+        let memory = self.kernel.cpu.memory();
+        for (i, instr) in program.iter().enumerate() {
+            memory.write_u32(entry + i as u64 * 4, *instr);
+        }
+        self.next_code += (program.len() as u64 * 4).next_multiple_of(0x100);
+        entry
+    }
+
+    pub fn stack(&mut self) -> u64 {
+        self.next_stack += STACK_SIZE;
+        self.next_stack
+    }
+
+    /// Create and start a thread from the host, as a loader would
+    pub fn spawn(&mut self, entry: u64, arg: u64, priority: u32, core: i32) -> Handle {
+        let stack = self.stack();
+        let handle = self
+            .kernel
+            .create_thread(entry, arg, stack, priority, core)
+            .unwrap();
+        self.kernel.start_thread(handle).unwrap();
+        handle
+    }
+
+    /// Start a thread that sends `request` on `session` and stores the
+    /// result in slot `out`
+    pub fn send(&mut self, session: Handle, request: &Message, out: u64) -> Handle {
+        let mut code = call(svc::SEND_SYNC_REQUEST, &[session as u64]);
+        code.extend(store(0, self.slot(out)));
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = self.load(&code);
+        let thread = self.spawn(entry, 0, 44, 0);
+        let id = self.kernel.thread_from_handle(thread, None).unwrap();
+        let tls = self.kernel.scheduler.thread(id).unwrap().tls_address;
+        let mut bytes = vec![0; MESSAGE_BUFFER_SIZE];
+        request.write(&mut bytes).unwrap();
+        self.kernel.cpu.memory().write(tls, &bytes);
+        thread
+    }
+
+    pub fn slot(&self, index: u64) -> u64 {
+        self.data + index * 8
+    }
+
+    pub fn read(&self, index: u64) -> u64 {
+        self.kernel.cpu.memory().read_u64(self.slot(index)).unwrap()
+    }
+
+    pub fn result(&self, index: u64) -> ResultCode {
+        ResultCode(self.read(index) as u32)
+    }
+
+    pub fn run(&mut self) -> KernelExit {
+        for _ in 0..MAX_ROUNDS {
+            if let Some(exit) = self.kernel.run_round() {
+                return exit;
+            }
+        }
+        panic!("guest did not finish within {MAX_ROUNDS} rounds");
+    }
+}
+
+/// Store Xreg into `addr`, clobbering X9
+pub fn store(reg: u8, addr: u64) -> Vec<u32> {
+    let mut code = arm64::mov_imm64(9, addr).to_vec();
+    code.push(arm64::str_imm(reg, 9, 0));
+    code
+}
+
+/// Bump the counter in slot 0 and store the new value in `addr`, so the
+/// slots record the order in which threads got there
+pub fn record(guest: &Guest, addr: u64) -> Vec<u32> {
+    let mut code = arm64::mov_imm64(9, guest.slot(0)).to_vec();
+    code.push(arm64::ldr_imm(10, 9, 0));
+    code.push(arm64::add_imm(10, 10, 1));
+    code.push(arm64::str_imm(10, 9, 0));
+    code.extend(store(10, addr));
+    code
+}
+
+pub fn svc(id: u32) -> u32 {
+    arm64::svc(id as u16)
+}
+
+pub fn sleep(ns: i64) -> Vec<u32> {
+    let mut code = arm64::mov_imm64(0, ns as u64).to_vec();
+    code.push(svc(svc::SLEEP_THREAD));
+    code
+}
+
+/// Spin until the value at `addr` becomes non-zero
+pub fn wait_for_flag(addr: u64) -> Vec<u32> {
+    let mut code = arm64::mov_imm64(11, addr).to_vec();
+    code.push(arm64::ldr_imm(12, 11, 0));
+    code.push(arm64::cbz(12, -4));
+    code
+}
+
+pub fn set_flag(addr: u64) -> Vec<u32> {
+    let mut code = arm64::mov_imm64(11, addr).to_vec();
+    code.push(arm64::movz(12, 1, 0));
+    code.push(arm64::str_imm(12, 11, 0));
+    code
+}
+
+/// Load `args` into X0, X1, ... and issue SVC `id`
+pub fn call(id: u32, args: &[u64]) -> Vec<u32> {
+    let mut code = Vec::new();
+    for (reg, &value) in args.iter().enumerate() {
+        code.extend(arm64::mov_imm64(reg as u8, value));
+    }
+    code.push(svc(id));
+    code
+}
+
+/// Load the 64-bit value at `addr` into Xreg
+pub fn load(reg: u8, addr: u64) -> Vec<u32> {
+    let mut code = arm64::mov_imm64(reg, addr).to_vec();
+    code.push(arm64::ldr_imm(reg, reg, 0));
+    code
+}
+
+/// A CMIF request, with the header aligned to 16 bytes as libnx does
+pub fn cmif_request(command: u32, send_pid: bool, args: &[u32]) -> Message {
+    let message = Message {
+        pid: send_pid.then_some(0),
+        ..Default::default()
+    };
+    cmif_frame(message, command, args)
+}
+
+/// Make `message`, with its special header already filled in, a CMIF
+/// request
+pub fn cmif_frame(mut message: Message, command: u32, args: &[u32]) -> Message {
+    message.kind = cmif::TYPE_REQUEST;
+    message.data = vec![0; cmif::padding(message.data_offset())];
+    message.data.extend([cmif::IN_MAGIC, 1, command, 0]);
+    message.data.extend(args);
+    message.data.resize(message.data.len() + 4, 0);
+    message
+}
+
+/// Result code of a CMIF response
+pub fn cmif_result(reply: &Message) -> ResultCode {
+    let padding = cmif::padding(reply.data_offset());
+    assert_eq!(reply.data[padding], cmif::OUT_MAGIC);
+    ResultCode(reply.data[padding + 2])
+}
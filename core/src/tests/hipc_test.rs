@@ -0,0 +1,151 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::hipc::{
+        BufferDescriptor, MESSAGE_BUFFER_SIZE, Message, ReceiveListEntry, StaticDescriptor,
+    };
+    use crate::kernel::result;
+
+    fn to_bytes(words: &[u32]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        bytes.resize(MESSAGE_BUFFER_SIZE, 0);
+        bytes
+    }
+
+    /// A request using every part of the format, with addresses above 4GB
+    /// to exercise the split address fields
+    fn full_message() -> (Vec<u32>, Message) {
+        let words = vec![
+            0x0111_0004, // type 4, one X, one A, one B
+            0x8000_0C03, // special header, three data words, one C buffer
+            0x0000_0023, // PID, one copied and one moved handle
+            0x0000_1234,
+            0x0000_0000,
+            0x0000_AAAA,
+            0x0000_BBBB,
+            0x0080_5042, // X: index 2, 0x80 bytes
+            0x1234_5678,
+            0x0000_2000, // A: 0x2000 bytes, mode 1
+            0x0000_1000,
+            0x5000_0001,
+            0x0000_0010, // B: 0x1_0000_0010 bytes
+            0x0000_0000,
+            0x3100_0048,
+            1,
+            2,
+            3,
+            0x0000_4000, // C: 0x100 bytes
+            0x0100_0008,
+        ];
+        let message = Message {
+            kind: 4,
+            special_header: false,
+            pid: Some(0x1234),
+            copy_handles: vec![0xAAAA],
+            move_handles: vec![0xBBBB],
+            x_buffers: vec![StaticDescriptor {
+                index: 2,
+                address: 0x15_1234_5678,
+                size: 0x80,
+            }],
+            a_buffers: vec![BufferDescriptor {
+                address: 0x5_0000_1000,
+                size: 0x2000,
+                mode: 1,
+            }],
+            b_buffers: vec![BufferDescriptor {
+                address: 0x123_0000_0000,
+                size: 0x1_0000_0010,
+                mode: 0,
+            }],
+            w_buffers: vec![],
+            data: vec![1, 2, 3],
+            c_buffers: vec![ReceiveListEntry {
+                address: 0x8_0000_4000,
+                size: 0x100,
+            }],
+        };
+        (words, message)
+    }
+
+    #[test]
+    fn test_parse_message_fields() {
+        let (words, expected) = full_message();
+        let message = Message::parse(&to_bytes(&words)).unwrap();
+        assert_eq!(message, expected);
+        assert_eq!(message.data_offset(), 15 * 4);
+    }
+
+    #[test]
+    fn test_write_message_matches_layout() {
+        let (words, message) = full_message();
+        let mut buffer = vec![0; MESSAGE_BUFFER_SIZE];
+        message.write(&mut buffer).unwrap();
+        assert_eq!(buffer, to_bytes(&words));
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let message = Message {
+            kind: 0x10,
+            copy_handles: vec![1, 2, 3],
+            w_buffers: vec![BufferDescriptor {
+                address: 0xFFFF_FFFF_F000,
+                size: 0xF_FFFF_FFFF,
+                mode: 3,
+            }],
+            data: (0..20).collect(),
+            ..Default::default()
+        };
+        let mut buffer = vec![0; MESSAGE_BUFFER_SIZE];
+        message.write(&mut buffer).unwrap();
+        assert_eq!(Message::parse(&buffer).unwrap(), message);
+        assert_eq!(message.data_offset(), (2 + 4 + 3) * 4);
+    }
+
+    #[test]
+    fn test_empty_special_header_kept() {
+        // Special header flag set, but no PID or handles after it
+        let words = [0x0000_0004, 0x8000_0002, 0, 0x4943_4653, 7];
+        let message = Message::parse(&to_bytes(&words)).unwrap();
+        assert!(message.special_header);
+        assert_eq!(message.data, [0x4943_4653, 7]);
+        assert_eq!(message.data_offset(), 3 * 4);
+
+        let mut buffer = vec![0; MESSAGE_BUFFER_SIZE];
+        message.write(&mut buffer).unwrap();
+        assert_eq!(buffer, to_bytes(&words));
+    }
+
+    #[test]
+    fn test_receive_list_modes() {
+        // Mode 2 is a single C buffer; modes 0 and 1 have none
+        for (flags, count) in [(0, 0), (1, 0), (2, 1), (4, 2)] {
+            let mut words = vec![0, flags << 10];
+            words.extend([0; 4]);
+            let message = Message::parse(&to_bytes(&words)).unwrap();
+            assert_eq!(message.c_buffers.len(), count, "flags {flags}");
+        }
+    }
+
+    #[test]
+    fn test_message_size_limits() {
+        let (words, message) = full_message();
+        let bytes = to_bytes(&words);
+        assert_eq!(
+            Message::parse(&bytes[..words.len() * 4 - 4]),
+            Err(result::MESSAGE_TOO_LARGE)
+        );
+
+        let mut small = vec![0; 16];
+        assert_eq!(message.write(&mut small), Err(result::MESSAGE_TOO_LARGE));
+
+        let oversized = Message {
+            copy_handles: vec![0; 16],
+            ..Default::default()
+        };
+        assert_eq!(
+            oversized.write(&mut vec![0; MESSAGE_BUFFER_SIZE]),
+            Err(result::OUT_OF_RANGE)
+        );
+    }
+}
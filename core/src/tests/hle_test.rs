@@ -0,0 +1,160 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::hle::{self, TRAMPOLINE_SVC_BASE, Target};
+    use crate::kernel::process::Module;
+    use crate::kernel::{KernelExit, result, svc};
+    use crate::tests::arm64;
+    use crate::tests::guest::{Guest, store, svc};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// A guest function that halts the test if it ever runs
+    fn original(guest: &mut Guest) -> u64 {
+        guest.load(&[arm64::brk(0x40), arm64::brk(0x41), arm64::ret()])
+    }
+
+    /// Call `function` with `args`, store X0 in slot 1 and exit
+    fn caller(function: u64, args: &[u64], guest: &Guest) -> Vec<u32> {
+        let mut code = Vec::new();
+        for (reg, &value) in args.iter().enumerate() {
+            code.extend(arm64::mov_imm64(reg as u8, value));
+        }
+        code.extend(arm64::mov_imm64(16, function));
+        code.push(arm64::blr(16));
+        code.extend(store(0, guest.slot(1)));
+        code.push(svc(svc::EXIT_THREAD));
+        code
+    }
+
+    #[test]
+    fn test_replace_by_address() {
+        let mut guest = Guest::new();
+        let memcpy = original(&mut guest);
+        guest
+            .kernel
+            .replace_function(Target::Address(memcpy), hle::memcpy)
+            .unwrap();
+        let memory = guest.kernel.cpu.memory();
+        assert_eq!(
+            memory.read_u32(memcpy),
+            Some(arm64::svc(TRAMPOLINE_SVC_BASE as u16))
+        );
+        assert_eq!(memory.read_u32(memcpy + 4), Some(arm64::ret()));
+
+        let (src, dst) = (guest.slot(10), guest.slot(20));
+        memory.write(src, b"replaced in Rust");
+        let main = caller(memcpy, &[dst, src, 16], &guest);
+        let main = guest.load(&main);
+        guest.spawn(main, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(memory.read_vec(dst, 16).unwrap(), b"replaced in Rust");
+        assert_eq!(guest.read(1), dst, "memcpy returns its destination");
+    }
+
+    #[test]
+    fn test_symbol_installs_once_module_loads() {
+        let mut guest = Guest::new();
+        guest.kernel.replace_string_functions().unwrap();
+        assert_eq!(guest.kernel.hle.installed().count(), 0);
+
+        let base = original(&mut guest);
+        guest.kernel.process.modules.push(Module {
+            name: String::from("sdk"),
+            base,
+            size: 0x100,
+            symbols: [(String::from("strlen"), 0)].into(),
+            ..Default::default()
+        });
+        assert_eq!(guest.kernel.install_replacements(), 1);
+        assert_eq!(guest.kernel.install_replacements(), 0);
+        let installed: Vec<_> = guest.kernel.hle.installed().collect();
+        assert_eq!(installed, [(&Target::Symbol(String::from("strlen")), base)]);
+
+        let text = guest.slot(10);
+        guest.kernel.cpu.memory().write(text, b"sixteen chars ok\0");
+        let main = caller(base, &[text], &guest);
+        let main = guest.load(&main);
+        guest.spawn(main, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.read(1), 16);
+    }
+
+    #[test]
+    fn test_module_offset_and_closure_handler() {
+        let mut guest = Guest::new();
+        let base = guest.load(&[arm64::nop(); 8]);
+        guest.kernel.process.modules.push(Module {
+            name: String::from("main"),
+            base,
+            size: 0x100,
+            ..Default::default()
+        });
+        let function = base + 0x10;
+
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let target = Target::Offset {
+            module: String::from("main"),
+            offset: 0x10,
+        };
+        guest
+            .kernel
+            .replace_function(target, move |_, core| {
+                counter.set(counter.get() + 1);
+                core.set_x(0, core.get_x(0) * 2);
+            })
+            .unwrap();
+        assert_eq!(
+            guest
+                .kernel
+                .replace_function(Target::Address(function), hle::stub(0)),
+            Err(result::INVALID_STATE),
+            "the function is already replaced"
+        );
+
+        let main = caller(function, &[21], &guest);
+        let main = guest.load(&main);
+        guest.spawn(main, 0, 44, 0);
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.read(1), 42);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn test_replace_code_that_already_ran() {
+        let mut guest = Guest::new();
+        let function = guest.load(&[arm64::movz(0, 1, 0), arm64::ret()]);
+        let main = caller(function, &[], &guest);
+        let main = guest.load(&main);
+        guest.spawn(main, 0, 44, 0);
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.read(1), 1);
+
+        // The cores may hold a translation of the original by now
+        guest
+            .kernel
+            .replace_function(Target::Address(function), hle::stub(2))
+            .unwrap();
+        guest.spawn(main, 0, 44, 0);
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.read(1), 2);
+    }
+
+    #[test]
+    fn test_replace_invalid_address() {
+        let mut guest = Guest::new();
+        let memory_size = guest.kernel.cpu.memory().size();
+        for address in [0x1002, memory_size - 4] {
+            assert_eq!(
+                guest
+                    .kernel
+                    .replace_function(Target::Address(address), hle::stub(0)),
+                Err(result::INVALID_ADDRESS)
+            );
+        }
+        assert_eq!(guest.kernel.hle.installed().count(), 0);
+        assert!(!guest.kernel.hle.is_trampoline(TRAMPOLINE_SVC_BASE));
+    }
+}
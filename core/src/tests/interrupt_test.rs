@@ -0,0 +1,160 @@
+#[cfg(test)]
+mod tests {
+    use crate::cpu::{CpuContext, HaltReason, InterruptReason, UnicornCPU};
+    use crate::kernel::result;
+    use crate::kernel::thread::ThreadState;
+    use crate::kernel::{KernelExit, svc};
+    use crate::tests::arm64;
+    use crate::tests::guest::{Guest, call, set_flag, sleep, store, svc, wait_for_flag};
+    use std::time::Duration;
+
+    /// Point `core` at `entry` with otherwise clean registers
+    fn start_at(core: &UnicornCPU, entry: u64) {
+        core.load_context(&CpuContext {
+            pc: entry,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn test_interrupt_from_another_thread() {
+        let mut guest = Guest::new();
+        // b .
+        let entry = guest.load(&[arm64::branch(0)]);
+        let core = guest.kernel.cpu.cores[0].clone();
+        start_at(&core, entry);
+
+        let remote = core.clone();
+        let interrupter = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            remote.interrupt(InterruptReason::Reschedule);
+        });
+        let before = core.instructions();
+        let halt = core.run_until_halt();
+        interrupter.join().unwrap();
+
+        let HaltReason::Interrupted(interrupts) = halt else {
+            panic!("expected an interrupt, got {halt:?}");
+        };
+        assert!(interrupts.contains(InterruptReason::Reschedule));
+        assert!(!interrupts.contains(InterruptReason::Timer));
+        assert_eq!(core.get_pc(), entry);
+        assert!(core.instructions() > before, "the loop was running");
+    }
+
+    #[test]
+    fn test_pending_interrupt_stops_before_first_block() {
+        let mut guest = Guest::new();
+        let mut code = arm64::mov_imm64(0, 0x1234).to_vec();
+        code.push(arm64::brk(0));
+        let entry = guest.load(&code);
+        let core = guest.kernel.cpu.cores[1].clone();
+        start_at(&core, entry);
+
+        assert!(
+            guest
+                .kernel
+                .cpu
+                .interrupt_core(1, InterruptReason::Reschedule)
+        );
+        assert!(
+            !guest
+                .kernel
+                .cpu
+                .interrupt_core(99, InterruptReason::Reschedule)
+        );
+        assert!(matches!(
+            core.run_until_halt(),
+            HaltReason::Interrupted(interrupts) if interrupts.contains(InterruptReason::Reschedule)
+        ));
+        assert_eq!(core.get_pc(), entry);
+        assert_eq!(core.get_x(0), 0, "nothing ran");
+
+        assert_eq!(core.run_until_halt(), HaltReason::Breakpoint(0));
+        assert_eq!(core.get_x(0), 0x1234);
+    }
+
+    #[test]
+    fn test_timer_fires_at_instruction_count() {
+        let mut guest = Guest::new();
+        let entry = guest.load(&[arm64::branch(0)]);
+        let core = guest.kernel.cpu.cores[2].clone();
+        start_at(&core, entry);
+
+        let at = core.instructions() + 500;
+        core.set_timer(at);
+        assert!(matches!(
+            core.run_until_halt(),
+            HaltReason::Interrupted(interrupts) if interrupts.contains(InterruptReason::Timer)
+        ));
+        // `b .` is a block of one instruction, so the timer fires exactly
+        assert_eq!(core.instructions(), at);
+
+        // The timer is one-shot
+        assert_eq!(core.run_for(100), HaltReason::Stopped);
+        core.set_timer(core.instructions() + 10);
+        core.cancel_timer();
+        assert_eq!(core.run_for(100), HaltReason::Stopped);
+    }
+
+    #[test]
+    fn test_timer_counts_whole_blocks() {
+        let mut guest = Guest::new();
+        // Three NOPs and a branch back to the first: one block of four
+        let entry = guest.load(&[arm64::nop(), arm64::nop(), arm64::nop(), arm64::branch(-12)]);
+        let core = guest.kernel.cpu.cores[2].clone();
+        start_at(&core, entry);
+
+        let start = core.instructions();
+        core.set_timer(start + 10);
+        assert!(matches!(
+            core.run_until_halt(),
+            HaltReason::Interrupted(interrupts) if interrupts.contains(InterruptReason::Timer)
+        ));
+        // The timer fires at the first block boundary past it
+        assert_eq!(core.instructions(), start + 12);
+        assert_eq!(core.get_pc(), entry);
+    }
+
+    /// A higher-priority thread whose wait runs out 15us in wakes during
+    /// the second round and preempts a thread spinning on the same core
+    fn assert_wakes_mid_round(guest: &mut Guest, waiter: Vec<u32>) {
+        let mut spinner = wait_for_flag(guest.slot(2));
+        spinner.push(svc(svc::EXIT_THREAD));
+        let spinner = guest.load(&spinner);
+        guest.spawn(spinner, 0, 44, 0);
+
+        let mut waiter = waiter;
+        waiter.extend(store(0, guest.slot(1)));
+        waiter.extend(set_flag(guest.slot(2)));
+        waiter.push(svc(svc::EXIT_THREAD));
+        let waiter = guest.load(&waiter);
+        let handle = guest.spawn(waiter, 0, 30, 0);
+        let waiter = guest.kernel.thread_from_handle(handle, None).unwrap();
+
+        assert_eq!(guest.kernel.run_round(), None);
+        let state = |guest: &Guest| guest.kernel.scheduler.thread(waiter).unwrap().state;
+        assert_eq!(state(guest), ThreadState::Waiting);
+        assert_eq!(guest.kernel.run_round(), None);
+        assert_eq!(
+            state(guest),
+            ThreadState::Terminated,
+            "woke before the round ended"
+        );
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+    }
+
+    #[test]
+    fn test_sleep_wakes_mid_slice() {
+        let mut guest = Guest::new();
+        assert_wakes_mid_round(&mut guest, sleep(15_000));
+    }
+
+    #[test]
+    fn test_wait_timeout_resolves_mid_slice() {
+        let mut guest = Guest::new();
+        let waiter = call(svc::WAIT_SYNCHRONIZATION, &[0, 0, 0, 15_000]);
+        assert_wakes_mid_round(&mut guest, waiter);
+        assert_eq!(guest.result(1), result::TIMED_OUT);
+    }
+}
@@ -0,0 +1,227 @@
+#[cfg(test)]
+mod tests {
+    use crate::crypto::{KeyAreaKey, Keyset};
+    use aes::Aes128;
+    use aes::cipher::generic_array::GenericArray;
+    use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+    use std::io::ErrorKind;
+
+    // Made-up keys; real ones come from the user's console
+    const KEK_SEED: [u8; 0x10] = [0x11; 0x10];
+    const KEY_SEED: [u8; 0x10] = [0x22; 0x10];
+    const MASTER_KEY_00: [u8; 0x10] = [0x30; 0x10];
+    const MASTER_KEY_01: [u8; 0x10] = [0x31; 0x10];
+    const HEADER_KEK_SOURCE: [u8; 0x10] = [0x44; 0x10];
+    const HEADER_KEY_SOURCE: [u8; 0x20] = [0x55; 0x20];
+    const APPLICATION_SOURCE: [u8; 0x10] = [0x66; 0x10];
+    const OCEAN_SOURCE: [u8; 0x10] = [0x67; 0x10];
+    const SYSTEM_SOURCE: [u8; 0x10] = [0x68; 0x10];
+    const TITLEKEK_SOURCE: [u8; 0x10] = [0x77; 0x10];
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02X}")).collect()
+    }
+
+    fn decrypt(key: &[u8; 0x10], data: &[u8]) -> Vec<u8> {
+        let cipher = Aes128::new(key.into());
+        let mut data = data.to_vec();
+        for block in data.chunks_exact_mut(0x10) {
+            cipher.decrypt_block(GenericArray::from_mut_slice(block));
+        }
+        data
+    }
+
+    fn encrypt(key: &[u8; 0x10], data: &[u8; 0x10]) -> [u8; 0x10] {
+        let mut block = GenericArray::clone_from_slice(data);
+        Aes128::new(key.into()).encrypt_block(&mut block);
+        block.into()
+    }
+
+    fn key(bytes: Vec<u8>) -> [u8; 0x10] {
+        bytes.try_into().unwrap()
+    }
+
+    fn prod_keys() -> String {
+        [
+            ("aes_kek_generation_source", &KEK_SEED[..]),
+            ("aes_key_generation_source", &KEY_SEED),
+            ("master_key_00", &MASTER_KEY_00),
+            ("master_key_01", &MASTER_KEY_01),
+            ("header_kek_source", &HEADER_KEK_SOURCE),
+            ("header_key_source", &HEADER_KEY_SOURCE),
+            ("key_area_key_application_source", &APPLICATION_SOURCE),
+            ("key_area_key_ocean_source", &OCEAN_SOURCE),
+            ("key_area_key_system_source", &SYSTEM_SOURCE),
+            ("titlekek_source", &TITLEKEK_SOURCE),
+        ]
+        .iter()
+        .map(|(name, value)| format!("{name} = {}\n", hex(value)))
+        .collect()
+    }
+
+    fn keyset(prod: &str) -> Keyset {
+        let mut keyset = Keyset::new();
+        keyset.add_prod_keys(prod).unwrap();
+        keyset.derive();
+        keyset
+    }
+
+    /// What the boot ROM's KEK generation makes of `source`
+    fn generate_kek(source: &[u8; 0x10], master_key: &[u8; 0x10]) -> [u8; 0x10] {
+        let kek = key(decrypt(master_key, &KEK_SEED));
+        let source_kek = key(decrypt(&kek, source));
+        key(decrypt(&source_kek, &KEY_SEED))
+    }
+
+    #[test]
+    fn test_keyset_derives_common_keys() {
+        let keyset = keyset(&prod_keys());
+        assert!(keyset.missing().is_empty(), "{:?}", keyset.missing());
+
+        let header_kek = generate_kek(&HEADER_KEK_SOURCE, &MASTER_KEY_00);
+        let header_key = keyset.header_key().unwrap();
+        assert_eq!(
+            header_key.to_vec(),
+            decrypt(&header_kek, &HEADER_KEY_SOURCE)
+        );
+
+        for (revision, master_key) in [(0, MASTER_KEY_00), (1, MASTER_KEY_01)] {
+            let sources = [APPLICATION_SOURCE, OCEAN_SOURCE, SYSTEM_SOURCE];
+            for (kind, source) in KeyAreaKey::ALL.into_iter().zip(sources) {
+                let derived = keyset.key_area_key(kind, revision).unwrap();
+                assert_eq!(derived, generate_kek(&source, &master_key), "{kind:?}");
+            }
+            let titlekek = keyset.titlekek(revision).unwrap();
+            assert_eq!(encrypt(&master_key, &titlekek), TITLEKEK_SOURCE);
+        }
+        assert_eq!(KeyAreaKey::from_index(1), Some(KeyAreaKey::Ocean));
+        assert_eq!(KeyAreaKey::from_index(3), None);
+
+        let err = keyset.titlekek(2).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().contains("titlekek_02"), "{err}");
+    }
+
+    #[test]
+    fn test_keyset_keeps_supplied_keys() {
+        let master_kek = [0x99; 0x10];
+        let master_key_source = [0xAA; 0x10];
+        let prod = format!(
+            "# dumped keys\n\
+             header_key = {}\n\
+             master_kek_02 = {}\n\
+             master_key_source = {}\n\
+             titlekek_02 = {}\n\
+             tsec_key = 00112233445566778899AABBCCDDEEFF\n",
+            hex(&[0xEE; 0x20]),
+            hex(&master_kek),
+            hex(&master_key_source),
+            hex(&[0xDD; 0x10]),
+        );
+        let keyset = keyset(&prod);
+        assert_eq!(keyset.header_key().unwrap(), [0xEE; 0x20]);
+        assert_eq!(keyset.titlekek(2).unwrap(), [0xDD; 0x10]);
+
+        // master_key_02 came from its kek, and the key-area keys it would
+        // give still need their sources
+        let missing = keyset.missing();
+        assert_eq!(
+            missing,
+            [
+                "aes_kek_generation_source",
+                "aes_key_generation_source",
+                "key_area_key_application_source",
+                "key_area_key_ocean_source",
+                "key_area_key_system_source",
+            ]
+        );
+        let err = keyset.key_area_key(KeyAreaKey::System, 2).err().unwrap();
+        assert!(err.to_string().contains("key_area_key_system_02"), "{err}");
+    }
+
+    #[test]
+    fn test_keyset_reports_missing_keys() {
+        let empty = keyset("");
+        assert_eq!(
+            empty.missing(),
+            [
+                "aes_kek_generation_source",
+                "aes_key_generation_source",
+                "header_kek_source",
+                "header_key_source",
+                "master_key_00",
+            ]
+        );
+        assert_eq!(
+            empty.header_key().err().unwrap().kind(),
+            ErrorKind::NotFound
+        );
+
+        let without_header = prod_keys().replace("header_kek_source", "unused_source");
+        assert_eq!(keyset(&without_header).missing(), ["header_kek_source"]);
+    }
+
+    #[test]
+    fn test_keyset_rejects_bad_lines() {
+        let cases = [
+            (
+                "header_key = 0011",
+                "line 1",
+                "header_key should be 32 bytes, found 2",
+            ),
+            ("\nmaster_key_00 = xyz0", "line 2", "not hex"),
+            ("Master_Key_00 = 00", "line 1", "not a key name"),
+            ("master_key_00", "line 1", "expected name = value"),
+            (
+                "master_key_20 = 00",
+                "line 1",
+                "past the last master key revision",
+            ),
+            ("master_key = 00", "line 1", "needs a revision"),
+            ("titlekek_source_01 = 00", "line 1", "not indexed"),
+        ];
+        for (text, line, message) in cases {
+            let err = Keyset::new().add_prod_keys(text).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            let err = err.to_string();
+            assert!(
+                err.contains(line) && err.contains(message),
+                "{text:?}: {err}"
+            );
+        }
+
+        let err = Keyset::new().add_title_keys("0011 = 00").err().unwrap();
+        assert!(err.to_string().contains("rights ID"), "{err}");
+    }
+
+    #[test]
+    fn test_keyset_loads_key_files() {
+        let rights_id = [
+            0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0, 0, 1,
+        ];
+        let title_key = [0x5E; 0x10];
+        let titlekek = key(decrypt(&MASTER_KEY_01, &TITLEKEK_SOURCE));
+        let encrypted = encrypt(&titlekek, &title_key);
+
+        let path = std::env::temp_dir().join(format!("oboromi-keys-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        let err = Keyset::load(&path).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().contains("prod.keys"), "{err}");
+
+        std::fs::write(path.join("prod.keys"), prod_keys()).unwrap();
+        let title_keys = format!("{} = {}\n", hex(&rights_id), hex(&encrypted));
+        std::fs::write(path.join("title.keys"), title_keys).unwrap();
+        let keyset = Keyset::load(&path);
+        std::fs::remove_dir_all(&path).unwrap();
+        let keyset = keyset.unwrap();
+
+        assert!(keyset.header_key().is_ok());
+        assert_eq!(keyset.title_key(&rights_id, 1).unwrap(), title_key);
+        assert_ne!(keyset.title_key(&rights_id, 0).unwrap(), title_key);
+        let err = keyset.title_key(&[0; 0x10], 1).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().contains(&"0".repeat(32)), "{err}");
+    }
+}
@@ -1,4 +1,35 @@
+pub mod arm64;
+#[cfg(test)]
+pub mod bytes;
+#[cfg(test)]
+pub mod guest;
 pub mod run;
+pub mod cmif_test;
+pub mod cnmt_test;
+pub mod elf_test;
+pub mod hipc_test;
+pub mod hle_test;
+pub mod interrupt_test;
+pub mod keys_test;
 pub mod multicore_test;
+pub mod nacp_test;
+pub mod nca_test;
+pub mod npdm_test;
+pub mod nro_test;
+pub mod nso_test;
+pub mod partition_test;
+pub mod romfs_test;
+pub mod sm_test;
+pub mod svc_debug_test;
+pub mod svc_info_test;
+pub mod svc_ipc_test;
+pub mod svc_memory_test;
+pub mod svc_shared_memory_test;
+pub mod svc_sync_test;
+pub mod svc_thread_test;
+pub mod tipc_test;
+pub mod trace_test;
+pub mod unimplemented_test;
+pub mod vfs_test;
 
 pub use run::run_tests;
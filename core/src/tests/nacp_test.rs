@@ -0,0 +1,106 @@
+#[cfg(test)]
+mod tests {
+    use crate::fs::{MemoryDirectory, MemoryFile, VirtualDir};
+    use crate::loader::RESULT_INVALID_PACKAGE_FORMAT;
+    use crate::loader::nacp::{Language, NACP_SIZE, Nacp};
+    use crate::tests::bytes::{put, put_u32, put_u64};
+    use std::sync::Arc;
+
+    /// A NACP naming the title in `titles`, given as (language, name,
+    /// publisher)
+    fn build_nacp(titles: &[(Language, &str, &str)]) -> Vec<u8> {
+        let mut bytes = vec![0; NACP_SIZE];
+        let mut supported = 0u32;
+        for &(language, name, publisher) in titles {
+            let at = language as usize * 0x300;
+            put(&mut bytes, at, name.as_bytes());
+            put(&mut bytes, at + 0x200, publisher.as_bytes());
+            supported |= 1 << language as u32;
+        }
+        put(&mut bytes, 0x3000, b"978-0-00-000000-0");
+        bytes[0x3025] = 1;
+        put_u32(&mut bytes, 0x302C, supported);
+        put_u64(&mut bytes, 0x3038, 0x0100_0000_0000_1000);
+        put(&mut bytes, 0x3060, b"1.2.0");
+        put_u64(&mut bytes, 0x3070, 0x0100_0000_0000_2000);
+        put_u64(&mut bytes, 0x3078, 0x0100_0000_0000_1000);
+        put_u64(&mut bytes, 0x3080, 0x0040_0000);
+        put_u64(&mut bytes, 0x3088, 0x0010_0000);
+        put_u64(&mut bytes, 0x3090, 0x0020_0000);
+        put_u64(&mut bytes, 0x3098, 0x0008_0000);
+        put_u64(&mut bytes, 0x30A0, 0x0002_0000);
+        // Local communication IDs, which the cache sizes come after
+        put(&mut bytes, 0x30E0, &[0xEE; 0x10]);
+        put_u64(&mut bytes, 0x3170, 0x0080_0000);
+        put_u64(&mut bytes, 0x3178, 0x0004_0000);
+        bytes
+    }
+
+    #[test]
+    fn test_nacp_fields() {
+        let nacp = Nacp::parse(&build_nacp(&[
+            (
+                Language::AmericanEnglish,
+                "Example Game",
+                "Example Publisher",
+            ),
+            (Language::Japanese, "例のゲーム", "例の会社"),
+        ]))
+        .unwrap();
+        assert_eq!(nacp.titles[0].name, "Example Game");
+        assert_eq!(nacp.titles[0].publisher, "Example Publisher");
+        assert_eq!(nacp.title(Language::Japanese).unwrap().name, "例のゲーム");
+        assert_eq!(nacp.isbn, "978-0-00-000000-0");
+        assert_eq!(nacp.display_version, "1.2.0");
+        assert!(nacp.startup_user_account);
+        assert!(nacp.supports(Language::Japanese));
+        assert!(!nacp.supports(Language::French));
+        assert_eq!(nacp.presence_group_id, 0x0100_0000_0000_1000);
+        assert_eq!(nacp.add_on_content_base_id, 0x0100_0000_0000_2000);
+        assert_eq!(nacp.save_data_owner_id, 0x0100_0000_0000_1000);
+        assert_eq!(nacp.user_account_save_data_size, 0x0040_0000);
+        assert_eq!(nacp.user_account_save_data_journal_size, 0x0010_0000);
+        assert_eq!(nacp.device_save_data_size, 0x0020_0000);
+        assert_eq!(nacp.device_save_data_journal_size, 0x0008_0000);
+        assert_eq!(nacp.bcat_delivery_cache_storage_size, 0x0002_0000);
+        assert_eq!(nacp.cache_storage_size, 0x0080_0000);
+        assert_eq!(nacp.cache_storage_journal_size, 0x0004_0000);
+
+        assert_eq!(
+            Nacp::parse(&[0; NACP_SIZE - 1]).unwrap_err(),
+            RESULT_INVALID_PACKAGE_FORMAT
+        );
+    }
+
+    #[test]
+    fn test_nacp_language_fallback() {
+        let nacp = Nacp::parse(&build_nacp(&[(Language::German, "Beispiel", "Verlag")])).unwrap();
+        assert_eq!(
+            nacp.title(Language::AmericanEnglish).unwrap().name,
+            "Beispiel"
+        );
+        assert!(
+            Nacp::parse(&build_nacp(&[]))
+                .unwrap()
+                .title(Language::German)
+                .is_none()
+        );
+
+        let control = MemoryDirectory::new("section0");
+        for language in [Language::AmericanEnglish, Language::German] {
+            control.add_file(Arc::new(MemoryFile::read_only(
+                language.icon_name(),
+                vec![language as u8],
+            )));
+        }
+        let control: VirtualDir = Arc::new(control);
+        assert_eq!(Language::German.icon_name(), "icon_German.dat");
+        let icon = nacp.icon(&control, Language::AmericanEnglish).unwrap();
+        assert_eq!(icon.name(), "icon_AmericanEnglish.dat");
+        let icon = nacp.icon(&control, Language::French).unwrap();
+        assert_eq!(icon.name(), "icon_German.dat");
+
+        let empty: VirtualDir = Arc::new(MemoryDirectory::new("section0"));
+        assert!(nacp.icon(&empty, Language::German).is_none());
+    }
+}
@@ -0,0 +1,563 @@
+#[cfg(test)]
+mod tests {
+    use crate::crypto::Keyset;
+    use crate::fs::{
+        self, MemoryDirectory, MemoryFile, Nca, NcaContentType, NcaDistribution, NcaEncryption,
+        NcaFsType, NcaHashType, RomFs, RomFsBuilder, VfsDirectory, VirtualDir, VirtualFile,
+        write_pfs0,
+    };
+    use crate::tests::bytes::{put_u32, put_u64};
+    use aes::Aes128;
+    use aes::cipher::generic_array::GenericArray;
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    use sha2::{Digest, Sha256};
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+
+    // Made-up keys; real ones come from the user's console
+    const HEADER_KEY: [u8; 0x20] = [0x1C; 0x20];
+    const KEY_AREA_KEY: [u8; 0x10] = [0x2D; 0x10];
+    const SECTION_KEY: [u8; 0x10] = [0x3E; 0x10];
+    const TITLEKEK: [u8; 0x10] = [0x4F; 0x10];
+    const TITLE_KEY: [u8; 0x10] = [0x5A; 0x10];
+    const UPPER_COUNTER: u64 = 0x0000_0001_0000_0000;
+    const BLOCK_SIZE: usize = 0x200;
+    const HEADER_SIZE: usize = 0xC00;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn file(name: &str, data: &[u8]) -> VirtualFile {
+        Arc::new(MemoryFile::read_only(name, data.to_vec()))
+    }
+
+    fn keyset() -> Keyset {
+        let mut keyset = Keyset::new();
+        let prod = format!(
+            "header_key = {}\nkey_area_key_application_00 = {}\ntitlekek_00 = {}\n",
+            hex(&HEADER_KEY),
+            hex(&KEY_AREA_KEY),
+            hex(&TITLEKEK),
+        );
+        keyset.add_prod_keys(&prod).unwrap();
+        keyset
+    }
+
+    fn ecb_encrypt(key: &[u8; 0x10], block: &[u8]) -> [u8; 0x10] {
+        let mut block = GenericArray::clone_from_slice(block);
+        Aes128::new(key.into()).encrypt_block(&mut block);
+        block.into()
+    }
+
+    /// AES-CTR as NCAs use it, for data at `offset` in the NCA
+    fn ctr(key: &[u8; 0x10], upper: u64, offset: u64, data: &mut [u8]) {
+        for (i, chunk) in data.chunks_mut(0x10).enumerate() {
+            let counter = ((upper as u128) << 64) | (offset / 0x10 + i as u64) as u128;
+            let stream = ecb_encrypt(key, &counter.to_be_bytes());
+            chunk.iter_mut().zip(stream).for_each(|(b, k)| *b ^= k);
+        }
+    }
+
+    /// AES-XTS with Nintendo's big-endian tweak, in 0x200-byte sectors
+    fn xts_encrypt(key: &[u8; 0x20], data: &mut [u8]) {
+        let tweak_key: [u8; 0x10] = key[0x10..].try_into().unwrap();
+        let data_key: [u8; 0x10] = key[..0x10].try_into().unwrap();
+        for (sector, sector_data) in data.chunks_mut(0x200).enumerate() {
+            let mut tweak = ecb_encrypt(&tweak_key, &(sector as u128).to_be_bytes());
+            for block in sector_data.chunks_exact_mut(0x10) {
+                block.iter_mut().zip(tweak).for_each(|(b, t)| *b ^= t);
+                block.copy_from_slice(&ecb_encrypt(&data_key, block));
+                block.iter_mut().zip(tweak).for_each(|(b, t)| *b ^= t);
+                let tweak_value = u128::from_le_bytes(tweak);
+                let carry = if tweak_value >> 127 != 0 { 0x87 } else { 0 };
+                tweak = ((tweak_value << 1) ^ carry).to_le_bytes();
+            }
+        }
+    }
+
+    /// A PFS0 section under a single hash table, and its FS header hash data
+    fn sha256_section(pfs0: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let table: Vec<u8> = pfs0.chunks(BLOCK_SIZE).flat_map(Sha256::digest).collect();
+        let data_offset = table.len().next_multiple_of(BLOCK_SIZE);
+        let mut section = table.clone();
+        section.resize(data_offset, 0);
+        section.extend(pfs0);
+
+        let mut hash_data = vec![0; 0xF8];
+        hash_data[..0x20].copy_from_slice(&Sha256::digest(&table));
+        put_u32(&mut hash_data, 0x20, BLOCK_SIZE as u32);
+        put_u32(&mut hash_data, 0x24, 2);
+        put_u64(&mut hash_data, 0x30, table.len() as u64);
+        put_u64(&mut hash_data, 0x38, data_offset as u64);
+        put_u64(&mut hash_data, 0x40, pfs0.len() as u64);
+        (section, hash_data)
+    }
+
+    /// Hashes of `data`'s blocks, each padded with zeros
+    fn ivfc_hashes(data: &[u8]) -> Vec<u8> {
+        data.chunks(BLOCK_SIZE)
+            .flat_map(|block| {
+                let mut padded = block.to_vec();
+                padded.resize(BLOCK_SIZE, 0);
+                Sha256::digest(&padded)
+            })
+            .collect()
+    }
+
+    /// A RomFS section under an IVFC tree, and its FS header hash data
+    fn ivfc_section(romfs: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut levels = vec![romfs.to_vec()];
+        while levels[0].len() > BLOCK_SIZE {
+            levels.insert(0, ivfc_hashes(&levels[0]));
+        }
+        let mut hash_data = vec![0; 0xF8];
+        hash_data[..4].copy_from_slice(b"IVFC");
+        put_u32(&mut hash_data, 0x4, 0x20000);
+        put_u32(&mut hash_data, 0x8, 0x20);
+        put_u32(&mut hash_data, 0xC, levels.len() as u32 + 1);
+        let mut section = Vec::new();
+        for (i, level) in levels.iter().enumerate() {
+            let at = 0x10 + i * 0x18;
+            put_u64(&mut hash_data, at, section.len() as u64);
+            put_u64(&mut hash_data, at + 8, level.len() as u64);
+            put_u32(&mut hash_data, at + 0x10, BLOCK_SIZE.trailing_zeros());
+            section.extend(level);
+            section.resize(section.len().next_multiple_of(BLOCK_SIZE), 0);
+        }
+        hash_data[0xC0..0xE0].copy_from_slice(&ivfc_hashes(&levels[0]));
+        (section, hash_data)
+    }
+
+    struct Section {
+        fs_type: u8,
+        hash_type: u8,
+        encryption: u8,
+        plain: Vec<u8>,
+        hash_data: Vec<u8>,
+        patch_info: Vec<u8>,
+    }
+
+    impl Section {
+        fn new(fs_type: u8, hash_type: u8, (plain, hash_data): (Vec<u8>, Vec<u8>)) -> Self {
+            Self {
+                fs_type,
+                hash_type,
+                encryption: 3,
+                plain,
+                hash_data,
+                patch_info: vec![0; 0x40],
+            }
+        }
+    }
+
+    /// An NCA3 of `sections`, encrypted with the key area, or with the
+    /// title key when there is a rights ID
+    fn build_nca(content_type: u8, rights_id: [u8; 0x10], sections: &[Section]) -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE];
+        header[0x200..0x204].copy_from_slice(b"NCA3");
+        header[0x205] = content_type;
+        put_u64(&mut header, 0x210, 0x0100_0000_0000_1000);
+        put_u32(&mut header, 0x21C, 0x000C_1100);
+        header[0x230..0x240].copy_from_slice(&rights_id);
+        header[0x320..0x330].copy_from_slice(&ecb_encrypt(&KEY_AREA_KEY, &SECTION_KEY));
+        let key = if rights_id == [0; 0x10] {
+            SECTION_KEY
+        } else {
+            TITLE_KEY
+        };
+
+        let mut body = Vec::new();
+        for (index, section) in sections.iter().enumerate() {
+            let offset = HEADER_SIZE + body.len();
+            let mut data = section.plain.clone();
+            data.resize(data.len().next_multiple_of(0x200), 0);
+            if section.encryption != 1 {
+                ctr(&key, UPPER_COUNTER, offset as u64, &mut data);
+            }
+            let entry = 0x240 + index * 0x10;
+            put_u32(&mut header, entry, (offset / 0x200) as u32);
+            put_u32(
+                &mut header,
+                entry + 4,
+                ((offset + data.len()) / 0x200) as u32,
+            );
+            body.extend(data);
+
+            let fs_header = &mut header[0x400 + index * 0x200..][..0x200];
+            fs_header[0] = 2;
+            fs_header[2] = section.fs_type;
+            fs_header[3] = section.hash_type;
+            fs_header[4] = section.encryption;
+            fs_header[0x08..0x100].copy_from_slice(&section.hash_data);
+            fs_header[0x100..0x140].copy_from_slice(&section.patch_info);
+            put_u64(fs_header, 0x140, UPPER_COUNTER);
+            let hash = Sha256::digest(&*fs_header);
+            header[0x280 + index * 0x20..][..0x20].copy_from_slice(&hash);
+        }
+        put_u64(&mut header, 0x208, (HEADER_SIZE + body.len()) as u64);
+        xts_encrypt(&HEADER_KEY, &mut header);
+        header.extend(body);
+        header
+    }
+
+    fn exefs() -> Vec<u8> {
+        let code = vec![0xC0; 0x700];
+        write_pfs0(&[file("main.npdm", b"META"), file("main", &code)]).unwrap()
+    }
+
+    fn romfs() -> Vec<u8> {
+        let tree = MemoryDirectory::new("");
+        tree.add_file(file("large.bin", &[0x1A; 0x900]));
+        let data = MemoryDirectory::new("data");
+        data.add_file(file("config.ini", b"[game]"));
+        tree.add_subdirectory(Arc::new(data));
+        let mut builder = RomFsBuilder::new();
+        builder.add(&(Arc::new(tree) as VirtualDir)).unwrap();
+        builder.build("romfs").read_all().unwrap()
+    }
+
+    fn program_nca() -> Vec<u8> {
+        build_nca(
+            0,
+            [0; 0x10],
+            &[
+                Section::new(1, 2, sha256_section(&exefs())),
+                Section::new(0, 3, ivfc_section(&romfs())),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_nca_program_sections() {
+        let nca = Nca::open(file("program.nca", &program_nca()), &keyset()).unwrap();
+        assert_eq!(nca.header.content_type, NcaContentType::Program);
+        assert_eq!(nca.header.distribution, NcaDistribution::Download);
+        assert_eq!(nca.header.program_id, 0x0100_0000_0000_1000);
+        assert_eq!(nca.header.sdk_version, 0x000C_1100);
+        assert!(!nca.header.has_rights_id());
+
+        let sections = nca.sections();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].fs_type, NcaFsType::PartitionFs);
+        assert_eq!(sections[0].hash_type, NcaHashType::HierarchicalSha256);
+        assert_eq!(sections[1].hash_type, NcaHashType::HierarchicalIntegrity);
+        assert_eq!(sections[1].encryption, NcaEncryption::AesCtr);
+        let (plain, _) = ivfc_section(&romfs());
+        let storage = sections[1].storage().read_vec(0, plain.len()).unwrap();
+        assert!(storage == plain, "section decrypts to its plain text");
+
+        let names: Vec<_> = nca
+            .subdirectories()
+            .unwrap()
+            .iter()
+            .map(|d| d.name())
+            .collect();
+        assert_eq!(names, ["exefs", "romfs"]);
+        let main = nca.exefs().unwrap().file("main").unwrap();
+        assert_eq!(main.read_all().unwrap(), vec![0xC0; 0x700]);
+        assert_eq!(nca.romfs().unwrap().read_all().unwrap(), romfs());
+
+        let root: VirtualDir = Arc::new(nca);
+        let config = fs::open_file(&root, "romfs/data/config.ini").unwrap();
+        assert_eq!(config.read_all().unwrap(), b"[game]");
+        let npdm = fs::open_file(&root, "exefs/main.npdm").unwrap();
+        assert_eq!(npdm.read_all().unwrap(), b"META");
+    }
+
+    #[test]
+    fn test_nca_detects_tampering() {
+        let image = program_nca();
+        // Damage large.bin, the first file in the RomFS data, which is the
+        // last IVFC level and so the RomFS section's last bytes
+        let (plain, hash_data) = ivfc_section(&romfs());
+        let levels = u32::from_le_bytes(hash_data[0xC..0x10].try_into().unwrap()) as usize;
+        let data_at = 0x10 + (levels - 2) * 0x18;
+        let data_offset = u64::from_le_bytes(hash_data[data_at..data_at + 8].try_into().unwrap());
+        let section_offset = image.len() - plain.len().next_multiple_of(0x200);
+        let mut tampered = image.clone();
+        tampered[section_offset + data_offset as usize + 0x200 + 0x300] ^= 1;
+
+        let nca = Nca::open(file("program.nca", &tampered), &keyset()).unwrap();
+        let romfs = nca.subdirectory("romfs").unwrap();
+        let config = fs::open_file(&romfs, "data/config.ini").unwrap();
+        assert_eq!(config.read_all().unwrap(), b"[game]", "other blocks read");
+        let err = romfs.file("large.bin").unwrap().read_all().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("does not match its hash"), "{err}");
+
+        let mut header = image.clone();
+        header[0x410] ^= 1;
+        let err = Nca::open(file("program.nca", &header), &keyset())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("section 0"), "{err}");
+
+        let mut wrong_key = Keyset::new();
+        wrong_key
+            .add_prod_keys(&format!("header_key = {}", hex(&[0; 0x20])))
+            .unwrap();
+        let err = Nca::open(file("program.nca", &image), &wrong_key)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_nca_reports_missing_keys() {
+        let image = program_nca();
+        let err = Nca::open(file("program.nca", &image), &Keyset::new())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(err.to_string().contains("header_key"), "{err}");
+
+        let mut header_only = Keyset::new();
+        header_only
+            .add_prod_keys(&format!("header_key = {}", hex(&HEADER_KEY)))
+            .unwrap();
+        let err = Nca::open(file("program.nca", &image), &header_only)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert!(
+            err.to_string().contains("key_area_key_application_00"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn test_nca_title_key() {
+        let rights_id = [0x01, 0, 0, 0, 0, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let image = build_nca(2, rights_id, &[Section::new(0, 3, ivfc_section(&romfs()))]);
+        let mut keys = keyset();
+        let err = Nca::open(file("control.nca", &image), &keys).err().unwrap();
+        assert!(err.to_string().contains("title.keys"), "{err}");
+
+        let title_keys = format!(
+            "{} = {}",
+            hex(&rights_id),
+            hex(&ecb_encrypt(&TITLEKEK, &TITLE_KEY))
+        );
+        keys.add_title_keys(&title_keys).unwrap();
+        let nca = Nca::open(file("control.nca", &image), &keys).unwrap();
+        assert!(nca.header.has_rights_id());
+        assert_eq!(nca.header.content_type, NcaContentType::Control);
+        assert_eq!(nca.romfs().unwrap().read_all().unwrap(), romfs());
+    }
+
+    /// A bucket tree of `entries` running to `end`, in one entry set, and
+    /// the header the FS header keeps for it
+    fn bucket_tree(entries: &[Vec<u8>], end: u64) -> ([u8; 0x10], Vec<u8>) {
+        let mut header = [0; 0x10];
+        header[..4].copy_from_slice(b"BKTR");
+        put_u32(&mut header, 4, 1);
+        put_u32(&mut header, 8, entries.len() as u32);
+        let mut table = vec![0; 0x8000];
+        put_u32(&mut table, 4, 1);
+        put_u64(&mut table, 8, end);
+        put_u32(&mut table, 0x4004, entries.len() as u32);
+        put_u64(&mut table, 0x4008, end);
+        let mut at = 0x4010;
+        for entry in entries {
+            table[at..at + entry.len()].copy_from_slice(entry);
+            at += entry.len();
+        }
+        (header, table)
+    }
+
+    /// An update NCA whose RomFS section holds `physical`, encrypted in
+    /// subsections under `generations`, then its relocation table mapping
+    /// `(virtual, physical, storage)` ranges and its CTR-EX table
+    fn build_update_nca(
+        physical: &[u8],
+        relocations: &[(u64, u64, u32)],
+        virtual_size: u64,
+        generations: &[(u64, u32)],
+        (hash_type, hash_data): (u8, Vec<u8>),
+    ) -> Vec<u8> {
+        let relocations: Vec<Vec<u8>> = relocations
+            .iter()
+            .map(|(virt, phys, storage)| {
+                [
+                    &virt.to_le_bytes()[..],
+                    &phys.to_le_bytes(),
+                    &storage.to_le_bytes(),
+                ]
+                .concat()
+            })
+            .collect();
+        let subsections: Vec<Vec<u8>> = generations
+            .iter()
+            .map(|(offset, generation)| {
+                [
+                    &offset.to_le_bytes()[..],
+                    &[0; 4],
+                    &generation.to_le_bytes(),
+                ]
+                .concat()
+            })
+            .collect();
+        let physical_end = physical.len().next_multiple_of(0x10) as u64;
+        let (indirect_header, indirect) = bucket_tree(&relocations, virtual_size);
+        let (ctr_ex_header, ctr_ex) = bucket_tree(&subsections, physical_end);
+
+        let mut patch_info = vec![0; 0x40];
+        put_u64(&mut patch_info, 0x00, physical_end);
+        put_u64(&mut patch_info, 0x08, indirect.len() as u64);
+        patch_info[0x10..0x20].copy_from_slice(&indirect_header);
+        put_u64(&mut patch_info, 0x20, physical_end + indirect.len() as u64);
+        put_u64(&mut patch_info, 0x28, ctr_ex.len() as u64);
+        patch_info[0x30..0x40].copy_from_slice(&ctr_ex_header);
+        let mut plain = physical.to_vec();
+        plain.resize(physical_end as usize, 0);
+        plain.extend(indirect);
+        plain.extend(ctr_ex);
+        let section = Section {
+            fs_type: 0,
+            hash_type,
+            encryption: 4,
+            plain,
+            hash_data,
+            patch_info,
+        };
+
+        // Re-encrypt the subsections under their own generations
+        let mut image = build_nca(0, [0; 0x10], &[section]);
+        for (i, &(start, generation)) in generations.iter().enumerate() {
+            let end = generations.get(i + 1).map_or(physical_end, |next| next.0);
+            let at = HEADER_SIZE + start as usize;
+            let chunk = &mut image[at..HEADER_SIZE + end as usize];
+            ctr(&SECTION_KEY, UPPER_COUNTER, at as u64, chunk);
+            ctr(
+                &SECTION_KEY,
+                UPPER_COUNTER | generation as u64,
+                at as u64,
+                chunk,
+            );
+        }
+        image
+    }
+
+    #[test]
+    fn test_nca_ctr_ex_section() {
+        // Two subsections under generations 1 and 2, then the tables under
+        // the section's own counter
+        let data: Vec<u8> = (0..0x800u32).map(|i| i as u8).collect();
+        let image = build_update_nca(
+            &data,
+            &[(0, 0, 1)],
+            0x800,
+            &[(0, 1), (0x400, 2)],
+            (1, vec![0; 0xF8]),
+        );
+        let nca = Nca::open(file("update.nca", &image), &keyset()).unwrap();
+        let section = &nca.sections()[0];
+        assert_eq!(section.encryption, NcaEncryption::AesCtrEx);
+        assert!(section.patch.is_some());
+        let storage = section.storage();
+        assert_eq!(storage.read_vec(0x3F8, 0x10).unwrap(), &data[0x3F8..0x408]);
+        let (_, indirect) = bucket_tree(&[vec![0; 0x14]], 0x800);
+        assert_eq!(storage.read_vec(0x800, 0x20).unwrap(), &indirect[..0x20]);
+        assert!(
+            nca.subdirectories().unwrap().is_empty(),
+            "patches need a base"
+        );
+        assert_eq!(section.data().err().unwrap().kind(), ErrorKind::Unsupported);
+    }
+
+    /// A RomFS of the base tree with `data/config.ini` replaced and a file
+    /// added, as an update would ship it
+    fn patched_romfs() -> Vec<u8> {
+        let update = MemoryDirectory::new("");
+        let data = MemoryDirectory::new("data");
+        data.add_file(file("config.ini", b"[patched]"));
+        data.add_file(file("dlc.bin", b"new in 1.1"));
+        update.add_subdirectory(Arc::new(data));
+        let base = RomFs::open(file("romfs", &romfs())).unwrap();
+        let mut builder = RomFsBuilder::new();
+        builder
+            .add(&(Arc::new(base) as VirtualDir))
+            .unwrap()
+            .add(&(Arc::new(update) as VirtualDir))
+            .unwrap();
+        builder.build("romfs").read_all().unwrap()
+    }
+
+    /// Relocations laying `patched` over `base` in 0x200-byte blocks, with
+    /// the blocks that differ packed into the update's own data
+    fn relocate(base: &[u8], patched: &[u8]) -> (Vec<(u64, u64, u32)>, Vec<u8>) {
+        let mut relocations: Vec<(u64, u64, u32)> = Vec::new();
+        let mut physical = Vec::new();
+        for (i, block) in patched.chunks(0x200).enumerate() {
+            let offset = (i * 0x200) as u64;
+            let entry = if base.get(offset as usize..offset as usize + block.len()) == Some(block) {
+                (offset, offset, 0)
+            } else {
+                physical.extend(block);
+                (offset, (physical.len() - block.len()) as u64, 1)
+            };
+            let continues = relocations.last().is_some_and(|&(virt, phys, storage)| {
+                storage == entry.2 && phys + (offset - virt) == entry.1
+            });
+            if !continues {
+                relocations.push(entry);
+            }
+        }
+        (relocations, physical)
+    }
+
+    #[test]
+    fn test_nca_patches_base_romfs() {
+        let (base_plain, base_hash) = ivfc_section(&romfs());
+        let base_image = build_nca(
+            0,
+            [0; 0x10],
+            &[Section::new(0, 3, (base_plain.clone(), base_hash))],
+        );
+        let base = Nca::open(file("base.nca", &base_image), &keyset()).unwrap();
+
+        let patched = patched_romfs();
+        let (patched_plain, patched_hash) = ivfc_section(&patched);
+        let (relocations, physical) = relocate(&base_plain, &patched_plain);
+        assert!(relocations.iter().any(|r| r.2 == 0) && relocations.iter().any(|r| r.2 == 1));
+        let update_image = build_update_nca(
+            &physical,
+            &relocations,
+            patched_plain.len() as u64,
+            &[(0, 1), (0x200, 2)],
+            (3, patched_hash),
+        );
+        let update = Nca::open(file("update.nca", &update_image), &keyset()).unwrap();
+
+        let image = update.patched_romfs(&base).unwrap();
+        assert_eq!(image.read_all().unwrap(), patched);
+        let romfs: VirtualDir = Arc::new(RomFs::open(image).unwrap());
+        let config = fs::open_file(&romfs, "data/config.ini").unwrap();
+        assert_eq!(config.read_all().unwrap(), b"[patched]");
+        let dlc = fs::open_file(&romfs, "data/dlc.bin").unwrap();
+        assert_eq!(dlc.read_all().unwrap(), b"new in 1.1");
+        let large = romfs.file("large.bin").unwrap();
+        assert_eq!(
+            large.read_all().unwrap(),
+            vec![0x1A; 0x900],
+            "read from the base"
+        );
+
+        let err = base.patched_romfs(&base).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // Over the wrong base, the blocks read from it fail their hashes
+        let tree = MemoryDirectory::new("");
+        tree.add_file(file("large.bin", &[0x2B; 0x900]));
+        let mut builder = RomFsBuilder::new();
+        builder.add(&(Arc::new(tree) as VirtualDir)).unwrap();
+        let other = ivfc_section(&builder.build("romfs").read_all().unwrap());
+        let other_image = build_nca(0, [0; 0x10], &[Section::new(0, 3, other)]);
+        let other = Nca::open(file("other.nca", &other_image), &keyset()).unwrap();
+        let image = update.patched_romfs(&other).unwrap();
+        let err = image.read_all().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,442 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::capabilities::Capabilities;
+    use crate::kernel::debug::Cause;
+    use crate::kernel::handle::CURRENT_PROCESS;
+    use crate::kernel::info;
+    use crate::kernel::memory::{AddressSpaceType, MemoryState};
+    use crate::kernel::process::USER_CORE_COUNT;
+    use crate::kernel::resource_limit::LimitableResource;
+    use crate::kernel::result::{self, ResultCode};
+    use crate::kernel::{Kernel, KernelExit, svc};
+    use crate::loader::npdm::{Npdm, ServiceAccess};
+    use crate::loader::{RESULT_INVALID_META, RESULT_INVALID_PROGRAM_ID};
+    use crate::tests::bytes::{put_u32, put_u64};
+    use crate::tests::guest::{Guest, call, store, svc};
+
+    const PROGRAM_ID: u64 = 0x0100_0000_0000_1000;
+
+    /// ThreadInfo allowing priorities `highest..=lowest` on cores
+    /// `min_core..=max_core`
+    fn thread_info(highest: u32, lowest: u32, min_core: u32, max_core: u32) -> u32 {
+        0b111 | lowest << 4 | highest << 10 | min_core << 16 | max_core << 24
+    }
+
+    /// EnableSystemCalls descriptors permitting `ids`
+    fn system_calls(ids: &[u32]) -> Vec<u32> {
+        let mut masks = [0u32; 8];
+        for id in ids {
+            masks[(id / 24) as usize] |= 1 << (id % 24);
+        }
+        (0..8)
+            .filter(|&i| masks[i] != 0)
+            .map(|i| 0b1111 | masks[i] << 5 | (i as u32) << 29)
+            .collect()
+    }
+
+    /// MapRange pair for `pages` pages at `address`
+    fn map_range(address: u64, pages: u32, read_only: bool) -> [u32; 2] {
+        let page = (address >> 12) as u32;
+        [
+            0b11_1111 | page << 7 | (read_only as u32) << 31,
+            0b11_1111 | pages << 7,
+        ]
+    }
+
+    fn interrupts(first: u32, second: u32) -> u32 {
+        0b111_1111_1111 | first << 12 | second << 22
+    }
+
+    fn handle_table_size(size: u32) -> u32 {
+        0x7FFF | size << 16
+    }
+
+    fn services(names: &[(&str, bool)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for &(name, is_server) in names {
+            bytes.push((name.len() as u8 - 1) | (is_server as u8) << 7);
+            bytes.extend(name.as_bytes());
+        }
+        bytes
+    }
+
+    fn words(descriptors: &[u32]) -> Vec<u8> {
+        descriptors.iter().flat_map(|d| d.to_le_bytes()).collect()
+    }
+
+    /// Append fs, service and capability sections to `block`, pointing the
+    /// offset/size pairs at `table` to them
+    fn add_sections(block: &mut Vec<u8>, table: usize, services: &[u8], capabilities: &[u8]) {
+        let fs = block.len();
+        block.extend([0; 0x1C]);
+        for (i, section) in [services, capabilities].into_iter().enumerate() {
+            let offset = block.len();
+            block.extend(section);
+            block.resize(block.len().next_multiple_of(0x10), 0);
+            put_u32(block, table + 8 + i * 8, offset as u32);
+            put_u32(block, table + 12 + i * 8, section.len() as u32);
+        }
+        put_u32(block, table, fs as u32);
+        put_u32(block, table + 4, 0x1C);
+    }
+
+    /// A 64-bit NPDM with a 39-bit address space, main thread priority 44
+    /// on core 0, whose ACID allows `acid` and ACI0 requests `aci`
+    fn build_npdm(acid: &[u32], aci: &[u32], program_id: u64) -> Vec<u8> {
+        let mut meta = vec![0; 0x80];
+        meta[..4].copy_from_slice(b"META");
+        meta[0xC] = 1 | (AddressSpaceType::Bits39 as u8) << 1;
+        meta[0xE] = 44;
+        meta[0xF] = 0;
+        put_u32(&mut meta, 0x14, 0x10_0000);
+        put_u32(&mut meta, 0x18, 3);
+        put_u32(&mut meta, 0x1C, 0x4_0001);
+        meta[0x20..0x28].copy_from_slice(b"TestGame");
+        meta[0x30..0x35].copy_from_slice(b"HAC-X");
+
+        let mut acid_block = vec![0; 0x240];
+        acid_block[0x200..0x204].copy_from_slice(b"ACID");
+        put_u32(&mut acid_block, 0x20C, 1);
+        put_u64(&mut acid_block, 0x210, PROGRAM_ID);
+        put_u64(&mut acid_block, 0x218, PROGRAM_ID + 0xFFF);
+        let allowed = services(&[("fsp-srv", false), ("hid", false), ("test*", true)]);
+        add_sections(&mut acid_block, 0x220, &allowed, &words(acid));
+
+        let mut aci_block = vec![0; 0x40];
+        aci_block[..4].copy_from_slice(b"ACI0");
+        put_u64(&mut aci_block, 0x10, program_id);
+        let requested = services(&[("fsp-srv", false), ("test:u", true)]);
+        add_sections(&mut aci_block, 0x20, &requested, &words(aci));
+
+        let aci_offset = meta.len();
+        let acid_offset = aci_offset + aci_block.len();
+        put_u32(&mut meta, 0x70, aci_offset as u32);
+        put_u32(&mut meta, 0x74, aci_block.len() as u32);
+        put_u32(&mut meta, 0x78, acid_offset as u32);
+        put_u32(&mut meta, 0x7C, acid_block.len() as u32);
+        [meta, aci_block, acid_block].concat()
+    }
+
+    /// Capabilities of a process that may use the SVCs in `ids`, priorities
+    /// 28..=59 on cores 0..=2, one map and two interrupts
+    fn capabilities(ids: &[u32]) -> Vec<u32> {
+        let mut descriptors = vec![thread_info(28, 59, 0, 2)];
+        descriptors.extend(system_calls(ids));
+        descriptors.extend(map_range(0x7000_0000, 4, false));
+        descriptors.push(interrupts(42, 0x3FF));
+        descriptors.push(interrupts(57, 58));
+        descriptors.push(handle_table_size(256));
+        descriptors
+    }
+
+    fn test_npdm(svcs: &[u32]) -> Npdm {
+        let caps = capabilities(svcs);
+        Npdm::parse(&build_npdm(&caps, &caps, PROGRAM_ID + 7)).unwrap()
+    }
+
+    #[test]
+    fn test_parse_npdm() {
+        let npdm = test_npdm(&[svc::EXIT_THREAD]);
+        assert_eq!(npdm.name, "TestGame");
+        assert_eq!(npdm.product_code, "HAC-X");
+        assert!(npdm.is_64bit);
+        assert_eq!(npdm.address_space_type, AddressSpaceType::Bits39);
+        assert_eq!(npdm.main_thread_priority, 44);
+        assert_eq!(npdm.main_thread_stack_size, 0x4_0001);
+        assert_eq!(npdm.system_resource_size, 0x10_0000);
+        assert_eq!(npdm.version, 3);
+        assert!(npdm.acid.production);
+        assert_eq!(npdm.aci.program_id, PROGRAM_ID + 7);
+        assert_eq!(
+            npdm.aci.service_access,
+            [
+                ServiceAccess {
+                    name: String::from("fsp-srv"),
+                    is_server: false
+                },
+                ServiceAccess {
+                    name: String::from("test:u"),
+                    is_server: true
+                },
+            ]
+        );
+        assert_eq!(npdm.acid.service_access.len(), 3);
+        assert_eq!(npdm.aci.fs_access.len(), 0x1C);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_metadata() {
+        let caps = capabilities(&[]);
+        let bytes = build_npdm(&caps, &caps, PROGRAM_ID);
+        assert_eq!(Npdm::parse(&bytes[..0x40]), Err(RESULT_INVALID_META));
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(Npdm::parse(&bad_magic), Err(RESULT_INVALID_META));
+
+        // The ACID section runs past the end of the file
+        assert_eq!(
+            Npdm::parse(&bytes[..bytes.len() - 1]),
+            Err(RESULT_INVALID_META)
+        );
+
+        let outside = build_npdm(&caps, &caps, PROGRAM_ID + 0x1000);
+        assert_eq!(Npdm::parse(&outside), Err(RESULT_INVALID_PROGRAM_ID));
+    }
+
+    #[test]
+    fn test_decode_capabilities() {
+        let npdm = test_npdm(&[svc::SET_HEAP_SIZE, svc::EXIT_THREAD, svc::GET_SYSTEM_INFO]);
+        let caps = npdm.capabilities(USER_CORE_COUNT).unwrap();
+        assert_eq!(caps.core_mask, 0b111);
+        assert_eq!(caps.priority_mask, ((1u64 << 60) - 1) & !((1 << 28) - 1));
+        assert!(caps.is_svc_permitted(svc::SET_HEAP_SIZE));
+        assert!(caps.is_svc_permitted(svc::EXIT_THREAD));
+        assert!(caps.is_svc_permitted(svc::GET_SYSTEM_INFO));
+        assert!(!caps.is_svc_permitted(svc::GET_INFO));
+        assert_eq!(caps.memory_maps.len(), 1);
+        assert_eq!(caps.memory_maps[0].address, 0x7000_0000);
+        assert_eq!(caps.memory_maps[0].size, 0x4000);
+        assert!(caps.memory_map(0x7000_1000, 0x3000).is_some());
+        assert!(caps.memory_map(0x7000_1000, 0x4000).is_none());
+        assert!(caps.interrupts.iter().eq(&[42, 57, 58]));
+        assert_eq!(caps.handle_table_size, Some(256));
+    }
+
+    #[test]
+    fn test_capabilities_reject_invalid_descriptors() {
+        let info = thread_info(28, 59, 0, 2);
+        assert_eq!(
+            Capabilities::parse(&[info, info], USER_CORE_COUNT),
+            Err(result::INVALID_COMBINATION)
+        );
+        assert_eq!(
+            Capabilities::parse(&system_calls(&[1]), USER_CORE_COUNT),
+            Err(result::INVALID_COMBINATION),
+            "ThreadInfo is required"
+        );
+        assert_eq!(
+            Capabilities::parse(&[thread_info(28, 59, 0, 4)], USER_CORE_COUNT),
+            Err(result::INVALID_CORE_ID)
+        );
+        // A MapRange without its size descriptor
+        let [address, _] = map_range(0x7000_0000, 1, false);
+        assert_eq!(
+            Capabilities::parse(&[info, address], USER_CORE_COUNT),
+            Err(result::INVALID_COMBINATION)
+        );
+        // 0x0000_FFFF has 16 trailing ones, 0x0001_FFFF an unknown 17
+        assert_eq!(
+            Capabilities::parse(&[info, 0x0001_FFFF], USER_CORE_COUNT),
+            Err(result::INVALID_ARGUMENT)
+        );
+        assert!(Capabilities::parse(&[info, u32::MAX], USER_CORE_COUNT).is_ok());
+    }
+
+    #[test]
+    fn test_aci_must_stay_within_acid() {
+        let acid = capabilities(&[svc::EXIT_THREAD]);
+        let aci = capabilities(&[svc::EXIT_THREAD, svc::GET_INFO]);
+        let npdm = Npdm::parse(&build_npdm(&acid, &aci, PROGRAM_ID)).unwrap();
+        assert_eq!(npdm.capabilities(USER_CORE_COUNT), Err(RESULT_INVALID_META));
+
+        let mut kernel = Kernel::new();
+        assert_eq!(kernel.create_process(&npdm), Err(RESULT_INVALID_META));
+    }
+
+    #[test]
+    fn test_main_thread_starts_with_own_handle() {
+        let npdm = test_npdm(&[svc::EXIT_THREAD]);
+        let mut kernel = Kernel::new();
+        kernel.create_process(&npdm).unwrap();
+        assert_eq!(kernel.process.program_id, PROGRAM_ID + 7);
+        assert_eq!(kernel.process.main_thread_stack_size, 0x4_1000);
+        let mut guest = Guest::with_kernel(kernel);
+
+        let mut code = store(1, guest.slot(1));
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&code);
+        let handle = guest.kernel.start_main_thread(entry).unwrap();
+        let id = guest.kernel.thread_from_handle(handle, None).unwrap();
+        let thread = guest.kernel.scheduler.thread(id).unwrap();
+        assert_eq!(thread.priority, 44);
+        let stack = thread.context.sp - 0x4_1000;
+        let space = &guest.kernel.process.address_space;
+        assert!(space.layout.stack.contains(stack, 0x4_1000));
+        assert_eq!(space.query(stack).state, MemoryState::Stack);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.read(1), handle as u64);
+
+        let mut kernel = Kernel::new();
+        kernel.create_process(&npdm).unwrap();
+        let mut guest = Guest::with_kernel(kernel);
+        guest.spawn(entry, 0, 44, 0);
+        assert_eq!(
+            guest.kernel.create_process(&npdm),
+            Err(result::INVALID_STATE),
+            "threads already exist"
+        );
+    }
+
+    #[test]
+    fn test_unpermitted_svc_stops_process() {
+        let npdm = test_npdm(&[svc::EXIT_THREAD, svc::GET_INFO]);
+        let mut kernel = Kernel::new();
+        kernel.create_process(&npdm).unwrap();
+        let mut guest = Guest::with_kernel(kernel);
+
+        // GetInfo(IsSvcPermitted) reports the capabilities before the
+        // unpermitted svcGetSystemInfo ends the process
+        let mut code = call(svc::GET_INFO, &[0, 26, 0, svc::EXIT_THREAD as u64]);
+        code.extend(store(1, guest.slot(1)));
+        code.extend(call(
+            svc::GET_INFO,
+            &[0, 26, 0, svc::GET_SYSTEM_INFO as u64],
+        ));
+        code.extend(store(1, guest.slot(2)));
+        code.extend(call(svc::GET_SYSTEM_INFO, &[0, 0, 0]));
+        code.extend(store(0, guest.slot(3)));
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&code);
+        let handle = guest.kernel.start_main_thread(entry).unwrap();
+        let id = guest.kernel.thread_from_handle(handle, None).unwrap();
+
+        assert_eq!(
+            guest.run(),
+            KernelExit::Crashed {
+                core: 0,
+                thread: id
+            }
+        );
+        assert_eq!(guest.read(1), 1);
+        assert_eq!(guest.read(2), 0);
+        assert_eq!(guest.read(3), 0, "the process never got past the SVC");
+        let report = guest.kernel.debug.last_report.as_ref().unwrap();
+        assert_eq!(report.cause, Cause::InvalidSystemCall(svc::GET_SYSTEM_INFO));
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let npdm = test_npdm(&[]);
+        let mut kernel = Kernel::new();
+        kernel.create_process(&npdm).unwrap();
+        let mut guest = Guest::with_kernel(kernel);
+        let limit = &mut guest.kernel.process.resource_limit;
+        assert_eq!(limit.limit(LimitableResource::Threads), 96);
+        limit.set_limit(LimitableResource::Threads, 1).unwrap();
+        limit.set_limit(LimitableResource::Events, 1).unwrap();
+
+        let stack = guest.stack();
+        let kernel = &mut guest.kernel;
+        let free_threads = |kernel: &Kernel| {
+            kernel
+                .get_info(info::FREE_THREAD_COUNT, CURRENT_PROCESS, 0, 0)
+                .unwrap()
+        };
+        assert_eq!(free_threads(kernel), 1);
+        let thread = kernel.create_thread(0, 0, stack, 44, 0).unwrap();
+        assert_eq!(free_threads(kernel), 0);
+        assert_eq!(
+            kernel.create_thread(0, 0, stack, 44, 0),
+            Err(result::LIMIT_REACHED)
+        );
+        let limit = &mut kernel.process.resource_limit;
+        assert_eq!(
+            limit.set_limit(LimitableResource::Threads, 0),
+            Err(result::INVALID_STATE)
+        );
+        kernel.close_handle(thread).unwrap();
+        assert_eq!(free_threads(kernel), 1);
+        assert_eq!(
+            kernel
+                .process
+                .resource_limit
+                .current(LimitableResource::Threads),
+            0
+        );
+
+        let (writable, readable) = kernel.create_event().unwrap();
+        assert_eq!(kernel.create_event(), Err(result::LIMIT_REACHED));
+        kernel.close_handle(writable).unwrap();
+        kernel.close_handle(readable).unwrap();
+        assert!(kernel.create_event().is_ok());
+        assert_eq!(
+            kernel
+                .process
+                .resource_limit
+                .peak(LimitableResource::Events),
+            1
+        );
+
+        let process = &mut kernel.process;
+        let limit = &mut process.resource_limit;
+        limit
+            .set_limit(LimitableResource::PhysicalMemory, 0x40_0000)
+            .unwrap();
+        assert_eq!(process.set_heap_size(0x60_0000), Err(result::LIMIT_REACHED));
+        process.set_heap_size(0x40_0000).unwrap();
+        assert_eq!(
+            process
+                .resource_limit
+                .current(LimitableResource::PhysicalMemory),
+            0x40_0000
+        );
+        process.set_heap_size(0).unwrap();
+        assert_eq!(
+            process
+                .resource_limit
+                .current(LimitableResource::PhysicalMemory),
+            0
+        );
+    }
+
+    #[test]
+    fn test_memory_mapping_and_interrupt_events() {
+        let npdm = test_npdm(&[
+            svc::EXIT_THREAD,
+            svc::QUERY_MEMORY_MAPPING,
+            svc::CREATE_INTERRUPT_EVENT,
+        ]);
+        let mut kernel = Kernel::new();
+        kernel.create_process(&npdm).unwrap();
+        let mut guest = Guest::with_kernel(kernel);
+
+        let mut code = call(svc::QUERY_MEMORY_MAPPING, &[0, 0, 0x7000_1000, 0x1000]);
+        code.extend(store(0, guest.slot(1)));
+        code.extend(store(1, guest.slot(2)));
+        code.extend(call(
+            svc::QUERY_MEMORY_MAPPING,
+            &[0, 0, 0x7000_0000, 0x4000],
+        ));
+        code.extend(store(1, guest.slot(3)));
+        code.extend(call(
+            svc::QUERY_MEMORY_MAPPING,
+            &[0, 0, 0x7000_4000, 0x1000],
+        ));
+        code.extend(store(0, guest.slot(4)));
+        code.extend(call(svc::CREATE_INTERRUPT_EVENT, &[0, 57, 1]));
+        code.extend(store(0, guest.slot(5)));
+        code.extend(call(svc::CREATE_INTERRUPT_EVENT, &[0, 43, 1]));
+        code.extend(store(0, guest.slot(6)));
+        code.extend(call(svc::CREATE_INTERRUPT_EVENT, &[0, 42, 2]));
+        code.extend(store(0, guest.slot(7)));
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&code);
+        guest.kernel.start_main_thread(entry).unwrap();
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+        let (page, base) = (guest.read(2), guest.read(3));
+        assert_eq!(page, base + 0x1000, "both share one mapping");
+        let space = &guest.kernel.process.address_space;
+        assert!(space.layout.kernel_map.contains(base, 0x4000));
+        let info = space.query(base);
+        assert_eq!(info.state, MemoryState::Io);
+        assert_eq!(info.size, 0x4000);
+        assert_eq!(guest.result(4), result::NOT_FOUND);
+        assert_eq!(guest.result(5), ResultCode::SUCCESS);
+        assert_eq!(guest.result(6), result::NOT_FOUND);
+        assert_eq!(guest.result(7), result::INVALID_ENUM_VALUE);
+    }
+}
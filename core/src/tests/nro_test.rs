@@ -0,0 +1,187 @@
+#[cfg(test)]
+mod tests {
+    use crate::fs::{MemoryFile, VirtualFile};
+    use crate::kernel::memory::{MemoryPermission, MemoryState};
+    use crate::kernel::result;
+    use crate::kernel::{KernelExit, svc};
+    use crate::loader::nro::Nro;
+    use crate::loader::{RESULT_INVALID_NRO, Segment, Segments};
+    use crate::tests::arm64;
+    use crate::tests::bytes::{put_u32, put_u64};
+    use crate::tests::guest::Guest;
+    use std::sync::Arc;
+
+    const TEXT_SIZE: usize = 0x1000;
+    const RO: usize = 0x1000;
+    const DATA: usize = 0x2000;
+    const DATA_SIZE: usize = 0x1000;
+    const BSS_SIZE: u32 = 0x1800;
+    const BUILD_ID: [u8; 0x20] = [0xAB; 0x20];
+
+    /// Where the test program leaves X0 and X1, inside the config page
+    const SAVED_X0: u16 = 0xF00;
+    const SAVED_X1: u16 = 0xF08;
+
+    /// An NRO whose entry point branches over the header to a program that
+    /// saves X0 and X1 and exits, followed by `assets` if there are any
+    fn build_nro(assets: Option<&[&[u8]; 3]>) -> Vec<u8> {
+        let mut image = vec![0; DATA + DATA_SIZE];
+        let mut program = vec![arm64::branch(0x80)];
+        program.resize(0x80 / 4, 0);
+        program.push(arm64::str_imm(0, 0, SAVED_X0));
+        program.push(arm64::str_imm(1, 0, SAVED_X1));
+        program.push(arm64::svc(svc::EXIT_THREAD as u16));
+        for (i, instr) in program.iter().enumerate() {
+            put_u32(&mut image, i * 4, *instr);
+        }
+
+        image[0x10..0x14].copy_from_slice(b"NRO0");
+        put_u32(&mut image, 0x18, (DATA + DATA_SIZE) as u32);
+        let segments = [(0, TEXT_SIZE), (RO, 0x800), (DATA, DATA_SIZE)];
+        for (i, (offset, size)) in segments.into_iter().enumerate() {
+            put_u32(&mut image, 0x20 + i * 8, offset as u32);
+            put_u32(&mut image, 0x24 + i * 8, size as u32);
+        }
+        put_u32(&mut image, 0x38, BSS_SIZE);
+        image[0x40..0x60].copy_from_slice(&BUILD_ID);
+        image[RO..RO + 4].copy_from_slice(b"rodt");
+        image[DATA..DATA + 4].copy_from_slice(b"data");
+
+        if let Some(sections) = assets {
+            let mut aset = vec![0; 0x38];
+            aset[..4].copy_from_slice(b"ASET");
+            for (i, section) in sections.iter().enumerate() {
+                let offset = aset.len() as u64;
+                put_u64(&mut aset, 0x08 + i * 0x10, offset);
+                put_u64(&mut aset, 0x10 + i * 0x10, section.len() as u64);
+                aset.extend(*section);
+            }
+            image.extend(aset);
+        }
+        image
+    }
+
+    fn open(bytes: Vec<u8>) -> VirtualFile {
+        Arc::new(MemoryFile::read_only("hello.nro", bytes))
+    }
+
+    #[test]
+    fn test_parse_nro() {
+        let nacp = [0x4E; 0x10];
+        let nro = Nro::parse(&open(build_nro(Some(&[b"JPEG", &nacp, b""])))).unwrap();
+        assert_eq!(nro.name, "hello.nro");
+        assert_eq!(
+            nro.segments,
+            Segments {
+                text: Segment {
+                    offset: 0,
+                    size: TEXT_SIZE as u64
+                },
+                ro: Segment {
+                    offset: RO as u64,
+                    size: 0x800
+                },
+                data: Segment {
+                    offset: DATA as u64,
+                    size: DATA_SIZE as u64
+                },
+                bss_size: BSS_SIZE as u64,
+            }
+        );
+        assert_eq!(nro.segments.size(), 0x5000);
+        assert_eq!(nro.build_id, BUILD_ID);
+        assert_eq!(nro.image.len(), DATA + DATA_SIZE);
+
+        let assets = nro.assets.unwrap();
+        let icon = assets.icon.unwrap();
+        assert_eq!(icon.name(), "icon.jpg");
+        assert_eq!(icon.read_all().unwrap(), b"JPEG");
+        assert_eq!(assets.nacp.unwrap().read_all().unwrap(), nacp);
+        assert!(assets.romfs.is_none(), "empty sections are missing");
+
+        let bare = Nro::parse(&open(build_nro(None))).unwrap();
+        assert!(bare.assets.is_none());
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_nro() {
+        let mut bad_magic = build_nro(None);
+        bad_magic[0x10] = b'X';
+        assert_eq!(Nro::parse(&open(bad_magic)).err(), Some(RESULT_INVALID_NRO));
+
+        let mut unaligned = build_nro(None);
+        put_u32(&mut unaligned, 0x28, RO as u32 + 0x10);
+        assert_eq!(Nro::parse(&open(unaligned)).err(), Some(RESULT_INVALID_NRO));
+
+        let truncated = build_nro(None)[..DATA].to_vec();
+        assert_eq!(Nro::parse(&open(truncated)).err(), Some(RESULT_INVALID_NRO));
+
+        // The RomFS runs past the end of the file
+        let mut bytes = build_nro(Some(&[b"", b"", b"romfs"]));
+        let romfs_size = DATA + DATA_SIZE + 0x30;
+        put_u64(&mut bytes, romfs_size, 6);
+        assert_eq!(Nro::parse(&open(bytes)).err(), Some(RESULT_INVALID_NRO));
+    }
+
+    #[test]
+    fn test_load_nro() {
+        let nro = Nro::parse(&open(build_nro(None))).unwrap();
+        let mut guest = Guest::new();
+        let handle = guest.kernel.load_nro(&nro, "sdmc:/hello.nro -v").unwrap();
+
+        let process = &guest.kernel.process;
+        let module = &process.modules[0];
+        assert_eq!(process.modules.len(), 1);
+        assert_eq!(module.name, "hello.nro");
+        assert_eq!(module.size, 0x5000);
+        assert_eq!(module.build_id, BUILD_ID);
+        let base = module.base;
+        let space = &process.address_space;
+        assert!(space.layout.code.contains(base, module.size));
+        for (offset, state, permission) in [
+            (0, MemoryState::Code, MemoryPermission::READ_EXECUTE),
+            (RO, MemoryState::Code, MemoryPermission::READ),
+            (DATA, MemoryState::CodeData, MemoryPermission::READ_WRITE),
+            (0x4800, MemoryState::CodeData, MemoryPermission::READ_WRITE),
+        ] {
+            let info = space.query(base + offset as u64);
+            assert_eq!((info.state, info.permission), (state, permission));
+        }
+        let memory = guest.kernel.cpu.memory();
+        assert_eq!(memory.read_vec(base + RO as u64, 4).unwrap(), b"rodt");
+        assert_eq!(memory.read_vec(base + DATA as u64, 4).unwrap(), b"data");
+        assert_eq!(memory.read_u64(base + 0x4000).unwrap(), 0, "BSS is zeroed");
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        let config = base + 0x5000;
+        assert_eq!(memory.read_u64(config + SAVED_X0 as u64).unwrap(), config);
+        assert_eq!(memory.read_u64(config + SAVED_X1 as u64).unwrap(), u64::MAX);
+
+        // MainThreadHandle comes first and EndOfList last, with argv after
+        // the entries
+        let entry = |index: u64| {
+            let at = config + index * 0x18;
+            (
+                memory.read_u32(at).unwrap(),
+                memory.read_u64(at + 8).unwrap(),
+                memory.read_u64(at + 16).unwrap(),
+            )
+        };
+        assert_eq!(entry(0), (1, handle as u64, 0));
+        let entries: Vec<_> = (0..7).map(entry).collect();
+        let (_, low, high) = entries.iter().find(|e| e.0 == 6).unwrap();
+        assert_eq!((*low, *high), (u64::MAX, u64::MAX), "every SVC is allowed");
+        let (_, _, argv) = entries.iter().find(|e| e.0 == 5).unwrap();
+        assert_eq!(memory.read_vec(*argv, 19).unwrap(), b"sdmc:/hello.nro -v\0");
+        assert_eq!(entry(6), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_load_nro_needs_fresh_kernel() {
+        let nro = Nro::parse(&open(build_nro(None))).unwrap();
+        let mut guest = Guest::new();
+        let entry = guest.load(&[arm64::svc(svc::EXIT_THREAD as u16)]);
+        guest.spawn(entry, 0, 44, 0);
+        assert_eq!(guest.kernel.load_nro(&nro, ""), Err(result::INVALID_STATE));
+    }
+}
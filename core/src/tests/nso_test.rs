@@ -0,0 +1,191 @@
+#[cfg(test)]
+mod tests {
+    use crate::fs::{MemoryDirectory, MemoryFile, VirtualDir, VirtualFile};
+    use crate::kernel::memory::{MemoryPermission, MemoryState, PAGE_SIZE};
+    use crate::kernel::{KernelExit, svc};
+    use crate::loader::nso::Nso;
+    use crate::loader::{Mod0, RESULT_INVALID_NSO};
+    use crate::tests::arm64;
+    use crate::tests::bytes::put_u32;
+    use crate::tests::guest::{Guest, store};
+    use sha2::{Digest, Sha256};
+    use std::sync::Arc;
+
+    const MOD0: usize = 0x10;
+    const BSS_SIZE: u32 = 0x2100;
+
+    /// Text that starts by running `program` and has a MOD0 header right
+    /// after the branch to it
+    fn text(program: &[u32]) -> Vec<u8> {
+        let mut text = vec![0; 0x40];
+        put_u32(&mut text, 0, arm64::branch(0x40));
+        put_u32(&mut text, 4, MOD0 as u32);
+        text[MOD0..MOD0 + 4].copy_from_slice(b"MOD0");
+        // .dynamic at the start of data, BSS after it and the module
+        // object at the end of BSS; offsets are from MOD0
+        let relative = [0x2000, 0x2100, 0x2100 + BSS_SIZE, 0x1000, 0x1010, 0x4000];
+        for (i, offset) in relative.into_iter().enumerate() {
+            put_u32(&mut text, MOD0 + 4 + i * 4, offset - MOD0 as u32);
+        }
+        text.extend(program.iter().flat_map(|instr| instr.to_le_bytes()));
+        text
+    }
+
+    /// An NSO holding `text`, one page of read-only data and 0x100 bytes of
+    /// data, every segment hashed and compressed if `compress`
+    fn build_nso(text: &[u8], build_id: u8, compress: bool) -> Vec<u8> {
+        let ro = [b"rodt".as_slice(), &[0x11; 0xFFC]].concat();
+        let data = [b"data".as_slice(), &[0x22; 0xFC]].concat();
+        let mut header = vec![0; 0x100];
+        header[..4].copy_from_slice(b"NSO0");
+        put_u32(
+            &mut header,
+            0x0C,
+            0b111_000 | if compress { 0b111 } else { 0 },
+        );
+
+        let mut stored = Vec::new();
+        let mut memory_offset = 0;
+        for (i, segment) in [text, &ro, &data].into_iter().enumerate() {
+            let bytes = if compress {
+                lz4_flex::block::compress(segment)
+            } else {
+                segment.to_vec()
+            };
+            put_u32(&mut header, 0x10 + i * 0x10, (0x100 + stored.len()) as u32);
+            put_u32(&mut header, 0x14 + i * 0x10, memory_offset as u32);
+            put_u32(&mut header, 0x18 + i * 0x10, segment.len() as u32);
+            put_u32(&mut header, 0x60 + i * 4, bytes.len() as u32);
+            header[0xA0 + i * 0x20..0xC0 + i * 0x20].copy_from_slice(&Sha256::digest(segment));
+            stored.extend(bytes);
+            memory_offset += segment.len().next_multiple_of(PAGE_SIZE as usize);
+        }
+        put_u32(&mut header, 0x3C, BSS_SIZE);
+        header[0x40..0x60].fill(build_id);
+        [header, stored].concat()
+    }
+
+    fn open(name: &str, bytes: Vec<u8>) -> VirtualFile {
+        Arc::new(MemoryFile::read_only(name, bytes))
+    }
+
+    #[test]
+    fn test_parse_nso() {
+        let text = text(&[arm64::nop()]);
+        let nso = Nso::parse(&open("main", build_nso(&text, 0x42, true))).unwrap();
+        assert_eq!(nso.name, "main");
+        assert_eq!(nso.build_id, [0x42; 0x20]);
+        assert_eq!(nso.segments.text.size, text.len() as u64);
+        assert_eq!(nso.segments.ro.offset, 0x1000);
+        assert_eq!(nso.segments.data.offset, 0x2000);
+        assert_eq!(nso.segments.data.size, 0x100);
+        assert_eq!(nso.segments.bss_size, BSS_SIZE as u64);
+        assert_eq!(nso.segments.size(), 0x5000);
+        assert_eq!(&nso.image[..text.len()], text);
+        assert_eq!(&nso.image[0x1000..0x1004], b"rodt");
+        assert_eq!(&nso.image[0x2000..0x2004], b"data");
+        assert_eq!(
+            nso.mod0,
+            Some(Mod0 {
+                offset: MOD0 as u64,
+                dynamic: 0x2000,
+                bss_start: 0x2100,
+                bss_end: 0x4200,
+                eh_frame_hdr_start: 0x1000,
+                eh_frame_hdr_end: 0x1010,
+                module_object: 0x4000,
+            })
+        );
+
+        let plain = Nso::parse(&open("main", build_nso(&text, 0x42, false))).unwrap();
+        assert_eq!(plain.image, nso.image);
+        assert_eq!(plain.segments, nso.segments);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_nso() {
+        let text = text(&[]);
+        let mut bad_magic = build_nso(&text, 0, false);
+        bad_magic[3] = b'1';
+        assert_eq!(
+            Nso::parse(&open("main", bad_magic)).err(),
+            Some(RESULT_INVALID_NSO)
+        );
+
+        // A byte of stored read-only data no longer matches its hash
+        let mut corrupt = build_nso(&text, 0, false);
+        corrupt[0x100 + text.len() + 8] ^= 1;
+        assert_eq!(
+            Nso::parse(&open("main", corrupt)).err(),
+            Some(RESULT_INVALID_NSO)
+        );
+
+        // The compressed text stops short of its decompressed size
+        let mut truncated = build_nso(&text, 0, true);
+        put_u32(&mut truncated, 0x18, text.len() as u32 + 4);
+        assert_eq!(
+            Nso::parse(&open("main", truncated)).err(),
+            Some(RESULT_INVALID_NSO)
+        );
+
+        let mut unaligned = build_nso(&text, 0, true);
+        put_u32(&mut unaligned, 0x24, 0x1010);
+        assert_eq!(
+            Nso::parse(&open("main", unaligned)).err(),
+            Some(RESULT_INVALID_NSO)
+        );
+    }
+
+    #[test]
+    fn test_load_exefs_consecutively() {
+        let mut guest = Guest::new();
+        let exefs = MemoryDirectory::read_only("exefs");
+        // rtld runs first and records that it did
+        let mut program = arm64::mov_imm64(1, 1).to_vec();
+        program.extend(store(1, guest.slot(1)));
+        program.push(arm64::svc(svc::EXIT_THREAD as u16));
+        let modules = [("sdk", 3, &[][..]), ("main", 2, &[]), ("rtld", 1, &program)];
+        for (name, build_id, program) in modules {
+            let bytes = build_nso(&text(program), build_id, true);
+            exefs.add_file(open(name, bytes));
+        }
+        exefs.add_file(open("main.npdm", b"META".to_vec()));
+        let exefs: VirtualDir = Arc::new(exefs);
+
+        let nsos = Nso::parse_exefs(&exefs).unwrap();
+        let names: Vec<_> = nsos.iter().map(|nso| nso.name.as_str()).collect();
+        assert_eq!(names, ["rtld", "main", "sdk"]);
+
+        let entry = guest.kernel.load_nsos(&nsos).unwrap();
+        let modules = &guest.kernel.process.modules;
+        assert_eq!(modules.len(), 3);
+        for (i, module) in modules.iter().enumerate() {
+            assert_eq!(module.name, names[i]);
+            assert_eq!(module.base, entry + i as u64 * 0x5000);
+            assert_eq!(module.build_id, [i as u8 + 1; 0x20]);
+        }
+        let space = &guest.kernel.process.address_space;
+        for (offset, state, permission) in [
+            (0x5000, MemoryState::Code, MemoryPermission::READ_EXECUTE),
+            (0x6000, MemoryState::Code, MemoryPermission::READ),
+            (0x7000, MemoryState::CodeData, MemoryPermission::READ_WRITE),
+            (0x9000, MemoryState::CodeData, MemoryPermission::READ_WRITE),
+            (0xA000, MemoryState::Code, MemoryPermission::READ_EXECUTE),
+        ] {
+            let info = space.query(entry + offset);
+            assert_eq!((info.state, info.permission), (state, permission));
+        }
+
+        guest.kernel.start_main_thread(entry).unwrap();
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.read(1), 1);
+    }
+
+    #[test]
+    fn test_exefs_needs_main() {
+        let exefs = MemoryDirectory::read_only("exefs");
+        exefs.add_file(open("rtld", build_nso(&text(&[]), 0, true)));
+        let exefs: VirtualDir = Arc::new(exefs);
+        assert_eq!(Nso::parse_exefs(&exefs).err(), Some(RESULT_INVALID_NSO));
+    }
+}
@@ -0,0 +1,147 @@
+#[cfg(test)]
+mod tests {
+    use crate::fs::{
+        self, MemoryFile, PartitionFs, VfsDirectory, VirtualDir, VirtualFile, Xci, XciPartition,
+        write_pfs0,
+    };
+    use sha2::{Digest, Sha256};
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+
+    const HASHED_SIZE: usize = 0x200;
+
+    fn file(name: &str, data: &[u8]) -> VirtualFile {
+        Arc::new(MemoryFile::read_only(name, data.to_vec()))
+    }
+
+    /// An HFS0 image of `entries`, each hashed over its first 0x200 bytes
+    fn build_hfs0(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut table = Vec::new();
+        let mut strings = Vec::new();
+        let mut offset = 0u64;
+        for (name, data) in entries {
+            table.extend(offset.to_le_bytes());
+            table.extend((data.len() as u64).to_le_bytes());
+            table.extend((strings.len() as u32).to_le_bytes());
+            table.extend((HASHED_SIZE as u32).to_le_bytes());
+            table.extend([0; 8]);
+            table.extend(Sha256::digest(&data[..HASHED_SIZE.min(data.len())]));
+            strings.extend(name.as_bytes());
+            strings.push(0);
+            offset += data.len() as u64;
+        }
+        let mut image = b"HFS0".to_vec();
+        image.extend((entries.len() as u32).to_le_bytes());
+        image.extend((strings.len() as u32).to_le_bytes());
+        image.extend([0; 4]);
+        image.extend(table);
+        image.extend(strings);
+        for (_, data) in entries {
+            image.extend(*data);
+        }
+        image
+    }
+
+    /// A cartridge image with its root partition at 0x1000
+    fn build_xci(partitions: &[(&str, &[u8])]) -> Vec<u8> {
+        let root = build_hfs0(partitions);
+        let header_size = 0x10 + partitions.len() * 0x40 + 0x20;
+        let mut image = vec![0; 0x1000];
+        image[0x100..0x104].copy_from_slice(b"HEAD");
+        image[0x130..0x138].copy_from_slice(&0x1000u64.to_le_bytes());
+        image[0x138..0x140].copy_from_slice(&(header_size as u64).to_le_bytes());
+        image[0x140..0x160].copy_from_slice(&Sha256::digest(&root[..header_size]));
+        image.extend(root);
+        image
+    }
+
+    #[test]
+    fn test_pfs0_round_trip() {
+        let files = [
+            file("main", b"NSO0 main"),
+            file("main.npdm", b"META"),
+            file("empty", b""),
+        ];
+        let image = write_pfs0(&files).unwrap();
+        assert_eq!(&image[..4], b"PFS0");
+        let header_size = image.len() - 13;
+        assert_eq!(header_size % 0x20, 0, "contents start aligned");
+
+        let pfs = PartitionFs::open(file("exefs.nsp", &image)).unwrap();
+        assert!(!pfs.is_hfs());
+        assert_eq!(pfs.name(), "exefs.nsp");
+        let names: Vec<_> = pfs.files().unwrap().iter().map(|f| f.name()).collect();
+        assert_eq!(names, ["main", "main.npdm", "empty"]);
+        let root: VirtualDir = Arc::new(pfs);
+        let npdm = fs::open_file(&root, "main.npdm").unwrap();
+        assert_eq!(npdm.read_all().unwrap(), b"META");
+        assert_eq!(root.file("main").unwrap().read_all().unwrap(), b"NSO0 main");
+        assert_eq!(root.file("empty").unwrap().size(), 0);
+        assert!(!root.is_writable());
+    }
+
+    #[test]
+    fn test_partition_rejects_bad_archives() {
+        let image = write_pfs0(&[file("a", b"0123456789")]).unwrap();
+        let mut bad_magic = image.clone();
+        bad_magic[0] = b'X';
+        let err = PartitionFs::open(file("a.nsp", &bad_magic)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let truncated = &image[..image.len() - 1];
+        let err = PartitionFs::open(file("a.nsp", truncated)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut many_entries = image;
+        many_entries[4] = 0xFF;
+        assert!(PartitionFs::open(file("a.nsp", &many_entries)).is_err());
+    }
+
+    #[test]
+    fn test_hfs0_verifies_entries() {
+        let large = vec![0x5A; 0x300];
+        let image = build_hfs0(&[("small.nca", b"header"), ("large.nca", &large)]);
+        let hfs = PartitionFs::open(file("secure", &image)).unwrap();
+        assert!(hfs.is_hfs());
+        assert_eq!(hfs.file("large.nca").unwrap().read_all().unwrap(), large);
+
+        // Only the first 0x200 bytes are hashed
+        let mut tail = image.clone();
+        *tail.last_mut().unwrap() ^= 1;
+        assert!(PartitionFs::open(file("secure", &tail)).is_ok());
+
+        let mut head = image;
+        let large_start = head.len() - large.len();
+        head[large_start] ^= 1;
+        let err = PartitionFs::open(file("secure", &head)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("large.nca"));
+    }
+
+    #[test]
+    fn test_xci_partitions() {
+        let update = build_hfs0(&[("update.nca", b"system update")]);
+        let secure = build_hfs0(&[("program.nca", b"game"), ("meta.cnmt.nca", b"cnmt")]);
+        let image = build_xci(&[("update", &update), ("secure", &secure)]);
+        let xci = Xci::open(file("game.xci", &image)).unwrap();
+        assert_eq!(xci.subdirectories().unwrap().len(), 2);
+
+        let secure = xci.partition(XciPartition::Secure).unwrap();
+        assert_eq!(secure.name(), "secure");
+        let program = secure.file("program.nca").unwrap();
+        assert_eq!(program.read_all().unwrap(), b"game");
+        let update = xci.partition(XciPartition::Update).unwrap();
+        assert_eq!(update.files().unwrap()[0].name(), "update.nca");
+        let missing = xci.partition(XciPartition::Logo).err().unwrap();
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+
+        let root: VirtualDir = Arc::new(xci);
+        let nca = fs::open_file(&root, "secure/meta.cnmt.nca").unwrap();
+        assert_eq!(nca.read_all().unwrap(), b"cnmt");
+
+        let mut tampered = image;
+        tampered[0x1000 + 0x10] ^= 1;
+        let err = Xci::open(file("game.xci", &tampered)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
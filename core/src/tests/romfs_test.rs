@@ -0,0 +1,177 @@
+#[cfg(test)]
+mod tests {
+    use crate::fs::{
+        self, HostDirectory, MemoryDirectory, MemoryFile, RomFs, RomFsBuilder, VfsDirectory,
+        VirtualDir, VirtualFile,
+    };
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+
+    fn file(name: &str, data: &[u8]) -> VirtualFile {
+        Arc::new(MemoryFile::read_only(name, data.to_vec()))
+    }
+
+    fn dir(name: &str, files: &[(&str, &[u8])], subdirectories: Vec<VirtualDir>) -> VirtualDir {
+        let dir = MemoryDirectory::new(name);
+        for (name, data) in files {
+            dir.add_file(file(name, data));
+        }
+        for subdirectory in subdirectories {
+            dir.add_subdirectory(subdirectory);
+        }
+        Arc::new(dir)
+    }
+
+    fn names(files: Vec<VirtualFile>) -> Vec<String> {
+        files.iter().map(|f| f.name()).collect()
+    }
+
+    fn dir_names(dirs: Vec<VirtualDir>) -> Vec<String> {
+        dirs.iter().map(|d| d.name()).collect()
+    }
+
+    /// Build `tree` and open the image as a RomFS, copied into memory the
+    /// way it would sit inside a container
+    fn round_trip(builder: &RomFsBuilder) -> VirtualDir {
+        let image = builder.build("romfs.bin").read_all().unwrap();
+        Arc::new(RomFs::open(file("romfs.bin", &image)).unwrap())
+    }
+
+    #[test]
+    fn test_romfs_round_trip() {
+        let many: Vec<(String, Vec<u8>)> = (0..30)
+            .map(|i| (format!("level{i:02}.bin"), vec![i as u8; i]))
+            .collect();
+        let many: Vec<(&str, &[u8])> = many
+            .iter()
+            .map(|(name, data)| (name.as_str(), data.as_slice()))
+            .collect();
+        let tree = dir(
+            "",
+            &[("icon.png", b"PNG"), ("empty.txt", b"")],
+            vec![
+                dir(
+                    "data",
+                    &[("config.ini", b"[game]")],
+                    vec![dir("nested", &[("deep.txt", b"down here")], vec![])],
+                ),
+                dir("levels", &many, vec![]),
+                dir("unused", &[], vec![]),
+            ],
+        );
+        let mut builder = RomFsBuilder::new();
+        builder.add(&tree).unwrap();
+        let romfs = round_trip(&builder);
+
+        assert_eq!(names(romfs.files().unwrap()), ["empty.txt", "icon.png"]);
+        assert_eq!(
+            dir_names(romfs.subdirectories().unwrap()),
+            ["data", "levels", "unused"]
+        );
+        let deep = fs::open_file(&romfs, "data/nested/deep.txt").unwrap();
+        assert_eq!(deep.read_all().unwrap(), b"down here");
+        assert_eq!(romfs.file("empty.txt").unwrap().size(), 0);
+
+        let levels = romfs.subdirectory("levels").unwrap();
+        assert_eq!(levels.files().unwrap().len(), 30);
+        for (name, data) in &many {
+            let level = levels.file(name).unwrap();
+            assert_eq!(level.read_all().unwrap(), *data, "{name}");
+        }
+        assert!(
+            romfs
+                .subdirectory("unused")
+                .unwrap()
+                .files()
+                .unwrap()
+                .is_empty()
+        );
+
+        let missing = romfs.file("config.ini").err().unwrap();
+        assert_eq!(missing.kind(), ErrorKind::NotFound, "only in data/");
+        let missing = fs::open_directory(&romfs, "data/other").err().unwrap();
+        assert_eq!(missing.kind(), ErrorKind::NotFound);
+        assert!(!romfs.is_writable());
+    }
+
+    #[test]
+    fn test_romfs_layout() {
+        let mut builder = RomFsBuilder::new();
+        builder
+            .add(&dir("", &[("a", b"12345"), ("b", b"6789")], vec![]))
+            .unwrap();
+        let image = builder.build("romfs.bin");
+        assert_eq!(image.name(), "romfs.bin");
+        let bytes = image.read_all().unwrap();
+        let word =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        assert_eq!(word(0), 0x50, "header size");
+        assert_eq!(word(0x48), 0x200, "file data offset");
+        assert_eq!(&bytes[0x200..0x205], b"12345");
+        assert_eq!(&bytes[0x210..0x214], b"6789", "files start 0x10 aligned");
+        assert_eq!(word(0x08), 0x220, "tables follow the data");
+
+        let mut partial = [0; 8];
+        assert_eq!(image.read_at(0x1FE, &mut partial).unwrap(), 8);
+        assert_eq!(partial, *b"\0\x0012345\0");
+    }
+
+    #[test]
+    fn test_builder_overlays_and_reads_host_trees() {
+        let path = std::env::temp_dir().join(format!("oboromi-romfs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(path.join("data")).unwrap();
+        std::fs::write(path.join("data/config.ini"), b"[modded]").unwrap();
+        std::fs::write(path.join("data/extra.bin"), b"new").unwrap();
+        let mods: VirtualDir = Arc::new(HostDirectory::open(&path, false).unwrap());
+
+        let base = dir(
+            "",
+            &[("icon.png", b"PNG")],
+            vec![dir(
+                "data",
+                &[("config.ini", b"[game]"), ("map.bin", b"map")],
+                vec![],
+            )],
+        );
+        let mut builder = RomFsBuilder::new();
+        builder.add(&base).unwrap().add(&mods).unwrap();
+        let romfs = round_trip(&builder);
+        std::fs::remove_dir_all(&path).unwrap();
+
+        let data = romfs.subdirectory("data").unwrap();
+        assert_eq!(
+            names(data.files().unwrap()),
+            ["config.ini", "extra.bin", "map.bin"]
+        );
+        assert_eq!(
+            data.file("config.ini").unwrap().read_all().unwrap(),
+            b"[modded]"
+        );
+        assert_eq!(data.file("map.bin").unwrap().read_all().unwrap(), b"map");
+        assert_eq!(romfs.file("icon.png").unwrap().read_all().unwrap(), b"PNG");
+    }
+
+    #[test]
+    fn test_romfs_rejects_bad_images() {
+        let err = RomFs::open(file("romfs.bin", &[0; 0x50])).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut builder = RomFsBuilder::new();
+        builder
+            .add(&dir("", &[("a", b"1"), ("b", b"2")], vec![]))
+            .unwrap();
+        let mut bytes = builder.build("romfs.bin").read_all().unwrap();
+        let truncated = &bytes[..bytes.len() - 4];
+        let err = RomFs::open(file("romfs.bin", truncated)).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // Point the second file's sibling link (after the 0x24-byte first
+        // entry) back at the first
+        let file_meta = u64::from_le_bytes(bytes[0x38..0x40].try_into().unwrap()) as usize;
+        bytes[file_meta + 0x28..file_meta + 0x2C].copy_from_slice(&0u32.to_le_bytes());
+        let romfs = RomFs::open(file("romfs.bin", &bytes)).unwrap();
+        let err = romfs.files().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
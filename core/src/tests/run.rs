@@ -1,5 +1,6 @@
 //! Test suite for Dynarmic JIT backend
 use crate::cpu::UnicornCPU;
+use crate::tests::arm64;
 use std::time::{Duration, Instant};
 
 const TEST_BASE_ADDR: u64 = 0x0000_1000;
@@ -52,41 +53,6 @@ impl TestResult {
     }
 }
 
-mod arm64 {
-    pub fn add_imm(rd: u8, rn: u8, imm12: u16) -> u32 {
-        0x91000000 | ((imm12 as u32) << 10) | ((rn as u32) << 5) | (rd as u32)
-    }
-
-    pub fn sub_imm(rd: u8, rn: u8, imm12: u16) -> u32 {
-        0xD1000000 | ((imm12 as u32) << 10) | ((rn as u32) << 5) | (rd as u32)
-    }
-
-    pub fn add_reg(rd: u8, rn: u8, rm: u8) -> u32 {
-        0x8B000000 | ((rm as u32) << 16) | ((rn as u32) << 5) | (rd as u32)
-    }
-
-    pub fn mov_reg(rd: u8, rm: u8) -> u32 {
-        0xAA0003E0 | ((rm as u32) << 16) | (rd as u32)
-    }
-
-    pub fn branch(offset: i32) -> u32 {
-        let imm26 = (offset >> 2) & 0x3FFFFFF;
-        0x14000000 | (imm26 as u32)
-    }
-
-    pub fn ret() -> u32 {
-        0xD65F03C0
-    }
-
-    pub fn nop() -> u32 {
-        0xD503201F
-    }
-
-    pub fn brk(imm16: u16) -> u32 {
-        0xD4200000 | ((imm16 as u32) << 5)
-    }
-}
-
 /// This prevents timeout issues on slower hardware during actual tests
 /// No timeout is enforced here as initial compilation can take variable time
 fn warmup_jit() {
@@ -0,0 +1,191 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::handle::{Handle, KernelObject};
+    use crate::kernel::hipc::Message;
+    use crate::kernel::ipc::SessionHandler;
+    use crate::kernel::result::{self, ResultCode};
+    use crate::kernel::{KernelExit, svc};
+    use crate::nn::{self, cmif, sm};
+    use crate::tests::guest::{Guest, call, store, svc};
+
+    const FOREVER: u64 = u64::MAX;
+
+    fn name(name: &str) -> nn::ServiceName {
+        nn::service_name(name).unwrap()
+    }
+
+    /// Send TIPC command `command` to `session` with a name as its first
+    /// argument, returning the result and moved handles
+    fn request(
+        guest: &mut Guest,
+        session: &mut dyn SessionHandler,
+        command: u16,
+        name: &[u8; 8],
+        args: &[u32],
+    ) -> (ResultCode, Vec<Handle>) {
+        let name = u64::from_le_bytes(*name);
+        let mut data = vec![name as u32, (name >> 32) as u32];
+        data.extend(args);
+        let message = Message {
+            kind: 16 + command,
+            data,
+            ..Default::default()
+        };
+        let reply = session.handle_request(&mut guest.kernel, &message).unwrap();
+        (ResultCode(reply.data[0]), reply.move_handles)
+    }
+
+    /// A session to `sm:` that has registered as a client
+    fn sm_session(guest: &mut Guest) -> Box<dyn SessionHandler> {
+        let mut session = cmif::serve(sm::State::new());
+        let register = Message {
+            kind: 16,
+            pid: Some(0),
+            data: vec![0, 0],
+            ..Default::default()
+        };
+        let reply = session
+            .handle_request(&mut guest.kernel, &register)
+            .unwrap();
+        assert_eq!(reply.data, [0]);
+        session
+    }
+
+    fn is_client_session(guest: &Guest, handle: Handle) -> bool {
+        let object = guest.kernel.process.handles.get(handle);
+        matches!(object, Some(KernelObject::ClientSession(_)))
+    }
+
+    #[test]
+    fn test_registry_has_module_services() {
+        let guest = Guest::new();
+        let services = &guest.kernel.sys.services;
+        for service in ["fsp-srv", "hid", "vi:m", "appletOE", "set:sys", "nvdrv"] {
+            let entry = services.get(name(service));
+            assert!(entry.is_some_and(|s| s.object.is_some()), "{service}");
+        }
+        assert!(services.get(name("missing")).is_none());
+        assert!(services.len() > 160, "most modules have several names");
+
+        assert_eq!(nn::service_name("fsp-srv"), Some(*b"fsp-srv\0"));
+        assert_eq!(nn::service_name("too-long!"), None);
+    }
+
+    #[test]
+    fn test_get_service_limits_sessions() {
+        let mut guest = Guest::new();
+        let mut session = sm_session(&mut guest);
+
+        let (code, handles) = request(&mut guest, session.as_mut(), 1, b"fsp-srv\0", &[]);
+        assert_eq!(code, ResultCode::SUCCESS);
+        assert!(is_client_session(&guest, handles[0]));
+
+        for bad in [b"\0fsp-srv", b"fsp\0srv\0"] {
+            let (code, handles) = request(&mut guest, session.as_mut(), 1, bad, &[]);
+            assert_eq!(code, sm::RESULT_INVALID_SERVICE_NAME);
+            assert!(handles.is_empty());
+        }
+
+        guest
+            .kernel
+            .sys
+            .services
+            .get_mut(name("hid"))
+            .unwrap()
+            .max_sessions = 2;
+        let (_, first) = request(&mut guest, session.as_mut(), 1, b"hid\0\0\0\0\0", &[]);
+        let (_, second) = request(&mut guest, session.as_mut(), 1, b"hid\0\0\0\0\0", &[]);
+        assert!(is_client_session(&guest, second[0]));
+        let (code, _) = request(&mut guest, session.as_mut(), 1, b"hid\0\0\0\0\0", &[]);
+        assert_eq!(code, result::OUT_OF_SESSIONS);
+
+        guest.kernel.close_handle(first[0]).unwrap();
+        let (code, _) = request(&mut guest, session.as_mut(), 1, b"hid\0\0\0\0\0", &[]);
+        assert_eq!(code, ResultCode::SUCCESS, "closing a session frees a slot");
+    }
+
+    #[test]
+    fn test_register_and_unregister_service() {
+        let mut guest = Guest::new();
+        let mut session = sm_session(&mut guest);
+        let service = b"guest\0\0\0";
+
+        // RegisterService(name, is_light, max_sessions)
+        let (code, handles) = request(&mut guest, session.as_mut(), 2, service, &[0, 1]);
+        assert_eq!(code, ResultCode::SUCCESS);
+        let port = handles[0];
+        assert!(matches!(
+            guest.kernel.process.handles.get(port),
+            Some(KernelObject::ServerPort(_))
+        ));
+        let (code, _) = request(&mut guest, session.as_mut(), 2, service, &[0, 1]);
+        assert_eq!(code, sm::RESULT_ALREADY_REGISTERED);
+        let (code, _) = request(&mut guest, session.as_mut(), 2, b"fsp-srv\0", &[0, 1]);
+        assert_eq!(code, sm::RESULT_ALREADY_REGISTERED);
+
+        assert_eq!(guest.kernel.accept_session(port), Err(result::NOT_FOUND));
+        let (code, client) = request(&mut guest, session.as_mut(), 1, service, &[]);
+        assert_eq!(code, ResultCode::SUCCESS);
+        assert!(is_client_session(&guest, client[0]));
+        let (code, _) = request(&mut guest, session.as_mut(), 1, service, &[]);
+        assert_eq!(code, result::OUT_OF_SESSIONS);
+        let server = guest.kernel.accept_session(port).unwrap();
+        assert!(matches!(
+            guest.kernel.process.handles.get(server),
+            Some(KernelObject::ServerSession(_))
+        ));
+
+        let (code, _) = request(&mut guest, session.as_mut(), 3, service, &[]);
+        assert_eq!(code, ResultCode::SUCCESS);
+        for command in [1, 3] {
+            let (code, _) = request(&mut guest, session.as_mut(), command, service, &[]);
+            assert_eq!(code, sm::RESULT_NOT_REGISTERED);
+        }
+    }
+
+    #[test]
+    fn test_server_port_wakes_waiter() {
+        let mut guest = Guest::new();
+        let (port_handle, port) = guest.kernel.create_server_port(4).unwrap();
+        guest
+            .kernel
+            .cpu
+            .memory()
+            .write_u32(guest.slot(16), port_handle);
+
+        let mut code = call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 1, FOREVER]);
+        code.extend(store(0, guest.slot(1)));
+        code.extend(store(1, guest.slot(2)));
+        code.extend(call(svc::ACCEPT_SESSION, &[0, port_handle as u64]));
+        code.extend(store(0, guest.slot(3)));
+        code.extend(store(1, guest.slot(4)));
+        code.extend(call(svc::ACCEPT_SESSION, &[0, port_handle as u64]));
+        code.extend(store(0, guest.slot(5)));
+        code.push(svc(svc::EXIT_THREAD));
+        let code = guest.load(&code);
+        guest.spawn(code, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::Deadlock);
+        assert_eq!(guest.read(1), 0, "still waiting for a session");
+        guest.kernel.connect_port(port, None).unwrap();
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+        assert_eq!(guest.read(2), 0);
+        assert_eq!(guest.result(3), ResultCode::SUCCESS);
+        assert!(matches!(
+            guest.kernel.process.handles.get(guest.read(4) as Handle),
+            Some(KernelObject::ServerSession(_))
+        ));
+        assert_eq!(guest.result(5), result::NOT_FOUND);
+
+        // Closing the port fails sessions it never accepted
+        let client = guest.kernel.connect_port(port, None).unwrap();
+        guest.kernel.close_handle(port_handle).unwrap();
+        assert_eq!(
+            guest.kernel.connect_port(port, None),
+            Err(result::PORT_CLOSED)
+        );
+        guest.kernel.close_handle(client).unwrap();
+    }
+}
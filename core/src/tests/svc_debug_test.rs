@@ -0,0 +1,217 @@
+#[cfg(test)]
+mod tests {
+    use crate::cpu::HaltReason;
+    use crate::kernel::debug::{BREAK_NOTIFICATION_ONLY, BreakReason, Cause};
+    use crate::kernel::process::Module;
+    use crate::kernel::result::{self, ResultCode};
+    use crate::kernel::thread::ThreadState;
+    use crate::kernel::{KernelExit, svc};
+    use crate::tests::arm64;
+    use crate::tests::guest::{Guest, call, store, svc};
+    use std::sync::{Mutex, Once};
+
+    /// Keeps `(target, message)` of every log record
+    struct Capture;
+
+    static RECORDS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let entry = (record.target().to_string(), record.args().to_string());
+            RECORDS.lock().unwrap().push(entry);
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&Capture).unwrap();
+            log::set_max_level(log::LevelFilter::Info);
+        });
+    }
+
+    fn add_main_module(guest: &mut Guest) -> u64 {
+        let base = guest.kernel.process.address_space.layout.code.base;
+        guest.kernel.process.modules.push(Module {
+            name: String::from("main"),
+            base,
+            size: 0x10000,
+            ..Default::default()
+        });
+        base
+    }
+
+    #[test]
+    fn test_break_stops_process_with_report() {
+        let mut guest = Guest::new();
+        let base = add_main_module(&mut guest);
+        let dir = std::env::temp_dir().join(format!("oboromi-crash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        guest.kernel.debug.report_dir = Some(dir.clone());
+        let argument = guest.slot(8);
+        guest.kernel.cpu.memory().write(argument, b"abort!");
+
+        // A thread on another core, which the break ends too
+        let mut code = call(svc::SLEEP_THREAD, &[1_000_000_000]);
+        code.push(svc(svc::EXIT_THREAD));
+        let sleeper = guest.load(&code);
+        guest.spawn(sleeper, 0, 44, 1);
+
+        // Reason 1 is an assertion failure
+        let code = call(svc::BREAK, &[1, argument, 6]);
+        let svc_offset = (code.len() as u64 - 1) * 4;
+        let entry = guest.load(&code);
+        let thread = guest.spawn(entry, 0, 44, 0);
+        let id = guest.kernel.thread_from_handle(thread, None).unwrap();
+
+        assert_eq!(
+            guest.run(),
+            KernelExit::Crashed {
+                core: 0,
+                thread: id
+            }
+        );
+        let report = guest.kernel.debug.last_report.clone().unwrap();
+        assert_eq!(
+            report.cause,
+            Cause::Break {
+                reason: BreakReason::Assert,
+                argument: b"abort!".to_vec()
+            }
+        );
+        assert_eq!(report.threads.len(), 2);
+        let crashed = report.threads.iter().find(|t| t.id == id).unwrap();
+        assert_eq!(crashed.core, Some(0));
+        assert_eq!(crashed.context.pc, entry + svc_offset + 4);
+        assert_eq!(crashed.backtrace[0], crashed.context.pc);
+        assert!(report.memory.iter().any(|m| m.label == "PC"));
+
+        let text = report.to_string();
+        assert!(text.contains("svcBreak (Assert), 6 argument bytes"));
+        assert!(text.contains(&format!("main+{:#x}", entry + svc_offset + 4 - base)));
+        let written = std::fs::read_to_string(dir.join(report.file_name())).unwrap();
+        assert_eq!(written, text);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let kernel = &guest.kernel;
+        assert!(
+            kernel
+                .scheduler
+                .threads()
+                .all(|t| t.state == ThreadState::Terminated),
+            "the whole process stopped"
+        );
+    }
+
+    #[test]
+    fn test_break_notification_continues() {
+        let mut guest = Guest::new();
+        // Reason 4 tells a debugger a module was loaded
+        let reason = BREAK_NOTIFICATION_ONLY as u64 | 4;
+        let mut code = call(svc::BREAK, &[reason, 0, 0]);
+        code.extend(store(0, guest.slot(1)));
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&code);
+        guest.spawn(entry, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+        assert!(guest.kernel.debug.last_report.is_none());
+    }
+
+    #[test]
+    fn test_exception_report_walks_frame_pointers() {
+        let mut guest = Guest::new();
+        add_main_module(&mut guest);
+        // Two frame records on the data area: {previous FP, return address}
+        let (outer, inner) = (guest.slot(0x40), guest.slot(0x20));
+        let memory = guest.kernel.cpu.memory();
+        memory.write_u64(inner, outer);
+        memory.write_u64(inner + 8, 0x1111);
+        memory.write_u64(outer, 0);
+        memory.write_u64(outer + 8, 0x2222);
+
+        let mut code = arm64::mov_imm64(29, inner).to_vec();
+        code.extend(arm64::mov_imm64(30, 0x1111));
+        code.push(arm64::brk(3));
+        let entry = guest.load(&code);
+        guest.spawn(entry, 0, 44, 0);
+
+        assert!(matches!(
+            guest.run(),
+            KernelExit::Halted {
+                reason: HaltReason::Breakpoint(3),
+                ..
+            }
+        ));
+        let report = guest.kernel.debug.last_report.as_ref().unwrap();
+        assert_eq!(report.cause, Cause::Exception(HaltReason::Breakpoint(3)));
+        let pc = entry + (code.len() as u64 - 1) * 4;
+        assert_eq!(report.threads[0].backtrace, [pc, 0x1111, 0x2222]);
+        assert!(report.to_string().contains("#2  0x0000000000002222  ?"));
+    }
+
+    #[test]
+    fn test_return_from_exception_without_exception() {
+        let mut guest = Guest::new();
+        let code = call(
+            svc::RETURN_FROM_EXCEPTION,
+            &[result::INVALID_STATE.0 as u64],
+        );
+        let entry = guest.load(&code);
+        let thread = guest.spawn(entry, 0, 44, 0);
+        let id = guest.kernel.thread_from_handle(thread, None).unwrap();
+
+        assert_eq!(
+            guest.run(),
+            KernelExit::Crashed {
+                core: 0,
+                thread: id
+            }
+        );
+        let report = guest.kernel.debug.last_report.as_ref().unwrap();
+        assert_eq!(
+            report.cause,
+            Cause::ReturnFromException(result::INVALID_STATE)
+        );
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+    }
+
+    #[test]
+    fn test_output_debug_string_logs_under_process_target() {
+        capture_logs();
+        let mut guest = Guest::new();
+        guest.kernel.process.program_id = 0x0100_0000_0000_D00D;
+        let text = guest.slot(8);
+        guest
+            .kernel
+            .cpu
+            .memory()
+            .write(text, b"Hello from the guest\n");
+
+        let mut code = call(svc::OUTPUT_DEBUG_STRING, &[text, 21]);
+        code.extend(store(0, guest.slot(1)));
+        code.extend(call(svc::OUTPUT_DEBUG_STRING, &[0, 8]));
+        code.extend(store(0, guest.slot(2)));
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&code);
+        guest.spawn(entry, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+        assert_eq!(guest.result(2), result::INVALID_CURRENT_MEMORY);
+        let records = RECORDS.lock().unwrap();
+        let lines: Vec<_> = records
+            .iter()
+            .filter(|(target, _)| target == "guest::010000000000d00d")
+            .map(|(_, message)| message.as_str())
+            .collect();
+        assert_eq!(lines, ["Hello from the guest"]);
+    }
+}
@@ -0,0 +1,158 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::handle::{CURRENT_PROCESS, CURRENT_THREAD};
+    use crate::kernel::info::{self, ns_to_ticks};
+    use crate::kernel::result::{self, ResultCode};
+    use crate::kernel::{KernelExit, SLICE_NS, svc};
+    use crate::tests::guest::{Guest, call, sleep, store, svc};
+
+    #[test]
+    fn test_process_info_follows_layout() {
+        let mut guest = Guest::new();
+        guest.kernel.process.program_id = 0x0100_0000_0000_1000;
+        let kernel = &guest.kernel;
+        let layout = &kernel.process.address_space.layout;
+        let get = |kind| kernel.get_info(kind, CURRENT_PROCESS, 0, 0).unwrap();
+
+        assert_eq!(get(info::ALIAS_REGION_ADDRESS), layout.alias.base);
+        assert_eq!(get(info::ALIAS_REGION_SIZE), layout.alias.size);
+        assert_eq!(get(info::HEAP_REGION_ADDRESS), layout.heap.base);
+        assert_eq!(get(info::HEAP_REGION_SIZE), layout.heap.size);
+        assert_eq!(get(info::STACK_REGION_ADDRESS), layout.stack.base);
+        assert_eq!(get(info::STACK_REGION_SIZE), layout.stack.size);
+        assert_eq!(get(info::ASLR_REGION_ADDRESS), layout.address_space.base);
+        assert_eq!(get(info::ASLR_REGION_SIZE), layout.address_space.size);
+        assert_eq!(get(info::PROGRAM_ID), 0x0100_0000_0000_1000);
+        assert_eq!(get(info::IS_APPLICATION), 1);
+        assert_eq!(get(info::TOTAL_MEMORY_SIZE), layout.address_space.size);
+        assert_ne!(get(info::USER_EXCEPTION_CONTEXT_ADDRESS), 0);
+        // Without an NPDM the process has no limit to count threads against
+        assert_eq!(get(info::FREE_THREAD_COUNT), 0);
+
+        assert_eq!(
+            kernel.get_info(info::HEAP_REGION_SIZE, CURRENT_THREAD, 0, 0),
+            Err(result::INVALID_HANDLE)
+        );
+        assert_eq!(
+            kernel.get_info(info::HEAP_REGION_SIZE, CURRENT_PROCESS, 1, 0),
+            Err(result::INVALID_COMBINATION)
+        );
+        assert_eq!(
+            kernel.get_info(19, CURRENT_PROCESS, 0, 0),
+            Err(result::INVALID_ENUM_VALUE)
+        );
+    }
+
+    #[test]
+    fn test_used_memory_tracks_heap() {
+        let mut guest = Guest::new();
+        let used = |guest: &Guest| {
+            let kernel = &guest.kernel;
+            kernel
+                .get_info(info::USED_MEMORY_SIZE, CURRENT_PROCESS, 0, 0)
+                .unwrap()
+        };
+        let before = used(&guest);
+        assert!(before >= 0x20_0000, "the harness heap is counted");
+        guest
+            .kernel
+            .process
+            .address_space
+            .set_heap_size(0x40_0000)
+            .unwrap();
+        assert_eq!(used(&guest), before + 0x20_0000);
+        assert_eq!(
+            guest.kernel.get_system_info(
+                info::SYSTEM_USED_PHYSICAL_MEMORY_SIZE,
+                0,
+                info::POOL_APPLICATION
+            ),
+            Ok(before + 0x20_0000)
+        );
+    }
+
+    #[test]
+    fn test_random_entropy() {
+        let guest = Guest::new();
+        let kernel = &guest.kernel;
+        let entropy: Vec<_> = (0..4)
+            .map(|i| kernel.get_info(info::RANDOM_ENTROPY, 0, i, 0).unwrap())
+            .collect();
+        assert_eq!(entropy, kernel.process.random_entropy);
+        assert_eq!(
+            kernel.get_info(info::RANDOM_ENTROPY, 0, 4, 0),
+            Err(result::INVALID_COMBINATION)
+        );
+        assert_eq!(
+            kernel.get_info(info::RANDOM_ENTROPY, CURRENT_PROCESS, 0, 0),
+            Err(result::INVALID_HANDLE)
+        );
+    }
+
+    #[test]
+    fn test_tick_counts() {
+        let mut guest = Guest::new();
+        let mut code = sleep(1_000_000);
+        code.extend(call(
+            svc::GET_INFO,
+            &[
+                0,
+                info::THREAD_TICK_COUNT as u64,
+                CURRENT_THREAD as u64,
+                u64::MAX,
+            ],
+        ));
+        code.extend(store(0, guest.slot(1)));
+        code.extend(store(1, guest.slot(2)));
+        code.extend(call(
+            svc::GET_INFO,
+            &[0, info::IDLE_TICK_COUNT as u64, 0, 0],
+        ));
+        code.extend(store(0, guest.slot(3)));
+        code.extend(store(1, guest.slot(4)));
+        code.extend(call(svc::GET_INFO, &[0, 27, 0, 0]));
+        code.extend(store(0, guest.slot(5)));
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&code);
+        guest.spawn(entry, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+        assert!(guest.read(2) > 0, "the thread ran for some time");
+        assert_eq!(guest.result(3), ResultCode::SUCCESS);
+        assert!(
+            guest.read(4) >= ns_to_ticks(1_000_000 - SLICE_NS),
+            "core 0 idled while the thread slept, less the slice it went to sleep in"
+        );
+        assert_eq!(guest.result(5), result::INVALID_ENUM_VALUE);
+    }
+
+    #[test]
+    fn test_system_info() {
+        let guest = Guest::new();
+        let kernel = &guest.kernel;
+        let total = info::SYSTEM_TOTAL_PHYSICAL_MEMORY_SIZE;
+        assert_eq!(
+            kernel.get_system_info(total, 0, info::POOL_APPLICATION),
+            Ok(kernel.process.total_memory_size())
+        );
+        assert_eq!(
+            kernel.get_system_info(total, 0, info::POOL_SYSTEM_UNSAFE),
+            Ok(0)
+        );
+        assert_eq!(
+            kernel.get_system_info(total, 0, info::POOL_SYSTEM_UNSAFE + 1),
+            Err(result::INVALID_COMBINATION)
+        );
+        assert_eq!(
+            kernel.get_system_info(total, CURRENT_PROCESS, 0),
+            Err(result::INVALID_HANDLE)
+        );
+
+        let range = info::SYSTEM_INITIAL_PROCESS_ID_RANGE;
+        let first = kernel.get_system_info(range, 0, 0).unwrap();
+        let last = kernel.get_system_info(range, 0, 1).unwrap();
+        assert!((first..=last).contains(&1));
+        assert!(!(first..=last).contains(&kernel.process.id));
+    }
+}
@@ -0,0 +1,411 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::handle::{Handle, KernelObject};
+    use crate::kernel::hipc::{MESSAGE_BUFFER_SIZE, Message, ReceiveListEntry, StaticDescriptor};
+    use crate::kernel::ipc::SessionHandler;
+    use crate::kernel::process::APPLICATION_PROCESS_ID;
+    use crate::kernel::result::{self, ResultCode};
+    use crate::kernel::{Kernel, KernelExit, svc};
+    use crate::tests::arm64;
+    use crate::tests::guest::{Guest, call, cmif_request, cmif_result, sleep, store, svc};
+
+    const FOREVER: u64 = u64::MAX;
+
+    fn tls(guest: &Guest, thread: Handle) -> u64 {
+        let id = guest.kernel.thread_from_handle(thread, None).unwrap();
+        guest.kernel.scheduler.thread(id).unwrap().tls_address
+    }
+
+    fn write_message(guest: &Guest, addr: u64, message: &Message) {
+        let mut bytes = vec![0; MESSAGE_BUFFER_SIZE];
+        message.write(&mut bytes).unwrap();
+        guest.kernel.cpu.memory().write(addr, &bytes);
+    }
+
+    fn read_message(guest: &Guest, addr: u64) -> Message {
+        let bytes = guest
+            .kernel
+            .cpu
+            .memory()
+            .read_vec(addr, MESSAGE_BUFFER_SIZE)
+            .unwrap();
+        Message::parse(&bytes).unwrap()
+    }
+
+    fn write_str(guest: &Guest, addr: u64, text: &[u8]) {
+        guest.kernel.cpu.memory().write(addr, text);
+    }
+
+    #[test]
+    fn test_connect_to_sm_and_send_requests() {
+        let mut guest = Guest::new();
+        write_str(&guest, guest.slot(10), b"sm:\0");
+        let user_buffer = guest.slot(0x200);
+        let name = u64::from_le_bytes(*b"missing\0");
+        let get_service = cmif_request(1, false, &[name as u32, (name >> 32) as u32]);
+        write_message(&guest, user_buffer, &get_service);
+
+        let mut main = call(svc::CONNECT_TO_NAMED_PORT, &[0, guest.slot(10)]);
+        main.extend(store(0, guest.slot(1)));
+        main.push(arm64::mov_reg(19, 1));
+        main.push(arm64::mov_reg(0, 19));
+        main.push(svc(svc::SEND_SYNC_REQUEST));
+        main.extend(store(0, guest.slot(2)));
+        main.extend(arm64::mov_imm64(0, user_buffer));
+        main.extend(arm64::mov_imm64(1, 0x1000));
+        main.push(arm64::mov_reg(2, 19));
+        main.push(svc(svc::SEND_SYNC_REQUEST_WITH_USER_BUFFER));
+        main.extend(store(0, guest.slot(3)));
+        main.push(arm64::mov_reg(0, 19));
+        main.push(svc(svc::CLOSE_HANDLE));
+        main.push(svc(svc::EXIT_THREAD));
+        let main = guest.load(&main);
+        let main = guest.spawn(main, 0, 44, 0);
+        write_message(&guest, tls(&guest, main), &cmif_request(0, true, &[0, 0]));
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS, "ConnectToNamedPort");
+        assert_eq!(guest.result(2), ResultCode::SUCCESS);
+        assert_eq!(guest.result(3), ResultCode::SUCCESS);
+
+        let reply = read_message(&guest, tls(&guest, main));
+        assert_eq!(reply.kind, 0);
+        assert_eq!(cmif_result(&reply), ResultCode::SUCCESS, "RegisterClient");
+        let reply = read_message(&guest, user_buffer);
+        assert_eq!(
+            cmif_result(&reply),
+            ResultCode::new(21, 7),
+            "GetServiceHandle for a service nobody registered"
+        );
+        assert_eq!(
+            guest.kernel.ipc.session_count(),
+            0,
+            "closed with its handle"
+        );
+    }
+
+    #[test]
+    fn test_ipc_svc_argument_errors() {
+        let mut guest = Guest::new();
+        write_str(&guest, guest.slot(10), b"unknown\0");
+        write_str(&guest, guest.slot(12), b"abcdefghijkl");
+
+        let mut main = Vec::new();
+        for (op, args, out) in [
+            (svc::CONNECT_TO_NAMED_PORT, [0, guest.slot(10), 0], 1),
+            (svc::CONNECT_TO_NAMED_PORT, [0, guest.slot(12), 0], 2),
+            (svc::CONNECT_TO_NAMED_PORT, [0, 0x100, 0], 3),
+            (svc::SEND_SYNC_REQUEST, [0x1234, 0, 0], 4),
+            (
+                svc::SEND_SYNC_REQUEST_WITH_USER_BUFFER,
+                [guest.slot(1), 0x1000, 0],
+                5,
+            ),
+            (
+                svc::SEND_SYNC_REQUEST_WITH_USER_BUFFER,
+                [guest.slot(0x200), 0x10, 0],
+                6,
+            ),
+            (svc::CREATE_SESSION, [0, 0, 1], 7),
+        ] {
+            main.extend(call(op, &args));
+            main.extend(store(0, guest.slot(out)));
+        }
+        main.push(svc(svc::EXIT_THREAD));
+        let main = guest.load(&main);
+        guest.spawn(main, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), result::NOT_FOUND);
+        assert_eq!(guest.result(2), result::OUT_OF_RANGE, "name too long");
+        assert_eq!(guest.result(3), result::INVALID_POINTER);
+        assert_eq!(guest.result(4), result::INVALID_HANDLE);
+        assert_eq!(guest.result(5), result::INVALID_ADDRESS);
+        assert_eq!(guest.result(6), result::INVALID_SIZE);
+        assert_eq!(guest.result(7), result::NOT_IMPLEMENTED, "light session");
+    }
+
+    /// Replies with a summary of the request and a new event, keeping the
+    /// handle it is moved
+    struct Inspector;
+
+    impl SessionHandler for Inspector {
+        fn handle_request(
+            &mut self,
+            kernel: &mut Kernel,
+            request: &Message,
+        ) -> Result<Message, ResultCode> {
+            if request.data.is_empty() {
+                return Err(result::INVALID_STATE);
+            }
+            let (writable, readable) = kernel.create_event()?;
+            kernel.signal_event(writable)?;
+            kernel.close_handle(writable)?;
+            Ok(Message {
+                copy_handles: vec![readable],
+                data: vec![
+                    request.pid.unwrap_or(0) as u32,
+                    request.copy_handles.len() as u32,
+                    request.x_buffers.len() as u32,
+                    request.data.iter().sum(),
+                    match request.move_handles[..] {
+                        [moved] => kernel.take_moved_handle(moved)?,
+                        _ => 0,
+                    },
+                ],
+                ..Default::default()
+            })
+        }
+    }
+
+    #[test]
+    fn test_host_session_handler_receives_request() {
+        let mut guest = Guest::new();
+        guest
+            .kernel
+            .register_named_port("inspect", || Box::new(Inspector));
+        write_str(&guest, guest.slot(10), b"inspect\0");
+        let (_, readable) = guest.kernel.create_event().unwrap();
+        let (_, moved) = guest.kernel.create_event().unwrap();
+        let user_buffer = guest.slot(0x200);
+        write_message(&guest, user_buffer, &Message::default());
+
+        let mut main = call(svc::CONNECT_TO_NAMED_PORT, &[0, guest.slot(10)]);
+        main.push(arm64::mov_reg(19, 1));
+        main.push(arm64::mov_reg(0, 19));
+        main.push(svc(svc::SEND_SYNC_REQUEST));
+        main.extend(store(0, guest.slot(1)));
+        main.extend(arm64::mov_imm64(0, user_buffer));
+        main.extend(arm64::mov_imm64(1, 0x1000));
+        main.push(arm64::mov_reg(2, 19));
+        main.push(svc(svc::SEND_SYNC_REQUEST_WITH_USER_BUFFER));
+        main.extend(store(0, guest.slot(2)));
+        main.push(svc(svc::EXIT_THREAD));
+        let main = guest.load(&main);
+        let main = guest.spawn(main, 0, 44, 0);
+        let request = Message {
+            kind: 0x10,
+            pid: Some(0),
+            copy_handles: vec![readable],
+            move_handles: vec![moved],
+            x_buffers: vec![StaticDescriptor::default()],
+            data: vec![1, 2, 3],
+            ..Default::default()
+        };
+        write_message(&guest, tls(&guest, main), &request);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+        assert_eq!(guest.result(2), result::INVALID_STATE, "handler error");
+
+        let reply = read_message(&guest, tls(&guest, main));
+        assert_eq!(reply.data[..4], [APPLICATION_PROCESS_ID as u32, 1, 1, 6]);
+        let handles = &guest.kernel.process.handles;
+        assert_eq!(handles.get(moved), None, "moved away from the client");
+        assert!(matches!(
+            handles.get(reply.data[4]),
+            Some(KernelObject::ReadableEvent(_))
+        ));
+        let event = guest.kernel.process.handles.get(reply.copy_handles[0]);
+        let Some(KernelObject::ReadableEvent(id)) = event else {
+            panic!("expected an event handle, got {event:?}");
+        };
+        assert!(guest.kernel.process.events[&id].signaled);
+    }
+
+    #[test]
+    fn test_guest_server_reply_and_receive() {
+        // Once with the server already waiting, once with the request
+        // queued before the server asks for it
+        for client_first in [false, true] {
+            let mut guest = Guest::new();
+            let (server, client) = guest.kernel.create_session().unwrap();
+            let (_, readable) = guest.kernel.create_event().unwrap();
+            guest.kernel.cpu.memory().write_u32(guest.slot(16), server);
+            write_str(&guest, guest.slot(30), b"payload!");
+
+            let (client_delay, server_delay) = if client_first {
+                (0, 100_000)
+            } else {
+                (100_000, 0)
+            };
+
+            let mut server_code = sleep(server_delay);
+            server_code.extend(call(
+                svc::REPLY_AND_RECEIVE,
+                &[0, guest.slot(16), 1, 0, FOREVER],
+            ));
+            server_code.extend(store(0, guest.slot(2)));
+            server_code.extend(store(1, guest.slot(3)));
+            server_code.push(arm64::mrs_tpidrro_el0(20));
+            // Received layout: header, special header, PID, one handle,
+            // one X descriptor, then the data word at 0x20
+            for (offset, out) in [(0x14, 4), (0x0C, 5), (0x1C, 6)] {
+                server_code.push(arm64::ldr_w_imm(21, 20, offset));
+                server_code.extend(store(21, guest.slot(out)));
+            }
+            server_code.push(arm64::ldr_w_imm(21, 20, 0x20));
+            server_code.push(arm64::add_imm(21, 21, 1));
+            server_code.push(arm64::movz(22, 0, 0));
+            server_code.push(arm64::str_w_imm(22, 20, 0));
+            server_code.push(arm64::movz(22, 1, 0));
+            server_code.push(arm64::str_w_imm(22, 20, 4));
+            server_code.push(arm64::str_w_imm(21, 20, 8));
+            server_code.extend(call(
+                svc::REPLY_AND_RECEIVE,
+                &[0, guest.slot(16), 0, server as u64, 0],
+            ));
+            server_code.extend(store(0, guest.slot(7)));
+            server_code.extend(call(
+                svc::REPLY_AND_RECEIVE,
+                &[0, guest.slot(16), 1, 0, FOREVER],
+            ));
+            server_code.extend(store(0, guest.slot(8)));
+            server_code.extend(store(1, guest.slot(9)));
+            server_code.push(svc(svc::EXIT_THREAD));
+            let server_code = guest.load(&server_code);
+            let server_thread = guest.spawn(server_code, 0, 44, 1);
+
+            let mut client_code = sleep(client_delay);
+            client_code.extend(call(svc::SEND_SYNC_REQUEST, &[client as u64]));
+            client_code.extend(store(0, guest.slot(1)));
+            client_code.extend(call(svc::CLOSE_HANDLE, &[client as u64]));
+            client_code.push(svc(svc::EXIT_THREAD));
+            let client_code = guest.load(&client_code);
+            let client_thread = guest.spawn(client_code, 0, 44, 0);
+
+            let receive_list = Message {
+                c_buffers: vec![ReceiveListEntry {
+                    address: guest.slot(40),
+                    size: 0x40,
+                }],
+                ..Default::default()
+            };
+            write_message(&guest, tls(&guest, server_thread), &receive_list);
+            let request = Message {
+                kind: 4,
+                pid: Some(0),
+                copy_handles: vec![readable],
+                x_buffers: vec![StaticDescriptor {
+                    index: 0,
+                    address: guest.slot(30),
+                    size: 8,
+                }],
+                data: vec![0x1234],
+                ..Default::default()
+            };
+            write_message(&guest, tls(&guest, client_thread), &request);
+
+            assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+            assert_eq!(guest.result(1), ResultCode::SUCCESS, "SendSyncRequest");
+            assert_eq!(guest.result(2), ResultCode::SUCCESS, "receive");
+            assert_eq!(guest.read(3), 0, "index of the server session");
+
+            let copied = guest.read(4) as Handle;
+            assert_ne!(copied, readable, "receiver gets its own handle");
+            assert_eq!(
+                guest.kernel.process.handles.get(copied),
+                guest.kernel.process.handles.get(readable)
+            );
+            assert_eq!(guest.read(5), APPLICATION_PROCESS_ID, "PID filled in");
+            assert_eq!(guest.read(6), guest.slot(40) & 0xFFFF_FFFF, "X moved to C");
+            let memory = guest.kernel.cpu.memory();
+            assert_eq!(memory.read_vec(guest.slot(40), 8).unwrap(), b"payload!");
+
+            let reply = read_message(&guest, tls(&guest, client_thread));
+            assert_eq!(reply.data, [0x1235]);
+            assert_eq!(guest.result(7), result::TIMED_OUT, "reply only");
+            assert_eq!(guest.result(8), result::SESSION_CLOSED);
+            assert_eq!(guest.read(9), 0);
+        }
+    }
+
+    #[test]
+    fn test_large_statics_to_separate_c_buffers() {
+        let mut guest = Guest::new();
+        let (server, client) = guest.kernel.create_session().unwrap();
+        guest.kernel.cpu.memory().write_u32(guest.slot(16), server);
+        let x_buffers = [guest.slot(0x8000), guest.slot(0x9000)];
+        let c_buffers = [guest.slot(0xA000), guest.slot(0xB000)];
+        for (i, &x) in x_buffers.iter().enumerate() {
+            guest.kernel.cpu.memory().write(x, &[i as u8 + 1; 0x8000]);
+        }
+
+        let mut server_code = call(svc::REPLY_AND_RECEIVE, &[0, guest.slot(16), 1, 0, FOREVER]);
+        server_code.extend(store(0, guest.slot(1)));
+        server_code.extend(call(svc::CLOSE_HANDLE, &[server as u64]));
+        server_code.push(svc(svc::EXIT_THREAD));
+        let server_code = guest.load(&server_code);
+        let server_thread = guest.spawn(server_code, 0, 44, 1);
+
+        let mut client_code = call(svc::SEND_SYNC_REQUEST, &[client as u64]);
+        client_code.extend(store(0, guest.slot(2)));
+        client_code.push(svc(svc::EXIT_THREAD));
+        let client_code = guest.load(&client_code);
+        let client_thread = guest.spawn(client_code, 0, 44, 0);
+
+        let receive_list = Message {
+            c_buffers: c_buffers
+                .map(|address| ReceiveListEntry {
+                    address,
+                    size: 0x8000,
+                })
+                .to_vec(),
+            ..Default::default()
+        };
+        write_message(&guest, tls(&guest, server_thread), &receive_list);
+        let request = Message {
+            kind: 4,
+            x_buffers: (0..2)
+                .map(|i| StaticDescriptor {
+                    index: i as u8,
+                    address: x_buffers[i],
+                    size: 0x8000,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        write_message(&guest, tls(&guest, client_thread), &request);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS, "receive");
+        assert_eq!(guest.result(2), result::SESSION_CLOSED);
+        let memory = guest.kernel.cpu.memory();
+        for (i, &c) in c_buffers.iter().enumerate() {
+            assert_eq!(memory.read_vec(c, 0x8000).unwrap(), [i as u8 + 1; 0x8000]);
+        }
+    }
+
+    #[test]
+    fn test_closing_server_fails_waiting_client() {
+        let mut guest = Guest::new();
+        let (server, client) = guest.kernel.create_session().unwrap();
+
+        let mut client_code = call(svc::SEND_SYNC_REQUEST, &[client as u64]);
+        client_code.extend(store(0, guest.slot(1)));
+        client_code.extend(call(svc::SEND_SYNC_REQUEST, &[client as u64]));
+        client_code.extend(store(0, guest.slot(2)));
+        client_code.push(svc(svc::EXIT_THREAD));
+        let client_code = guest.load(&client_code);
+        let client_thread = guest.spawn(client_code, 0, 44, 0);
+        write_message(&guest, tls(&guest, client_thread), &Message::default());
+
+        let mut closer = sleep(100_000);
+        closer.extend(call(svc::CLOSE_HANDLE, &[server as u64]));
+        closer.push(svc(svc::EXIT_THREAD));
+        let closer = guest.load(&closer);
+        guest.spawn(closer, 0, 44, 1);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(
+            guest.result(1),
+            result::SESSION_CLOSED,
+            "woken by the close"
+        );
+        assert_eq!(
+            guest.result(2),
+            result::SESSION_CLOSED,
+            "sent after the close"
+        );
+    }
+}
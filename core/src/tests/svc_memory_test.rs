@@ -0,0 +1,332 @@
+#[cfg(test)]
+mod tests {
+    use crate::cpu::HaltReason;
+    use crate::kernel::Kernel;
+    use crate::kernel::memory::{MemoryInfo, MemoryPermission, MemoryState};
+    use crate::kernel::result::{self, ResultCode};
+    use crate::kernel::svc;
+    use crate::tests::arm64;
+
+    /// Load `program` (followed by a BRK) into the code region and run it on core 0
+    fn run_guest(kernel: &mut Kernel, program: &[u32]) {
+        let code = kernel.process.address_space.layout.code.base;
+        let memory = kernel.cpu.memory();
+        kernel
+            .process
+            .address_space
+            .map(
+                code,
+                0x1000,
+                MemoryState::Code,
+                MemoryPermission::READ_EXECUTE,
+            )
+            .expect("code region should be free");
+
+        for (i, instr) in program.iter().chain([arm64::brk(0)].iter()).enumerate() {
+            memory.write_u32(code + i as u64 * 4, *instr);
+        }
+
+        let core = kernel.cpu.get_core(0).unwrap().clone();
+        core.set_pc(code);
+        let halt = kernel.run_core(0);
+        assert!(
+            matches!(halt, HaltReason::Breakpoint(0)),
+            "guest stopped with {halt:?}"
+        );
+    }
+
+    fn x(kernel: &Kernel, reg: u32) -> u64 {
+        kernel.cpu.get_core(0).unwrap().get_x(reg)
+    }
+
+    fn result_of(kernel: &Kernel) -> ResultCode {
+        ResultCode(x(kernel, 0) as u32)
+    }
+
+    fn read_memory_info(kernel: &Kernel, addr: u64) -> (u64, u64, u32, u32, u32) {
+        let bytes = kernel
+            .cpu
+            .memory()
+            .read_vec(addr, MemoryInfo::SIZE)
+            .unwrap();
+        let u64_at = |o: usize| u64::from_le_bytes(bytes[o..o + 8].try_into().unwrap());
+        let u32_at = |o: usize| u32::from_le_bytes(bytes[o..o + 4].try_into().unwrap());
+        (
+            u64_at(0x00),
+            u64_at(0x08),
+            u32_at(0x10),
+            u32_at(0x14),
+            u32_at(0x18),
+        )
+    }
+
+    #[test]
+    fn test_set_heap_size_grows_zeroed_heap() {
+        let mut kernel = Kernel::new();
+        let heap = kernel.process.address_space.layout.heap.base;
+        // Stale data left over in RAM must not leak into the new heap
+        kernel.cpu.memory().fill(heap, 0x40_0000, 0xAA);
+
+        let mut program = Vec::new();
+        program.extend(arm64::mov_imm64(1, 0x40_0000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        run_guest(&mut kernel, &program);
+
+        assert_eq!(result_of(&kernel), ResultCode::SUCCESS);
+        assert_eq!(x(&kernel, 1), heap);
+        let memory = kernel.cpu.memory();
+        assert_eq!(memory.read_u64(heap), Some(0));
+        assert_eq!(memory.read_u64(heap + 0x3F_FFF8), Some(0));
+        assert_eq!(kernel.process.address_space.heap_size(), 0x40_0000);
+    }
+
+    #[test]
+    fn test_set_heap_size_shrink_discards_pages() {
+        let mut kernel = Kernel::new();
+        let heap = kernel.process.address_space.layout.heap.base;
+
+        let mut program = Vec::new();
+        program.extend(arm64::mov_imm64(1, 0x40_0000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        // Dirty a page in the upper half, shrink past it, then grow again
+        program.extend(arm64::mov_imm64(20, heap + 0x30_0000));
+        program.extend(arm64::mov_imm64(2, 0xDEAD_BEEF));
+        program.push(arm64::str_imm(2, 20, 0));
+        program.extend(arm64::mov_imm64(1, 0x20_0000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        program.extend(arm64::mov_imm64(1, 0x40_0000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        program.push(arm64::ldr_imm(3, 20, 0));
+        run_guest(&mut kernel, &program);
+
+        assert_eq!(result_of(&kernel), ResultCode::SUCCESS);
+        assert_eq!(x(&kernel, 3), 0);
+    }
+
+    #[test]
+    fn test_set_heap_size_rejects_bad_sizes() {
+        let mut kernel = Kernel::new();
+        let too_big = kernel.process.address_space.layout.heap.size + 0x20_0000;
+
+        let mut program = Vec::new();
+        program.extend(arm64::mov_imm64(1, 0x1000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        program.push(arm64::mov_reg(19, 0));
+        program.extend(arm64::mov_imm64(1, too_big));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        run_guest(&mut kernel, &program);
+
+        assert_eq!(ResultCode(x(&kernel, 19) as u32), result::INVALID_SIZE);
+        assert_eq!(result_of(&kernel), result::OUT_OF_MEMORY);
+    }
+
+    #[test]
+    fn test_query_memory_writes_memory_info() {
+        let mut kernel = Kernel::new();
+        let heap = kernel.process.address_space.layout.heap.base;
+        let out = heap + 0x1000;
+
+        let mut program = Vec::new();
+        program.extend(arm64::mov_imm64(1, 0x20_0000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        program.extend(arm64::mov_imm64(0, out));
+        program.extend(arm64::mov_imm64(2, heap + 0x1234));
+        program.push(arm64::svc(svc::QUERY_MEMORY as u16));
+        run_guest(&mut kernel, &program);
+
+        assert_eq!(result_of(&kernel), ResultCode::SUCCESS);
+        assert_eq!(x(&kernel, 1), 0, "page info");
+        let (base, size, state, attr, perm) = read_memory_info(&kernel, out);
+        assert_eq!(base, heap);
+        assert_eq!(size, 0x20_0000);
+        assert_eq!(state, MemoryState::Normal as u32);
+        assert_eq!(attr, 0);
+        assert_eq!(perm, MemoryPermission::READ_WRITE.0);
+    }
+
+    #[test]
+    fn test_query_memory_outside_address_space() {
+        let mut kernel = Kernel::new();
+        let heap = kernel.process.address_space.layout.heap.base;
+        let end = kernel.process.address_space.layout.address_space.end();
+
+        let mut program = Vec::new();
+        program.extend(arm64::mov_imm64(1, 0x20_0000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        program.extend(arm64::mov_imm64(0, heap));
+        program.extend(arm64::mov_imm64(2, u64::MAX - 0xFFF));
+        program.push(arm64::svc(svc::QUERY_MEMORY as u16));
+        program.push(arm64::mov_reg(19, 0));
+        program.extend(arm64::mov_imm64(0, 0x10));
+        program.push(arm64::svc(svc::QUERY_MEMORY as u16));
+        run_guest(&mut kernel, &program);
+
+        assert_eq!(ResultCode(x(&kernel, 19) as u32), ResultCode::SUCCESS);
+        let (base, size, state, _, perm) = read_memory_info(&kernel, heap);
+        assert_eq!(base, end);
+        assert_eq!(size, end.wrapping_neg());
+        assert_eq!(state, MemoryState::Inaccessible as u32);
+        assert_eq!(perm, 0);
+        // 0x10 is below the address space and cannot hold the output
+        assert_eq!(result_of(&kernel), result::INVALID_POINTER);
+    }
+
+    #[test]
+    fn test_map_memory_mirrors_into_stack_region() {
+        let mut kernel = Kernel::new();
+        let heap = kernel.process.address_space.layout.heap.base;
+        let stack = kernel.process.address_space.layout.stack.base;
+        let info_out = heap + 0x10_0000;
+
+        let mut program = Vec::new();
+        program.extend(arm64::mov_imm64(1, 0x20_0000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        program.extend(arm64::mov_imm64(19, heap));
+        program.extend(arm64::mov_imm64(20, stack));
+        program.extend(arm64::mov_imm64(2, 0x1111));
+        program.push(arm64::str_imm(2, 19, 0));
+        // MapMemory(stack, heap, 0x2000)
+        program.push(arm64::mov_reg(0, 20));
+        program.push(arm64::mov_reg(1, 19));
+        program.extend(arm64::mov_imm64(2, 0x2000));
+        program.push(arm64::svc(svc::MAP_MEMORY as u16));
+        program.push(arm64::mov_reg(21, 0));
+        // Read through the mirror, then write a new value through it
+        program.push(arm64::ldr_imm(22, 20, 0));
+        program.extend(arm64::mov_imm64(2, 0x2222));
+        program.push(arm64::str_imm(2, 20, 8));
+        // QueryMemory(heap) while mapped
+        program.extend(arm64::mov_imm64(0, info_out));
+        program.push(arm64::mov_reg(2, 19));
+        program.push(arm64::svc(svc::QUERY_MEMORY as u16));
+        // UnmapMemory(stack, heap, 0x2000)
+        program.push(arm64::mov_reg(0, 20));
+        program.push(arm64::mov_reg(1, 19));
+        program.extend(arm64::mov_imm64(2, 0x2000));
+        program.push(arm64::svc(svc::UNMAP_MEMORY as u16));
+        program.push(arm64::ldr_imm(23, 19, 8));
+        run_guest(&mut kernel, &program);
+
+        assert_eq!(ResultCode(x(&kernel, 21) as u32), ResultCode::SUCCESS);
+        assert_eq!(result_of(&kernel), ResultCode::SUCCESS);
+        assert_eq!(x(&kernel, 22), 0x1111, "mirror sees source contents");
+        assert_eq!(
+            x(&kernel, 23),
+            0x2222,
+            "source sees writes made through mirror"
+        );
+
+        let (base, size, state, attr, perm) = read_memory_info(&kernel, info_out);
+        assert_eq!((base, size), (heap, 0x2000));
+        assert_eq!(state, MemoryState::Normal as u32);
+        assert_eq!(attr, 1, "source is locked while mapped");
+        assert_eq!(perm, 0);
+
+        let space = &kernel.process.address_space;
+        assert_eq!(space.query(stack).state, MemoryState::Free);
+        assert_eq!(space.query(heap).size, 0x20_0000, "heap block merged back");
+    }
+
+    #[test]
+    fn test_map_memory_result_codes() {
+        let mut kernel = Kernel::new();
+        let heap = kernel.process.address_space.layout.heap.base;
+        let stack = kernel.process.address_space.layout.stack.base;
+
+        let map = |dst: u64, src: u64, size: u64, save: u8| {
+            let mut code = Vec::new();
+            code.extend(arm64::mov_imm64(0, dst));
+            code.extend(arm64::mov_imm64(1, src));
+            code.extend(arm64::mov_imm64(2, size));
+            code.push(arm64::svc(svc::MAP_MEMORY as u16));
+            code.push(arm64::mov_reg(save, 0));
+            code
+        };
+
+        let mut program = Vec::new();
+        program.extend(arm64::mov_imm64(1, 0x20_0000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        program.extend(map(stack + 0x10, heap, 0x1000, 19));
+        program.extend(map(stack, heap, 0x1001, 20));
+        program.extend(map(stack, heap, 0, 21));
+        program.extend(map(heap + 0x10_0000, heap, 0x1000, 22));
+        program.extend(map(stack, heap + 0x20_0000, 0x1000, 23));
+        program.extend(map(stack, heap, 0x1000, 24));
+        program.extend(map(stack, heap + 0x1000, 0x1000, 25));
+        run_guest(&mut kernel, &program);
+
+        let code = |reg| ResultCode(x(&kernel, reg) as u32);
+        assert_eq!(code(19), result::INVALID_ADDRESS);
+        assert_eq!(code(20), result::INVALID_SIZE);
+        assert_eq!(code(21), result::INVALID_SIZE);
+        assert_eq!(code(22), result::INVALID_MEMORY_REGION);
+        assert_eq!(
+            code(23),
+            result::INVALID_CURRENT_MEMORY,
+            "source not mapped"
+        );
+        assert_eq!(code(24), ResultCode::SUCCESS);
+        assert_eq!(
+            code(25),
+            result::INVALID_CURRENT_MEMORY,
+            "destination in use"
+        );
+    }
+
+    #[test]
+    fn test_unmap_memory_requires_existing_mapping() {
+        let mut kernel = Kernel::new();
+        let heap = kernel.process.address_space.layout.heap.base;
+        let stack = kernel.process.address_space.layout.stack.base;
+
+        let mut program = Vec::new();
+        program.extend(arm64::mov_imm64(1, 0x20_0000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        program.extend(arm64::mov_imm64(0, stack));
+        program.extend(arm64::mov_imm64(1, heap));
+        program.extend(arm64::mov_imm64(2, 0x1000));
+        program.push(arm64::svc(svc::UNMAP_MEMORY as u16));
+        run_guest(&mut kernel, &program);
+
+        assert_eq!(result_of(&kernel), result::INVALID_CURRENT_MEMORY);
+    }
+
+    #[test]
+    fn test_set_memory_attribute() {
+        let mut kernel = Kernel::new();
+        let heap = kernel.process.address_space.layout.heap.base;
+        let uncached = 1 << 3;
+
+        let set_attr = |addr: u64, mask: u64, attr: u64, save: u8| {
+            let mut code = Vec::new();
+            code.extend(arm64::mov_imm64(0, addr));
+            code.extend(arm64::mov_imm64(1, 0x1000));
+            code.extend(arm64::mov_imm64(2, mask));
+            code.extend(arm64::mov_imm64(3, attr));
+            code.push(arm64::svc(svc::SET_MEMORY_ATTRIBUTE as u16));
+            code.push(arm64::mov_reg(save, 0));
+            code
+        };
+
+        let mut program = Vec::new();
+        program.extend(arm64::mov_imm64(1, 0x20_0000));
+        program.push(arm64::svc(svc::SET_HEAP_SIZE as u16));
+        program.extend(set_attr(heap, uncached, uncached, 19));
+        program.extend(set_attr(heap, 0, uncached, 20));
+        program.extend(set_attr(heap, 1, 1, 21));
+        program.extend(set_attr(heap + 0x20_0000, uncached, uncached, 22));
+        program.extend(set_attr(heap + 0x10, uncached, uncached, 23));
+        run_guest(&mut kernel, &program);
+
+        let code = |reg| ResultCode(x(&kernel, reg) as u32);
+        assert_eq!(code(19), ResultCode::SUCCESS);
+        assert_eq!(code(20), result::INVALID_COMBINATION);
+        assert_eq!(code(21), result::INVALID_COMBINATION);
+        assert_eq!(code(22), result::INVALID_CURRENT_MEMORY);
+        assert_eq!(code(23), result::INVALID_ADDRESS);
+
+        let info = kernel.process.address_space.query(heap);
+        assert_eq!(info.attribute.0, uncached as u32);
+        assert_eq!(info.size, 0x1000);
+    }
+}
@@ -0,0 +1,263 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::handle::KernelObject;
+    use crate::kernel::memory::{MemoryAttribute, MemoryPermission, MemoryState};
+    use crate::kernel::result::{self, ResultCode};
+    use crate::kernel::shared_memory::PERMISSION_DONT_CARE;
+    use crate::kernel::{KernelExit, svc};
+    use crate::tests::arm64;
+    use crate::tests::guest::{Guest, call, load, store, svc};
+
+    const R: MemoryPermission = MemoryPermission::READ;
+    const RW: MemoryPermission = MemoryPermission::READ_WRITE;
+
+    /// Where tests map objects: the start of the alias region, which is free
+    fn target(guest: &Guest) -> u64 {
+        guest.kernel.process.address_space.layout.alias.base
+    }
+
+    #[test]
+    fn test_host_shared_memory_through_svcs() {
+        let mut guest = Guest::new();
+        let addr = target(&guest);
+        let shared = guest.kernel.create_host_shared_memory(0x2000, R).unwrap();
+        guest
+            .kernel
+            .write_memory_object(shared, 0x1008, &0x1234u64.to_le_bytes())
+            .unwrap();
+
+        let map = |permission: MemoryPermission| {
+            call(
+                svc::MAP_SHARED_MEMORY,
+                &[shared as u64, addr, 0x2000, permission.0 as u64],
+            )
+        };
+        let mut code = map(RW);
+        code.extend(store(0, guest.slot(1)));
+        code.extend(map(R));
+        code.extend(store(0, guest.slot(2)));
+        code.extend(load(0, addr + 0x1008));
+        code.extend(store(0, guest.slot(3)));
+        code.extend(map(R));
+        code.extend(store(0, guest.slot(4)));
+        code.extend(call(
+            svc::UNMAP_SHARED_MEMORY,
+            &[shared as u64, addr, 0x2000],
+        ));
+        code.extend(store(0, guest.slot(5)));
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&code);
+        guest.spawn(entry, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), result::INVALID_NEW_MEMORY_PERMISSION);
+        assert_eq!(guest.result(2), ResultCode::SUCCESS);
+        assert_eq!(guest.read(3), 0x1234, "the host's data is mapped in");
+        assert_eq!(guest.result(4), result::INVALID_STATE, "already mapped");
+        assert_eq!(guest.result(5), ResultCode::SUCCESS);
+        let info = guest.kernel.process.address_space.query(addr);
+        assert_eq!(info.state, MemoryState::Free);
+
+        let mut value = [0; 8];
+        guest
+            .kernel
+            .read_memory_object(shared, 0x1008, &mut value)
+            .unwrap();
+        assert_eq!(u64::from_le_bytes(value), 0x1234, "kept after unmapping");
+    }
+
+    #[test]
+    fn test_guest_shared_memory() {
+        let mut guest = Guest::new();
+        let addr = target(&guest);
+        let kernel = &mut guest.kernel;
+
+        assert_eq!(
+            kernel.create_shared_memory(0x1000, MemoryPermission::NONE, R),
+            Err(result::INVALID_NEW_MEMORY_PERMISSION)
+        );
+        assert_eq!(
+            kernel.create_shared_memory(0x1000, RW, MemoryPermission::EXECUTE),
+            Err(result::INVALID_NEW_MEMORY_PERMISSION)
+        );
+        assert_eq!(
+            kernel.create_shared_memory(0x1800, RW, R),
+            Err(result::INVALID_SIZE)
+        );
+        let shared = kernel
+            .create_shared_memory(0x1000, RW, PERMISSION_DONT_CARE)
+            .unwrap();
+
+        // The creator maps with its own permission
+        assert_eq!(
+            kernel.map_shared_memory(shared, addr, 0x1000, R),
+            Err(result::INVALID_NEW_MEMORY_PERMISSION)
+        );
+        assert_eq!(
+            kernel.map_shared_memory(shared, addr, 0x2000, RW),
+            Err(result::INVALID_SIZE)
+        );
+        kernel.map_shared_memory(shared, addr, 0x1000, RW).unwrap();
+        let info = kernel.process.address_space.query(addr);
+        assert_eq!((info.state, info.permission), (MemoryState::Shared, RW));
+
+        // Host writes land in the mapping while it exists
+        kernel.write_memory_object(shared, 0xFF8, &[7; 8]).unwrap();
+        assert_eq!(
+            kernel.cpu.memory().read_u64(addr + 0xFF8),
+            Some(0x0707_0707_0707_0707)
+        );
+        assert_eq!(
+            kernel.write_memory_object(shared, 0xFF9, &[7; 8]),
+            Err(result::OUT_OF_RANGE)
+        );
+
+        // Closing the handle leaves the mapping alone
+        let copy = kernel
+            .process
+            .handles
+            .add(KernelObject::SharedMemory(1))
+            .unwrap();
+        kernel.close_handle(shared).unwrap();
+        assert_eq!(kernel.process.shared_memory.len(), 1);
+        assert_eq!(
+            kernel.unmap_shared_memory(copy, addr + 0x1000, 0x1000),
+            Err(result::INVALID_CURRENT_MEMORY)
+        );
+        kernel.unmap_shared_memory(copy, addr, 0x1000).unwrap();
+        assert_eq!(kernel.cpu.memory().read_u64(addr + 0xFF8), Some(0));
+        kernel.close_handle(copy).unwrap();
+        assert!(kernel.process.shared_memory.is_empty());
+    }
+
+    #[test]
+    fn test_transfer_memory() {
+        let mut guest = Guest::new();
+        let addr = target(&guest);
+        let source = guest.slot(0x10000);
+        let kernel = &mut guest.kernel;
+        kernel.cpu.memory().write_u64(source + 8, 0xABCD);
+
+        assert_eq!(
+            kernel.create_transfer_memory(source, 0x2000, MemoryPermission::EXECUTE),
+            Err(result::INVALID_NEW_MEMORY_PERMISSION)
+        );
+        assert_eq!(
+            kernel.create_transfer_memory(addr, 0x2000, MemoryPermission::NONE),
+            Err(result::INVALID_CURRENT_MEMORY),
+            "only the creator's own memory can be lent"
+        );
+        let transfer = kernel
+            .create_transfer_memory(source, 0x2000, MemoryPermission::NONE)
+            .unwrap();
+        let info = kernel.process.address_space.query(source);
+        assert_eq!(info.permission, MemoryPermission::NONE);
+        assert_eq!(info.attribute, MemoryAttribute::LOCKED);
+        assert_eq!(
+            kernel.create_transfer_memory(source, 0x1000, MemoryPermission::NONE),
+            Err(result::INVALID_CURRENT_MEMORY),
+            "already lent"
+        );
+
+        // A host service reads the lent pages in place
+        let mut value = [0; 8];
+        kernel.read_memory_object(transfer, 8, &mut value).unwrap();
+        assert_eq!(u64::from_le_bytes(value), 0xABCD);
+
+        assert_eq!(
+            kernel.map_transfer_memory(transfer, addr, 0x2000, R),
+            Err(result::INVALID_STATE)
+        );
+        kernel
+            .map_transfer_memory(transfer, addr, 0x2000, MemoryPermission::NONE)
+            .unwrap();
+        let info = kernel.process.address_space.query(addr);
+        assert_eq!((info.state, info.permission), (MemoryState::Transfered, RW));
+        assert_eq!(kernel.cpu.memory().read_u64(addr + 8), Some(0xABCD));
+        kernel.write_memory_object(transfer, 16, &[1; 8]).unwrap();
+        kernel
+            .unmap_transfer_memory(transfer, addr, 0x2000)
+            .unwrap();
+        assert_eq!(
+            kernel.cpu.memory().read_u64(source + 16),
+            Some(0x0101_0101_0101_0101),
+            "the borrower's writes come back"
+        );
+
+        kernel.close_handle(transfer).unwrap();
+        let info = kernel.process.address_space.query(source);
+        assert_eq!(info.state, MemoryState::Normal);
+        assert_eq!(info.permission, RW);
+        assert_eq!(info.attribute, MemoryAttribute::NONE);
+        assert!(kernel.process.transfer_memory.is_empty());
+    }
+
+    #[test]
+    fn test_memory_object_svcs() {
+        let mut guest = Guest::new();
+        let addr = target(&guest);
+        let source = guest.slot(0x10000);
+
+        // Call `id` on the handle the guest stored in slot `slot`
+        let on_handle = |slot: u64, id: u32, args: &[u64]| {
+            let mut code = load(0, guest.slot(slot));
+            for (reg, &value) in args.iter().enumerate() {
+                code.extend(arm64::mov_imm64(reg as u8 + 1, value));
+            }
+            code.push(svc(id));
+            code
+        };
+        let mut code = call(
+            svc::CREATE_TRANSFER_MEMORY,
+            &[0, source, 0x1000, R.0 as u64],
+        );
+        code.extend(store(0, guest.slot(1)));
+        code.extend(store(1, guest.slot(2)));
+        code.extend(on_handle(
+            2,
+            svc::MAP_TRANSFER_MEMORY,
+            &[addr, 0x1000, R.0 as u64],
+        ));
+        code.extend(store(0, guest.slot(3)));
+        code.extend(on_handle(2, svc::UNMAP_TRANSFER_MEMORY, &[addr, 0x1000]));
+        code.extend(store(0, guest.slot(4)));
+        code.extend(call(
+            svc::CREATE_SHARED_MEMORY,
+            &[0, 0x1000, RW.0 as u64, PERMISSION_DONT_CARE.0 as u64],
+        ));
+        code.extend(store(0, guest.slot(5)));
+        code.extend(store(1, guest.slot(6)));
+        code.extend(on_handle(
+            6,
+            svc::MAP_SHARED_MEMORY,
+            &[addr, 0x1000, RW.0 as u64],
+        ));
+        code.extend(store(0, guest.slot(7)));
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&code);
+        guest.spawn(entry, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+        let handles = &guest.kernel.process.handles;
+        assert!(matches!(
+            handles.get(guest.read(2) as u32),
+            Some(KernelObject::TransferMemory(_))
+        ));
+        assert_eq!(guest.result(3), ResultCode::SUCCESS);
+        assert_eq!(guest.result(4), ResultCode::SUCCESS);
+        assert_eq!(guest.result(5), ResultCode::SUCCESS);
+        assert!(matches!(
+            handles.get(guest.read(6) as u32),
+            Some(KernelObject::SharedMemory(_))
+        ));
+        assert_eq!(guest.result(7), ResultCode::SUCCESS);
+        let info = guest.kernel.process.address_space.query(addr);
+        assert_eq!(info.state, MemoryState::Shared);
+        let info = guest.kernel.process.address_space.query(source);
+        assert_eq!(
+            (info.permission, info.attribute),
+            (R, MemoryAttribute::LOCKED)
+        );
+    }
+}
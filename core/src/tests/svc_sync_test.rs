@@ -0,0 +1,639 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::handle::{CURRENT_THREAD, Handle};
+    use crate::kernel::result::{self, ResultCode};
+    use crate::kernel::sync::HAS_WAITERS;
+    use crate::kernel::{KernelExit, svc};
+    use crate::tests::arm64;
+    use crate::tests::guest::{Guest, call, load, record, sleep, store, svc};
+
+    const FOREVER: u64 = u64::MAX;
+
+    /// Put a handle array for `svcWaitSynchronization` at slot `index`
+    fn write_handles(guest: &Guest, index: u64, handles: &[Handle]) {
+        let memory = guest.kernel.cpu.memory();
+        for (i, &handle) in handles.iter().enumerate() {
+            memory.write_u32(guest.slot(index) + i as u64 * 4, handle);
+        }
+    }
+
+    /// Copy the 32-bit word at `addr` into `out`
+    fn copy_word(addr: u64, out: u64) -> Vec<u32> {
+        let mut code = arm64::mov_imm64(11, addr).to_vec();
+        code.push(arm64::ldr_w_imm(12, 11, 0));
+        code.extend(store(12, out));
+        code
+    }
+
+    fn word(guest: &Guest, addr: u64) -> u32 {
+        guest.kernel.cpu.memory().read_u32(addr).unwrap()
+    }
+
+    /// `svcArbitrateLock` with the tag taken from the handle stored at `tag`
+    fn arbitrate_lock(owner: Handle, mutex: u64, tag: u64) -> Vec<u32> {
+        let mut code = load(2, tag);
+        code.extend(arm64::mov_imm64(0, owner as u64));
+        code.extend(arm64::mov_imm64(1, mutex));
+        code.push(svc(svc::ARBITRATE_LOCK));
+        code
+    }
+
+    /// Take the mutex with a plain store of the thread's own handle, as an
+    /// uncontended user-space lock would, then wait on the condition variable
+    fn lock_and_wait(mutex: u64, key: u64, own_handle: u64, timeout: u64) -> Vec<u32> {
+        let mut code = load(19, own_handle);
+        code.extend(arm64::mov_imm64(11, mutex));
+        code.push(arm64::str_w_imm(19, 11, 0));
+        code.extend(arm64::mov_imm64(0, mutex));
+        code.extend(arm64::mov_imm64(1, key));
+        code.push(arm64::mov_reg(2, 19));
+        code.extend(arm64::mov_imm64(3, timeout));
+        code.push(svc(svc::WAIT_PROCESS_WIDE_KEY_ATOMIC));
+        code
+    }
+
+    #[test]
+    fn test_signal_event_wakes_waiters_on_other_cores() {
+        let mut guest = Guest::new();
+        let (writable, readable) = guest.kernel.create_event().unwrap();
+        write_handles(&guest, 16, &[readable]);
+
+        let mut handles = Vec::new();
+        for (core, out) in [(0, 3), (2, 5)] {
+            let mut waiter = call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 1, FOREVER]);
+            waiter.extend(store(0, guest.slot(out)));
+            waiter.extend(store(1, guest.slot(out + 1)));
+            waiter.extend(record(&guest, guest.slot(out + 4)));
+            waiter.push(svc(svc::EXIT_THREAD));
+            let waiter = guest.load(&waiter);
+            handles.push(guest.spawn(waiter, 0, 44, core));
+        }
+
+        let mut signaler = sleep(100_000);
+        signaler.extend(record(&guest, guest.slot(1)));
+        signaler.extend(call(svc::SIGNAL_EVENT, &[writable as u64]));
+        signaler.extend(store(0, guest.slot(2)));
+        signaler.push(svc(svc::EXIT_THREAD));
+        let signaler = guest.load(&signaler);
+        guest.spawn(signaler, 0, 44, 1);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(2), ResultCode::SUCCESS, "SignalEvent");
+        assert_eq!(guest.read(1), 1, "signaler runs before the waiters wake");
+        for out in [3, 5] {
+            assert_eq!(guest.result(out), ResultCode::SUCCESS);
+            assert_eq!(guest.read(out + 1), 0, "signaled handle index");
+            assert!(guest.read(out + 4) > 1);
+        }
+    }
+
+    #[test]
+    fn test_wait_synchronization_times_out_before_signal() {
+        let mut guest = Guest::new();
+        let (writable, readable) = guest.kernel.create_event().unwrap();
+        write_handles(&guest, 16, &[readable]);
+
+        let mut waiter = call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 1, 0]);
+        waiter.extend(store(0, guest.slot(1)));
+        waiter.extend(call(
+            svc::WAIT_SYNCHRONIZATION,
+            &[0, guest.slot(16), 1, 50_000],
+        ));
+        waiter.extend(store(0, guest.slot(2)));
+        waiter.extend(call(
+            svc::WAIT_SYNCHRONIZATION,
+            &[0, guest.slot(16), 1, FOREVER],
+        ));
+        waiter.extend(store(0, guest.slot(3)));
+        waiter.push(svc(svc::EXIT_THREAD));
+        let waiter = guest.load(&waiter);
+        guest.spawn(waiter, 0, 44, 0);
+
+        let mut signaler = sleep(200_000);
+        signaler.extend(call(svc::SIGNAL_EVENT, &[writable as u64]));
+        signaler.push(svc(svc::EXIT_THREAD));
+        let signaler = guest.load(&signaler);
+        guest.spawn(signaler, 0, 44, 1);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(
+            guest.result(1),
+            result::TIMED_OUT,
+            "zero timeout only polls"
+        );
+        assert_eq!(guest.result(2), result::TIMED_OUT);
+        assert_eq!(guest.result(3), ResultCode::SUCCESS);
+        assert!(guest.kernel.scheduler.now() >= 200_000);
+    }
+
+    #[test]
+    fn test_wait_synchronization_reports_signaled_index() {
+        let mut guest = Guest::new();
+        let (_, first) = guest.kernel.create_event().unwrap();
+        let (writable, second) = guest.kernel.create_event().unwrap();
+
+        let mut worker = sleep(300_000);
+        worker.push(svc(svc::EXIT_THREAD));
+        let worker = guest.load(&worker);
+        let worker = guest.spawn(worker, 0, 44, 2);
+        write_handles(&guest, 16, &[first, second, worker]);
+
+        let mut waiter = call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 3, FOREVER]);
+        waiter.extend(store(0, guest.slot(1)));
+        waiter.extend(store(1, guest.slot(2)));
+        waiter.extend(call(svc::CLEAR_EVENT, &[second as u64]));
+        waiter.extend(call(
+            svc::WAIT_SYNCHRONIZATION,
+            &[0, guest.slot(16), 3, FOREVER],
+        ));
+        waiter.extend(store(0, guest.slot(3)));
+        waiter.extend(store(1, guest.slot(4)));
+        waiter.push(svc(svc::EXIT_THREAD));
+        let waiter = guest.load(&waiter);
+        guest.spawn(waiter, 0, 44, 0);
+
+        let mut signaler = sleep(100_000);
+        signaler.extend(call(svc::SIGNAL_EVENT, &[writable as u64]));
+        signaler.push(svc(svc::EXIT_THREAD));
+        let signaler = guest.load(&signaler);
+        guest.spawn(signaler, 0, 44, 1);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+        assert_eq!(guest.read(2), 1, "second event");
+        assert_eq!(guest.result(3), ResultCode::SUCCESS);
+        assert_eq!(guest.read(4), 2, "worker thread exit");
+    }
+
+    #[test]
+    fn test_wait_synchronization_on_thread_handle() {
+        let mut guest = Guest::new();
+
+        let mut worker = sleep(100_000);
+        worker.extend(record(&guest, guest.slot(1)));
+        worker.push(svc(svc::EXIT_THREAD));
+        let worker = guest.load(&worker);
+        let worker = guest.spawn(worker, 0, 44, 1);
+        write_handles(&guest, 16, &[worker]);
+
+        let mut main = call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 1, FOREVER]);
+        main.extend(store(0, guest.slot(3)));
+        main.extend(record(&guest, guest.slot(2)));
+        main.extend(call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 1, 0]));
+        main.extend(store(0, guest.slot(4)));
+        main.push(svc(svc::EXIT_THREAD));
+        let main = guest.load(&main);
+        guest.spawn(main, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(3), ResultCode::SUCCESS);
+        assert_eq!(guest.read(1), 1, "worker exits first");
+        assert_eq!(guest.read(2), 2);
+        assert_eq!(
+            guest.result(4),
+            ResultCode::SUCCESS,
+            "exited thread stays signaled"
+        );
+    }
+
+    #[test]
+    fn test_wait_synchronization_rejects_bad_arguments() {
+        let mut guest = Guest::new();
+        let (writable, _) = guest.kernel.create_event().unwrap();
+        write_handles(&guest, 16, &[writable]);
+        write_handles(&guest, 17, &[0x1234]);
+
+        let mut main = call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(18), 0x41, 0]);
+        main.extend(store(0, guest.slot(1)));
+        main.extend(call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 1, 0]));
+        main.extend(store(0, guest.slot(2)));
+        main.extend(call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(17), 1, 0]));
+        main.extend(store(0, guest.slot(3)));
+        main.extend(call(svc::WAIT_SYNCHRONIZATION, &[0, 0x100, 1, 0]));
+        main.extend(store(0, guest.slot(4)));
+        main.extend(call(svc::WAIT_SYNCHRONIZATION, &[0, 0, 0, 10_000]));
+        main.extend(store(0, guest.slot(5)));
+        main.push(svc(svc::EXIT_THREAD));
+        let main = guest.load(&main);
+        guest.spawn(main, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), result::OUT_OF_RANGE);
+        assert_eq!(guest.result(2), result::INVALID_HANDLE, "writable event");
+        assert_eq!(guest.result(3), result::INVALID_HANDLE);
+        assert_eq!(guest.result(4), result::INVALID_POINTER);
+        assert_eq!(
+            guest.result(5),
+            result::TIMED_OUT,
+            "no handles acts as a sleep"
+        );
+    }
+
+    #[test]
+    fn test_event_signal_clear_and_reset() {
+        let mut guest = Guest::new();
+
+        let mut main = call(svc::CREATE_EVENT, &[]);
+        main.extend(store(0, guest.slot(1)));
+        main.push(arm64::mov_reg(19, 1));
+        main.push(arm64::mov_reg(20, 2));
+        main.extend(arm64::mov_imm64(11, guest.slot(16)));
+        main.push(arm64::str_w_imm(20, 11, 0));
+        for (op, handle, out) in [
+            (svc::SIGNAL_EVENT, 20, 2),
+            (svc::RESET_SIGNAL, 20, 3),
+            (svc::SIGNAL_EVENT, 19, 4),
+            (svc::RESET_SIGNAL, 19, 5),
+            (svc::RESET_SIGNAL, 20, 6),
+            (svc::SIGNAL_EVENT, 19, 0),
+            (svc::CLEAR_EVENT, 19, 7),
+        ] {
+            main.push(arm64::mov_reg(0, handle));
+            main.push(svc(op));
+            if out != 0 {
+                main.extend(store(0, guest.slot(out)));
+            }
+        }
+        main.extend(call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 1, 0]));
+        main.extend(store(0, guest.slot(8)));
+        main.push(arm64::mov_reg(0, 19));
+        main.push(svc(svc::SIGNAL_EVENT));
+        main.extend(call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 1, 0]));
+        main.extend(store(0, guest.slot(9)));
+        main.extend(call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 1, 0]));
+        main.extend(store(0, guest.slot(10)));
+        for handle in [19, 20] {
+            main.push(arm64::mov_reg(0, handle));
+            main.push(svc(svc::CLOSE_HANDLE));
+        }
+        main.push(svc(svc::EXIT_THREAD));
+        let main = guest.load(&main);
+        guest.spawn(main, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS, "CreateEvent");
+        assert_eq!(
+            guest.result(2),
+            result::INVALID_HANDLE,
+            "signal readable end"
+        );
+        assert_eq!(guest.result(3), result::INVALID_STATE, "reset unsignaled");
+        assert_eq!(guest.result(4), ResultCode::SUCCESS);
+        assert_eq!(
+            guest.result(5),
+            result::INVALID_HANDLE,
+            "reset writable end"
+        );
+        assert_eq!(guest.result(6), ResultCode::SUCCESS);
+        assert_eq!(guest.result(7), ResultCode::SUCCESS);
+        assert_eq!(guest.result(8), result::TIMED_OUT, "cleared");
+        assert_eq!(guest.result(9), ResultCode::SUCCESS);
+        assert_eq!(
+            guest.result(10),
+            ResultCode::SUCCESS,
+            "waiting does not clear"
+        );
+        assert!(guest.kernel.process.events.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_synchronization() {
+        let mut guest = Guest::new();
+        let (_, readable) = guest.kernel.create_event().unwrap();
+        write_handles(&guest, 16, &[readable]);
+
+        let mut waiter = call(svc::WAIT_SYNCHRONIZATION, &[0, guest.slot(16), 1, FOREVER]);
+        waiter.extend(store(0, guest.slot(1)));
+        waiter.push(svc(svc::EXIT_THREAD));
+        let waiter = guest.load(&waiter);
+        let waiter = guest.spawn(waiter, 0, 44, 0);
+
+        let mut canceller = sleep(100_000);
+        canceller.extend(call(svc::CANCEL_SYNCHRONIZATION, &[waiter as u64]));
+        canceller.extend(store(0, guest.slot(2)));
+        // Cancelling a thread that is not waiting fails its next wait
+        canceller.extend(call(svc::CANCEL_SYNCHRONIZATION, &[CURRENT_THREAD as u64]));
+        canceller.extend(call(
+            svc::WAIT_SYNCHRONIZATION,
+            &[0, guest.slot(16), 1, FOREVER],
+        ));
+        canceller.extend(store(0, guest.slot(3)));
+        canceller.extend(call(
+            svc::WAIT_SYNCHRONIZATION,
+            &[0, guest.slot(16), 1, 10_000],
+        ));
+        canceller.extend(store(0, guest.slot(4)));
+        canceller.extend(call(svc::CANCEL_SYNCHRONIZATION, &[0x1234]));
+        canceller.extend(store(0, guest.slot(5)));
+        canceller.push(svc(svc::EXIT_THREAD));
+        let canceller = guest.load(&canceller);
+        guest.spawn(canceller, 0, 44, 1);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), result::CANCELLED);
+        assert_eq!(guest.result(2), ResultCode::SUCCESS);
+        assert_eq!(guest.result(3), result::CANCELLED);
+        assert_eq!(
+            guest.result(4),
+            result::TIMED_OUT,
+            "cancellation is consumed"
+        );
+        assert_eq!(guest.result(5), result::INVALID_HANDLE);
+    }
+
+    #[test]
+    fn test_arbitrate_unlock_hands_mutex_to_highest_priority_waiter() {
+        let mut guest = Guest::new();
+        let mutex = guest.slot(20);
+
+        let mut owner = sleep(100_000);
+        owner.extend(record(&guest, guest.slot(1)));
+        owner.extend(call(svc::ARBITRATE_UNLOCK, &[mutex]));
+        owner.extend(store(0, guest.slot(4)));
+        owner.push(svc(svc::EXIT_THREAD));
+        let owner = guest.load(&owner);
+        let owner = guest.spawn(owner, 0, 44, 0);
+        guest
+            .kernel
+            .cpu
+            .memory()
+            .write_u32(mutex, owner | HAS_WAITERS);
+
+        // The low-priority waiter queues first
+        let mut handles = Vec::new();
+        for (delay, core, priority, out) in [(0, 1, 40, 2), (10_000, 2, 30, 3)] {
+            let mut waiter = sleep(delay);
+            waiter.extend(arbitrate_lock(owner, mutex, guest.slot(10 + out)));
+            waiter.extend(store(0, guest.slot(out + 3)));
+            waiter.extend(record(&guest, guest.slot(out)));
+            waiter.extend(copy_word(mutex, guest.slot(out + 6)));
+            waiter.extend(call(svc::ARBITRATE_UNLOCK, &[mutex]));
+            waiter.push(svc(svc::EXIT_THREAD));
+            let waiter = guest.load(&waiter);
+            let handle = guest.spawn(waiter, 0, priority, core);
+            guest
+                .kernel
+                .cpu
+                .memory()
+                .write_u64(guest.slot(10 + out), handle as u64);
+            handles.push(handle);
+        }
+        let (low, high) = (handles[0], handles[1]);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(4), ResultCode::SUCCESS);
+        assert_eq!(guest.result(5), ResultCode::SUCCESS);
+        assert_eq!(guest.result(6), ResultCode::SUCCESS);
+        assert_eq!(guest.read(1), 1);
+        assert_eq!(
+            guest.read(3),
+            2,
+            "high priority waiter gets the mutex first"
+        );
+        assert_eq!(guest.read(2), 3);
+        assert_eq!(
+            guest.read(9),
+            (high | HAS_WAITERS) as u64,
+            "other waiter remains"
+        );
+        assert_eq!(guest.read(8), low as u64);
+        assert_eq!(word(&guest, mutex), 0, "unlocked at the end");
+    }
+
+    #[test]
+    fn test_arbitrate_lock_errors() {
+        let mut guest = Guest::new();
+        let (free, taken, held) = (guest.slot(20), guest.slot(21), guest.slot(22));
+        let memory = guest.kernel.cpu.memory();
+        memory.write_u32(taken, 0x1234 | HAS_WAITERS);
+
+        let mut main = call(svc::ARBITRATE_LOCK, &[0x1234, free + 2, 0]);
+        main.extend(store(0, guest.slot(1)));
+        main.extend(call(svc::ARBITRATE_LOCK, &[0x1234, free, 0]));
+        main.extend(store(0, guest.slot(2)));
+        main.extend(call(svc::ARBITRATE_LOCK, &[0x1234, taken, 0]));
+        main.extend(store(0, guest.slot(3)));
+        main.extend(call(svc::ARBITRATE_UNLOCK, &[0x100]));
+        main.extend(store(0, guest.slot(4)));
+        main.extend(call(svc::ARBITRATE_UNLOCK, &[held]));
+        main.extend(store(0, guest.slot(5)));
+        main.push(svc(svc::EXIT_THREAD));
+        let main = guest.load(&main);
+        let main = guest.spawn(main, 0, 44, 0);
+        guest.kernel.cpu.memory().write_u32(held, main);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), result::INVALID_ADDRESS);
+        assert_eq!(
+            guest.result(2),
+            ResultCode::SUCCESS,
+            "value changed, retry in user space"
+        );
+        assert_eq!(guest.result(3), result::INVALID_HANDLE);
+        assert_eq!(guest.result(4), result::INVALID_CURRENT_MEMORY);
+        assert_eq!(guest.result(5), ResultCode::SUCCESS);
+        assert_eq!(word(&guest, held), 0);
+    }
+
+    #[test]
+    fn test_condition_variable_signal_relocks_mutex() {
+        let mut guest = Guest::new();
+        let (mutex, key) = (guest.slot(20), guest.slot(21));
+
+        let mut signaler = sleep(100_000);
+        signaler.extend(load(19, guest.slot(11)));
+        signaler.extend(arm64::mov_imm64(11, mutex));
+        signaler.push(arm64::str_w_imm(19, 11, 0));
+        signaler.extend(call(svc::SIGNAL_PROCESS_WIDE_KEY, &[key, 1]));
+        signaler.extend(copy_word(key, guest.slot(9)));
+        signaler.extend(record(&guest, guest.slot(1)));
+        signaler.extend(call(svc::ARBITRATE_UNLOCK, &[mutex]));
+        signaler.extend(sleep(100_000));
+        signaler.extend(call(svc::SIGNAL_PROCESS_WIDE_KEY, &[key, FOREVER]));
+        signaler.push(svc(svc::EXIT_THREAD));
+        let signaler = guest.load(&signaler);
+        let signaler = guest.spawn(signaler, 0, 44, 0);
+        guest
+            .kernel
+            .cpu
+            .memory()
+            .write_u64(guest.slot(11), signaler as u64);
+
+        // The low-priority waiter starts waiting first
+        let mut handles = Vec::new();
+        for (core, priority, out) in [(1, 44, 2), (2, 40, 3)] {
+            let mut waiter = lock_and_wait(mutex, key, guest.slot(10 + out), FOREVER);
+            waiter.extend(store(0, guest.slot(out + 2)));
+            waiter.extend(record(&guest, guest.slot(out)));
+            waiter.extend(copy_word(mutex, guest.slot(out + 4)));
+            waiter.extend(call(svc::ARBITRATE_UNLOCK, &[mutex]));
+            waiter.push(svc(svc::EXIT_THREAD));
+            let waiter = guest.load(&waiter);
+            let handle = guest.spawn(waiter, 0, priority, core);
+            guest
+                .kernel
+                .cpu
+                .memory()
+                .write_u64(guest.slot(10 + out), handle as u64);
+            handles.push(handle);
+        }
+        let (low, high) = (handles[0], handles[1]);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(4), ResultCode::SUCCESS);
+        assert_eq!(guest.result(5), ResultCode::SUCCESS);
+        assert_eq!(guest.read(1), 1, "signaler still holds the mutex");
+        assert_eq!(guest.read(3), 2, "high priority waiter is signaled first");
+        assert_eq!(guest.read(2), 3);
+        assert_eq!(guest.read(7), high as u64, "handed over on unlock");
+        assert_eq!(guest.read(6), low as u64, "relocked a free mutex");
+        assert_eq!(guest.read(9), 1, "a waiter is left on the key");
+        assert_eq!(word(&guest, key), 0, "no waiters left");
+        assert_eq!(word(&guest, mutex), 0);
+    }
+
+    #[test]
+    fn test_condition_variable_timeout_releases_mutex() {
+        let mut guest = Guest::new();
+        let (mutex, key) = (guest.slot(20), guest.slot(21));
+
+        let mut waiter = call(
+            svc::WAIT_PROCESS_WIDE_KEY_ATOMIC,
+            &[mutex + 1, key, 0, FOREVER],
+        );
+        waiter.extend(store(0, guest.slot(3)));
+        waiter.extend(lock_and_wait(mutex, key, guest.slot(10), 50_000));
+        waiter.extend(store(0, guest.slot(1)));
+        waiter.push(svc(svc::EXIT_THREAD));
+        let waiter = guest.load(&waiter);
+        let waiter = guest.spawn(waiter, 0, 44, 0);
+        guest
+            .kernel
+            .cpu
+            .memory()
+            .write_u64(guest.slot(10), waiter as u64);
+
+        let mut observer = sleep(10_000);
+        observer.extend(copy_word(mutex, guest.slot(2)));
+        observer.extend(copy_word(key, guest.slot(4)));
+        observer.push(svc(svc::EXIT_THREAD));
+        let observer = guest.load(&observer);
+        guest.spawn(observer, 0, 44, 1);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(3), result::INVALID_ADDRESS);
+        assert_eq!(guest.result(1), result::TIMED_OUT);
+        assert_eq!(guest.read(2), 0, "mutex released while waiting");
+        assert_eq!(guest.read(4), 1, "key marks a waiter");
+        assert!(guest.kernel.scheduler.now() >= 50_000);
+    }
+
+    #[test]
+    fn test_signal_to_address_wakes_by_priority() {
+        let mut guest = Guest::new();
+        let address = guest.slot(22);
+
+        // The low-priority waiter queues first
+        for (delay, core, priority, out) in [(0, 1, 44, 2), (10_000, 2, 30, 3)] {
+            let mut waiter = sleep(delay);
+            waiter.extend(call(svc::WAIT_FOR_ADDRESS, &[address, 0, 1, FOREVER]));
+            waiter.extend(store(0, guest.slot(out + 3)));
+            waiter.extend(record(&guest, guest.slot(out)));
+            waiter.push(svc(svc::EXIT_THREAD));
+            let waiter = guest.load(&waiter);
+            guest.spawn(waiter, 0, priority, core);
+        }
+
+        let mut signaler = sleep(100_000);
+        signaler.extend(record(&guest, guest.slot(1)));
+        signaler.extend(call(svc::SIGNAL_TO_ADDRESS, &[address, 0, 0, 1]));
+        signaler.extend(store(0, guest.slot(7)));
+        signaler.extend(sleep(100_000));
+        signaler.extend(call(svc::SIGNAL_TO_ADDRESS, &[address, 0, 0, 0]));
+        signaler.extend(store(0, guest.slot(8)));
+        signaler.push(svc(svc::EXIT_THREAD));
+        let signaler = guest.load(&signaler);
+        guest.spawn(signaler, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.read(1), 1);
+        assert_eq!(guest.read(3), 2, "high priority waiter wakes first");
+        assert_eq!(guest.read(2), 3);
+        for out in 5..=8 {
+            assert_eq!(guest.result(out), ResultCode::SUCCESS);
+        }
+    }
+
+    #[test]
+    fn test_signal_and_modify_by_waiting_count() {
+        for (count, expected) in [(1, 0), (2, -1), (0, -2)] {
+            let mut guest = Guest::new();
+            let address = guest.slot(22);
+
+            for core in [1, 2] {
+                let mut waiter = call(svc::WAIT_FOR_ADDRESS, &[address, 2, 0, FOREVER]);
+                waiter.push(svc(svc::EXIT_THREAD));
+                let waiter = guest.load(&waiter);
+                guest.spawn(waiter, 0, 44, core);
+            }
+
+            let mut signaler = sleep(100_000);
+            signaler.extend(call(svc::SIGNAL_TO_ADDRESS, &[address, 2, 0, count]));
+            signaler.extend(store(0, guest.slot(1)));
+            signaler.extend(copy_word(address, guest.slot(2)));
+            // Release whoever is still waiting
+            signaler.extend(call(svc::SIGNAL_TO_ADDRESS, &[address, 0, 0, 0]));
+            signaler.push(svc(svc::EXIT_THREAD));
+            let signaler = guest.load(&signaler);
+            guest.spawn(signaler, 0, 44, 0);
+
+            assert_eq!(guest.run(), KernelExit::AllThreadsExited, "count {count}");
+            assert_eq!(guest.result(1), ResultCode::SUCCESS, "count {count}");
+            assert_eq!(guest.read(2) as i32, expected, "count {count}");
+        }
+    }
+
+    #[test]
+    fn test_address_arbiter_values_and_errors() {
+        let mut guest = Guest::new();
+        let (address, counter) = (guest.slot(22), guest.slot(23));
+
+        let mut main = Vec::new();
+        for (op, args, out) in [
+            (svc::WAIT_FOR_ADDRESS, [address, 2, 5, FOREVER], 1),
+            (svc::WAIT_FOR_ADDRESS, [address, 1, 1, 0], 2),
+            (svc::WAIT_FOR_ADDRESS, [address, 3, 0, 0], 3),
+            (svc::WAIT_FOR_ADDRESS, [address + 2, 0, 0, 0], 4),
+            (svc::SIGNAL_TO_ADDRESS, [counter, 1, 7, 0], 5),
+            (svc::SIGNAL_TO_ADDRESS, [counter, 1, 0, 0], 6),
+            (svc::SIGNAL_TO_ADDRESS, [counter, 2, 1, 1], 7),
+            (svc::SIGNAL_TO_ADDRESS, [counter, 3, 0, 0], 8),
+        ] {
+            main.extend(call(op, &args));
+            main.extend(store(0, guest.slot(out)));
+        }
+        main.push(svc(svc::EXIT_THREAD));
+        let main = guest.load(&main);
+        guest.spawn(main, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), result::INVALID_STATE, "value differs");
+        assert_eq!(guest.result(2), result::TIMED_OUT);
+        assert_eq!(
+            word(&guest, address) as i32,
+            -1,
+            "decremented before waiting"
+        );
+        assert_eq!(guest.result(3), result::INVALID_ENUM_VALUE);
+        assert_eq!(guest.result(4), result::INVALID_ADDRESS);
+        assert_eq!(guest.result(5), result::INVALID_STATE, "value differs");
+        assert_eq!(guest.result(6), ResultCode::SUCCESS);
+        assert_eq!(guest.result(7), ResultCode::SUCCESS);
+        assert_eq!(
+            word(&guest, counter),
+            2,
+            "incremented, then no waiters adds one"
+        );
+        assert_eq!(guest.result(8), result::INVALID_ENUM_VALUE);
+    }
+}
@@ -0,0 +1,397 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::handle::CURRENT_THREAD;
+    use crate::kernel::memory::MemoryState;
+    use crate::kernel::result::{self, ResultCode};
+    use crate::kernel::thread::ThreadState;
+    use crate::kernel::{KernelExit, svc};
+    use crate::tests::arm64;
+    use crate::tests::guest::{Guest, record, set_flag, sleep, store, svc, wait_for_flag};
+
+    #[test]
+    fn test_create_thread_runs_on_requested_core() {
+        let mut guest = Guest::new();
+        let stack = guest.stack();
+
+        let mut worker = Vec::new();
+        worker.push(arm64::mov_reg(19, 0));
+        worker.push(svc(svc::GET_CURRENT_PROCESSOR_NUMBER));
+        worker.push(arm64::str_imm(0, 19, 0));
+        worker.push(arm64::mrs_tpidrro_el0(1));
+        worker.push(arm64::str_imm(1, 19, 8));
+        worker.push(svc(svc::EXIT_THREAD));
+        let worker = guest.load(&worker);
+
+        let mut main = Vec::new();
+        main.extend(arm64::mov_imm64(1, worker));
+        main.extend(arm64::mov_imm64(2, guest.slot(2)));
+        main.extend(arm64::mov_imm64(3, stack));
+        main.extend(arm64::mov_imm64(4, 44));
+        main.extend(arm64::mov_imm64(5, 1));
+        main.push(svc(svc::CREATE_THREAD));
+        main.extend(store(0, guest.slot(4)));
+        main.push(arm64::mov_reg(0, 1));
+        main.push(svc(svc::START_THREAD));
+        main.extend(store(0, guest.slot(5)));
+        main.push(svc(svc::GET_CURRENT_PROCESSOR_NUMBER));
+        main.extend(store(0, guest.slot(1)));
+        main.push(svc(svc::EXIT_THREAD));
+        let main = guest.load(&main);
+        guest.spawn(main, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(4), ResultCode::SUCCESS, "CreateThread");
+        assert_eq!(guest.result(5), ResultCode::SUCCESS, "StartThread");
+        assert_eq!(guest.read(1), 0, "main thread core");
+        assert_eq!(guest.read(2), 1, "worker thread core");
+
+        let tls = guest.read(3);
+        assert_ne!(tls, 0);
+        let space = &guest.kernel.process.address_space;
+        assert_eq!(space.query(tls).state, MemoryState::ThreadLocal);
+    }
+
+    #[test]
+    fn test_higher_priority_thread_preempts_creator() {
+        for (priority, expected_worker, expected_main) in [(30, 1, 2), (50, 2, 1)] {
+            let mut guest = Guest::new();
+            let stack = guest.stack();
+
+            let mut worker = record(&guest, guest.slot(1));
+            worker.push(svc(svc::EXIT_THREAD));
+            let worker = guest.load(&worker);
+
+            let mut main = Vec::new();
+            main.extend(arm64::mov_imm64(1, worker));
+            main.extend(arm64::mov_imm64(3, stack));
+            main.extend(arm64::mov_imm64(4, priority));
+            main.extend(arm64::mov_imm64(5, 0));
+            main.push(svc(svc::CREATE_THREAD));
+            main.push(arm64::mov_reg(0, 1));
+            main.push(svc(svc::START_THREAD));
+            main.extend(record(&guest, guest.slot(2)));
+            main.push(svc(svc::EXIT_THREAD));
+            let main = guest.load(&main);
+            guest.spawn(main, 0, 44, 0);
+
+            assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+            assert_eq!(
+                guest.read(1),
+                expected_worker,
+                "worker at priority {priority}"
+            );
+            assert_eq!(
+                guest.read(2),
+                expected_main,
+                "main with worker at priority {priority}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_waking_thread_preempts_busy_lower_priority_thread() {
+        let mut guest = Guest::new();
+        let flag = guest.slot(8);
+
+        // Would spin forever if the sleeper never got the core back
+        let mut low = wait_for_flag(flag);
+        low.extend(record(&guest, guest.slot(1)));
+        low.push(svc(svc::EXIT_THREAD));
+        let low = guest.load(&low);
+
+        let mut high = sleep(1_000_000);
+        high.extend(record(&guest, guest.slot(2)));
+        high.extend(set_flag(flag));
+        high.push(svc(svc::EXIT_THREAD));
+        let high = guest.load(&high);
+
+        guest.spawn(low, 0, 50, 0);
+        guest.spawn(high, 0, 30, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.read(2), 1);
+        assert_eq!(guest.read(1), 2);
+        assert!(guest.kernel.scheduler.now() >= 1_000_000);
+    }
+
+    #[test]
+    fn test_equal_priority_threads_are_not_time_sliced() {
+        let mut guest = Guest::new();
+
+        let mut a = record(&guest, guest.slot(1));
+        a.extend(record(&guest, guest.slot(2)));
+        a.push(svc(svc::EXIT_THREAD));
+        let a = guest.load(&a);
+        let mut b = record(&guest, guest.slot(3));
+        b.push(svc(svc::EXIT_THREAD));
+        let b = guest.load(&b);
+
+        guest.spawn(a, 0, 44, 0);
+        guest.spawn(b, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!((guest.read(1), guest.read(2), guest.read(3)), (1, 2, 3));
+    }
+
+    #[test]
+    fn test_yield_round_robins_equal_priority() {
+        let mut guest = Guest::new();
+
+        let mut a = record(&guest, guest.slot(1));
+        a.extend(sleep(0));
+        a.extend(record(&guest, guest.slot(2)));
+        a.push(svc(svc::EXIT_THREAD));
+        let a = guest.load(&a);
+        let mut b = record(&guest, guest.slot(3));
+        b.extend(sleep(0));
+        b.extend(record(&guest, guest.slot(4)));
+        b.push(svc(svc::EXIT_THREAD));
+        let b = guest.load(&b);
+
+        guest.spawn(a, 0, 44, 0);
+        guest.spawn(b, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        let order = [1, 2, 3, 4].map(|i| guest.read(i));
+        assert_eq!(order, [1, 3, 2, 4]);
+    }
+
+    #[test]
+    fn test_yield_kinds_and_lower_priority_threads() {
+        // Only yielding to any thread lets a lower-priority thread in
+        for (ns, expected) in [(0, [1, 2, 3]), (-2, [1, 3, 2])] {
+            let mut guest = Guest::new();
+
+            let mut high = record(&guest, guest.slot(1));
+            high.extend(sleep(ns));
+            high.extend(record(&guest, guest.slot(2)));
+            high.push(svc(svc::EXIT_THREAD));
+            let high = guest.load(&high);
+            let mut low = record(&guest, guest.slot(3));
+            low.push(svc(svc::EXIT_THREAD));
+            let low = guest.load(&low);
+
+            guest.spawn(high, 0, 30, 0);
+            guest.spawn(low, 0, 50, 0);
+
+            assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+            assert_eq!([1, 2, 3].map(|i| guest.read(i)), expected, "yield {ns}");
+        }
+    }
+
+    #[test]
+    fn test_yield_with_migration_moves_to_idle_core() {
+        for (ns, expected_core) in [(0, 0), (-1, 1)] {
+            let mut guest = Guest::new();
+
+            let mut program = sleep(ns);
+            program.push(svc(svc::GET_CURRENT_PROCESSOR_NUMBER));
+            program.extend(store(0, guest.slot(1)));
+            program.push(svc(svc::EXIT_THREAD));
+            let entry = guest.load(&program);
+
+            let handle = guest.spawn(entry, 0, 44, 0);
+            let id = guest.kernel.thread_from_handle(handle, None).unwrap();
+            guest.kernel.scheduler.set_core_mask(id, 0, 0b11);
+
+            assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+            assert_eq!(guest.read(1), expected_core, "yield {ns}");
+        }
+    }
+
+    #[test]
+    fn test_sleep_thread_wakes_in_deadline_order() {
+        let mut guest = Guest::new();
+
+        let mut a = sleep(2_000_000);
+        a.extend(store(0, guest.slot(3)));
+        a.extend(record(&guest, guest.slot(1)));
+        a.push(svc(svc::EXIT_THREAD));
+        let a = guest.load(&a);
+        let mut b = sleep(1_000_000);
+        b.extend(record(&guest, guest.slot(2)));
+        b.push(svc(svc::EXIT_THREAD));
+        let b = guest.load(&b);
+
+        guest.spawn(a, 0, 44, 0);
+        guest.spawn(b, 1, 44, 1);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(3), ResultCode::SUCCESS);
+        assert_eq!((guest.read(1), guest.read(2)), (2, 1));
+        assert!(guest.kernel.scheduler.now() >= 2_000_000);
+    }
+
+    #[test]
+    fn test_threads_on_different_cores_run_concurrently() {
+        let mut guest = Guest::new();
+        let flag = guest.slot(8);
+
+        let mut waiter = wait_for_flag(flag);
+        waiter.extend(record(&guest, guest.slot(1)));
+        waiter.push(svc(svc::EXIT_THREAD));
+        let waiter = guest.load(&waiter);
+        let mut setter = record(&guest, guest.slot(2));
+        setter.extend(set_flag(flag));
+        setter.push(svc(svc::EXIT_THREAD));
+        let setter = guest.load(&setter);
+
+        guest.spawn(waiter, 0, 44, 0);
+        guest.spawn(setter, 0, 44, 1);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!((guest.read(1), guest.read(2)), (2, 1));
+    }
+
+    #[test]
+    fn test_thread_priority_svcs() {
+        let mut guest = Guest::new();
+
+        let get_priority = |handle: u32| {
+            let mut code = arm64::mov_imm64(1, handle as u64).to_vec();
+            code.push(svc(svc::GET_THREAD_PRIORITY));
+            code
+        };
+        let set_priority = |priority: u64| {
+            let mut code = arm64::mov_imm64(0, CURRENT_THREAD as u64).to_vec();
+            code.extend(arm64::mov_imm64(1, priority));
+            code.push(svc(svc::SET_THREAD_PRIORITY));
+            code
+        };
+
+        let mut program = get_priority(CURRENT_THREAD);
+        program.extend(store(1, guest.slot(1)));
+        program.extend(set_priority(20));
+        program.extend(store(0, guest.slot(2)));
+        program.extend(get_priority(CURRENT_THREAD));
+        program.extend(store(1, guest.slot(3)));
+        program.extend(set_priority(64));
+        program.extend(store(0, guest.slot(4)));
+        program.extend(get_priority(0x1234));
+        program.extend(store(0, guest.slot(5)));
+        program.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&program);
+        guest.spawn(entry, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.read(1), 44);
+        assert_eq!(guest.result(2), ResultCode::SUCCESS);
+        assert_eq!(guest.read(3), 20);
+        assert_eq!(guest.result(4), result::INVALID_PRIORITY);
+        assert_eq!(guest.result(5), result::INVALID_HANDLE);
+    }
+
+    #[test]
+    fn test_set_thread_core_mask() {
+        let mut guest = Guest::new();
+
+        let set_mask = |core: i32, mask: u64| {
+            let mut code = arm64::mov_imm64(0, CURRENT_THREAD as u64).to_vec();
+            code.extend(arm64::mov_imm64(1, core as u32 as u64));
+            code.extend(arm64::mov_imm64(2, mask));
+            code.push(svc(svc::SET_THREAD_CORE_MASK));
+            code
+        };
+
+        let mut program = set_mask(2, 0b100);
+        program.extend(store(0, guest.slot(1)));
+        program.push(svc(svc::GET_CURRENT_PROCESSOR_NUMBER));
+        program.extend(store(0, guest.slot(2)));
+        program.extend(arm64::mov_imm64(2, CURRENT_THREAD as u64));
+        program.push(svc(svc::GET_THREAD_CORE_MASK));
+        program.extend(store(1, guest.slot(3)));
+        program.extend(store(2, guest.slot(4)));
+        program.extend(set_mask(0, 0));
+        program.extend(store(0, guest.slot(5)));
+        program.extend(set_mask(1, 0b100));
+        program.extend(store(0, guest.slot(6)));
+        program.extend(set_mask(4, 0b1_0000));
+        program.extend(store(0, guest.slot(7)));
+        program.extend(set_mask(-5, 0b1));
+        program.extend(store(0, guest.slot(8)));
+        program.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&program);
+        guest.spawn(entry, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+        assert_eq!(guest.read(2), 2, "thread moved to its new core");
+        assert_eq!((guest.read(3), guest.read(4)), (2, 0b100));
+        assert_eq!(guest.result(5), result::INVALID_COMBINATION);
+        assert_eq!(guest.result(6), result::INVALID_COMBINATION);
+        assert_eq!(guest.result(7), result::INVALID_CORE_ID);
+        assert_eq!(guest.result(8), result::INVALID_CORE_ID);
+    }
+
+    #[test]
+    fn test_create_and_start_thread_result_codes() {
+        let mut guest = Guest::new();
+        let stack = guest.stack();
+
+        let create = |priority: u64, core: i32| {
+            let mut code = arm64::mov_imm64(1, 0).to_vec();
+            code.extend(arm64::mov_imm64(3, stack));
+            code.extend(arm64::mov_imm64(4, priority));
+            code.extend(arm64::mov_imm64(5, core as u32 as u64));
+            code.push(svc(svc::CREATE_THREAD));
+            code
+        };
+
+        let mut program = create(64, 0);
+        program.extend(store(0, guest.slot(1)));
+        program.extend(create(44, 5));
+        program.extend(store(0, guest.slot(2)));
+        program.extend(arm64::mov_imm64(0, 0x1234));
+        program.push(svc(svc::START_THREAD));
+        program.extend(store(0, guest.slot(3)));
+        // A thread that is already running cannot be started again
+        program.extend(create(44, -2));
+        program.push(arm64::mov_reg(19, 1));
+        program.push(arm64::mov_reg(0, 19));
+        program.push(svc(svc::START_THREAD));
+        program.push(arm64::mov_reg(0, 19));
+        program.push(svc(svc::START_THREAD));
+        program.extend(store(0, guest.slot(4)));
+        program.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&program);
+        guest.spawn(entry, 0, 44, 0);
+
+        // The second thread starts at address 0 and hits an undefined
+        // instruction, which is reported rather than swallowed
+        let exit = guest.run();
+        assert!(matches!(exit, KernelExit::Halted { .. }), "{exit:?}");
+        assert_eq!(guest.result(1), result::INVALID_PRIORITY);
+        assert_eq!(guest.result(2), result::INVALID_CORE_ID);
+        assert_eq!(guest.result(3), result::INVALID_HANDLE);
+        assert_eq!(guest.result(4), result::INVALID_STATE);
+    }
+
+    #[test]
+    fn test_exited_thread_is_destroyed_when_handle_closed() {
+        let mut guest = Guest::new();
+        let entry = guest.load(&[svc(svc::EXIT_THREAD)]);
+        let handle = guest.spawn(entry, 0, 44, 0);
+        let id = guest.kernel.thread_from_handle(handle, None).unwrap();
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        let thread = guest.kernel.scheduler.thread(id).unwrap();
+        assert_eq!(thread.state, ThreadState::Terminated);
+        let tls = thread.tls_address;
+
+        assert_eq!(guest.kernel.close_handle(handle), Ok(()));
+        assert!(guest.kernel.scheduler.thread(id).is_none());
+        assert_eq!(
+            guest.kernel.close_handle(handle),
+            Err(result::INVALID_HANDLE)
+        );
+
+        // The freed TLS slot is handed to the next thread
+        let stack = guest.stack();
+        let next = guest.kernel.create_thread(entry, 0, stack, 44, 0).unwrap();
+        let next = guest.kernel.thread_from_handle(next, None).unwrap();
+        assert_eq!(
+            guest.kernel.scheduler.thread(next).unwrap().tls_address,
+            tls
+        );
+    }
+}
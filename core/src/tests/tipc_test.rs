@@ -0,0 +1,126 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::handle::KernelObject;
+    use crate::kernel::hipc::{MESSAGE_BUFFER_SIZE, Message};
+    use crate::kernel::ipc::SessionHandler;
+    use crate::kernel::result::ResultCode;
+    use crate::nn::ServiceTrait;
+    use crate::nn::sf::{Command, Context};
+    use crate::nn::{cmif, sm};
+    use crate::tests::guest::Guest;
+
+    /// Send the hand-built request `words` and return the reply's words, up
+    /// to the last non-zero one
+    fn exchange(guest: &mut Guest, session: &mut dyn SessionHandler, words: &[u32]) -> Vec<u32> {
+        let mut buffer: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        buffer.resize(MESSAGE_BUFFER_SIZE, 0);
+        let request = Message::parse(&buffer).unwrap();
+        let reply = session.handle_request(&mut guest.kernel, &request).unwrap();
+
+        let mut buffer = vec![0; MESSAGE_BUFFER_SIZE];
+        reply.write(&mut buffer).unwrap();
+        let mut reply: Vec<u32> = buffer
+            .chunks(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        while reply.last() == Some(&0) {
+            reply.pop();
+        }
+        reply
+    }
+
+    #[test]
+    fn test_tipc_requests_to_sm() {
+        let mut guest = Guest::new();
+        let mut session = cmif::serve(sm::State::new());
+        let name = u64::from_le_bytes(*b"missing\0");
+        let (name_low, name_high) = (name as u32, (name >> 32) as u32);
+
+        // GetServiceHandle before RegisterClient
+        let get_service = [0x11, 2, name_low, name_high];
+        let reply = exchange(&mut guest, session.as_mut(), &get_service);
+        assert_eq!(reply, [0x11, 1, 0x415], "InvalidClient");
+
+        // RegisterClient without the kernel sending the PID
+        let reply = exchange(&mut guest, session.as_mut(), &[0x10, 2]);
+        assert_eq!(reply, [0x10, 1, 0x415]);
+
+        // RegisterClient: special header with the PID, then a placeholder
+        let register = [0x10, 0x8000_0002, 1, 0, 0, 0, 0];
+        let reply = exchange(&mut guest, session.as_mut(), &register);
+        assert_eq!(reply, [0x10, 1], "success, with the result as data");
+
+        let reply = exchange(&mut guest, session.as_mut(), &get_service);
+        assert_eq!(reply, [0x11, 1, 0xE15], "NotRegistered");
+
+        // Command 16 does not exist
+        let reply = exchange(&mut guest, session.as_mut(), &[0x20, 0]);
+        assert_eq!(reply, [0x20, 1, 0x1BA0A], "UnknownCommandId");
+
+        // The same object answers CMIF on the same session
+        let cmif_get_service = [0x4, 0xA, 0, 0, cmif::IN_MAGIC, 1, 1, 0, name_low, name_high];
+        let reply = exchange(&mut guest, session.as_mut(), &cmif_get_service);
+        assert_eq!(reply, [0, 8, 0, 0, cmif::OUT_MAGIC, 0, 0xE15]);
+
+        let reply = exchange(&mut guest, session.as_mut(), &[15, 0]);
+        assert_eq!(reply, [] as [u32; 0], "close has an empty reply");
+    }
+
+    struct Opener;
+
+    impl Opener {
+        fn get_values(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+            let value: u32 = ctx.pop()?;
+            ctx.push(value as u8);
+            ctx.push(0x1_0000_0002u64 * value as u64);
+            Ok(())
+        }
+
+        fn open(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+            ctx.push_object(Opener);
+            Ok(())
+        }
+
+        fn fail(&mut self, ctx: &mut Context) -> Result<(), ResultCode> {
+            ctx.push_object(Opener);
+            Err(ResultCode::new(2, 1))
+        }
+    }
+
+    impl ServiceTrait for Opener {
+        fn commands() -> &'static [Command<Self>] {
+            const COMMANDS: &[Command<Opener>] = &[
+                Command::new(0, "GetValues", Opener::get_values),
+                Command::new(1, "Open", Opener::open),
+                Command::new(2, "Fail", Opener::fail),
+            ];
+            COMMANDS
+        }
+    }
+
+    #[test]
+    fn test_tipc_output_and_objects() {
+        let mut guest = Guest::new();
+        let mut session = cmif::serve(Opener);
+
+        // No alignment padding before the arguments; the u64 output is
+        // aligned after the u8
+        let reply = exchange(&mut guest, session.as_mut(), &[0x10, 1, 3]);
+        assert_eq!(reply, [0x10, 5, 0, 3, 0, 6, 3]);
+        let reply = exchange(&mut guest, session.as_mut(), &[0x10, 0]);
+        assert_eq!(reply, [0x10, 1, 0x1940A], "InvalidHeaderSize");
+
+        // Interfaces come back as moved session handles
+        let reply = exchange(&mut guest, session.as_mut(), &[0x11, 0]);
+        let [0x11, 0x8000_0001, 0x20, handle] = reply[..] else {
+            panic!("unexpected reply {reply:x?}");
+        };
+        let object = guest.kernel.process.handles.get(handle);
+        assert!(matches!(object, Some(KernelObject::ClientSession(_))));
+        assert_eq!(guest.kernel.ipc.session_count(), 1);
+
+        let reply = exchange(&mut guest, session.as_mut(), &[0x12, 0]);
+        assert_eq!(reply, [0x12, 1, 0x202], "nothing but the error");
+        assert_eq!(guest.kernel.ipc.session_count(), 1);
+    }
+}
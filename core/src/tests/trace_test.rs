@@ -0,0 +1,149 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::hipc::{MESSAGE_BUFFER_SIZE, Message};
+    use crate::kernel::trace::{Event, Phase, Track};
+    use crate::kernel::{KernelExit, svc};
+    use crate::tests::arm64;
+    use crate::tests::guest::{Guest, call, set_flag, sleep, svc, wait_for_flag};
+
+    const SFCI: u32 = 0x4943_4653;
+
+    fn on_thread(events: &[Event], thread: u64) -> Vec<&Event> {
+        events
+            .iter()
+            .filter(|e| e.track == Track::Thread(thread))
+            .collect()
+    }
+
+    #[test]
+    fn test_trace_svcs_waits_and_ipc() {
+        let mut guest = Guest::new();
+        guest.kernel.trace.start();
+        guest.kernel.cpu.memory().write(guest.slot(10), b"sm:\0");
+
+        let mut main = sleep(15_000);
+        main.extend(call(svc::CONNECT_TO_NAMED_PORT, &[0, guest.slot(10)]));
+        main.push(arm64::mov_reg(0, 1));
+        main.push(svc(svc::SEND_SYNC_REQUEST));
+        main.push(svc(svc::EXIT_THREAD));
+        let main = guest.load(&main);
+        let handle = guest.spawn(main, 0, 44, 0);
+        let id = guest.kernel.thread_from_handle(handle, None).unwrap();
+
+        // sm:'s RegisterClient, a CMIF request aligned as libnx does
+        let mut request = Message {
+            kind: 4,
+            pid: Some(0),
+            ..Default::default()
+        };
+        let padding = (16 - request.data_offset() % 16) % 16 / 4;
+        request.data = vec![0; padding];
+        request.data.extend([SFCI, 0, 0, 0, 0, 0]);
+        let mut bytes = vec![0; MESSAGE_BUFFER_SIZE];
+        request.write(&mut bytes).unwrap();
+        let tls = guest.kernel.scheduler.thread(id).unwrap().tls_address;
+        guest.kernel.cpu.memory().write(tls, &bytes);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        let events = guest.kernel.trace.events();
+        let thread = on_thread(events, id);
+        let summary: Vec<_> = thread
+            .iter()
+            .map(|e| (e.phase, e.category, e.name.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Phase::Begin, "svc", "SleepThread"),
+                (Phase::Begin, "wait", "wait"),
+                (Phase::End, "wait", ""),
+                (Phase::End, "svc", ""),
+                (Phase::Begin, "svc", "ConnectToNamedPort"),
+                (Phase::End, "svc", ""),
+                (Phase::Begin, "svc", "SendSyncRequest"),
+                (Phase::Instant, "ipc", summary[7].2),
+                (Phase::End, "svc", ""),
+                (Phase::Begin, "svc", "ExitThread"),
+                (Phase::End, "svc", ""),
+            ]
+        );
+        assert!(thread[1].args.contains(&("on", String::from("Sleep"))));
+        assert!(
+            thread[2].time >= 15_000,
+            "the wait lasts until the thread runs"
+        );
+        assert!(thread[7].name.ends_with("#0"));
+        assert!(thread[7].args.contains(&("command", String::from("0"))));
+
+        let core: Vec<_> = events
+            .iter()
+            .filter(|e| e.track == Track::Core(0))
+            .collect();
+        assert!(!core.is_empty());
+        assert!(core.iter().all(|e| e.name == format!("thread {id}")));
+
+        let json = guest.kernel.trace.to_chrome_json();
+        assert!(json.starts_with("{\"displayTimeUnit\":\"ns\",\"traceEvents\":["));
+        assert!(json.contains("\"name\":\"SleepThread\""));
+        assert!(json.contains(&format!("\"args\":{{\"name\":\"Thread {id}\"}}")));
+        assert!(json.contains("\"ph\":\"X\""));
+        assert!(json.contains("\"ts\":0.000"));
+        assert!(json.trim_end().ends_with("]}"));
+    }
+
+    #[test]
+    fn test_core_track_shows_preemption() {
+        let mut guest = Guest::new();
+        guest.kernel.trace.start();
+        let mut spinner = wait_for_flag(guest.slot(2));
+        spinner.push(svc(svc::EXIT_THREAD));
+        let spinner = guest.load(&spinner);
+        let spinner = guest.spawn(spinner, 0, 44, 0);
+        let spinner = guest.kernel.thread_from_handle(spinner, None).unwrap();
+
+        let mut waiter = sleep(15_000);
+        waiter.extend(set_flag(guest.slot(2)));
+        waiter.push(svc(svc::EXIT_THREAD));
+        let waiter = guest.load(&waiter);
+        let waiter = guest.spawn(waiter, 0, 30, 0);
+        let waiter = guest.kernel.thread_from_handle(waiter, None).unwrap();
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        let spans: Vec<_> = guest
+            .kernel
+            .trace
+            .events()
+            .iter()
+            .filter(|e| e.track == Track::Core(0))
+            .map(|e| {
+                let Phase::Complete { duration } = e.phase else {
+                    panic!("core tracks only have spans");
+                };
+                (e.name.clone(), e.time, duration)
+            })
+            .collect();
+        let name = |id: u64| format!("thread {id}");
+        // The waiter sleeps within the first round, the spinner takes over
+        // until the waiter's deadline, then finishes after it
+        assert_eq!(spans[0], (name(waiter), 0, 10_000));
+        assert_eq!(spans[1], (name(spinner), 10_000, 5_000));
+        assert_eq!(spans[2].0, name(waiter));
+        assert_eq!(spans[2].1, 15_000);
+        assert_eq!(spans[3].0, name(spinner));
+    }
+
+    #[test]
+    fn test_stopped_tracer_records_nothing() {
+        let mut guest = Guest::new();
+        guest.kernel.trace.stop();
+        assert!(!guest.kernel.trace.is_enabled());
+        let mut code = sleep(1_000);
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&code);
+        guest.spawn(entry, 0, 44, 0);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert!(guest.kernel.trace.events().is_empty());
+        assert!(!guest.kernel.trace.to_chrome_json().contains("\"ph\":\"B\""));
+    }
+}
@@ -0,0 +1,214 @@
+#[cfg(test)]
+mod tests {
+    use crate::kernel::hipc::{MESSAGE_BUFFER_SIZE, Message};
+    use crate::kernel::ipc::SessionHandler;
+    use crate::kernel::result::ResultCode;
+    use crate::kernel::{KernelExit, svc};
+    use crate::nn::cmif::{self, Session};
+    use crate::nn::sf::{self, RESULT_UNKNOWN_COMMAND_ID};
+    use crate::nn::unimplemented::{Policy, Report, Reporter};
+    use crate::nn::{self, ServiceTrait, sm};
+    use crate::tests::guest::{Guest, call, cmif_frame, cmif_request, cmif_result, store, svc};
+
+    struct Empty;
+
+    impl ServiceTrait for Empty {
+        fn name() -> &'static str {
+            "Empty"
+        }
+    }
+
+    fn fsp_srv() -> nn::ServiceName {
+        nn::service_name("fsp-srv").unwrap()
+    }
+
+    fn report(command: u32, pc: u64) -> Report {
+        Report {
+            service: Some(fsp_srv()),
+            interface: Some("Empty"),
+            command: Some(command),
+            input: vec![command],
+            pc: Some(pc),
+        }
+    }
+
+    #[test]
+    fn test_reporter_counts_sites() {
+        let mut reporter = Reporter::default();
+        assert_eq!(reporter.report(report(1, 0x1000)), Policy::Error);
+        for _ in 0..3 {
+            reporter.report(report(2, 0x1000));
+        }
+        reporter.report(report(1, 0x2000));
+        reporter.report(report(1, 0x1000));
+
+        let sites = reporter.sites();
+        let counts: Vec<_> = sites
+            .iter()
+            .map(|&(r, hits)| (r.command, r.pc, hits))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                (Some(2), Some(0x1000), 3),
+                (Some(1), Some(0x1000), 2),
+                (Some(1), Some(0x2000), 1),
+            ]
+        );
+
+        let summary = reporter.summary();
+        let mut lines = summary.lines();
+        assert_eq!(lines.next(), Some("3 unimplemented sites"));
+        assert_eq!(
+            lines.next(),
+            Some(
+                "       3  command 2 of Empty (fsp-srv) at PC 0x0000000000001000, input [00000002]"
+            )
+        );
+        assert_eq!(lines.count(), 2);
+    }
+
+    #[test]
+    fn test_unknown_command_policy() {
+        let mut guest = Guest::new();
+        let mut session = Session::for_service(sf::object(Empty), fsp_srv());
+        let request = cmif_request(7, false, &[0x1234, 5]);
+
+        let reply = session.handle_request(&mut guest.kernel, &request).unwrap();
+        assert_eq!(cmif_result(&reply), RESULT_UNKNOWN_COMMAND_ID);
+        let sites = guest.kernel.sys.unimplemented.sites();
+        let (report, hits) = sites[0];
+        assert_eq!(hits, 1);
+        assert_eq!(report.service, Some(fsp_srv()));
+        assert_eq!(report.interface, Some("Empty"));
+        assert_eq!(report.command, Some(7));
+        assert_eq!(
+            report.input,
+            [0x1234, 5, 0, 0, 0, 0],
+            "with the spare words"
+        );
+        assert_eq!(report.pc, None, "sent by the host");
+
+        guest.kernel.sys.unimplemented.policy = Policy::Stub;
+        let reply = session.handle_request(&mut guest.kernel, &request).unwrap();
+        assert_eq!(cmif_result(&reply), ResultCode::SUCCESS);
+
+        // TIPC requests are reported the same way
+        let request = Message {
+            kind: 16 + 3,
+            data: vec![9],
+            ..Default::default()
+        };
+        let reply = session.handle_request(&mut guest.kernel, &request).unwrap();
+        assert_eq!(reply.data, [0]);
+        let sites = guest.kernel.sys.unimplemented.sites();
+        assert_eq!(sites.len(), 2);
+        assert_eq!(sites[0].1, 2, "the CMIF site was hit twice");
+        assert_eq!(
+            (sites[1].0.command, &sites[1].0.input[..]),
+            (Some(3), &[9][..])
+        );
+    }
+
+    #[test]
+    fn test_missing_service_policy() {
+        let mut guest = Guest::new();
+        let mut session = cmif::serve(sm::State::new());
+        let register = Message {
+            kind: 16,
+            pid: Some(0),
+            data: vec![0, 0],
+            ..Default::default()
+        };
+        session
+            .handle_request(&mut guest.kernel, &register)
+            .unwrap();
+        let name = u64::from_le_bytes(*b"missing\0");
+        let get_service = Message {
+            kind: 16 + 1,
+            data: vec![name as u32, (name >> 32) as u32],
+            ..Default::default()
+        };
+
+        let reply = session
+            .handle_request(&mut guest.kernel, &get_service)
+            .unwrap();
+        assert_eq!(reply.data, [sm::RESULT_NOT_REGISTERED.0]);
+
+        guest.kernel.sys.unimplemented.policy = Policy::Stub;
+        let reply = session
+            .handle_request(&mut guest.kernel, &get_service)
+            .unwrap();
+        assert_eq!(reply.data, [0]);
+        assert_eq!(reply.move_handles.len(), 1, "a session to a placeholder");
+
+        let sites = guest.kernel.sys.unimplemented.sites();
+        assert_eq!(sites.len(), 1);
+        let (report, hits) = sites[0];
+        assert_eq!(hits, 2);
+        assert_eq!((report.interface, report.command), (None, None));
+        assert_eq!(report.to_string(), "service (missing)");
+        assert!(guest.kernel.sys.services.get(*b"missing\0").is_some());
+    }
+
+    #[test]
+    fn test_halt_policy_stops_kernel() {
+        let mut guest = Guest::new();
+        guest.kernel.sys.unimplemented.policy = Policy::Halt;
+        let session = Session::for_service(sf::object(Empty), fsp_srv());
+        let handle = guest.kernel.create_host_session(Box::new(session)).unwrap();
+
+        let mut code = call(svc::SEND_SYNC_REQUEST, &[handle as u64]);
+        let svc_offset = (code.len() as u64 - 1) * 4;
+        code.extend(store(0, guest.slot(1)));
+        code.push(svc(svc::EXIT_THREAD));
+        let entry = guest.load(&code);
+        let thread = guest.spawn(entry, 0, 44, 0);
+
+        let id = guest.kernel.thread_from_handle(thread, None).unwrap();
+        let tls = guest.kernel.scheduler.thread(id).unwrap().tls_address;
+        let mut bytes = vec![0; MESSAGE_BUFFER_SIZE];
+        cmif_request(42, false, &[]).write(&mut bytes).unwrap();
+        guest.kernel.cpu.memory().write(tls, &bytes);
+
+        assert_eq!(
+            guest.run(),
+            KernelExit::Unimplemented {
+                core: 0,
+                thread: id
+            }
+        );
+        let sites = guest.kernel.sys.unimplemented.sites();
+        assert_eq!(sites[0].0.command, Some(42));
+        assert_eq!(sites[0].0.pc, Some(entry + svc_offset));
+
+        // The request still failed, so the thread can carry on if resumed
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+    }
+
+    #[test]
+    fn test_unknown_command_closes_moved_handles() {
+        let mut guest = Guest::new();
+        let session = Session::for_service(sf::object(Empty), fsp_srv());
+        let handle = guest.kernel.create_host_session(Box::new(session)).unwrap();
+        let before = guest.kernel.process.handles.len();
+
+        let (writable, readable) = guest.kernel.create_event().unwrap();
+        guest.kernel.close_handle(writable).unwrap();
+        let request = Message {
+            move_handles: vec![readable],
+            ..Default::default()
+        };
+        let request = cmif_frame(request, 42, &[]);
+        guest.send(handle, &request, 1);
+
+        assert_eq!(guest.run(), KernelExit::AllThreadsExited);
+        assert_eq!(guest.result(1), ResultCode::SUCCESS);
+        let sites = guest.kernel.sys.unimplemented.sites();
+        assert_eq!(sites[0].0.command, Some(42));
+        // All that is left is the sending thread's handle
+        assert_eq!(guest.kernel.process.handles.len(), before + 1);
+        assert_eq!(guest.kernel.process.handles.get(readable), None);
+    }
+}
@@ -0,0 +1,174 @@
+#[cfg(test)]
+mod tests {
+    use crate::fs::{
+        self, File, HostDirectory, MemoryDirectory, MemoryFile, OffsetFile, VfsDirectory, VfsFile,
+        VirtualDir, VirtualFile,
+    };
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    /// An empty host directory unique to `test`, removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(test: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("oboromi-vfs-{test}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn names(files: Vec<VirtualFile>) -> Vec<String> {
+        files.iter().map(|f| f.name()).collect()
+    }
+
+    /// What every writable backend must do
+    fn exercise_writable_tree(root: VirtualDir) {
+        let file = fs::create_file(
+            &fs::create_directories(&root, "save/slot0").unwrap(),
+            "a.bin",
+        )
+        .unwrap();
+        assert_eq!(file.size(), 0);
+        assert!(file.is_writable());
+        assert_eq!(file.write_at(4, b"data").unwrap(), 4);
+        assert_eq!(file.read_all().unwrap(), b"\0\0\0\0data");
+        file.set_size(6).unwrap();
+        assert_eq!(file.read_all().unwrap(), b"\0\0\0\0da");
+
+        let reopened = fs::open_file(&root, "/save/./slot0/a.bin").unwrap();
+        assert_eq!(reopened.read_all().unwrap(), b"\0\0\0\0da");
+        let mut buf = [0; 8];
+        assert_eq!(reopened.read_at(4, &mut buf).unwrap(), 2);
+        assert_eq!(
+            reopened.read_exact_at(4, &mut buf).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+
+        let slot = fs::open_directory(&root, "save/slot0").unwrap();
+        assert_eq!(
+            slot.create_file("a.bin").unwrap_err().kind(),
+            ErrorKind::AlreadyExists
+        );
+        slot.create_file("b.bin").unwrap();
+        assert_eq!(names(slot.files().unwrap()), ["a.bin", "b.bin"]);
+        slot.delete_file("a.bin").unwrap();
+        assert_eq!(names(slot.files().unwrap()), ["b.bin"]);
+        assert_eq!(
+            fs::open_file(&root, "save/slot0/a.bin").unwrap_err().kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(
+            fs::open_file(&root, "save/../escape").unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+
+        root.delete_subdirectory("save").unwrap();
+        assert!(root.subdirectories().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_memory_tree() {
+        let root: VirtualDir = Arc::new(MemoryDirectory::new(""));
+        exercise_writable_tree(root);
+    }
+
+    #[test]
+    fn test_host_tree() {
+        let temp = TempDir::new("host");
+        let root: VirtualDir = Arc::new(HostDirectory::open(&temp.0, true).unwrap());
+        exercise_writable_tree(root);
+    }
+
+    #[test]
+    fn test_read_only_trees() {
+        let temp = TempDir::new("read-only");
+        std::fs::write(temp.0.join("main"), b"code").unwrap();
+        let host: VirtualDir = Arc::new(HostDirectory::open(&temp.0, false).unwrap());
+        let file = host.file("main").unwrap();
+        assert_eq!(file.read_all().unwrap(), b"code");
+        assert_eq!(
+            file.write_at(0, b"x").unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            host.create_file("new").unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+
+        let memory = MemoryDirectory::read_only("exefs");
+        memory.add_file(Arc::new(MemoryFile::read_only("npdm", b"META".to_vec())));
+        assert!(!memory.is_writable());
+        assert_eq!(memory.file("npdm").unwrap().read_all().unwrap(), b"META");
+        assert_eq!(
+            memory.delete_file("npdm").unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn test_memory_file_rejects_huge_offsets() {
+        let file = MemoryFile::new("save.bin", b"data".to_vec());
+        for offset in [u64::MAX, u64::MAX - 1, 1 << 62] {
+            assert_eq!(
+                file.write_at(offset, b"xy").unwrap_err().kind(),
+                ErrorKind::FileTooLarge,
+                "offset {offset:#x}"
+            );
+        }
+        assert_eq!(
+            file.set_size(1 << 62).unwrap_err().kind(),
+            ErrorKind::FileTooLarge
+        );
+        assert_eq!(file.read_all().unwrap(), b"data", "left as it was");
+    }
+
+    #[test]
+    fn test_offset_file_is_a_window() {
+        let parent: VirtualFile =
+            Arc::new(MemoryFile::new("pfs0", b"headerABCDEFtrailer".to_vec()));
+        let view = OffsetFile::new(parent.clone(), "entry", 6, 6);
+        assert_eq!(view.name(), "entry");
+        assert_eq!(view.size(), 6);
+        assert_eq!(view.read_all().unwrap(), b"ABCDEF");
+
+        let mut buf = [0; 4];
+        assert_eq!(view.read_at(4, &mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"EF");
+        assert_eq!(view.read_at(6, &mut buf).unwrap(), 0);
+
+        assert_eq!(
+            view.write_at(4, b"xyz").unwrap(),
+            2,
+            "clipped to the window"
+        );
+        assert_eq!(parent.read_all().unwrap(), b"headerABCDxytrailer");
+        assert!(view.write_at(6, b"z").is_err());
+        assert!(view.set_size(7).is_err());
+    }
+
+    #[test]
+    fn test_mapped_file() {
+        let temp = TempDir::new("mapped");
+        let path = temp.0.join("title.nsp");
+        std::fs::write(&path, b"PFS0 contents").unwrap();
+
+        let file = File::open(&path).unwrap();
+        assert_eq!(&file[..4], b"PFS0");
+        assert_eq!(file.name(), "title.nsp");
+        assert!(!file.is_writable());
+        let file: VirtualFile = Arc::new(file);
+        let view = OffsetFile::new(file, "contents", 5, 8);
+        assert_eq!(view.read_all().unwrap(), b"contents");
+        assert!(File::open(temp.0.join("missing")).is_err());
+    }
+}